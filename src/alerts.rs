@@ -0,0 +1,325 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+use crate::error::PawprintError;
+use crate::hooks::Event;
+use crate::process;
+use crate::profile;
+
+// Where to send a message when the tunnel drops, fails over, or a subscription
+// runs low on traffic or close to expiring, as the `[alerts]` table of
+// config.toml:
+//
+//   [alerts]
+//   webhook = "https://hooks.example/pawprint"   # gets a JSON POST
+//   telegram_token = "123456:ABC..."
+//   telegram_chat_id = "42"
+//   quota_percent = 90                           # the defaults
+//   expiry_days = 3
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Alerts {
+    pub webhook: Option<String>,
+    pub telegram_token: Option<String>,
+    pub telegram_chat_id: Option<String>,
+    #[serde(default = "default_quota_percent")]
+    pub quota_percent: u8,
+    #[serde(default = "default_expiry_days")]
+    pub expiry_days: u64,
+}
+
+fn default_quota_percent() -> u8 {
+    90
+}
+
+fn default_expiry_days() -> u64 {
+    3
+}
+
+// Each message gets this long, so the core is never held up for longer.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static ALERTS: OnceLock<Alerts> = OnceLock::new();
+
+// Sets the sinks for the rest of the process; only the first call counts.
+pub fn install(alerts: Alerts) -> Result<(), PawprintError> {
+    if alerts.telegram_token.is_some() != alerts.telegram_chat_id.is_some() {
+        return Err("Telegram alerts need both telegram_token and telegram_chat_id".into());
+    }
+    let _ = ALERTS.set(alerts);
+    Ok(())
+}
+
+// What a provider reports in its `subscription-userinfo` header:
+// `upload=1234; download=5678; total=10737418240; expire=1767225600`. Zero or
+// missing means unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Usage {
+    pub upload: u64,
+    pub download: u64,
+    pub total: u64,
+    // Unix time.
+    pub expire: Option<u64>,
+}
+
+impl Usage {
+    pub fn parse(header: &str) -> Option<Usage> {
+        let mut usage = Usage::default();
+        let mut any = false;
+        for field in header.split(';') {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            let Ok(value) = value.trim().parse::<f64>() else {
+                continue;
+            };
+            // Some providers send floats.
+            let value = value as u64;
+            match key.trim().to_ascii_lowercase().as_str() {
+                "upload" => usage.upload = value,
+                "download" => usage.download = value,
+                "total" => usage.total = value,
+                "expire" if value > 0 => usage.expire = Some(value),
+                _ => continue,
+            }
+            any = true;
+        }
+        any.then_some(usage)
+    }
+
+    pub fn used(&self) -> u64 {
+        self.upload.saturating_add(self.download)
+    }
+
+    // None if unlimited.
+    pub fn percent(&self) -> Option<u64> {
+        (self.total > 0).then(|| self.used().saturating_mul(100) / self.total)
+    }
+
+    // Whole days left, None if it never expires; zero once expired.
+    pub fn days_left(&self, now: u64) -> Option<u64> {
+        self.expire
+            .map(|expire| expire.saturating_sub(now) / 86_400)
+    }
+}
+
+fn gib(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / (1u64 << 30) as f64)
+}
+
+// Whether `event` is alerted on, so callers that would not otherwise wait for
+// it know to.
+pub fn wants(event: Event) -> bool {
+    ALERTS.get().is_some() && matches!(event, Event::Disconnected | Event::Failover)
+}
+
+// Alerts on a disconnect or failover, with the hook variables of the event.
+pub fn event(event: Event, vars: &[(&str, String)]) {
+    if !wants(event) {
+        return;
+    }
+    let var = |key: &str| {
+        vars.iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_str())
+    };
+    let profile = profile::active().ok().flatten();
+    let text = match event {
+        Event::Failover => format!(
+            "pawprint-vpn failed over from {} to {}",
+            var("PREVIOUS_PROFILE").unwrap_or("?"),
+            profile.as_deref().unwrap_or("?")
+        ),
+        _ => match &profile {
+            Some(name) => format!("pawprint-vpn disconnected from {}", name),
+            None => "pawprint-vpn disconnected".to_string(),
+        },
+    };
+    let details = vars
+        .iter()
+        .map(|(key, value)| (key.to_ascii_lowercase(), json!(value)))
+        .collect();
+    send(event.as_str(), &text, profile.as_deref(), details);
+}
+
+// Alerts already sent per subscription, so `update` run from a timer warns
+// once per crossing rather than on every fetch.
+fn sent_file() -> PathBuf {
+    process::state_dir().join("alerts.json")
+}
+
+fn sent() -> BTreeMap<String, Vec<String>> {
+    fs::read_to_string(sent_file())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+// The usage each subscription reported on its last fetch, for sorting
+// profiles by the traffic they have left.
+fn usage_file() -> PathBuf {
+    process::state_dir().join("usage.json")
+}
+
+pub fn last_usage() -> BTreeMap<String, Usage> {
+    fs::read_to_string(usage_file())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save(path: PathBuf, content: &impl Serialize) {
+    let saved = fs::create_dir_all(process::state_dir())
+        .and_then(|_| fs::write(&path, serde_json::to_string_pretty(content)?));
+    if let Err(e) = saved {
+        debug!("Could not save {}: {}", path.display(), e);
+    }
+}
+
+// Records a subscription's usage, warns when it is past the quota or expiry
+// threshold, and alerts the first time it is if sinks are set.
+pub fn usage(url: &str, usage: &Usage) {
+    let mut usages = last_usage();
+    if usages.get(url) != Some(usage) {
+        usages.insert(url.to_string(), usage.clone());
+        save(usage_file(), &usages);
+    }
+    let thresholds = ALERTS.get();
+    let quota_percent = thresholds.map_or(default_quota_percent(), |a| a.quota_percent);
+    let expiry_days = thresholds.map_or(default_expiry_days(), |a| a.expiry_days);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // Subscription URLs usually carry the account token; messages leave the host.
+    let name = url::Url::parse(url)
+        .ok()
+        .and_then(|parsed| parsed.host_str().map(str::to_string))
+        .unwrap_or_else(|| "?".to_string());
+
+    let mut crossed = Vec::new();
+    if let Some(percent) = usage.percent().filter(|p| *p >= quota_percent as u64) {
+        crossed.push((
+            "quota",
+            format!(
+                "Subscription at {} has used {}% of its traffic ({} of {})",
+                name,
+                percent,
+                gib(usage.used()),
+                gib(usage.total)
+            ),
+        ));
+    }
+    if let Some(days) = usage.days_left(now).filter(|d| *d <= expiry_days) {
+        let text = if usage.expire.is_some_and(|expire| expire <= now) {
+            format!("Subscription at {} has expired", name)
+        } else {
+            format!("Subscription at {} expires in {} day(s)", name, days)
+        };
+        crossed.push(("expiry", text));
+    }
+
+    for (_, text) in &crossed {
+        warn!("{}", text);
+    }
+    if thresholds.is_none() {
+        return;
+    }
+    let mut sent_all = sent();
+    let before = sent_all.get(url).cloned().unwrap_or_default();
+    let now_crossed: Vec<String> = crossed.iter().map(|(kind, _)| kind.to_string()).collect();
+    for (kind, text) in &crossed {
+        if !before.iter().any(|k| k == kind) {
+            let details = BTreeMap::from([
+                ("subscription".to_string(), json!(name)),
+                ("upload".to_string(), json!(usage.upload)),
+                ("download".to_string(), json!(usage.download)),
+                ("total".to_string(), json!(usage.total)),
+                ("expire".to_string(), json!(usage.expire)),
+            ]);
+            send(kind, text, None, details);
+        }
+    }
+    if before != now_crossed {
+        if now_crossed.is_empty() {
+            sent_all.remove(url);
+        } else {
+            sent_all.insert(url.to_string(), now_crossed);
+        }
+        save(sent_file(), &sent_all);
+    }
+}
+
+fn post(url: &str, body: &Value) -> Result<(), String> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+    agent
+        .post(url)
+        .header("Content-Type", "application/json")
+        .send(body.to_string())
+        .map(drop)
+        .map_err(|e| e.to_string())
+}
+
+fn send(kind: &str, text: &str, profile: Option<&str>, details: BTreeMap<String, Value>) {
+    let Some(alerts) = ALERTS.get() else {
+        return;
+    };
+    if let Some(webhook) = &alerts.webhook {
+        let body = json!({
+            "event": kind,
+            "message": text,
+            "profile": profile,
+            "details": details,
+        });
+        if let Err(e) = post(webhook, &body) {
+            warn!("The webhook alert failed: {}", e);
+        }
+    }
+    if let (Some(token), Some(chat)) = (&alerts.telegram_token, &alerts.telegram_chat_id) {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", token);
+        // The error would show the token as part of the URL.
+        if post(&url, &json!({ "chat_id": chat, "text": text })).is_err() {
+            warn!("The Telegram alert failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_is_read_from_the_header() {
+        let usage =
+            Usage::parse("upload=1073741824; download=8589934592; total=10737418240; expire=0")
+                .unwrap();
+        assert_eq!(usage.used(), 9 << 30);
+        assert_eq!(usage.percent(), Some(90));
+        assert_eq!(usage.expire, None);
+
+        let usage = Usage::parse("upload=0;download=2.5E2;total=0;expire=1000000").unwrap();
+        assert_eq!(usage.download, 250);
+        assert_eq!(usage.percent(), None);
+        assert_eq!(usage.days_left(1000000 - 2 * 86_400 - 5), Some(2));
+        assert_eq!(usage.days_left(2000000), Some(0));
+
+        assert_eq!(Usage::parse("nonsense"), None);
+    }
+
+    #[test]
+    fn alerts_need_a_whole_telegram_sink() {
+        let alerts: Alerts = toml::from_str("telegram_token = \"1:a\"").unwrap();
+        assert_eq!(alerts.quota_percent, 90);
+        assert!(install(alerts).is_err());
+        assert!(toml::from_str::<Alerts>("telegram = 1").is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use serde_json::Value;
+
+use crate::error::PawprintError;
+use crate::parser::Node;
+use crate::spec::{
+    DnsRoute, FragmentSpec, InboundSpec, LogSpec, MuxSpec, NoiseSpec, RoutingRules, SniffingSpec,
+    SockoptSpec, StatsSpec, TunSpec,
+};
+use crate::target::CoreTarget;
+use crate::{singbox, xray};
+
+// Everything besides the nodes that shapes the generated config.
+#[derive(Default)]
+pub struct BuildOptions {
+    pub target: CoreTarget,
+    pub inbounds: Vec<InboundSpec>,
+    // Upstream resolvers reached through the proxy, e.g. https://1.1.1.1/dns-query.
+    pub dns_servers: Vec<String>,
+    pub dns_routes: Vec<DnsRoute>,
+    pub rules: RoutingRules,
+    pub tun: Option<TunSpec>,
+    // Pick between the nodes by latency instead of always using the first.
+    pub balance: bool,
+    // Dial each node through the one before it; traffic leaves through the last.
+    pub chain: bool,
+    pub mux: Option<MuxSpec>,
+    pub fragment: Option<FragmentSpec>,
+    pub noises: Vec<NoiseSpec>,
+    pub sniffing: Option<SniffingSpec>,
+    pub sockopt: Option<SockoptSpec>,
+    pub stats: Option<StatsSpec>,
+    pub log: Option<LogSpec>,
+}
+
+// Turns parsed nodes into the config format of one core.
+pub trait Backend {
+    fn name(&self) -> &str;
+
+    fn build(&self, nodes: &[Node], options: &BuildOptions) -> Result<Value, PawprintError>;
+}
+
+struct Xray;
+
+impl Backend for Xray {
+    fn name(&self) -> &str {
+        "Xray"
+    }
+
+    fn build(&self, nodes: &[Node], options: &BuildOptions) -> Result<Value, PawprintError> {
+        if options.tun.is_some() && !options.target.supports_tun() {
+            return Err(PawprintError::Unsupported {
+                target: options.target.to_string(),
+                feature: "run a tun inbound; use xray@25.8 or newer, or a sing-box target"
+                    .to_string(),
+            });
+        }
+        if let Some(server) = options.dns_servers.iter().find(|s| s.starts_with("tls://")) {
+            return Err(PawprintError::Unsupported {
+                target: options.target.to_string(),
+                feature: format!(
+                    "resolve through DNS over TLS ({}); use an https:// server or a sing-box target",
+                    server
+                ),
+            });
+        }
+        if !options.noises.is_empty() && !options.target.supports_noises() {
+            return Err(PawprintError::Unsupported {
+                target: options.target.to_string(),
+                feature: "send noise packets; use xray@24.9 or newer".to_string(),
+            });
+        }
+        Ok(serde_json::to_value(xray::build_config(nodes, options)?)?)
+    }
+}
+
+struct SingBox;
+
+impl Backend for SingBox {
+    fn name(&self) -> &str {
+        "sing-box"
+    }
+
+    fn build(&self, nodes: &[Node], options: &BuildOptions) -> Result<Value, PawprintError> {
+        if !options.noises.is_empty() {
+            return Err(PawprintError::Unsupported {
+                target: options.target.to_string(),
+                feature: "send noise packets; use an xray target".to_string(),
+            });
+        }
+        if options.fragment.is_some() && !options.target.supports_tls_fragment() {
+            return Err(PawprintError::Unsupported {
+                target: options.target.to_string(),
+                feature: "fragment the TLS handshake; use sing-box@1.12 or an xray target"
+                    .to_string(),
+            });
+        }
+        singbox::build_config(nodes, options)
+    }
+}
+
+// The target picks the output format.
+pub fn for_target(target: &CoreTarget) -> Box<dyn Backend> {
+    match target {
+        CoreTarget::Xray(_) => Box::new(Xray),
+        CoreTarget::SingBox(_) => Box::new(SingBox),
+    }
+}
@@ -0,0 +1,530 @@
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::PawprintError;
+use crate::logfile;
+use crate::parser::Node;
+
+const BAR_WIDTH: usize = 30;
+
+// A bar on stderr counting finished items, drawn only on a terminal.
+struct Progress<'a> {
+    label: &'a str,
+    total: usize,
+    done: AtomicUsize,
+    failed: AtomicUsize,
+    // Held while drawing so lines from different workers do not mix.
+    stderr: Mutex<()>,
+}
+
+impl Progress<'_> {
+    fn finish_one(&self, ok: bool) {
+        let done = self.done.fetch_add(1, Ordering::SeqCst) + 1;
+        let failed = if ok {
+            self.failed.load(Ordering::SeqCst)
+        } else {
+            self.failed.fetch_add(1, Ordering::SeqCst) + 1
+        };
+        let _guard = self.stderr.lock().unwrap();
+        let filled = done * BAR_WIDTH / self.total.max(1);
+        // Clears whatever a log line left on the current line.
+        let _ = write!(
+            io::stderr(),
+            "\r\x1b[2K{} [{}{}] {}/{} ({} failed)",
+            self.label,
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            done,
+            self.total,
+            failed
+        );
+        if done == self.total {
+            let _ = writeln!(io::stderr());
+        }
+    }
+}
+
+// Runs `work` over `items` on up to `workers` threads and returns the results in
+// the input order. With a `progress` label the finished items are counted on a
+// bar while it runs.
+pub fn run<T, R, F>(
+    items: &[T],
+    workers: usize,
+    progress: Option<&str>,
+    work: F,
+) -> Vec<Result<R, PawprintError>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> Result<R, PawprintError> + Sync,
+{
+    let progress = progress
+        .filter(|_| io::stderr().is_terminal() && !items.is_empty())
+        .map(|label| Progress {
+            label,
+            total: items.len(),
+            done: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            stderr: Mutex::new(()),
+        });
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(items.len()));
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1).min(items.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(item) = items.get(index) else {
+                        break;
+                    };
+                    // Errors are not Send, so they are flattened to strings here.
+                    let result = work(item).map_err(|e| e.to_string());
+                    if let Some(progress) = &progress {
+                        progress.finish_one(result.is_ok());
+                    }
+                    results.lock().unwrap().push((index, result));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    results
+        .into_iter()
+        .map(|(_, result)| result.map_err(Into::into))
+        .collect()
+}
+
+fn milliseconds<S: Serializer>(latency: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+    latency.map(|d| d.as_secs_f64() * 1000.0).serialize(s)
+}
+
+// One measurement of a server, such as a single TCP handshake, with when it
+// finished.
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub at: String,
+    #[serde(
+        rename = "latency_ms",
+        serialize_with = "milliseconds",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub latency: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Sample {
+    // Stamped now, as the measurement just finished.
+    pub fn new(result: Result<Duration, String>) -> Sample {
+        let (latency, error) = match result {
+            Ok(latency) => (Some(latency), None),
+            Err(e) => (None, Some(e)),
+        };
+        Sample {
+            at: logfile::timestamp(),
+            latency,
+            error,
+        }
+    }
+}
+
+// The outcome for one server in a report.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub tag: String,
+    pub protocol: String,
+    pub server: String,
+    pub ok: bool,
+    // What succeeded, e.g. the latency or the file written.
+    #[serde(flatten)]
+    pub details: serde_json::Map<String, Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Every measurement the result was taken from, for tests.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<Sample>,
+}
+
+impl Entry {
+    pub fn new<T>(node: &Node, result: &Result<T, PawprintError>, details: Value) -> Entry {
+        let details = match details {
+            Value::Object(map) => map,
+            _ => serde_json::Map::new(),
+        };
+        Entry {
+            tag: node.tag().to_string(),
+            protocol: node.protocol().to_string(),
+            server: format!("{}:{}", node.address(), node.port()),
+            ok: result.is_ok(),
+            details,
+            error: result.as_ref().err().map(|e| e.to_string()),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn with_samples(mut self, samples: Vec<Sample>) -> Entry {
+        self.samples = samples;
+        self
+    }
+}
+
+// Quoted only when needed, as spreadsheets read it.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// A detail as text, empty if missing.
+fn plain(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+// Summary of a batch, written as JSON for scripts to pick up, as CSV for
+// spreadsheets when the file name ends in .csv, or as a page to share when it
+// ends in .html.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub entries: Vec<Entry>,
+}
+
+impl Report {
+    pub fn new(entries: Vec<Entry>) -> Report {
+        let succeeded = entries.iter().filter(|e| e.ok).count();
+        Report {
+            total: entries.len(),
+            succeeded,
+            failed: entries.len() - succeeded,
+            entries,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), PawprintError> {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        let content = match extension.as_deref() {
+            Some("csv") => self.to_csv(),
+            Some("html" | "htm") => self.to_html(),
+            _ => serde_json::to_string_pretty(self)? + "\n",
+        };
+        fs::write(path, content).map_err(|e| PawprintError::file(path, e))
+    }
+
+    // One row per sample, repeating the server's columns, or one per server
+    // without samples.
+    pub fn to_csv(&self) -> String {
+        let details: BTreeSet<&str> = self
+            .entries
+            .iter()
+            .flat_map(|entry| entry.details.keys().map(String::as_str))
+            .collect();
+        let mut header = vec!["tag", "protocol", "server", "ok"];
+        header.extend(&details);
+        header.extend([
+            "error",
+            "sample",
+            "sample_at",
+            "sample_latency_ms",
+            "sample_error",
+        ]);
+        let mut csv = header.join(",") + "\n";
+        for entry in &self.entries {
+            let mut columns = vec![
+                csv_field(&entry.tag),
+                csv_field(&entry.protocol),
+                csv_field(&entry.server),
+                entry.ok.to_string(),
+            ];
+            columns.extend(
+                details
+                    .iter()
+                    .map(|key| csv_field(&plain(entry.details.get(*key)))),
+            );
+            columns.push(csv_field(entry.error.as_deref().unwrap_or_default()));
+            let samples: Vec<Option<(usize, &Sample)>> = if entry.samples.is_empty() {
+                vec![None]
+            } else {
+                entry.samples.iter().enumerate().map(Some).collect()
+            };
+            for sample in samples {
+                let mut row = columns.clone();
+                match sample {
+                    Some((index, sample)) => row.extend([
+                        (index + 1).to_string(),
+                        csv_field(&sample.at),
+                        sample.latency.map_or(String::new(), |d| {
+                            format!("{:.3}", d.as_secs_f64() * 1000.0)
+                        }),
+                        csv_field(sample.error.as_deref().unwrap_or_default()),
+                    ]),
+                    None => {
+                        row.extend([String::new(), String::new(), String::new(), String::new()])
+                    }
+                }
+                csv += &(row.join(",") + "\n");
+            }
+        }
+        csv
+    }
+}
+
+fn html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const SPARK_WIDTH: f64 = 120.0;
+const SPARK_HEIGHT: f64 = 24.0;
+
+// The samples of a server as a line, higher for slower, with failed ones as red
+// dots along the bottom.
+fn sparkline(samples: &[Sample]) -> String {
+    if samples.is_empty() {
+        return String::new();
+    }
+    let max = samples
+        .iter()
+        .filter_map(|s| s.latency)
+        .max()
+        .map_or(1.0, |d| d.as_secs_f64().max(f64::EPSILON));
+    let step = SPARK_WIDTH / (samples.len().max(2) - 1) as f64;
+    let mut points = Vec::new();
+    let mut failures = String::new();
+    for (index, sample) in samples.iter().enumerate() {
+        let x = index as f64 * step;
+        match sample.latency {
+            Some(latency) => {
+                let y = SPARK_HEIGHT - 2.0 - latency.as_secs_f64() / max * (SPARK_HEIGHT - 4.0);
+                points.push(format!("{:.1},{:.1}", x, y));
+            }
+            None => {
+                failures += &format!(
+                    "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"2\" fill=\"#d33\"/>",
+                    x,
+                    SPARK_HEIGHT - 2.0
+                );
+            }
+        }
+    }
+    let line = match points.len() {
+        0 => String::new(),
+        1 => {
+            let (x, y) = points[0].split_once(',').unwrap_or_default();
+            format!("<circle cx=\"{}\" cy=\"{}\" r=\"2\" fill=\"#36c\"/>", x, y)
+        }
+        _ => format!(
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"#36c\" stroke-width=\"1.5\"/>",
+            points.join(" ")
+        ),
+    };
+    format!(
+        "<svg width=\"{}\" height=\"{}\" viewBox=\"-2 0 {} {}\">{}{}</svg>",
+        SPARK_WIDTH + 4.0,
+        SPARK_HEIGHT,
+        SPARK_WIDTH + 4.0,
+        SPARK_HEIGHT,
+        line,
+        failures
+    )
+}
+
+// Sorts the table by a clicked column, numerically where the cells are
+// numbers, with empty cells last; clicking again reverses the order.
+const SORT_SCRIPT: &str = r#"
+document.querySelectorAll("th").forEach((th, column) => th.addEventListener("click", () => {
+  const body = th.closest("table").tBodies[0];
+  const ascending = th.dataset.order !== "asc";
+  document.querySelectorAll("th").forEach(other => delete other.dataset.order);
+  th.dataset.order = ascending ? "asc" : "desc";
+  const key = row => row.cells[column].dataset.value ?? row.cells[column].textContent;
+  [...body.rows].sort((a, b) => {
+    const [x, y] = [key(a), key(b)];
+    if (x === "" || y === "") return (x === "") - (y === "");
+    const order = isNaN(x) || isNaN(y) ? x.localeCompare(y) : x - y;
+    return ascending ? order : -order;
+  }).forEach(row => body.appendChild(row));
+}));
+"#;
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse}th,td{padding:4px 10px;text-align:left;border-bottom:1px solid #ddd}\
+th{cursor:pointer;user-select:none;background:#f4f4f4}th[data-order=asc]::after{content:\" ▲\"}\
+th[data-order=desc]::after{content:\" ▼\"}td.num{text-align:right}.failed{color:#d33}";
+
+impl Report {
+    // A page with nothing to fetch, so it can be mailed or attached as it is.
+    pub fn to_html(&self) -> String {
+        let details: BTreeSet<&str> = self
+            .entries
+            .iter()
+            .flat_map(|entry| entry.details.keys().map(String::as_str))
+            .collect();
+        let samples = self.entries.iter().any(|entry| !entry.samples.is_empty());
+        let mut head = String::from("<th>tag</th><th>protocol</th><th>server</th>");
+        for key in &details {
+            head += &format!("<th>{}</th>", html(&key.replace('_', " ")));
+        }
+        if samples {
+            head += "<th>loss</th><th>samples</th>";
+        }
+        head += "<th>error</th>";
+
+        let mut rows = String::new();
+        for entry in &self.entries {
+            let class = if entry.ok { "" } else { " class=\"failed\"" };
+            rows += &format!(
+                "<tr{}><td>{}</td><td>{}</td><td>{}</td>",
+                class,
+                html(&entry.tag),
+                html(&entry.protocol),
+                html(&entry.server)
+            );
+            for key in &details {
+                let value = html(&plain(entry.details.get(*key)));
+                rows += &format!("<td class=\"num\" data-value=\"{}\">{}</td>", value, value);
+            }
+            if samples {
+                let lost = entry.samples.iter().filter(|s| s.latency.is_none()).count();
+                let (loss, shown) = match entry.samples.len() {
+                    0 => (String::new(), String::new()),
+                    total => {
+                        let loss = lost as f64 / total as f64 * 100.0;
+                        (format!("{:.1}", loss), format!("{:.0}%", loss))
+                    }
+                };
+                rows += &format!(
+                    "<td class=\"num\" data-value=\"{}\">{}</td><td data-value=\"\">{}</td>",
+                    loss,
+                    shown,
+                    sparkline(&entry.samples)
+                );
+            }
+            rows += &format!(
+                "<td>{}</td></tr>\n",
+                html(entry.error.as_deref().unwrap_or_default())
+            );
+        }
+
+        format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+             <title>pawprint-vpn test report</title><style>{}</style></head><body>\n\
+             <h1>Test report</h1>\n<p>{} servers, {} passed, {} failed; generated {}</p>\n\
+             <table><thead><tr>{}</tr></thead><tbody>\n{}</tbody></table>\n\
+             <script>{}</script></body></html>\n",
+            STYLE,
+            self.total,
+            self.succeeded,
+            self.failed,
+            html(&logfile::timestamp()),
+            head,
+            rows,
+            SORT_SCRIPT
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(tag: &str, details: Value, error: Option<&str>, samples: Vec<Sample>) -> Entry {
+        let Value::Object(details) = details else {
+            unreachable!()
+        };
+        Entry {
+            tag: tag.to_string(),
+            protocol: "vless".to_string(),
+            server: "203.0.113.1:443".to_string(),
+            ok: error.is_none(),
+            details,
+            error: error.map(str::to_string),
+            samples,
+        }
+    }
+
+    #[test]
+    fn reports_keep_every_sample_in_csv() {
+        let sample = |ms: Option<u64>| Sample {
+            at: "2026-10-14T09:30:00Z".to_string(),
+            latency: ms.map(Duration::from_millis),
+            error: ms.is_none().then(|| "timed out".to_string()),
+        };
+        let report = Report::new(vec![
+            entry(
+                "nl, fast",
+                json!({"latency_ms": 12}),
+                None,
+                vec![sample(Some(12)), sample(None)],
+            ),
+            entry("de", json!({}), Some("refused"), Vec::new()),
+        ]);
+        assert_eq!(
+            report.to_csv(),
+            "tag,protocol,server,ok,latency_ms,error,sample,sample_at,sample_latency_ms,sample_error\n\
+             \"nl, fast\",vless,203.0.113.1:443,true,12,,1,2026-10-14T09:30:00Z,12.000,\n\
+             \"nl, fast\",vless,203.0.113.1:443,true,12,,2,2026-10-14T09:30:00Z,,timed out\n\
+             de,vless,203.0.113.1:443,false,,refused,,,,\n"
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["entries"][0]["samples"][1]["error"], "timed out");
+        assert_eq!(json["entries"][0]["samples"][0]["latency_ms"], 12.0);
+        assert!(json["entries"][1].get("samples").is_none());
+    }
+
+    #[test]
+    fn html_reports_escape_and_draw_samples() {
+        let sample = |ms: Option<u64>| Sample {
+            at: String::new(),
+            latency: ms.map(Duration::from_millis),
+            error: None,
+        };
+        let report = Report::new(vec![
+            entry(
+                "<b>nl</b>",
+                json!({"latency_ms": 10}),
+                None,
+                vec![sample(Some(10)), sample(Some(20)), sample(None)],
+            ),
+            entry("de", json!({}), Some("refused"), Vec::new()),
+        ]);
+        let page = report.to_html();
+        assert!(page.contains("<td>&lt;b&gt;nl&lt;/b&gt;</td>"), "{}", page);
+        assert!(page.contains("<th>latency ms</th>"), "{}", page);
+        assert!(page.contains("data-value=\"33.3\">33%</td>"), "{}", page);
+        assert!(
+            page.contains("<polyline points=\"0.0,12.0 60.0,2.0\""),
+            "{}",
+            page
+        );
+        assert!(
+            page.contains("<circle cx=\"120.0\" cy=\"22.0\""),
+            "{}",
+            page
+        );
+        assert!(page.contains("<tr class=\"failed\">"), "{}", page);
+    }
+}
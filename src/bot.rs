@@ -0,0 +1,228 @@
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use pawprint_vpn::profile;
+
+use crate::daemon::{self, Options};
+
+// Lets the Telegram users listed in the `[bot]` table of config.toml control the
+// daemon from a chat:
+//
+//   [bot]
+//   token = "123456:ABC..."   # from @BotFather
+//   users = [12345678]        # numeric user ids; others are told theirs
+//
+// It answers /status, /list and /use <profile> through the same routes as the
+// HTTP API, so switching from a chat waits for other changes like a POST does.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bot {
+    pub token: String,
+    pub users: Vec<i64>,
+}
+
+// How long each getUpdates call waits for a message. The daemon takes as long
+// to notice a stop.
+const POLL: Duration = Duration::from_secs(5);
+
+const HELP: &str = "/status - whether the core runs, and on which profile\n\
+                    /list - the stored profiles\n\
+                    /use <profile> - switch to a profile (or enough of its name), restarting a running core";
+
+impl Bot {
+    fn call(&self, method: &str, body: &Value) -> Result<Value, String> {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(POLL + Duration::from_secs(10)))
+            .build()
+            .into();
+        let url = format!("https://api.telegram.org/bot{}/{}", self.token, method);
+        let mut response = agent
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .send(body.to_string())
+            // The error would show the token as part of the URL.
+            .map_err(|e| match e {
+                ureq::Error::StatusCode(code) => format!("Telegram answered {}", code),
+                _ => "Telegram is unreachable".to_string(),
+            })?;
+        let reply: Value = response
+            .body_mut()
+            .read_to_string()
+            .map_err(|e| e.to_string())
+            .and_then(|body| serde_json::from_str(&body).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Telegram sent an invalid reply: {}", e))?;
+        Ok(reply["result"].clone())
+    }
+
+    fn say(&self, chat: &Value, text: &str) {
+        if let Err(e) = self.call("sendMessage", &json!({ "chat_id": chat, "text": text })) {
+            warn!("Telegram bot reply failed: {}", e);
+        }
+    }
+}
+
+fn or_unknown(value: &Value) -> String {
+    match value {
+        Value::Null => "?".to_string(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn status_text(status: &Value) -> String {
+    let profile = or_unknown(&status["profile"]);
+    if status["running"] != true {
+        return format!("The core is not running (active profile: {})", profile);
+    }
+    let mut text = format!(
+        "Running profile {} (pid {})",
+        profile,
+        or_unknown(&status["pid"])
+    );
+    let quality = &status["quality"];
+    if let Some(loss) = quality["loss"].as_f64() {
+        let rtt = quality["rtt_ms"]
+            .as_f64()
+            .map_or("?".to_string(), |ms| format!("{:.0} ms", ms));
+        let jitter = quality["jitter_ms"]
+            .as_f64()
+            .map_or("?".to_string(), |ms| format!("{:.0} ms", ms));
+        text += &format!(
+            "\nRTT {}, jitter {}, loss {:.0}%",
+            rtt,
+            jitter,
+            loss * 100.0
+        );
+    }
+    text
+}
+
+fn list_text(profiles: &Value) -> String {
+    let lines: Vec<String> = profiles
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|p| {
+            let marker = if p["active"] == true { "▶" } else { "•" };
+            let latency = p["latency_ms"]
+                .as_u64()
+                .map_or(String::new(), |ms| format!(", {} ms", ms));
+            format!(
+                "{} {} ({} {}:{}{})",
+                marker,
+                or_unknown(&p["name"]),
+                or_unknown(&p["protocol"]),
+                or_unknown(&p["server"]),
+                or_unknown(&p["port"]),
+                latency
+            )
+        })
+        .collect();
+    if lines.is_empty() {
+        "No profiles stored yet".to_string()
+    } else {
+        lines.join("\n")
+    }
+}
+
+// The reply to a message's text.
+fn answer(options: &Options, text: &str) -> String {
+    let mut words = text.split_whitespace();
+    // Commands in groups come as /status@name_of_the_bot.
+    let command = words.next().unwrap_or_default();
+    let command = command.split('@').next().unwrap_or_default();
+    let result = match (command, words.next()) {
+        ("/status", _) => daemon::route(options, "GET", "/status").map(|s| status_text(&s)),
+        ("/list", _) => daemon::route(options, "GET", "/profiles").map(|p| list_text(&p)),
+        ("/use", Some(query)) => {
+            let name = match profile::resolve(query) {
+                Ok(name) => name,
+                Err(e) => return format!("✗ {}", e),
+            };
+            let path = format!("/profiles/{}/use", name);
+            daemon::route(options, "POST", &path)
+                .map(|s| format!("✓ Switched to {}\n{}", name, status_text(&s)))
+        }
+        ("/use", None) => return "Usage: /use <profile>".to_string(),
+        _ => return HELP.to_string(),
+    };
+    result.unwrap_or_else(|e| format!("✗ {}", e.message))
+}
+
+// Answers messages until a stop is requested or `done` is set.
+pub fn run(bot: &Bot, options: &Options, done: &AtomicBool) {
+    // Messages sent while the daemon was down are dropped, not acted on late.
+    let mut offset = match bot.call("getUpdates", &json!({ "offset": -1, "timeout": 0 })) {
+        Ok(updates) => updates[0]["update_id"].as_i64().map_or(0, |id| id + 1),
+        Err(e) => {
+            warn!("Telegram bot: {}", e);
+            0
+        }
+    };
+    info!("✓ Telegram bot answering {} user(s)", bot.users.len());
+    while !crate::watch::stop_requested() && !done.load(Ordering::SeqCst) {
+        let body = json!({ "offset": offset, "timeout": POLL.as_secs() });
+        let updates = match bot.call("getUpdates", &body) {
+            Ok(updates) => updates,
+            Err(e) => {
+                warn!("Telegram bot: {}", e);
+                crate::watch::sleep(POLL);
+                continue;
+            }
+        };
+        for update in updates.as_array().into_iter().flatten() {
+            if let Some(id) = update["update_id"].as_i64() {
+                offset = offset.max(id + 1);
+            }
+            let message = &update["message"];
+            let (Some(user), Some(text)) =
+                (message["from"]["id"].as_i64(), message["text"].as_str())
+            else {
+                continue;
+            };
+            let chat = &message["chat"]["id"];
+            if !bot.users.contains(&user) {
+                warn!("Telegram user {} is not in [bot] users", user);
+                bot.say(chat, &format!("Not allowed. Your user id is {}", user));
+                continue;
+            }
+            debug!("Telegram user {}: {}", user, text);
+            bot.say(chat, &answer(options, text));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_show_status_and_profiles() {
+        let status = json!({
+            "running": true, "pid": 42, "profile": "nl",
+            "quality": {"loss": 0.25, "rtt_ms": 80.4, "jitter_ms": null},
+        });
+        assert_eq!(
+            status_text(&status),
+            "Running profile nl (pid 42)\nRTT 80 ms, jitter ?, loss 25%"
+        );
+        let stopped = json!({"running": false, "profile": null});
+        assert_eq!(
+            status_text(&stopped),
+            "The core is not running (active profile: ?)"
+        );
+
+        let profiles = json!([
+            {"name": "nl", "active": true, "protocol": "vless", "server": "nl.example", "port": 443, "latency_ms": 80},
+            {"name": "de", "active": false, "protocol": "trojan", "server": "de.example", "port": 443, "latency_ms": null},
+        ]);
+        assert_eq!(
+            list_text(&profiles),
+            "▶ nl (vless nl.example:443, 80 ms)\n• de (trojan de.example:443)"
+        );
+        assert_eq!(list_text(&json!([])), "No profiles stored yet");
+    }
+}
@@ -0,0 +1,265 @@
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde_json::Value;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::error::PawprintError;
+use crate::jsonc;
+
+// Keys whose values identify or authenticate the user, as Xray and then
+// sing-box name them.
+const SECRET_KEYS: &[&str] = &[
+    "id",
+    "password",
+    "pass",
+    "privateKey",
+    "secretKey",
+    "preSharedKey",
+    "shortId",
+    "user",
+    "uuid",
+    "private_key",
+    "pre_shared_key",
+    "short_id",
+    "username",
+];
+
+// Only the tail of each log file is included.
+const LOG_TAIL_BYTES: u64 = 256 * 1024;
+
+pub struct BundleInputs<'a> {
+    pub config_files: &'a [PathBuf],
+    pub log_file: Option<&'a Path>,
+    pub xray: &'a str,
+}
+
+// Replaces secret values anywhere in the document, keeping its structure.
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if SECRET_KEYS.contains(&key.as_str()) && !value.is_object() && !value.is_array() {
+                    *value = Value::String("<redacted>".to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn is_uuid(candidate: &[u8]) -> bool {
+    candidate.len() == 36
+        && candidate.iter().enumerate().all(|(i, b)| match i {
+            8 | 13 | 18 | 23 => *b == b'-',
+            _ => b.is_ascii_hexdigit(),
+        })
+}
+
+// Logs are free text, so only UUID-shaped user ids can be found and masked there.
+fn redact_text(text: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len());
+    let mut i = 0;
+    while i < text.len() {
+        if i + 36 <= text.len() && is_uuid(&text[i..i + 36]) {
+            out.extend_from_slice(b"<redacted>");
+            i += 36;
+        } else {
+            out.push(text[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+fn command_output(program: &str, args: &[&str]) -> String {
+    match Command::new(program).args(args).output() {
+        Ok(output) => {
+            let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+            text.push_str(&String::from_utf8_lossy(&output.stderr));
+            if !output.status.success() {
+                text.push_str(&format!("\n({} exited with {})\n", program, output.status));
+            }
+            text
+        }
+        Err(e) => format!("{} {}: not available ({})\n", program, args.join(" "), e),
+    }
+}
+
+fn routing_table() -> String {
+    if cfg!(target_os = "linux") {
+        let mut text = command_output("ip", &["route", "show", "table", "all"]);
+        text.push_str(&command_output(
+            "ip",
+            &["-6", "route", "show", "table", "all"],
+        ));
+        text
+    } else if cfg!(target_os = "windows") {
+        command_output("route", &["print"])
+    } else {
+        command_output("netstat", &["-rn"])
+    }
+}
+
+fn versions(xray: &str) -> String {
+    format!(
+        "pawprint-vpn {}\nos: {} {}\n\n{}",
+        env!("CARGO_PKG_VERSION"),
+        env::consts::OS,
+        env::consts::ARCH,
+        command_output(xray, &["version"])
+    )
+}
+
+fn log_tail(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(LOG_TAIL_BYTES)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    Ok(tail)
+}
+
+fn redacted_config(path: &Path) -> Result<Vec<u8>, PawprintError> {
+    let content = fs::read_to_string(path)?;
+    let mut config = jsonc::parse(&content)?;
+    redact(&mut config);
+    Ok(serde_json::to_vec_pretty(&config)?)
+}
+
+fn append(tar: &mut tar::Builder<GzEncoder<File>>, name: &str, data: &[u8]) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    );
+    header.set_cksum();
+    tar.append_data(&mut header, format!("pawprint-bundle/{}", name), data)
+}
+
+pub fn create(output: &Path, inputs: &BundleInputs) -> Result<(), PawprintError> {
+    let file = File::create(output)
+        .map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    info!("Collecting versions...");
+    append(&mut tar, "versions.txt", versions(inputs.xray).as_bytes())?;
+
+    info!("Collecting routing table...");
+    append(&mut tar, "routes.txt", routing_table().as_bytes())?;
+
+    for (index, path) in inputs.config_files.iter().enumerate() {
+        info!("Adding redacted config {}...", path.display());
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "config.json".to_string());
+        match redacted_config(path) {
+            Ok(data) => append(&mut tar, &format!("configs/{}-{}", index, name), &data)?,
+            // Unparseable configs are left out: redaction cannot be guaranteed.
+            Err(e) => warn!("Skipping {}: {}", path.display(), e),
+        }
+    }
+
+    if let Some(log_file) = inputs.log_file {
+        let rotated = PathBuf::from(format!("{}.1", log_file.display()));
+        for path in [rotated.as_path(), log_file] {
+            if !path.exists() {
+                continue;
+            }
+            info!("Adding log {}...", path.display());
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            append(
+                &mut tar,
+                &format!("logs/{}", name),
+                &redact_text(&log_tail(path)?),
+            )?;
+        }
+    }
+
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const UUID: &str = "b831381d-6324-4d53-ad4f-8cda48b30811";
+
+    #[test]
+    fn redacts_xray_credentials() {
+        let mut config = json!({
+            "outbounds": [{
+                "protocol": "vless",
+                "settings": {"vnext": [{"address": "example.com", "users": [{"id": UUID}]}]},
+                "streamSettings": {"realitySettings": {"publicKey": "pbk", "shortId": "01ab"}},
+            }],
+        });
+        redact(&mut config);
+        let settings = &config["outbounds"][0];
+        assert_eq!(
+            settings["settings"]["vnext"][0]["users"][0]["id"],
+            "<redacted>"
+        );
+        assert_eq!(settings["settings"]["vnext"][0]["address"], "example.com");
+        assert_eq!(
+            settings["streamSettings"]["realitySettings"]["shortId"],
+            "<redacted>"
+        );
+        // Public keys are not secret.
+        assert_eq!(
+            settings["streamSettings"]["realitySettings"]["publicKey"],
+            "pbk"
+        );
+    }
+
+    #[test]
+    fn redacts_sing_box_credentials() {
+        let mut config = json!({
+            "outbounds": [
+                {
+                    "type": "vless",
+                    "server": "example.com",
+                    "uuid": UUID,
+                    "tls": {"reality": {"public_key": "pbk", "short_id": "01ab"}},
+                },
+                {"type": "tuic", "uuid": UUID, "password": "secret"},
+                {"type": "socks", "username": "me", "password": "secret"},
+            ],
+        });
+        redact(&mut config);
+        let text = config.to_string();
+        assert!(!text.contains(UUID), "{}", text);
+        assert!(!text.contains("01ab"), "{}", text);
+        assert!(!text.contains("secret"), "{}", text);
+        assert_eq!(config["outbounds"][0]["uuid"], "<redacted>");
+        assert_eq!(
+            config["outbounds"][0]["tls"]["reality"]["short_id"],
+            "<redacted>"
+        );
+        assert_eq!(config["outbounds"][0]["server"], "example.com");
+    }
+
+    #[test]
+    fn masks_uuids_in_logs() {
+        let log = format!("accepted tcp:1.1.1.1:443 [{}] email: x", UUID);
+        assert_eq!(
+            String::from_utf8(redact_text(log.as_bytes())).unwrap(),
+            "accepted tcp:1.1.1.1:443 [<redacted>] email: x"
+        );
+    }
+}
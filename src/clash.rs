@@ -0,0 +1,494 @@
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use tracing::warn;
+
+use crate::error::PawprintError;
+use crate::parser::{Node, ShadowsocksConfig};
+
+// Fetched by url-test and fallback groups to compare the proxies.
+const TEST_URL: &str = "http://cp.cloudflare.com/generate_204";
+const TEST_INTERVAL: u64 = 300;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum GroupType {
+    Select,
+    UrlTest,
+    Fallback,
+}
+
+impl GroupType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GroupType::Select => "select",
+            GroupType::UrlTest => "url-test",
+            GroupType::Fallback => "fallback",
+        }
+    }
+}
+
+// Mapping that keeps keys in insertion order, so proxies read like hand-written ones.
+fn mapping<const N: usize>(entries: [(&str, Value); N]) -> Mapping {
+    entries
+        .into_iter()
+        .map(|(key, value)| (Value::from(key), value))
+        .collect()
+}
+
+fn insert(map: &mut Mapping, key: &str, value: impl Into<Value>) {
+    map.insert(Value::from(key), value.into());
+}
+
+fn list(values: &str) -> Value {
+    Value::Sequence(values.split(',').map(Value::from).collect())
+}
+
+// Hysteria2 and TUIC always use TLS, so only the name, ALPN and certificate
+// checks are left to set.
+fn add_quic_tls(
+    proxy: &mut Mapping,
+    params: &HashMap<String, String>,
+    address: &str,
+    insecure_key: &str,
+) {
+    let param = |key: &str| params.get(key).filter(|v| !v.is_empty());
+    insert(
+        proxy,
+        "sni",
+        param("sni").map(String::as_str).unwrap_or(address),
+    );
+    if let Some(alpn) = param("alpn") {
+        insert(proxy, "alpn", list(alpn));
+    }
+    if param(insecure_key).is_some_and(|v| v == "1" || v == "true") {
+        insert(proxy, "skip-cert-verify", true);
+    }
+}
+
+// `servername_key` is `servername` for VLESS/VMess and `sni` for Trojan.
+fn add_tls(
+    proxy: &mut Mapping,
+    params: &HashMap<String, String>,
+    address: &str,
+    default_security: &str,
+    servername_key: &str,
+) {
+    let param = |key: &str| params.get(key).filter(|v| !v.is_empty());
+    let security = param("security")
+        .map(String::as_str)
+        .unwrap_or(default_security);
+    if security != "tls" && security != "reality" {
+        return;
+    }
+
+    insert(proxy, "tls", true);
+    insert(
+        proxy,
+        servername_key,
+        param("sni").map(String::as_str).unwrap_or(address),
+    );
+    if let Some(alpn) = param("alpn") {
+        insert(proxy, "alpn", list(alpn));
+    }
+    // REALITY only works with a uTLS fingerprint.
+    let fingerprint = param("fp")
+        .map(String::as_str)
+        .or((security == "reality").then_some("chrome"));
+    if let Some(fp) = fingerprint {
+        insert(proxy, "client-fingerprint", fp);
+    }
+    if ["allowInsecure", "insecure"]
+        .iter()
+        .any(|key| param(key).is_some_and(|v| v == "1" || v == "true"))
+    {
+        insert(proxy, "skip-cert-verify", true);
+    }
+    // A base64 ECHConfigList, or a DNS query like `example.com+https://...`
+    // whose domain mihomo looks the config up for.
+    if let Some(ech) = param("ech") {
+        let opts = if ech.contains('.') {
+            let domain = ech.split('+').next().unwrap_or_default();
+            mapping([
+                ("enable", true.into()),
+                ("query-server-name", domain.into()),
+            ])
+        } else {
+            mapping([("enable", true.into()), ("config", ech.as_str().into())])
+        };
+        insert(proxy, "ech-opts", opts);
+    }
+    if security == "reality" {
+        insert(
+            proxy,
+            "reality-opts",
+            mapping([
+                (
+                    "public-key",
+                    param("pbk").cloned().unwrap_or_default().into(),
+                ),
+                ("short-id", param("sid").cloned().unwrap_or_default().into()),
+            ]),
+        );
+    }
+}
+
+fn add_transport(proxy: &mut Mapping, params: &HashMap<String, String>) -> Result<(), String> {
+    let param = |key: &str| params.get(key).filter(|v| !v.is_empty());
+    let path = param("path").map(String::as_str).unwrap_or("/");
+    let host = param("host");
+    let ws_opts = |upgrade: bool| {
+        let mut opts = mapping([("path", path.into())]);
+        if let Some(host) = host {
+            insert(
+                &mut opts,
+                "headers",
+                mapping([("Host", host.as_str().into())]),
+            );
+        }
+        if upgrade {
+            insert(&mut opts, "v2ray-http-upgrade", true);
+        }
+        opts
+    };
+
+    let network = params.get("type").map(String::as_str).unwrap_or("tcp");
+    let (network, key, opts) = match network {
+        "tcp" | "raw" if param("headerType").is_some_and(|t| t == "http") => {
+            let mut opts = mapping([("method", "GET".into()), ("path", list(path))]);
+            if let Some(host) = host {
+                insert(&mut opts, "headers", mapping([("Host", list(host))]));
+            }
+            ("http", "http-opts", opts)
+        }
+        "tcp" | "raw" => {
+            insert(proxy, "network", "tcp");
+            return Ok(());
+        }
+        "ws" => ("ws", "ws-opts", ws_opts(false)),
+        // Clash.Meta speaks HTTPUpgrade as a flavour of its WebSocket transport.
+        "httpupgrade" => ("ws", "ws-opts", ws_opts(true)),
+        "grpc" => (
+            "grpc",
+            "grpc-opts",
+            mapping([(
+                "grpc-service-name",
+                param("serviceName").cloned().unwrap_or_default().into(),
+            )]),
+        ),
+        "h2" | "http" => {
+            let mut opts = mapping([("path", path.into())]);
+            if let Some(host) = host {
+                insert(&mut opts, "host", list(host));
+            }
+            ("h2", "h2-opts", opts)
+        }
+        other => {
+            return Err(format!(
+                "Clash.Meta does not support the {} transport",
+                other
+            ));
+        }
+    };
+    insert(proxy, "network", network);
+    insert(proxy, key, opts);
+    Ok(())
+}
+
+// SIP003 plugin options are `name;key=value;flag`.
+fn add_plugin(proxy: &mut Mapping, ss: &ShadowsocksConfig) -> Result<(), String> {
+    let Some(plugin) = ss.sip003() else {
+        return Ok(());
+    };
+    let options = &plugin.options;
+    let opts = match plugin.name {
+        "obfs-local" | "simple-obfs" => {
+            insert(proxy, "plugin", "obfs");
+            let mut opts = mapping([(
+                "mode",
+                options.get("obfs").copied().unwrap_or("http").into(),
+            )]);
+            if let Some(host) = options.get("obfs-host") {
+                insert(&mut opts, "host", *host);
+            }
+            opts
+        }
+        "v2ray-plugin"
+            if plugin
+                .option("mode")
+                .is_some_and(|mode| mode != "websocket") =>
+        {
+            return Err(format!(
+                "Clash.Meta runs v2ray-plugin in websocket mode only, not {}",
+                plugin.option("mode").unwrap_or_default()
+            ));
+        }
+        "v2ray-plugin" => {
+            insert(proxy, "plugin", "v2ray-plugin");
+            let mut opts = mapping([("mode", "websocket".into())]);
+            if options.contains_key("tls") {
+                insert(&mut opts, "tls", true);
+            }
+            if let Some(host) = options.get("host") {
+                insert(&mut opts, "host", *host);
+            }
+            if let Some(path) = options.get("path") {
+                insert(&mut opts, "path", *path);
+            }
+            opts
+        }
+        other => return Err(format!("Clash.Meta does not support the {} plugin", other)),
+    };
+    insert(proxy, "plugin-opts", opts);
+    Ok(())
+}
+
+fn build_proxy(node: &Node, name: &str) -> Result<Mapping, String> {
+    let kind = match node {
+        Node::Vless(_) => "vless",
+        Node::Vmess(_) => "vmess",
+        Node::Trojan(_) => "trojan",
+        Node::Shadowsocks(_) => "ss",
+        Node::Hysteria2(_) => "hysteria2",
+        Node::Tuic(_) => "tuic",
+        Node::Wireguard(_) => "wireguard",
+        Node::Plugin(plugin) => {
+            return Err(format!(
+                "{}:// nodes come from a plugin as Xray outbounds and cannot be converted to Clash",
+                plugin.scheme
+            ));
+        }
+    };
+    let mut proxy = mapping([
+        ("name", name.into()),
+        ("type", kind.into()),
+        ("server", node.address().into()),
+        ("port", node.port().into()),
+    ]);
+    match node {
+        Node::Vless(vless) => {
+            insert(&mut proxy, "uuid", vless.uuid.as_str());
+            insert(&mut proxy, "udp", true);
+            if let Some(flow) = vless.params.get("flow").filter(|f| !f.is_empty()) {
+                insert(&mut proxy, "flow", flow.as_str());
+            }
+            add_tls(
+                &mut proxy,
+                &vless.params,
+                &vless.address,
+                "tls",
+                "servername",
+            );
+            add_transport(&mut proxy, &vless.params)?;
+        }
+        Node::Vmess(vmess) => {
+            insert(&mut proxy, "uuid", vmess.uuid.as_str());
+            insert(&mut proxy, "alterId", vmess.alter_id);
+            insert(&mut proxy, "cipher", vmess.security.as_str());
+            insert(&mut proxy, "udp", true);
+            add_tls(
+                &mut proxy,
+                &vmess.params,
+                &vmess.address,
+                "none",
+                "servername",
+            );
+            add_transport(&mut proxy, &vmess.params)?;
+        }
+        Node::Trojan(trojan) => {
+            if trojan.params.get("security").is_some_and(|s| s == "none") {
+                return Err("Clash.Meta trojan proxies always use TLS".to_string());
+            }
+            insert(&mut proxy, "password", trojan.password.as_str());
+            insert(&mut proxy, "udp", true);
+            add_tls(&mut proxy, &trojan.params, &trojan.address, "tls", "sni");
+            // `tls` is implied for trojan.
+            proxy.remove("tls");
+            add_transport(&mut proxy, &trojan.params)?;
+        }
+        Node::Shadowsocks(ss) => {
+            insert(&mut proxy, "cipher", ss.method.as_str());
+            insert(&mut proxy, "password", ss.password.as_str());
+            insert(&mut proxy, "udp", true);
+            add_plugin(&mut proxy, ss)?;
+        }
+        Node::Hysteria2(hy2) => {
+            let param = |key: &str| hy2.params.get(key).filter(|v| !v.is_empty());
+            insert(&mut proxy, "password", hy2.auth.as_str());
+            add_quic_tls(&mut proxy, &hy2.params, &hy2.address, "insecure");
+            if let Some(obfs) = param("obfs") {
+                insert(&mut proxy, "obfs", obfs.as_str());
+                if let Some(password) = param("obfs-password") {
+                    insert(&mut proxy, "obfs-password", password.as_str());
+                }
+            }
+            for (key, field) in [("upmbps", "up"), ("downmbps", "down")] {
+                if let Some(mbps) = param(key) {
+                    insert(&mut proxy, field, mbps.as_str());
+                }
+            }
+        }
+        Node::Tuic(tuic) => {
+            let param = |key: &str| tuic.params.get(key).filter(|v| !v.is_empty());
+            insert(&mut proxy, "uuid", tuic.uuid.as_str());
+            insert(&mut proxy, "password", tuic.password.as_str());
+            insert(&mut proxy, "udp", true);
+            add_quic_tls(&mut proxy, &tuic.params, &tuic.address, "allow_insecure");
+            if proxy.get("alpn").is_none() {
+                insert(&mut proxy, "alpn", list("h3"));
+            }
+            if let Some(congestion) = param("congestion_control") {
+                insert(&mut proxy, "congestion-controller", congestion.as_str());
+            }
+            if let Some(mode) = param("udp_relay_mode") {
+                insert(&mut proxy, "udp-relay-mode", mode.as_str());
+            }
+        }
+        Node::Wireguard(wg) => {
+            let param = |key: &str| wg.params.get(key).filter(|v| !v.is_empty());
+            insert(&mut proxy, "private-key", wg.private_key.as_str());
+            insert(
+                &mut proxy,
+                "public-key",
+                param("publickey").cloned().unwrap_or_default().as_str(),
+            );
+            if let Some(psk) = param("presharedkey") {
+                insert(&mut proxy, "pre-shared-key", psk.as_str());
+            }
+            // Clash takes one address per family, without the prefix length.
+            for address in wg.local_addresses() {
+                let ip = address.split('/').next().unwrap_or(address);
+                let key = if ip.contains(':') { "ipv6" } else { "ip" };
+                if proxy.get(key).is_none() {
+                    insert(&mut proxy, key, ip);
+                }
+            }
+            if let Some(reserved) = wg.reserved() {
+                insert(
+                    &mut proxy,
+                    "reserved",
+                    Value::Sequence(reserved.into_iter().map(Value::from).collect()),
+                );
+            }
+            if let Some(mtu) = param("mtu").and_then(|m| m.parse::<u16>().ok()) {
+                insert(&mut proxy, "mtu", mtu);
+            }
+            insert(&mut proxy, "udp", true);
+        }
+        Node::Plugin(_) => unreachable!("rejected above"),
+    }
+    Ok(proxy)
+}
+
+// Clash.Meta `proxies:` document, plus a group over all of them when `group` is set.
+// Nodes Clash cannot express are skipped with a warning.
+pub fn build_document(
+    nodes: &[Node],
+    group: Option<(&str, GroupType)>,
+) -> Result<Mapping, PawprintError> {
+    let mut names: Vec<String> = Vec::new();
+    let mut proxies = Vec::new();
+    for node in nodes {
+        // Clash refers to proxies by name, so names must be unique.
+        let mut name = node.tag().to_string();
+        let mut suffix = 2;
+        while names.contains(&name) {
+            name = format!("{}-{}", node.tag(), suffix);
+            suffix += 1;
+        }
+        match build_proxy(node, &name) {
+            Ok(proxy) => {
+                proxies.push(Value::Mapping(proxy));
+                names.push(name);
+            }
+            Err(e) => warn!("Skipping {}: {}", node.tag(), e),
+        }
+    }
+    if proxies.is_empty() {
+        return Err("None of the share links can be expressed as Clash proxies".into());
+    }
+
+    let mut document = mapping([("proxies", Value::Sequence(proxies))]);
+    if let Some((name, kind)) = group {
+        let mut group = mapping([
+            ("name", name.into()),
+            ("type", kind.as_str().into()),
+            (
+                "proxies",
+                Value::Sequence(names.into_iter().map(Value::from).collect()),
+            ),
+        ]);
+        if let GroupType::UrlTest | GroupType::Fallback = kind {
+            insert(&mut group, "url", TEST_URL);
+            insert(&mut group, "interval", TEST_INTERVAL);
+        }
+        insert(
+            &mut document,
+            "proxy-groups",
+            Value::Sequence(vec![Value::Mapping(group)]),
+        );
+    }
+    Ok(document)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Registry;
+
+    fn document(links: &[&str], group: Option<(&str, GroupType)>) -> Result<Value, PawprintError> {
+        let registry = Registry::new();
+        let nodes: Vec<Node> = links.iter().map(|l| registry.parse(l).unwrap()).collect();
+        build_document(&nodes, group).map(Value::Mapping)
+    }
+
+    #[test]
+    fn proxies_carry_tls_and_transport() {
+        let doc = document(
+            &["vless://b831381d-6324-4d53-ad4f-8cda48b30811@nl.example:443?security=reality&sni=www.example.com&pbk=AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI&sid=ab12&type=grpc&serviceName=svc&flow=xtls-rprx-vision#nl"],
+            None,
+        )
+        .unwrap();
+        let proxy = &doc["proxies"][0];
+        assert_eq!(proxy["type"].as_str(), Some("vless"));
+        assert_eq!(proxy["servername"].as_str(), Some("www.example.com"));
+        assert_eq!(proxy["flow"].as_str(), Some("xtls-rprx-vision"));
+        // REALITY needs a fingerprint even when the link has none.
+        assert_eq!(proxy["client-fingerprint"].as_str(), Some("chrome"));
+        assert_eq!(proxy["reality-opts"]["short-id"].as_str(), Some("ab12"));
+        assert_eq!(proxy["network"].as_str(), Some("grpc"));
+        assert_eq!(
+            proxy["grpc-opts"]["grpc-service-name"].as_str(),
+            Some("svc")
+        );
+        assert!(doc.get("proxy-groups").is_none());
+    }
+
+    #[test]
+    fn names_are_unique_and_grouped() {
+        let doc = document(
+            &[
+                "trojan://a@a.example:443#nl",
+                "trojan://b@b.example:443?security=none#plain",
+                "trojan://c@c.example:443?type=ws&path=%2Fws&host=cdn.example#nl",
+            ],
+            Some(("auto", GroupType::UrlTest)),
+        )
+        .unwrap();
+        let names: Vec<&str> = doc["proxies"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .filter_map(|p| p["name"].as_str())
+            .collect();
+        assert_eq!(names, ["nl", "nl-2"]);
+        assert_eq!(
+            doc["proxies"][1]["ws-opts"]["headers"]["Host"].as_str(),
+            Some("cdn.example")
+        );
+        let group = &doc["proxy-groups"][0];
+        assert_eq!(group["type"].as_str(), Some("url-test"));
+        assert_eq!(group["proxies"][1].as_str(), Some("nl-2"));
+        assert_eq!(group["url"].as_str(), Some(TEST_URL));
+
+        assert!(document(&["trojan://b@b.example:443?security=none#plain"], None).is_err());
+    }
+}
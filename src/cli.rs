@@ -0,0 +1,1456 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use pawprint_vpn::clash::GroupType;
+use pawprint_vpn::export;
+use pawprint_vpn::geo;
+use pawprint_vpn::profile;
+use pawprint_vpn::server::ServerProtocol;
+use pawprint_vpn::service::{self, ServiceMode};
+use pawprint_vpn::spec::{
+    DnsRoute, DomainStrategy, LogLevel, NoiseSpec, SniffingSpec, StatsSpec, TunStack,
+};
+use pawprint_vpn::target::CoreTarget;
+use pawprint_vpn::vault::KeyStore;
+use pawprint_vpn::xraycore;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    // Without a subcommand the tool behaves like `convert`, as it did before
+    // subcommands existed.
+    #[command(
+        flatten,
+        next_help_heading = "Options without a subcommand (see `convert`)"
+    )]
+    pub generate: GenerateArgs,
+
+    // Path to output json, or - for stdout
+    #[arg(short, long, required_unless_present = "dry_run")]
+    pub output: Option<PathBuf>,
+
+    // Replace existing config
+    #[arg(short, long)]
+    pub force: bool,
+
+    // Write one config per server, named after its tag, into the --output directory
+    // (or as NDJSON lines to stdout)
+    #[arg(long)]
+    pub per_server: bool,
+
+    // Print the config to stdout instead of writing it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub batch: BatchArgs,
+
+    // Do not expand ${VARS} in the string values of spec, patch and JSON patch files
+    #[arg(long, global = true, help_heading = "Global options")]
+    pub no_env_subst: bool,
+
+    // Also write logs to this file (filter with RUST_LOG)
+    #[arg(long, global = true, help_heading = "Global options")]
+    pub log_file: Option<PathBuf>,
+
+    // Rotate the log file once it exceeds this many bytes
+    #[arg(
+        long,
+        global = true,
+        help_heading = "Global options",
+        default_value_t = 10 * 1024 * 1024
+    )]
+    pub log_max_size: u64,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    // Generate a config from a share link or subscription
+    Convert(Box<ConvertArgs>),
+
+    // Generate a config from every server of a subscription
+    Subscribe(Box<SubscribeArgs>),
+
+    // Refresh the servers of an existing config from links or a subscription,
+    // keeping its inbounds, routing and other hand edits
+    Update(Box<UpdateArgs>),
+
+    // Show what generating a config would change in an existing file, path by path
+    Diff(Box<DiffArgs>),
+
+    // Manage stored profiles and switch the active config between them
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+
+    // Download routing data files
+    Geo {
+        #[command(subcommand)]
+        action: GeoAction,
+    },
+
+    // Download and switch xray-core releases; `--xray xray` runs the installed one
+    Core {
+        #[command(subcommand)]
+        action: CoreAction,
+    },
+
+    // Install a systemd unit running pawprint-vpn, and control it
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+
+    // Hook pawprint-vpn into other system services
+    Integrate {
+        #[command(subcommand)]
+        target: IntegrateTarget,
+    },
+
+    // Generate a config from a declarative spec file
+    Apply {
+        // Spec describing inbounds, node links and output
+        #[arg(default_value = "pawprint.toml")]
+        spec: PathBuf,
+
+        // Replace existing config
+        #[arg(short, long)]
+        force: bool,
+
+        // Print the config to stdout instead of writing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    // Keep regenerating a spec's config as its subscriptions change, restarting
+    // xray when it runs that config
+    Watch {
+        // Spec listing the subscriptions
+        #[arg(default_value = "pawprint.toml")]
+        spec: PathBuf,
+
+        // Seconds between subscription fetches
+        #[arg(long, default_value_t = 3600)]
+        interval: u64,
+
+        // Where to remember the servers of the last update
+        #[arg(long)]
+        state_file: Option<PathBuf>,
+
+        // Pid file of the xray started by `run`
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+
+        #[command(flatten)]
+        hooks: HookArgs,
+    },
+
+    // Check generated configs for mistakes the core would only report at startup
+    Validate {
+        // Xray or sing-box config to check, repeatable
+        #[arg(required = true, value_name = "CONFIG")]
+        configs: Vec<PathBuf>,
+    },
+
+    // Interactively write a pawprint.toml and generate the first config from it
+    Init {
+        // Directory for pawprint.toml and config.json
+        #[arg(short, long, default_value = ".")]
+        dir: PathBuf,
+
+        // Replace existing files
+        #[arg(short, long)]
+        force: bool,
+
+        // Directory with share link parser plugins
+        #[arg(long)]
+        plugins_dir: Option<PathBuf>,
+    },
+
+    // Generate the Xray config of a server and the share link for its clients
+    Server(Box<ServerArgs>),
+
+    // Add, remove and list the clients of a config from `server`
+    User {
+        #[command(subcommand)]
+        action: UserAction,
+    },
+
+    // Generate a REALITY keypair and shortIds for a server
+    Keygen {
+        // Derive the public key from this private key instead of generating one
+        #[arg(long)]
+        private_key: Option<String>,
+
+        // How many shortIds to generate
+        #[arg(long, default_value_t = 1)]
+        short_ids: usize,
+
+        // Length of each shortId in bytes (at most 8)
+        #[arg(long, default_value_t = 8)]
+        short_id_bytes: usize,
+
+        // Server name clients should send, added to the link parameters
+        #[arg(long)]
+        sni: Option<String>,
+    },
+
+    // Collect redacted configs, versions, logs and routes for a bug report
+    Bundle {
+        // Tarball to write
+        #[arg(short, long, default_value = "pawprint-bundle.tar.gz")]
+        output: PathBuf,
+
+        // Generated config to include (secrets are redacted), repeatable
+        #[arg(long = "config-file", value_name = "FILE")]
+        config_files: Vec<PathBuf>,
+
+        // Xray binary to query for its version
+        #[arg(long, default_value = "xray")]
+        xray: String,
+    },
+
+    // Start xray with a generated config
+    Run {
+        // Config to run
+        #[arg(default_value = export::CONFIG_FILE)]
+        config: PathBuf,
+
+        // Xray binary to run
+        #[arg(long, default_value = "xray")]
+        xray: String,
+
+        // Run in the background, logging to the state directory
+        #[arg(short, long)]
+        detach: bool,
+
+        // Point the OS proxy settings at the config's SOCKS/HTTP inbound while
+        // xray runs (Windows, macOS, GNOME)
+        #[arg(long)]
+        system_proxy: bool,
+
+        // Block all other traffic with firewall rules while xray runs, allowing
+        // only the proxy servers and the local inbounds (Linux or macOS; the
+        // firewall commands go through sudo, doas or pkexec unless run as root)
+        #[arg(long)]
+        kill_switch: bool,
+
+        // Route all traffic into the config's TUN interface once xray created
+        // it, keeping the proxy servers on their current route (Linux; for Xray
+        // and sing-box without auto-route)
+        #[arg(long)]
+        tun_routes: bool,
+
+        // Send the system's DNS queries to the config's DNS server through the
+        // TUN interface, with systemd-resolved or by replacing /etc/resolv.conf
+        // (Linux)
+        #[arg(long)]
+        tun_dns: bool,
+
+        #[command(flatten)]
+        health: HealthArgs,
+
+        // Run the active profile and restart xray on a regenerated config
+        // whenever the profile or a file it uses changes, or on SIGHUP
+        #[arg(long, conflicts_with_all = ["config", "detach", "failover"])]
+        reload: bool,
+
+        // Where to record the running core
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+
+        #[command(flatten)]
+        hooks: HookArgs,
+    },
+
+    // Stop the xray started by `run`
+    Stop {
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+
+        #[command(flatten)]
+        hooks: HookArgs,
+    },
+
+    // Show whether xray is running
+    Status {
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+
+        // Also show where the active profile's server and the tunnel's exit
+        // are, and whose network they are on (ip-api.com, through the tunnel)
+        #[arg(long)]
+        geo: bool,
+    },
+
+    // Show xray's captured output, or the access or error log its config sets
+    Logs {
+        // Keep printing new lines until interrupted
+        #[arg(short, long)]
+        follow: bool,
+
+        // Lines to show from the end
+        #[arg(short = 'n', long, default_value_t = 20)]
+        lines: usize,
+
+        // The access log from the config's `log` section
+        #[arg(long, conflicts_with = "error")]
+        access: bool,
+
+        // The error log from the config's `log` section
+        #[arg(long)]
+        error: bool,
+
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+    },
+
+    // Stop xray and start it again in the background with the same config
+    Restart {
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+
+        #[command(flatten)]
+        hooks: HookArgs,
+    },
+
+    // Show traffic per inbound and outbound of a core started with --stats
+    Stats {
+        // Stats API address
+        #[arg(long, default_value = "127.0.0.1:10085")]
+        server: String,
+
+        // Xray binary used to query the API
+        #[arg(long, default_value = "xray")]
+        xray: String,
+
+        // Zero the counters after reading them
+        #[arg(long)]
+        reset: bool,
+    },
+
+    // Pick, test and connect servers interactively, with live logs and traffic
+    Tui {
+        // List the servers of this subscription next to the profiles
+        #[arg(long)]
+        subscription: Option<String>,
+
+        // Xray binary to run and query for traffic
+        #[arg(long, default_value = "xray")]
+        xray: String,
+
+        // Core version to generate subscription servers for
+        #[arg(short, long, default_value_t = CoreTarget::default())]
+        target: CoreTarget,
+
+        // Directory with share link parser plugins
+        #[arg(long)]
+        plugins_dir: Option<PathBuf>,
+
+        // Stats API address of the running core
+        #[arg(long, default_value = "127.0.0.1:10085")]
+        stats_server: String,
+
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+    },
+
+    // Serve a JSON control API for GUIs and scripts, Prometheus metrics on
+    // /metrics and a liveness probe on /healthz, until interrupted. POSTs need
+    // the bearer token in daemon.token in the state directory. The active
+    // config is regenerated, and a running core restarted, when the active
+    // profile or its files change or on SIGHUP
+    Daemon {
+        // Loopback address to listen on
+        #[arg(long, default_value = "127.0.0.1:9094", conflicts_with = "socket")]
+        listen: String,
+
+        // Listen on this unix socket instead
+        #[arg(long)]
+        socket: Option<PathBuf>,
+
+        // Xray binary to run and query for traffic
+        #[arg(long, default_value = "xray")]
+        xray: String,
+
+        // Stats API address of the running core
+        #[arg(long, default_value = "127.0.0.1:10085")]
+        stats_server: String,
+
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+
+        // URL fetched through the running core to sample round trip time,
+        // jitter and loss
+        #[arg(long, default_value = "http://cp.cloudflare.com/generate_204")]
+        probe_url: String,
+
+        // Seconds between link quality probes, 0 to not probe; a probe taking
+        // longer counts as lost
+        #[arg(long, default_value_t = 5)]
+        probe_interval: u64,
+
+        // Probes link quality is computed over
+        #[arg(long, default_value_t = 60)]
+        probe_window: usize,
+
+        // Also take commands from the Telegram bot set up in the [bot] table of
+        // config.toml
+        #[arg(long)]
+        telegram_bot: bool,
+
+        // Publish the core's state and traffic to the MQTT broker in the [mqtt]
+        // table of config.toml
+        #[arg(long)]
+        mqtt: bool,
+
+        #[command(flatten)]
+        hooks: HookArgs,
+    },
+
+    // Diagnose connectivity to a node
+    Test {
+        #[command(subcommand)]
+        kind: TestKind,
+    },
+
+    // Generate deployment files around a config, or configs for other clients
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GeoAction {
+    // Fetch the latest geoip.dat and geosite.dat, checking them against the
+    // published SHA-256 sums. `run` points xray at the default directory.
+    Update {
+        // Directory for the data files
+        #[arg(long, default_value_os_t = geo::default_dir())]
+        dir: PathBuf,
+
+        // Base URL serving geoip.dat, geosite.dat and their .sha256sum files
+        #[arg(long, default_value = geo::DEFAULT_SOURCE)]
+        source: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CoreAction {
+    // Download xray-core for this OS and CPU, check it against the release's
+    // SHA2-256 sum and use it from now on. A given version is pinned.
+    Install {
+        // Release to install, e.g. v25.8.3 (the latest by default)
+        version: Option<String>,
+
+        // Download it again even if it is already in use
+        #[arg(short, long)]
+        force: bool,
+
+        // Directory for the installed cores; only the default one is used by --xray xray
+        #[arg(long, default_value_os_t = xraycore::default_dir())]
+        dir: PathBuf,
+
+        // Base URL of the releases, for a mirror of github.com/XTLS/Xray-core
+        #[arg(long, default_value = xraycore::DEFAULT_SOURCE)]
+        source: String,
+    },
+    // Move to the latest release unless a version was pinned
+    Update {
+        #[arg(long, default_value_os_t = xraycore::default_dir())]
+        dir: PathBuf,
+
+        #[arg(long, default_value = xraycore::DEFAULT_SOURCE)]
+        source: String,
+    },
+    // Show the installed versions
+    List {
+        #[arg(long, default_value_os_t = xraycore::default_dir())]
+        dir: PathBuf,
+    },
+    // Let xray create the TUN interface of `run --tun` configs without root by
+    // giving it CAP_NET_ADMIN (Linux); needed again after `core update`
+    Setcap {
+        // Xray binary to grant it to
+        #[arg(long, default_value = "xray")]
+        xray: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum IntegrateTarget {
+    // Install a NetworkManager dispatcher script restarting the tunnel when a
+    // connection comes up or full connectivity returns, e.g. after roaming
+    // between Wi-Fi networks or resuming from suspend
+    Networkmanager {
+        // Restart this unit from `service install` instead of the core started by
+        // `run --detach`
+        #[arg(long, conflicts_with = "pid_file")]
+        unit: Option<String>,
+
+        // The unit is a system unit
+        #[arg(long, requires = "unit")]
+        system: bool,
+
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+
+        // Print the script instead of installing it
+        #[arg(long)]
+        print: bool,
+
+        // Replace an existing script
+        #[arg(short, long)]
+        force: bool,
+
+        // Remove the script
+        #[arg(long, conflicts_with_all = ["unit", "pid_file", "print", "force"])]
+        remove: bool,
+    },
+}
+
+// Which unit the service commands act on.
+#[derive(clap::Args, Debug)]
+pub struct UnitArgs {
+    // Use the system instance (/etc/systemd/system, needs root) instead of the user's
+    #[arg(long)]
+    pub system: bool,
+
+    // Unit name, without .service
+    #[arg(long, default_value = service::DEFAULT_NAME)]
+    pub name: String,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ServiceAction {
+    // Write the unit file and reload systemd
+    Install {
+        #[command(flatten)]
+        unit: UnitArgs,
+
+        // What the unit runs
+        #[arg(long, value_enum, default_value_t = ServiceMode::Run)]
+        mode: ServiceMode,
+
+        // Switch to this profile before each start (default: keep the active one)
+        #[arg(long)]
+        profile: Option<String>,
+
+        // Xray binary; a bare name is looked up in PATH now, as systemd's PATH is short
+        #[arg(long, default_value = "xray")]
+        xray: String,
+
+        // Pass --failover to `run`
+        #[arg(long)]
+        failover: bool,
+
+        // Pass --kill-switch to `run` (system units only, as it needs root)
+        #[arg(long, requires = "system")]
+        kill_switch: bool,
+
+        // Pass --tun-routes to `run` (system units only, as it needs root)
+        #[arg(long, requires = "system")]
+        tun_routes: bool,
+
+        // Pass --tun-dns to `run` (system units only, as it needs root)
+        #[arg(long, requires = "system")]
+        tun_dns: bool,
+
+        // Pass --system-proxy to `run` (user units only, as it changes desktop settings)
+        #[arg(long, conflicts_with = "system")]
+        system_proxy: bool,
+
+        // Print the unit instead of installing it
+        #[arg(long)]
+        print: bool,
+
+        // Replace an existing unit file
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    // Stop and disable the unit and remove its file
+    Uninstall {
+        #[command(flatten)]
+        unit: UnitArgs,
+    },
+
+    // Start the unit at boot (system) or login (user), and now
+    Enable {
+        #[command(flatten)]
+        unit: UnitArgs,
+    },
+
+    // No longer start the unit at boot or login, and stop it
+    Disable {
+        #[command(flatten)]
+        unit: UnitArgs,
+    },
+
+    Start {
+        #[command(flatten)]
+        unit: UnitArgs,
+    },
+
+    Stop {
+        #[command(flatten)]
+        unit: UnitArgs,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfileAction {
+    // Store a share link as a named profile
+    Add {
+        // Share link to store
+        #[arg(required_unless_present_any = ["from_clipboard", "from_qr"])]
+        url: Option<String>,
+
+        // Take the share link from the clipboard, as text or a copied QR code image
+        #[arg(long, conflicts_with_all = ["url", "from_qr"])]
+        from_clipboard: bool,
+
+        // Take the share link from a QR code in this image, e.g. a screenshot
+        #[arg(long, value_name = "IMAGE", conflicts_with = "url")]
+        from_qr: Option<PathBuf>,
+
+        // Profile name (defaults to the node's tag)
+        #[arg(short, long)]
+        name: Option<String>,
+
+        // Core version to generate for
+        #[arg(short, long, default_value_t = CoreTarget::default())]
+        target: CoreTarget,
+
+        // Directory with share link parser plugins
+        #[arg(long)]
+        plugins_dir: Option<PathBuf>,
+
+        // Replace an existing profile (and QR PNG)
+        #[arg(short, long)]
+        force: bool,
+
+        #[command(flatten)]
+        qr: QrArgs,
+    },
+
+    // List stored profiles, marking the active one
+    List {
+        // Put profiles under headings by subscription or server country
+        #[arg(long, value_enum)]
+        group_by: Option<profile::Group>,
+
+        // Put profiles under headings by the first capture group (or the whole
+        // match) of this pattern in the server's tag, e.g. '^(\w+)-'
+        #[arg(long, value_name = "REGEX", conflicts_with = "group_by")]
+        group_pattern: Option<String>,
+
+        #[arg(long, value_enum, default_value_t)]
+        sort: profile::Sort,
+
+        // Also show each server's IP, country, city and network (ip-api.com,
+        // through the tunnel if the core runs), and which share a host
+        #[arg(long)]
+        geo: bool,
+    },
+
+    // Delete a stored profile
+    Remove {
+        name: String,
+    },
+
+    // Regenerate the active config from a stored profile
+    Use {
+        // Profile name, or enough of it to match one loosely (`ger` for
+        // "🇩🇪 Germany-03")
+        name: String,
+    },
+
+    // Encrypt the stored profiles with age; they are decrypted as they are used
+    Encrypt {
+        // Where to keep the key: a file only you can read, a file protected by a
+        // passphrase asked for on each run, or the OS keyring
+        #[arg(long, value_enum)]
+        key: KeyStore,
+    },
+
+    // Store the profiles in plain text again and forget the key
+    Decrypt,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TestKind {
+    // Measure TCP connect latency, or real proxy delay, of each server
+    Latency(Box<LatencyArgs>),
+
+    // Trace the network path to a node's server with mtr or traceroute
+    Route {
+        // Share link or host name of the server
+        node: String,
+
+        // Give up after this many hops
+        #[arg(long, default_value_t = 30)]
+        max_hops: u32,
+
+        // Directory with share link parser plugins
+        #[arg(long)]
+        plugins_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct LatencyArgs {
+    // Share links to test, or names of stored profiles (matched loosely, so
+    // `ger` finds "🇩🇪 Germany-03")
+    #[arg(required_unless_present = "subscription")]
+    pub links: Vec<String>,
+
+    // Test every server of this subscription
+    #[arg(long)]
+    pub subscription: Option<String>,
+
+    // Time an HTTP request through a temporary xray instead of a TCP handshake
+    #[arg(long)]
+    pub real: bool,
+
+    // Xray binary for --real
+    #[arg(long, default_value = "xray")]
+    pub xray: String,
+
+    // URL fetched through the proxy for --real
+    #[arg(long, default_value = "http://cp.cloudflare.com/generate_204")]
+    pub url: String,
+
+    // Also measure download and upload speed through a temporary xray, one
+    // server at a time so they do not share the bandwidth
+    #[arg(long)]
+    pub speed: bool,
+
+    // Downloaded for --speed
+    #[arg(
+        long,
+        default_value = "https://speed.cloudflare.com/__down?bytes=100000000"
+    )]
+    pub download_url: String,
+
+    // Receives the upload for --speed
+    #[arg(long, default_value = "https://speed.cloudflare.com/__up")]
+    pub upload_url: String,
+
+    // Skip the upload for --speed
+    #[arg(long)]
+    pub no_upload: bool,
+
+    // Bytes uploaded for --speed
+    #[arg(long, default_value_t = 25_000_000)]
+    pub upload_bytes: u64,
+
+    // Stop each --speed transfer after this many seconds
+    #[arg(long, default_value_t = 10)]
+    pub speed_time: u64,
+
+    // Give up on a server after this many seconds
+    #[arg(long, default_value_t = 5)]
+    pub timeout: u64,
+
+    // TCP handshakes per server; the fastest counts
+    #[arg(long, default_value_t = 3)]
+    pub attempts: u32,
+
+    // Core version the temporary configs are built for
+    #[arg(short, long, default_value_t = CoreTarget::default())]
+    pub target: CoreTarget,
+
+    // Directory with share link parser plugins
+    #[arg(long)]
+    pub plugins_dir: Option<PathBuf>,
+
+    // --workers defaults to 16 for TCP tests and 4 for --real; --speed always
+    // tests one server at a time
+    #[command(flatten)]
+    pub batch: BatchArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ExportFormat {
+    // docker-compose.yml running xray with the generated config mounted
+    Docker(Box<DockerArgs>),
+
+    // ConfigMap with the generated config and a sidecar container snippet
+    K8s(Box<K8sArgs>),
+
+    // Clash.Meta `proxies:` YAML, optionally with a proxy group over them
+    Clash(Box<ClashArgs>),
+
+    // Share links rebuilt from the outbounds of an Xray config
+    Link {
+        // Xray config, outbound list or single outbound (JSON with comments allowed)
+        config: PathBuf,
+
+        // Only the outbound with this tag
+        #[arg(long)]
+        tag: Option<String>,
+
+        #[command(flatten)]
+        qr: QrArgs,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ServerArgs {
+    // Public address or domain clients connect to
+    #[arg(long)]
+    pub address: String,
+
+    #[arg(long, value_enum, default_value_t = ServerProtocol::VlessReality)]
+    pub protocol: ServerProtocol,
+
+    // Port the server listens on
+    #[arg(long, default_value_t = 443)]
+    pub port: u16,
+
+    // VLESS UUID or Trojan password (generated if omitted)
+    #[arg(long)]
+    pub secret: Option<String>,
+
+    // Name of the server in the share link
+    #[arg(long, default_value = "pawprint-server")]
+    pub tag: String,
+
+    // REALITY: site whose TLS handshake is borrowed, also served to probes
+    #[arg(long, default_value = "www.microsoft.com:443")]
+    pub dest: String,
+
+    // REALITY: server name clients may send (defaults to the --dest host), repeatable
+    #[arg(long = "sni", value_name = "NAME")]
+    pub server_names: Vec<String>,
+
+    // REALITY: reuse this private key instead of generating one
+    #[arg(long)]
+    pub private_key: Option<String>,
+
+    // Trojan: TLS certificate chain for --address
+    #[arg(long, value_name = "FILE")]
+    pub cert: Option<PathBuf>,
+
+    // Trojan: private key of the certificate
+    #[arg(long, value_name = "FILE")]
+    pub key: Option<PathBuf>,
+
+    // Trojan: WebSocket path
+    #[arg(long, default_value = "/ws")]
+    pub ws_path: String,
+
+    // Trojan: where other traffic goes, e.g. 80 for a local web server
+    #[arg(long, default_value = "80")]
+    pub fallback: String,
+
+    // Path to output json
+    #[arg(short, long, default_value = "server.json")]
+    pub output: PathBuf,
+
+    // Replace existing config
+    #[arg(short, long)]
+    pub force: bool,
+
+    #[command(flatten)]
+    pub qr: QrArgs,
+}
+
+// The server config the user commands edit.
+#[derive(clap::Args, Debug)]
+pub struct ServerConfigArgs {
+    // Server config written by `server`
+    #[arg(short, long, default_value = "server.json")]
+    pub config: PathBuf,
+}
+
+// How to print a user's share link.
+#[derive(clap::Args, Debug)]
+pub struct UserLinkArgs {
+    // Public address or domain clients connect to
+    #[arg(long)]
+    pub address: String,
+
+    // Name of the server in the share link (defaults to the user name)
+    #[arg(long)]
+    pub tag: Option<String>,
+
+    #[command(flatten)]
+    pub qr: QrArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UserAction {
+    // Give a new client its own UUID or password and print its share link
+    Add {
+        // Name of the user, kept as its email in the config
+        name: String,
+
+        // VLESS UUID or Trojan password (generated if omitted)
+        #[arg(long)]
+        secret: Option<String>,
+
+        #[command(flatten)]
+        server: ServerConfigArgs,
+
+        #[command(flatten)]
+        link: UserLinkArgs,
+    },
+    // Revoke a client's access
+    Remove {
+        name: String,
+
+        #[command(flatten)]
+        server: ServerConfigArgs,
+    },
+    // Show every client with its UUID or password
+    List {
+        #[command(flatten)]
+        server: ServerConfigArgs,
+    },
+    // Print the share link of an existing user again
+    Link {
+        name: String,
+
+        #[command(flatten)]
+        server: ServerConfigArgs,
+
+        #[command(flatten)]
+        link: UserLinkArgs,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct QrArgs {
+    // Print the share link as a QR code too
+    #[arg(long)]
+    pub qr: bool,
+
+    // Save the QR code as a PNG image
+    #[arg(long, value_name = "FILE")]
+    pub qr_png: Option<PathBuf>,
+}
+
+// Shell commands run on state changes, with PAWPRINT_EVENT, PAWPRINT_PROFILE,
+// PAWPRINT_CONFIG and PAWPRINT_PID set, e.g. to refresh a status bar.
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Hooks")]
+pub struct HookArgs {
+    // Run after xray starts
+    #[arg(long, value_name = "COMMAND")]
+    pub on_connect: Option<String>,
+
+    // Run after xray stops or exits
+    #[arg(long, value_name = "COMMAND")]
+    pub on_disconnect: Option<String>,
+
+    // Run after --failover moves to another profile, the one it left in
+    // PAWPRINT_PREVIOUS_PROFILE
+    #[arg(long, value_name = "COMMAND")]
+    pub on_failover: Option<String>,
+
+    // Run after the config is regenerated with freshly fetched subscriptions
+    #[arg(long, value_name = "COMMAND")]
+    pub on_subscription_update: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct HealthArgs {
+    // Run the active profile and switch to the next fastest profile when
+    // health checks through the proxy keep failing
+    #[arg(long, conflicts_with_all = ["config", "detach"])]
+    pub failover: bool,
+
+    // URL fetched through the proxy by each health check
+    #[arg(long, default_value = "http://cp.cloudflare.com/generate_204")]
+    pub health_url: String,
+
+    // Seconds between health checks
+    #[arg(long, default_value_t = 30)]
+    pub health_interval: u64,
+
+    // Give up on a health check after this many seconds
+    #[arg(long, default_value_t = 10)]
+    pub health_timeout: u64,
+
+    // Failed checks in a row before switching profiles
+    #[arg(long, default_value_t = 3)]
+    pub health_failures: u32,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ClashArgs {
+    // Share links to convert
+    #[arg(required_unless_present = "subscription")]
+    pub links: Vec<String>,
+
+    // Convert every server of this subscription
+    #[arg(long)]
+    pub subscription: Option<String>,
+
+    // Add a proxy group with this name containing every proxy
+    #[arg(long)]
+    pub group: Option<String>,
+
+    // How the group picks a proxy
+    #[arg(long, value_enum, default_value = "select", requires = "group")]
+    pub group_type: GroupType,
+
+    // File to write the YAML to instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    // Replace an existing output file
+    #[arg(short, long, requires = "output")]
+    pub force: bool,
+
+    // Directory with share link parser plugins
+    #[arg(long)]
+    pub plugins_dir: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DockerArgs {
+    #[command(flatten)]
+    pub generate: GenerateArgs,
+
+    // Directory to write config.json and docker-compose.yml to
+    #[arg(short, long, default_value = ".")]
+    pub dir: PathBuf,
+
+    // Xray image to run
+    #[arg(long, default_value = export::DEFAULT_IMAGE)]
+    pub image: String,
+
+    // Replace existing files
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct K8sArgs {
+    #[command(flatten)]
+    pub generate: GenerateArgs,
+
+    // Directory to write configmap.yaml and sidecar.yaml to
+    #[arg(short, long, default_value = ".")]
+    pub dir: PathBuf,
+
+    // ConfigMap name
+    #[arg(long, default_value = "pawprint-xray")]
+    pub name: String,
+
+    // Namespace for the ConfigMap
+    #[arg(long)]
+    pub namespace: Option<String>,
+
+    // Xray image to run as the sidecar
+    #[arg(long, default_value = export::DEFAULT_IMAGE)]
+    pub image: String,
+
+    // Replace existing files
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ConvertArgs {
+    // Share links like --config; - reads them from stdin, one per line
+    #[arg(value_name = "LINK", conflicts_with = "subscription")]
+    pub links: Vec<String>,
+
+    #[command(flatten)]
+    pub generate: GenerateArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct UpdateArgs {
+    // Config to update in place
+    pub file: PathBuf,
+
+    // Share links like --config; - reads them from stdin, one per line
+    #[arg(value_name = "LINK", conflicts_with = "subscription")]
+    pub links: Vec<String>,
+
+    // Put the server in place of the outbound with this tag, keeping the tag
+    #[arg(long)]
+    pub tag: Option<String>,
+
+    // Remove server outbounds the links no longer have, unless routing uses them
+    #[arg(long)]
+    pub prune: bool,
+
+    // Print the updated config to stdout instead of writing it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub generate: GenerateArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    // Existing config to compare with
+    pub file: PathBuf,
+
+    // Share links like --config; - reads them from stdin, one per line
+    #[arg(value_name = "LINK", conflicts_with = "subscription")]
+    pub links: Vec<String>,
+
+    #[command(flatten)]
+    pub generate: GenerateArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SubscribeArgs {
+    // Subscription URL serving a (base64 encoded) list of share links
+    pub url: String,
+
+    #[command(flatten)]
+    pub build: BuildArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct OutputArgs {
+    // Path to output json, or - for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: PathBuf,
+
+    // Replace existing config
+    #[arg(short, long)]
+    pub force: bool,
+
+    // Write one config per server, named after its tag, into the --output directory
+    // (or as NDJSON lines to stdout)
+    #[arg(long)]
+    pub per_server: bool,
+
+    // Print the config to stdout instead of writing it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    // --workers defaults to one per CPU and only matters with --per-server
+    #[command(flatten)]
+    pub batch: BatchArgs,
+}
+
+// For commands that work through many servers at once.
+#[derive(clap::Args, Debug, Default)]
+pub struct BatchArgs {
+    // Servers handled at the same time
+    #[arg(long)]
+    pub workers: Option<usize>,
+
+    // Write a JSON summary of what succeeded and failed to this file, CSV if it
+    // ends in .csv, or a sortable page with latency sparklines if it ends in
+    // .html; tests include every sample with its time
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+}
+
+// Where the nodes come from, plus how to build the config from them.
+#[derive(clap::Args, Debug)]
+pub struct GenerateArgs {
+    // Share link to parse, repeatable; - reads links from stdin, one per line
+    #[arg(short, long)]
+    pub config: Vec<String>,
+
+    // File with one share link per line (- for stdin); blank lines and # comments are skipped
+    #[arg(long, value_name = "FILE")]
+    pub links_file: Option<PathBuf>,
+
+    // WireGuard .conf file (e.g. from wgcf) to add as a node named after the file, repeatable
+    #[arg(long, value_name = "FILE")]
+    pub wireguard: Vec<PathBuf>,
+
+    // Subscription URL serving a (base64 encoded) list of share links
+    #[arg(long, conflicts_with_all = ["config", "links_file", "wireguard"])]
+    pub subscription: Option<String>,
+
+    #[command(flatten)]
+    pub build: BuildArgs,
+}
+
+// Inputs shared by every command that generates a config from parsed nodes.
+#[derive(clap::Args, Debug)]
+pub struct BuildArgs {
+    // Core version to generate for, e.g. xray@1.8, xray@25.x or sing-box@1.12
+    #[arg(short, long, default_value_t = CoreTarget::default())]
+    pub target: CoreTarget,
+
+    // Directory with share link parser plugins
+    // (defaults to ~/.config/pawprint-vpn/plugins)
+    #[arg(long)]
+    pub plugins_dir: Option<PathBuf>,
+
+    // Resolve queries through this server via the proxy instead of 1.1.1.1, e.g.
+    // https://1.1.1.1/dns-query, tls://9.9.9.9 (sing-box only), tcp://8.8.8.8 or 8.8.8.8, repeatable
+    #[arg(long = "dns", value_name = "SERVER")]
+    pub dns_servers: Vec<String>,
+
+    // Resolve a domain through a specific DNS server, e.g. corp.example=10.0.0.53, repeatable
+    #[arg(long = "dns-route", value_name = "DOMAIN=SERVER")]
+    pub dns_routes: Vec<DnsRoute>,
+
+    // Send matching traffic around the proxy, e.g. geoip:cn, geosite:cn, domain:lan, repeatable
+    #[arg(long, value_name = "MATCHER")]
+    pub direct: Vec<String>,
+
+    // Drop matching traffic, e.g. geosite:category-ads-all, repeatable
+    #[arg(long, value_name = "MATCHER")]
+    pub block: Vec<String>,
+
+    // Proxy matching traffic even if a --direct matcher covers it, repeatable
+    #[arg(long, value_name = "MATCHER")]
+    pub proxy: Vec<String>,
+
+    // TOML file with `direct`, `block` and `proxy` matcher lists
+    #[arg(long, value_name = "FILE")]
+    pub rules: Option<PathBuf>,
+
+    // Spread traffic over all servers by measured latency instead of using the first
+    #[arg(long)]
+    pub balance: bool,
+
+    // Dial each server through the one before it, e.g. -c ENTRY -c EXIT;
+    // traffic leaves through the last
+    #[arg(long, conflicts_with = "balance")]
+    pub chain: bool,
+
+    #[command(flatten)]
+    pub inbounds: InboundArgs,
+
+    #[command(flatten)]
+    pub tun: TunArgs,
+
+    #[command(flatten)]
+    pub tuning: TuningArgs,
+
+    // Count traffic per inbound and outbound and serve it for `pawprint-vpn stats`
+    #[arg(long)]
+    pub stats: bool,
+
+    // Loopback port of the stats API
+    #[arg(long, requires = "stats", default_value_t = StatsSpec::default_port())]
+    pub stats_port: u16,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    #[command(flatten)]
+    pub transforms: Transforms,
+}
+
+// The `log` section of the generated config; without any of these it has none.
+#[derive(clap::Args, Debug)]
+pub struct LogArgs {
+    // Log level of the core (pawprint-vpn's own is set with RUST_LOG)
+    #[arg(long, value_enum)]
+    pub log_level: Option<LogLevel>,
+
+    // Where the core logs every connection (Xray only)
+    #[arg(long)]
+    pub access_log: Option<PathBuf>,
+
+    // Where the core logs everything else, instead of stdout
+    #[arg(long)]
+    pub error_log: Option<PathBuf>,
+
+    // Also log DNS queries (Xray only)
+    #[arg(long)]
+    pub dns_log: bool,
+}
+
+// Clean-up of junk and duplicate servers, mostly for subscriptions.
+#[derive(clap::Args, Debug)]
+pub struct FilterArgs {
+    // Keep only servers whose tag matches this regex, repeatable
+    #[arg(long, value_name = "REGEX")]
+    pub include: Vec<String>,
+
+    // Drop servers whose tag matches this regex, repeatable
+    #[arg(long, value_name = "REGEX")]
+    pub exclude: Vec<String>,
+
+    // Keep only servers whose tag names one of these countries, e.g. JP,SG
+    #[arg(long = "country", value_name = "CODE", value_delimiter = ',')]
+    pub countries: Vec<String>,
+
+    // Drop servers with the protocol, address, port and UUID or password of an earlier one
+    #[arg(long)]
+    pub dedup: bool,
+
+    // Put the servers of each country, found from flags or names in the tag, together
+    #[arg(long)]
+    pub group_by_country: bool,
+
+    // Rewrite tags from a template with {tag}, {country}, {flag}, {index} (per
+    // country), {protocol} and {server}, e.g. "{flag} {country}-{index}"
+    #[arg(long, value_name = "TEMPLATE")]
+    pub rename: Option<String>,
+}
+
+// Local proxies the generated config listens on.
+#[derive(clap::Args, Debug)]
+pub struct InboundArgs {
+    // Address the inbounds listen on, e.g. 0.0.0.0 to share with the LAN
+    #[arg(long)]
+    pub listen: Option<String>,
+
+    // SOCKS inbound port
+    #[arg(long, default_value_t = 10808)]
+    pub socks_port: u16,
+
+    // Also open an HTTP proxy inbound on this port
+    #[arg(long)]
+    pub http_port: Option<u16>,
+
+    // Require these credentials on the SOCKS and HTTP inbounds
+    #[arg(long, value_name = "USER:PASS", value_parser = parse_credentials)]
+    pub auth: Option<(String, String)>,
+
+    // Sniff the domain of connections so domain rules match apps connecting by IP
+    #[arg(long)]
+    pub sniff: bool,
+
+    // Protocols to sniff, comma-separated
+    #[arg(long, requires = "sniff", value_delimiter = ',', default_values_t = SniffingSpec::default_protocols())]
+    pub sniff_protocols: Vec<String>,
+
+    // Route by the sniffed domain but still connect to the original address
+    #[arg(long, requires = "sniff")]
+    pub sniff_route_only: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TunArgs {
+    // Add a TUN inbound for system-wide proxying (xray@25.8+ or sing-box)
+    #[arg(long)]
+    pub tun: bool,
+
+    // TUN interface name
+    #[arg(long, requires = "tun")]
+    pub tun_name: Option<String>,
+
+    // TUN MTU; lower it if large transfers stall, e.g. on mobile hotspots
+    #[arg(long, requires = "tun")]
+    pub tun_mtu: Option<u32>,
+
+    // TUN interface address with prefix, repeatable (default 172.19.0.1/30 and fdfe:dcba:9876::1/126)
+    #[arg(long, value_name = "CIDR", requires = "tun")]
+    pub tun_address: Vec<String>,
+
+    // Network stack of the TUN inbound (sing-box only)
+    #[arg(long, value_enum, requires = "tun")]
+    pub tun_stack: Option<TunStack>,
+
+    // Do not route all traffic into the TUN interface (sing-box only)
+    #[arg(long, requires = "tun")]
+    pub no_auto_route: bool,
+}
+
+// Knobs against DPI and connection overhead, written into the proxy outbounds.
+#[derive(clap::Args, Debug)]
+pub struct TuningArgs {
+    // Multiplex connections to the server (the server must allow mux)
+    #[arg(long)]
+    pub mux: bool,
+
+    // Streams per multiplexed connection
+    #[arg(long, requires = "mux")]
+    pub mux_concurrency: Option<u32>,
+
+    // Separate pool of multiplexed UDP connections (Xray only)
+    #[arg(long, requires = "mux")]
+    pub xudp_concurrency: Option<u32>,
+
+    // Split the TLS ClientHello into fragments to get past DPI
+    #[arg(long)]
+    pub fragment: bool,
+
+    // What to fragment: tlshello, or a range of TCP packets such as 1-3 (Xray only)
+    #[arg(long, requires = "fragment")]
+    pub fragment_packets: Option<String>,
+
+    // Fragment size range in bytes, e.g. 100-200 (Xray only)
+    #[arg(long, requires = "fragment")]
+    pub fragment_length: Option<String>,
+
+    // Delay range between fragments in milliseconds, e.g. 10-20 (Xray only)
+    #[arg(long, requires = "fragment")]
+    pub fragment_interval: Option<String>,
+
+    // Junk UDP packet to send first, e.g. rand:10-20:10-16 or str:hello (xray@24.9+), repeatable
+    #[arg(long = "noise", value_name = "TYPE:PACKET[:DELAY]")]
+    pub noises: Vec<NoiseSpec>,
+
+    // Open connections with TCP Fast Open
+    #[arg(long)]
+    pub tcp_fast_open: bool,
+
+    // Mark outgoing packets (SO_MARK) for policy routing, Linux only
+    #[arg(long)]
+    pub mark: Option<u32>,
+
+    // How names are resolved before dialing, e.g. UseIPv4
+    #[arg(long, value_enum, ignore_case = true)]
+    pub domain_strategy: Option<DomainStrategy>,
+
+    // Seconds before idle connections are probed with TCP keep-alives (Xray only)
+    #[arg(long, value_name = "SECONDS")]
+    pub tcp_keep_alive_idle: Option<u32>,
+}
+
+fn parse_credentials(s: &str) -> Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((user, pass)) if !user.is_empty() && !pass.is_empty() => {
+            Ok((user.to_string(), pass.to_string()))
+        }
+        _ => Err(format!("Credentials must look like user:pass, got: {}", s)),
+    }
+}
+
+// Post-processing steps, applied in field order.
+#[derive(clap::Args, Debug)]
+pub struct Transforms {
+    // JSON config to build on: it adds sections, rules and outbounds, but values the
+    // generated config sets win
+    #[arg(long, value_name = "FILE")]
+    pub template: Option<PathBuf>,
+
+    // JSON fragment deep-merged into the generated config (arrays are combined and
+    // tagged entries merged), repeatable
+    #[arg(long = "merge", value_name = "FILE")]
+    pub merges: Vec<PathBuf>,
+
+    // JSON merge patch (RFC 7386, comments allowed) applied to the generated config, repeatable
+    #[arg(long = "patch", value_name = "FILE")]
+    pub patches: Vec<PathBuf>,
+
+    // JSON Patch (RFC 6902) operations applied after merge patches, repeatable
+    #[arg(long = "json-patch", value_name = "FILE")]
+    pub json_patches: Vec<PathBuf>,
+
+    // Rhai script that can modify the built config before it is saved
+    #[arg(long)]
+    pub post_script: Option<PathBuf>,
+
+    // jq expression applied to the generated JSON, e.g. '.inbounds[0].port = 1080'
+    #[arg(long)]
+    pub jq: Option<String>,
+}
@@ -0,0 +1,65 @@
+use std::io::ErrorKind;
+use std::process::Command;
+
+use crate::error::PawprintError;
+
+// Programs printing the clipboard, tried in order until one runs.
+#[cfg(target_os = "macos")]
+const TEXT: &[&[&str]] = &[&["pbpaste"]];
+#[cfg(windows)]
+const TEXT: &[&[&str]] = &[&["powershell", "-NoProfile", "-Command", "Get-Clipboard"]];
+#[cfg(not(any(target_os = "macos", windows)))]
+const TEXT: &[&[&str]] = &[
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-o"],
+    &["xsel", "--clipboard", "--output"],
+];
+
+// The same for a copied image, which only the Linux tools can hand over.
+#[cfg(not(any(target_os = "macos", windows)))]
+const IMAGE: &[&[&str]] = &[
+    &["wl-paste", "--type", "image/png"],
+    &["xclip", "-selection", "clipboard", "-t", "image/png", "-o"],
+];
+#[cfg(any(target_os = "macos", windows))]
+const IMAGE: &[&[&str]] = &[];
+
+fn first_output(commands: &[&[&str]], what: &str) -> Result<Vec<u8>, PawprintError> {
+    let mut failure = None;
+    for args in commands {
+        match Command::new(args[0]).args(&args[1..]).output() {
+            Ok(output) if output.status.success() => return Ok(output.stdout),
+            Ok(output) => {
+                failure = Some(format!(
+                    "{} failed: {}",
+                    args[0],
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => failure = Some(format!("Failed to run {}: {}", args[0], e)),
+        }
+    }
+    Err(failure
+        .unwrap_or_else(|| {
+            let names: Vec<&str> = commands.iter().map(|args| args[0]).collect();
+            if names.is_empty() {
+                format!("Reading {} from the clipboard is not supported here", what)
+            } else {
+                format!(
+                    "No clipboard tool found, install one of {}",
+                    names.join(", ")
+                )
+            }
+        })
+        .into())
+}
+
+pub fn text() -> Result<String, PawprintError> {
+    Ok(String::from_utf8_lossy(&first_output(TEXT, "text")?).into_owned())
+}
+
+// A copied image as PNG, e.g. a screenshot of a QR code.
+pub fn image() -> Result<Vec<u8>, PawprintError> {
+    first_output(IMAGE, "images")
+}
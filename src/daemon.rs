@@ -0,0 +1,970 @@
+use serde_json::{Value, json};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+use pawprint_vpn::hooks::{self, Event};
+use pawprint_vpn::metrics::Metrics;
+use pawprint_vpn::mqtt::{self, Broker, Client, Will};
+use pawprint_vpn::quality::{Quality, Window};
+use pawprint_vpn::{PawprintError, health, keygen, latency, process, profile, stats, watch};
+
+// Requests are small; anything bigger is not meant for this API.
+const MAX_BODY: usize = 64 * 1024;
+
+// POSTs must send `Authorization: Bearer <token>` with the token in this file,
+// readable only by the user, in the state directory. Pages in a browser cannot
+// read it, so they cannot switch servers through a form or a rebound DNS name.
+const TOKEN_FILE: &str = "daemon.token";
+
+pub struct Options {
+    pub listen: String,
+    pub socket: Option<PathBuf>,
+    pub xray: String,
+    pub stats_server: String,
+    pub pid_file: PathBuf,
+    pub env_subst: bool,
+    pub probe_url: String,
+    // Zero to not probe.
+    pub probe_interval: Duration,
+    pub probe_window: usize,
+    // Answers chat commands too.
+    pub bot: Option<crate::bot::Bot>,
+    pub mqtt: Option<mqtt::Settings>,
+}
+
+// Held while the API or a reload switches profiles or starts and stops the core.
+static CHANGES: Mutex<()> = Mutex::new(());
+
+fn lock_changes() -> MutexGuard<'static, ()> {
+    // Nothing is guarded but the order of changes, so a panic in one is no harm.
+    CHANGES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Link quality probes of the running core, sized by the Prober.
+static QUALITY: Mutex<Window> = Mutex::new(Window::new(0));
+
+fn window() -> MutexGuard<'static, Window> {
+    QUALITY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn quality() -> Option<Quality> {
+    window().quality()
+}
+
+// Who may use the API, beyond being able to connect to it.
+struct Access {
+    // `Host` values naming the loopback listener, or None on a unix socket,
+    // which no browser can reach.
+    hosts: Option<Vec<String>>,
+    token: String,
+}
+
+impl Access {
+    fn new(port: Option<u16>) -> Result<Access, PawprintError> {
+        let hosts = port.map(|port| {
+            ["127.0.0.1", "localhost", "[::1]"]
+                .iter()
+                .map(|host| format!("{}:{}", host, port))
+                .collect()
+        });
+        Ok(Access {
+            hosts,
+            token: token()?,
+        })
+    }
+
+    fn check(&self, method: &str, headers: &Headers) -> Result<(), ApiError> {
+        // Set by browsers on cross-origin requests; scripts and GUIs leave it out.
+        if headers.origin {
+            return Err(ApiError::new(
+                403,
+                "Requests from web pages are not allowed",
+            ));
+        }
+        if let Some(hosts) = &self.hosts {
+            let host = headers.host.as_deref().unwrap_or_default();
+            if !hosts.iter().any(|h| h.eq_ignore_ascii_case(host)) {
+                return Err(ApiError::new(403, format!("Unexpected Host: {}", host)));
+            }
+        }
+        if method != "GET" {
+            let token = headers
+                .authorization
+                .as_deref()
+                .and_then(|value| value.strip_prefix("Bearer "));
+            if !token.is_some_and(|token| same(token.trim(), &self.token)) {
+                let path = process::state_dir().join(TOKEN_FILE);
+                return Err(ApiError::new(
+                    401,
+                    format!("Send the token in {} as a bearer token", path.display()),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Compares in constant time, so the token cannot be guessed byte by byte.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |d, (x, y)| d | (x ^ y)) == 0
+}
+
+// The token from TOKEN_FILE, created on first use and kept across restarts.
+fn token() -> Result<String, PawprintError> {
+    let path = process::state_dir().join(TOKEN_FILE);
+    match fs::read_to_string(&path) {
+        Ok(token) if !token.trim().is_empty() => return Ok(token.trim().to_string()),
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(PawprintError::file(&path, e)),
+    }
+    let token = keygen::password()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| PawprintError::file(parent, e))?;
+    }
+    let mut file = fs::OpenOptions::new();
+    file.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+    file.open(&path)
+        .and_then(|mut file| writeln!(file, "{}", token))
+        .map_err(|e| PawprintError::file(&path, e))?;
+    info!("Created the API token in {}", path.display());
+    Ok(token)
+}
+
+// The request headers the API looks at.
+#[derive(Default)]
+struct Headers {
+    length: usize,
+    host: Option<String>,
+    origin: bool,
+    authorization: Option<String>,
+}
+
+// An error answered with `status` and `{"error": message}`.
+pub(crate) struct ApiError {
+    status: u16,
+    pub(crate) message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for ApiError {
+    fn from(e: Box<dyn std::error::Error>) -> ApiError {
+        ApiError::new(500, e.to_string())
+    }
+}
+
+impl From<pawprint_vpn::PawprintError> for ApiError {
+    fn from(e: pawprint_vpn::PawprintError) -> ApiError {
+        ApiError::new(500, e.to_string())
+    }
+}
+
+fn status(options: &Options) -> Result<Value, ApiError> {
+    let state = process::running(&options.pid_file)?;
+    Ok(json!({
+        "running": state.is_some(),
+        "pid": state.as_ref().map(|s| s.pid),
+        "config": state.as_ref().map(|s| s.config.display().to_string()),
+        "log": state.as_ref().and_then(|s| s.log.as_ref()).map(|l| l.display().to_string()),
+        "profile": profile::active()?,
+        // null before the first probe of this core.
+        "quality": quality(),
+    }))
+}
+
+// For supervisors: 200 while the core runs, 503 otherwise, with the latency the
+// last health check or latency test of the active profile measured, and the
+// last one that passed.
+fn healthz(options: &Options) -> Result<Value, ApiError> {
+    let state = process::running(&options.pid_file)?
+        .ok_or_else(|| ApiError::new(503, "The core is not running"))?;
+    let profile = profile::active()?;
+    let (latency, success) = match &profile {
+        Some(name) => (
+            profile::latencies()?.get(name).copied().flatten(),
+            profile::successes()?.remove(name),
+        ),
+        None => (None, None),
+    };
+    Ok(json!({
+        "pid": state.pid,
+        "profile": profile,
+        // null if never measured or the last measurement failed.
+        "latency_ms": latency,
+        // {"latency_ms", "at"}; null if no measurement ever passed.
+        "last_success": success,
+    }))
+}
+
+fn profiles() -> Result<Value, ApiError> {
+    let active = profile::active()?;
+    let latencies = profile::latencies()?;
+    let mut list = Vec::new();
+    for name in profile::names()? {
+        let node = profile::load_raw(&name)
+            .ok()
+            .and_then(|spec| crate::first_node(&spec));
+        list.push(json!({
+            "name": name,
+            "active": active.as_deref() == Some(name.as_str()),
+            "protocol": node.as_ref().map(|n| n.protocol()),
+            "server": node.as_ref().map(|n| n.address()),
+            "port": node.as_ref().map(|n| n.port()),
+            // null if never measured or the last measurement failed.
+            "latency_ms": latencies.get(&name).copied().flatten(),
+        }));
+    }
+    Ok(Value::Array(list))
+}
+
+fn traffic(options: &Options) -> Result<Value, ApiError> {
+    let traffic = stats::query(&options.xray, &options.stats_server, false)
+        .map_err(|e| ApiError::new(502, e.to_string()))?;
+    Ok(traffic
+        .iter()
+        .map(|t| {
+            json!({
+                "kind": t.kind,
+                "tag": t.tag,
+                "uplink": t.uplink,
+                "downlink": t.downlink,
+            })
+        })
+        .collect())
+}
+
+// Everything worth graphing, for Prometheus to scrape.
+fn metrics(options: &Options) -> Result<String, ApiError> {
+    let mut metrics = Metrics::default();
+    let state = process::running(&options.pid_file)?;
+    metrics.gauge(
+        "pawprint_core_up",
+        "Whether the core is running",
+        &[],
+        if state.is_some() { 1.0 } else { 0.0 },
+    );
+    if let Some(name) = profile::active()? {
+        metrics.gauge(
+            "pawprint_active_profile",
+            "The profile the active config was generated from",
+            &[("profile", &name)],
+            1.0,
+        );
+    }
+    for (name, latency) in profile::latencies()? {
+        metrics.gauge(
+            "pawprint_profile_healthy",
+            "Whether the last health check or latency test of a profile passed",
+            &[("profile", &name)],
+            if latency.is_some() { 1.0 } else { 0.0 },
+        );
+        if let Some(ms) = latency {
+            metrics.gauge(
+                "pawprint_profile_latency_seconds",
+                "Latency of the last passed health check or latency test of a profile",
+                &[("profile", &name)],
+                ms as f64 / 1000.0,
+            );
+        }
+    }
+    if let Some(quality) = quality() {
+        metrics.gauge(
+            "pawprint_link_probes",
+            "Link quality probes in the window",
+            &[],
+            quality.samples as f64,
+        );
+        metrics.gauge(
+            "pawprint_link_loss_ratio",
+            "Fraction of the link quality probes in the window that failed",
+            &[],
+            quality.loss,
+        );
+        for (name, help, ms) in [
+            (
+                "pawprint_link_rtt_seconds",
+                "Mean round trip time of the probes in the window that passed",
+                quality.rtt_ms,
+            ),
+            (
+                "pawprint_link_rtt_min_seconds",
+                "Shortest round trip time in the window",
+                quality.rtt_min_ms,
+            ),
+            (
+                "pawprint_link_rtt_max_seconds",
+                "Longest round trip time in the window",
+                quality.rtt_max_ms,
+            ),
+            (
+                "pawprint_link_jitter_seconds",
+                "Mean difference between consecutive round trip times in the window",
+                quality.jitter_ms,
+            ),
+        ] {
+            if let Some(ms) = ms {
+                metrics.gauge(name, help, &[], ms / 1000.0);
+            }
+        }
+    }
+    // `profile use` and `reload` fetch the subscriptions again to write it.
+    if let Ok(modified) = fs::metadata(profile::active_config()?).and_then(|m| m.modified()) {
+        metrics.gauge(
+            "pawprint_config_generated_timestamp_seconds",
+            "When the active config was last generated from its profile and subscriptions",
+            &[],
+            modified
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as f64,
+        );
+    }
+
+    let traffic = match state {
+        Some(_) => stats::query(&options.xray, &options.stats_server, false)
+            .inspect_err(|e| debug!("No traffic for /metrics: {}", e))
+            .ok(),
+        None => None,
+    };
+    metrics.gauge(
+        "pawprint_stats_up",
+        "Whether the core's stats API answered",
+        &[],
+        if traffic.is_some() { 1.0 } else { 0.0 },
+    );
+    for t in traffic.iter().flatten() {
+        for (direction, bytes) in [("uplink", t.uplink), ("downlink", t.downlink)] {
+            metrics.counter(
+                "pawprint_traffic_bytes_total",
+                "Bytes through an inbound or outbound since the core started",
+                &[("kind", &t.kind), ("tag", &t.tag), ("direction", direction)],
+                bytes as f64,
+            );
+        }
+    }
+    Ok(metrics.render())
+}
+
+// Regenerates the active config from `name` and restarts a running core on it.
+fn switch(options: &Options, name: &str) -> Result<Value, ApiError> {
+    if !profile::names()?.iter().any(|n| n == name) {
+        return Err(ApiError::new(404, format!("No such profile: {}", name)));
+    }
+    crate::profile_use(name, options.env_subst)?;
+    if process::running(&options.pid_file)?.is_some() {
+        start(options)?;
+    }
+    status(options)
+}
+
+// `switch` to the active profile again, refreshing its subscriptions.
+fn reload(options: &Options, name: &str) -> Result<Value, ApiError> {
+    let status = switch(options, name)?;
+    let config = profile::active_config()?.display().to_string();
+    hooks::fire(Event::SubscriptionUpdated, &[("CONFIG", config)]);
+    Ok(status)
+}
+
+// Starts the active config in the background, replacing a running core.
+fn start(options: &Options) -> Result<Value, ApiError> {
+    let config = profile::active_config()?;
+    if !config.exists() {
+        return Err(ApiError::new(
+            409,
+            "No active config yet, switch to a profile first",
+        ));
+    }
+    if process::running(&options.pid_file)?.is_some() {
+        crate::stop_core(&options.pid_file)?;
+    }
+    crate::run_core(
+        &config,
+        &options.xray,
+        true,
+        &options.pid_file,
+        crate::Setup::default(),
+    )?;
+    status(options)
+}
+
+pub(crate) fn route(options: &Options, method: &str, path: &str) -> Result<Value, ApiError> {
+    // Changes run one at a time; reads go ahead meanwhile.
+    let _changing = (method != "GET").then(lock_changes);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["status"]) => status(options),
+        ("GET", ["healthz"]) => healthz(options),
+        ("GET", ["profiles"]) => profiles(),
+        ("POST", ["profiles", name, "use"]) => switch(options, name),
+        ("GET", ["traffic"]) => traffic(options),
+        ("POST", ["latency"]) => {
+            crate::measure_profiles(&profile::names()?)?;
+            profiles()
+        }
+        ("POST", ["start"]) => start(options),
+        ("POST", ["stop"]) => {
+            crate::stop_core(&options.pid_file)?;
+            status(options)
+        }
+        // Fetches the subscriptions of the active profile again.
+        ("POST", ["reload"]) => {
+            let name = profile::active()?
+                .ok_or_else(|| ApiError::new(409, "No active profile to reload"))?;
+            reload(options, &name)
+        }
+        (
+            _,
+            [
+                "status" | "healthz" | "profiles" | "traffic" | "latency" | "start" | "stop"
+                | "reload",
+            ],
+        )
+        | (_, ["profiles", _, "use"]) => Err(ApiError::new(405, "Method not allowed")),
+        _ => Err(ApiError::new(404, format!("Not found: {}", path))),
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+// What a request is answered with: JSON for the API, text for /metrics.
+enum Body {
+    Json(Value),
+    Text(String),
+}
+
+// Reads one HTTP/1.1 request and answers it; the connection is closed after.
+fn serve(stream: impl Read + Write, options: &Options, access: &Access) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut headers = Headers::default();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => headers.length = value.parse().unwrap_or(0),
+            "host" => headers.host = Some(value.to_string()),
+            "origin" => headers.origin = true,
+            "authorization" => headers.authorization = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    let length = headers.length;
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = target.split('?').next().unwrap_or_default();
+    let result = if method.is_empty() || !path.starts_with('/') {
+        Err(ApiError::new(400, "Malformed request"))
+    } else if length > MAX_BODY {
+        Err(ApiError::new(413, "Request body too large"))
+    } else if let Err(e) = access.check(method, &headers) {
+        Err(e)
+    } else {
+        // Bodies are not used by any endpoint yet, but must be read off.
+        io::copy(&mut (&mut reader).take(length as u64), &mut io::sink())?;
+        match (method, path) {
+            ("GET", "/metrics") => metrics(options).map(Body::Text),
+            (_, "/metrics") => Err(ApiError::new(405, "Method not allowed")),
+            _ => route(options, method, path).map(Body::Json),
+        }
+    };
+    let (code, body) = match result {
+        Ok(body) => (200, body),
+        Err(e) => (e.status, Body::Json(json!({ "error": e.message }))),
+    };
+    // Scrapes and probes come every few seconds; they would drown everything else.
+    if matches!(path, "/metrics" | "/healthz") && code == 200 {
+        debug!("{} {} {}", method, path, code);
+    } else {
+        info!("{} {} {}", method, path, code);
+    }
+
+    let (content_type, body) = match body {
+        Body::Json(value) => (
+            "application/json",
+            serde_json::to_string_pretty(&value)? + "\n",
+        ),
+        // The Prometheus text exposition format.
+        Body::Text(text) => ("text/plain; version=0.0.4", text),
+    };
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason(code),
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+// Follows the active profile and the files it uses, regenerating the config
+// (and restarting a running core) when they change or on SIGHUP.
+struct Reloader {
+    profile: Option<String>,
+    changes: watch::Changes,
+    next_poll: Instant,
+}
+
+impl Reloader {
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    fn new(options: &Options) -> Reloader {
+        let mut reloader = Reloader {
+            profile: None,
+            changes: watch::Changes::default(),
+            next_poll: Instant::now(),
+        };
+        reloader.follow(options);
+        reloader
+    }
+
+    // Starts over with the profile active now, which the API may have switched.
+    fn follow(&mut self, options: &Options) {
+        self.profile = profile::active().ok().flatten();
+        let files = self
+            .profile
+            .as_deref()
+            .and_then(|name| profile::sources(name, options.env_subst).ok());
+        self.changes = watch::Changes::new(files.unwrap_or_default());
+    }
+
+    fn poll(&mut self, options: &Options) {
+        let hangup = watch::take_reload();
+        if !hangup && Instant::now() < self.next_poll {
+            return;
+        }
+        self.next_poll = Instant::now() + Reloader::INTERVAL;
+        if profile::active().ok().flatten() != self.profile {
+            self.follow(options);
+        }
+        let changed = self.changes.poll();
+        let Some(name) = self.profile.clone() else {
+            if hangup {
+                warn!("SIGHUP received, but there is no active profile to reload");
+            }
+            return;
+        };
+        if let Some(path) = changed.first() {
+            info!("{} changed, reloading profile {}", path.display(), name);
+        } else if hangup {
+            info!("SIGHUP received, reloading profile {}", name);
+        } else {
+            return;
+        }
+        let changing = lock_changes();
+        let result = reload(options, &name);
+        drop(changing);
+        match result {
+            Ok(_) => info!("✓ Reloaded profile {}", name),
+            Err(e) => warn!("Keeping the current config: {}", e.message),
+        }
+        // The files the profile uses may have changed with it.
+        self.follow(options);
+    }
+}
+
+// Samples round trip time and loss through the running core's local inbound,
+// the way health checks do, and starts over whenever the core restarts, as it
+// may be on another server then.
+struct Prober {
+    pid: Option<u32>,
+    next_probe: Instant,
+}
+
+impl Prober {
+    fn new(options: &Options) -> Prober {
+        *window() = Window::new(options.probe_window);
+        Prober {
+            pid: None,
+            next_probe: Instant::now(),
+        }
+    }
+
+    fn poll(&mut self, options: &Options) {
+        if options.probe_interval.is_zero() || Instant::now() < self.next_probe {
+            return;
+        }
+        self.next_probe = Instant::now() + options.probe_interval;
+        let state = process::running(&options.pid_file).ok().flatten();
+        let pid = state.as_ref().map(|s| s.pid);
+        if pid != self.pid {
+            window().clear();
+            self.pid = pid;
+        }
+        let Some(state) = state else {
+            return;
+        };
+        let proxy = crate::read_config(&state.config)
+            .ok()
+            .and_then(|config| health::proxy_url(&config));
+        let Some(proxy) = proxy else {
+            debug!("The config has no SOCKS or HTTP inbound to probe link quality through");
+            return;
+        };
+        let sample = latency::http_delay(&proxy, &options.probe_url, options.probe_interval)
+            .inspect_err(|e| debug!("Link quality probe failed: {}", e))
+            .ok();
+        window().push(sample);
+    }
+}
+
+// What `publish_state` sends each interval: topic, payload and whether the
+// broker keeps it for later subscribers. Traffic counters are left to the
+// next publish.
+fn mqtt_messages(prefix: &str, options: &Options) -> Vec<(String, String, bool)> {
+    let status = status(options).unwrap_or_else(|e| json!({ "error": e.message }));
+    let text = |value: &Value| match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    };
+    let mut messages = vec![
+        (format!("{}/state", prefix), "online".to_string(), true),
+        (
+            format!("{}/running", prefix),
+            text(&status["running"]),
+            true,
+        ),
+        (
+            format!("{}/profile", prefix),
+            text(&status["profile"]),
+            true,
+        ),
+        (format!("{}/status", prefix), status.to_string(), true),
+    ];
+    if status["running"] == true
+        && let Ok(traffic) = stats::query(&options.xray, &options.stats_server, false)
+    {
+        for t in traffic {
+            for (direction, bytes) in [("uplink", t.uplink), ("downlink", t.downlink)] {
+                let topic = format!("{}/traffic/{}/{}/{}", prefix, t.kind, t.tag, direction);
+                messages.push((topic, bytes.to_string(), false));
+            }
+        }
+    }
+    messages
+}
+
+// Publishes the core's state to an MQTT broker every interval, connecting again
+// after the broker goes away. The broker marks `<prefix>/state` offline if the
+// daemon dies without saying so.
+fn publish_state(settings: &mqtt::Settings, options: &Options, done: &AtomicBool) {
+    let broker = match Broker::parse(&settings.url) {
+        Ok(broker) => broker,
+        Err(e) => {
+            warn!("Not publishing to MQTT: {}", e);
+            return;
+        }
+    };
+    let availability = format!("{}/state", settings.prefix);
+    let will = Will {
+        topic: &availability,
+        message: b"offline",
+    };
+    let interval = Duration::from_secs(settings.interval.max(1));
+    let keep_alive = (settings.interval * 2).clamp(10, u16::MAX as u64) as u16;
+    let client_id = format!("pawprint-vpn-{}", std::process::id());
+    let mut client: Option<Client> = None;
+    let mut failing = false;
+    let mut next = Instant::now();
+    while !watch::stop_requested() && !done.load(Ordering::SeqCst) {
+        if Instant::now() < next {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+        next = Instant::now() + interval;
+        let mut connected = match client.take() {
+            Some(client) => client,
+            None => match Client::connect(&broker, &client_id, Some(&will), keep_alive) {
+                Ok(client) => {
+                    info!(
+                        "✓ Publishing to MQTT broker {}:{} under {}/",
+                        broker.host, broker.port, settings.prefix
+                    );
+                    failing = false;
+                    client
+                }
+                // Said once, not every interval while the broker is down.
+                Err(e) if !failing => {
+                    warn!("{}", e);
+                    failing = true;
+                    continue;
+                }
+                Err(e) => {
+                    debug!("{}", e);
+                    continue;
+                }
+            },
+        };
+        let published = mqtt_messages(&settings.prefix, options)
+            .iter()
+            .try_for_each(|(topic, payload, retain)| {
+                connected.publish(topic, payload.as_bytes(), *retain)
+            });
+        match published {
+            Ok(()) => client = Some(connected),
+            Err(e) => warn!("Publishing to MQTT failed: {}", e),
+        }
+    }
+    if let Some(mut client) = client {
+        let _ = client.publish(&availability, b"offline", true);
+        client.disconnect();
+    }
+}
+
+// Serves each connection on its own thread until SIGINT or SIGTERM, so a latency
+// test, a reload or a slow client never holds up /healthz and /metrics.
+fn accept_loop<S: Read + Write + Send>(
+    mut accept: impl FnMut() -> io::Result<S>,
+    options: &Options,
+    access: &Access,
+) -> Result<(), Box<dyn std::error::Error>> {
+    watch::install_signal_handlers();
+    watch::install_reload_handler();
+    let failed = AtomicBool::new(false);
+    let result = thread::scope(|scope| {
+        if let Some(bot) = &options.bot {
+            scope.spawn(|| crate::bot::run(bot, options, &failed));
+        }
+        if let Some(settings) = &options.mqtt {
+            scope.spawn(|| publish_state(settings, options, &failed));
+        }
+        scope.spawn(|| {
+            let mut prober = Prober::new(options);
+            while !watch::stop_requested() && !failed.load(Ordering::SeqCst) {
+                prober.poll(options);
+                thread::sleep(Duration::from_millis(100));
+            }
+        });
+        scope.spawn(|| {
+            let mut reloader = Reloader::new(options);
+            while !watch::stop_requested() && !failed.load(Ordering::SeqCst) {
+                reloader.poll(options);
+                thread::sleep(Duration::from_millis(100));
+            }
+        });
+        while !watch::stop_requested() {
+            match accept() {
+                Ok(stream) => {
+                    scope.spawn(move || {
+                        if let Err(e) = serve(stream, options, access) {
+                            warn!("Request failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    failed.store(true, Ordering::SeqCst);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    });
+    result?;
+    info!("Daemon stopped");
+    Ok(())
+}
+
+pub fn run(options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(socket) = &options.socket {
+        #[cfg(unix)]
+        {
+            use std::os::unix::net::UnixListener;
+            // A socket left by a daemon that did not shut down cleanly.
+            if socket.exists() {
+                std::fs::remove_file(socket)?;
+            }
+            let listener = UnixListener::bind(socket)
+                .map_err(|e| format!("Cannot listen on {}: {}", socket.display(), e))?;
+            listener.set_nonblocking(true)?;
+            let access = Access::new(None)?;
+            info!("✓ Control API listening on {}", socket.display());
+            let result = accept_loop(
+                || {
+                    let (stream, _) = listener.accept()?;
+                    stream.set_nonblocking(false)?;
+                    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+                    Ok(stream)
+                },
+                &options,
+                &access,
+            );
+            let _ = std::fs::remove_file(socket);
+            return result;
+        }
+        #[cfg(not(unix))]
+        return Err(format!(
+            "Unix sockets are not supported here, use --listen instead of {}",
+            socket.display()
+        )
+        .into());
+    }
+
+    let address: SocketAddr = options
+        .listen
+        .parse()
+        .map_err(|e| format!("Invalid listen address {}: {}", options.listen, e))?;
+    // The token guards changes, but status and traffic are open to anyone who
+    // can connect.
+    if !address.ip().is_loopback() {
+        return Err(format!(
+            "The control API is only served locally, listen on a loopback address instead of {}",
+            address
+        )
+        .into());
+    }
+    let listener =
+        TcpListener::bind(address).map_err(|e| format!("Cannot listen on {}: {}", address, e))?;
+    listener.set_nonblocking(true)?;
+    let address = listener.local_addr()?;
+    let access = Access::new(Some(address.port()))?;
+    info!("✓ Control API listening on http://{}", address);
+    accept_loop(
+        || {
+            let (stream, _) = listener.accept()?;
+            stream.set_nonblocking(false)?;
+            stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+            Ok(stream)
+        },
+        &options,
+        &access,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(host: Option<&str>, origin: bool, authorization: Option<&str>) -> Headers {
+        Headers {
+            host: host.map(str::to_string),
+            origin,
+            authorization: authorization.map(str::to_string),
+            ..Headers::default()
+        }
+    }
+
+    fn status(result: Result<(), ApiError>) -> u16 {
+        result.err().map_or(200, |e| e.status)
+    }
+
+    #[test]
+    fn access_needs_a_local_host_and_the_token_for_changes() {
+        let access = Access {
+            hosts: Some(vec![
+                "127.0.0.1:9090".to_string(),
+                "localhost:9090".to_string(),
+            ]),
+            token: "s3cret".to_string(),
+        };
+        let local = Some("LOCALHOST:9090");
+        assert_eq!(
+            status(access.check("GET", &headers(local, false, None))),
+            200
+        );
+        // A rebound DNS name still connects to loopback but names another host.
+        let rebound = Some("evil.example:9090");
+        assert_eq!(
+            status(access.check("GET", &headers(rebound, false, None))),
+            403
+        );
+        assert_eq!(
+            status(access.check("GET", &headers(None, false, None))),
+            403
+        );
+        assert_eq!(
+            status(access.check("GET", &headers(local, true, None))),
+            403
+        );
+
+        assert_eq!(
+            status(access.check("POST", &headers(local, false, None))),
+            401
+        );
+        let wrong = Some("Bearer s3cre7");
+        assert_eq!(
+            status(access.check("POST", &headers(local, false, wrong))),
+            401
+        );
+        let basic = Some("Basic s3cret");
+        assert_eq!(
+            status(access.check("POST", &headers(local, false, basic))),
+            401
+        );
+        let right = Some("Bearer s3cret");
+        assert_eq!(
+            status(access.check("POST", &headers(local, false, right))),
+            200
+        );
+        assert_eq!(
+            status(access.check("POST", &headers(local, true, right))),
+            403
+        );
+
+        // Unix sockets take any Host, but still the token.
+        let socket = Access {
+            hosts: None,
+            token: "s3cret".to_string(),
+        };
+        assert_eq!(
+            status(socket.check("GET", &headers(None, false, None))),
+            200
+        );
+        assert_eq!(
+            status(socket.check("POST", &headers(None, false, None))),
+            401
+        );
+    }
+
+    #[test]
+    fn tokens_compare_whole() {
+        assert!(same("abc", "abc"));
+        assert!(!same("abc", "abd"));
+        assert!(!same("abc", "abcd"));
+        assert!(!same("", "a"));
+    }
+}
@@ -0,0 +1,106 @@
+use serde_json::Value;
+use std::fmt;
+
+// One difference between two configs, at a path like
+// `.outbounds[tag=proxy].settings.vnext[0].port`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(String, Value),
+    Removed(String, Value),
+    Changed(String, Value, Value),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(path, value) => write!(f, "+ {}: {}", path, value),
+            Change::Removed(path, value) => write!(f, "- {}: {}", path, value),
+            Change::Changed(path, old, new) => write!(f, "~ {}: {} -> {}", path, old, new),
+        }
+    }
+}
+
+fn key_path(path: &str, key: &str) -> String {
+    let plain = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if plain {
+        format!("{}.{}", path, key)
+    } else {
+        format!("{}[{}]", path, Value::from(key))
+    }
+}
+
+// The tags of an array's items when every item has a distinct one, as inbounds
+// and outbounds usually do, so they are matched by tag rather than position.
+fn tags(items: &[Value]) -> Option<Vec<&str>> {
+    let tags: Vec<&str> = items
+        .iter()
+        .map(|item| item.get("tag").and_then(Value::as_str))
+        .collect::<Option<_>>()?;
+    let mut sorted = tags.clone();
+    sorted.sort_unstable();
+    sorted.dedup();
+    (sorted.len() == tags.len()).then_some(tags)
+}
+
+fn walk(path: String, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            for (key, old_value) in old_map {
+                let path = key_path(&path, key);
+                match new_map.get(key) {
+                    Some(new_value) => walk(path, old_value, new_value, changes),
+                    None => changes.push(Change::Removed(path, old_value.clone())),
+                }
+            }
+            for (key, new_value) in new_map {
+                if !old_map.contains_key(key) {
+                    changes.push(Change::Added(key_path(&path, key), new_value.clone()));
+                }
+            }
+        }
+        (Value::Array(old_items), Value::Array(new_items)) => {
+            if let (Some(old_tags), Some(new_tags)) = (tags(old_items), tags(new_items)) {
+                let item_path = |tag: &str| format!("{}[tag={}]", path, tag);
+                for (old_item, tag) in old_items.iter().zip(&old_tags) {
+                    match new_tags.iter().position(|t| t == tag) {
+                        Some(index) => walk(item_path(tag), old_item, &new_items[index], changes),
+                        None => changes.push(Change::Removed(item_path(tag), old_item.clone())),
+                    }
+                }
+                for (new_item, tag) in new_items.iter().zip(&new_tags) {
+                    if !old_tags.contains(tag) {
+                        changes.push(Change::Added(item_path(tag), new_item.clone()));
+                    }
+                }
+                return;
+            }
+            for index in 0..old_items.len().max(new_items.len()) {
+                let path = format!("{}[{}]", path, index);
+                match (old_items.get(index), new_items.get(index)) {
+                    (Some(old_item), Some(new_item)) => walk(path, old_item, new_item, changes),
+                    (Some(old_item), None) => changes.push(Change::Removed(path, old_item.clone())),
+                    (None, Some(new_item)) => changes.push(Change::Added(path, new_item.clone())),
+                    (None, None) => {}
+                }
+            }
+        }
+        _ if old != new => changes.push(Change::Changed(path, old.clone(), new.clone())),
+        _ => {}
+    }
+}
+
+// What changes from `old` to `new`, leaf by leaf. Items with tags are matched by
+// tag, so inserting an outbound does not show every later one as changed.
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let root = if old.is_object() || old.is_array() {
+        String::new()
+    } else {
+        ".".to_string()
+    };
+    walk(root, old, new, &mut changes);
+    changes
+}
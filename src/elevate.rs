@@ -0,0 +1,87 @@
+use std::path::Path;
+use std::sync::OnceLock;
+use tracing::info;
+
+use crate::error::PawprintError;
+use crate::undo::{args, command};
+
+// Tools tried, in order, to run a single command as root.
+const TOOLS: &[&str] = &["sudo", "doas", "pkexec"];
+
+// Only the steps that change the firewall, routes, resolver or file
+// capabilities run as root. When pawprint itself is not root they go through
+// the first of sudo, doas and pkexec found in PATH, which asks for consent in
+// its own way; the rest of pawprint keeps the user's privileges.
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn is_root() -> bool {
+    false
+}
+
+fn tool() -> Option<&'static str> {
+    static TOOL: OnceLock<Option<&'static str>> = OnceLock::new();
+    *TOOL.get_or_init(|| {
+        let path = std::env::var_os("PATH")?;
+        TOOLS.iter().copied().find(|tool| {
+            std::env::split_paths(&path).any(|dir| Path::new(&dir).join(tool).is_file())
+        })
+    })
+}
+
+// Fails early, before anything was changed, if `what` needs root and there is
+// no way to get it.
+pub fn check(what: &str) -> Result<(), PawprintError> {
+    if cfg!(unix) && !is_root() && tool().is_none() {
+        return Err(format!(
+            "{} needs root: run pawprint-vpn as root or install sudo, doas or pkexec",
+            what
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// `args` prefixed with the elevation tool unless pawprint is root already.
+// Saved undo steps keep the prefix, so `stop` run by the same user undoes them.
+pub fn root(args: Vec<String>) -> Vec<String> {
+    match tool() {
+        Some(tool) if cfg!(unix) && !is_root() => {
+            std::iter::once(tool.to_string()).chain(args).collect()
+        }
+        _ => args,
+    }
+}
+
+// Lets `binary` create TUN interfaces and bind low ports without root (Linux).
+pub fn grant_net_admin(binary: &Path) -> Result<(), PawprintError> {
+    if !cfg!(target_os = "linux") {
+        return Err("File capabilities are only supported on Linux".into());
+    }
+    check("Setting file capabilities")?;
+    let binary = binary.to_string_lossy();
+    command(&root(args([
+        "setcap",
+        "cap_net_admin,cap_net_bind_service=+ep",
+        &binary,
+    ])))?;
+    info!("✓ {} may now create TUN interfaces without root", binary);
+    Ok(())
+}
+
+// Whether `binary` can create a TUN interface as the current user. Unknown
+// (true) where getcap is missing.
+pub fn can_create_tun(binary: &Path) -> bool {
+    if !cfg!(target_os = "linux") || is_root() {
+        return true;
+    }
+    let binary = binary.to_string_lossy();
+    match command(&args(["getcap", &binary])) {
+        Ok(caps) => caps.contains("cap_net_admin"),
+        Err(_) => true,
+    }
+}
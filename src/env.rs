@@ -0,0 +1,242 @@
+use std::env;
+use std::path::Path;
+
+use crate::error::PawprintError;
+
+type Lookup<'a> = dyn Fn(&str) -> Option<String> + 'a;
+
+// Expands `${VAR}` and `${VAR:-default}` from the environment. `$${` produces a
+// literal `${`; any other `$` is left alone. Unset variables without a default
+// are an error so a missing secret never ends up as an empty string.
+//
+// Files are expanded after parsing, one string value at a time, so a value
+// holding quotes or newlines stays inside its string.
+pub fn substitute(input: &str, source: &Path) -> Result<String, PawprintError> {
+    expand(input, source, &|name| env::var(name).ok())
+}
+
+fn expand(input: &str, source: &Path, lookup: &Lookup<'_>) -> Result<String, PawprintError> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        if let Some(escaped) = after.strip_prefix("${") {
+            out.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let Some(body) = after.strip_prefix('{') else {
+            out.push('$');
+            rest = after;
+            continue;
+        };
+
+        let end = body
+            .find('}')
+            .ok_or_else(|| format!("Unterminated ${{...}} in {}", source.display()))?;
+        let expr = &body[..end];
+        let (name, default) = match expr.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expr, None),
+        };
+
+        // As in the shell, `:-` also applies to variables that are set but empty.
+        match (lookup(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => out.push_str(default),
+            (Some(value), _) => out.push_str(&value),
+            (None, Some(default)) => out.push_str(default),
+            (None, None) => {
+                return Err(format!(
+                    "Environment variable {} used in {} is not set",
+                    name,
+                    source.display()
+                )
+                .into());
+            }
+        }
+        rest = &body[end + 1..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+// Expands the strings of a parsed JSON document; keys are left alone.
+pub fn substitute_json(value: &mut serde_json::Value, source: &Path) -> Result<(), PawprintError> {
+    expand_json(value, source, &|name| env::var(name).ok())
+}
+
+fn expand_json(
+    value: &mut serde_json::Value,
+    source: &Path,
+    lookup: &Lookup<'_>,
+) -> Result<(), PawprintError> {
+    match value {
+        serde_json::Value::String(s) => *s = expand(s, source, lookup)?,
+        serde_json::Value::Array(items) => {
+            for item in items {
+                expand_json(item, source, lookup)?;
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for item in map.values_mut() {
+                expand_json(item, source, lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn expand_toml(
+    value: &mut toml::Value,
+    source: &Path,
+    lookup: &Lookup<'_>,
+) -> Result<(), PawprintError> {
+    match value {
+        toml::Value::String(s) => *s = expand(s, source, lookup)?,
+        toml::Value::Array(items) => {
+            for item in items {
+                expand_toml(item, source, lookup)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, item) in table.iter_mut() {
+                expand_toml(item, source, lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Parses TOML into `T`, expanding the strings in it first with `env_subst`.
+pub fn parse_toml<T: serde::de::DeserializeOwned>(
+    content: &str,
+    source: &Path,
+    env_subst: bool,
+) -> Result<T, PawprintError> {
+    parse_toml_with(
+        content,
+        source,
+        env_subst.then_some(&|name: &str| env::var(name).ok()),
+    )
+}
+
+fn parse_toml_with<T: serde::de::DeserializeOwned>(
+    content: &str,
+    source: &Path,
+    lookup: Option<&Lookup<'_>>,
+) -> Result<T, PawprintError> {
+    let invalid = |e: &dyn std::fmt::Display| format!("Invalid {}: {}", source.display(), e);
+    let Some(lookup) = lookup else {
+        return Ok(toml::from_str(content).map_err(|e| invalid(&e))?);
+    };
+    let mut value: toml::Value = toml::from_str(content).map_err(|e| invalid(&e))?;
+    expand_toml(&mut value, source, lookup)?;
+    Ok(value.try_into().map_err(|e| invalid(&e))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn vars() -> HashMap<&'static str, &'static str> {
+        HashMap::from([
+            ("USER", "alice"),
+            ("EMPTY", ""),
+            ("SECRET", "a\"b\\c\nd"),
+            ("INJECT", "x\", \"admin\": \"true"),
+        ])
+    }
+
+    fn run(input: &str) -> Result<String, PawprintError> {
+        let vars = vars();
+        expand(input, Path::new("test.toml"), &|name| {
+            vars.get(name).map(|v| v.to_string())
+        })
+    }
+
+    #[test]
+    fn expands_variables() {
+        assert_eq!(run("id=${USER}!").unwrap(), "id=alice!");
+        assert_eq!(run("${USER}${USER}").unwrap(), "alicealice");
+    }
+
+    #[test]
+    fn uses_defaults_for_unset_and_empty_variables() {
+        assert_eq!(run("${MISSING:-fallback}").unwrap(), "fallback");
+        assert_eq!(run("${EMPTY:-fallback}").unwrap(), "fallback");
+        assert_eq!(run("${USER:-fallback}").unwrap(), "alice");
+        assert_eq!(run("${MISSING:-}").unwrap(), "");
+        assert_eq!(run("${EMPTY}").unwrap(), "");
+    }
+
+    #[test]
+    fn escapes_and_plain_dollars_are_kept() {
+        assert_eq!(run("$${USER}").unwrap(), "${USER}");
+        assert_eq!(run("cost $5 $USER").unwrap(), "cost $5 $USER");
+        assert_eq!(run("trailing $").unwrap(), "trailing $");
+    }
+
+    #[test]
+    fn unset_variables_and_unterminated_braces_fail() {
+        let error = run("${MISSING}").unwrap_err().to_string();
+        assert!(error.contains("MISSING"), "{}", error);
+        assert!(run("${USER").is_err());
+    }
+
+    #[test]
+    fn json_values_keep_their_quotes() {
+        let vars = vars();
+        let lookup = |name: &str| vars.get(name).map(|v| v.to_string());
+        let mut value = json!({
+            "password": "${SECRET}",
+            "user": ["${INJECT}"],
+            "${USER}": 1,
+        });
+        expand_json(&mut value, Path::new("patch.json"), &lookup).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "password": "a\"b\\c\nd",
+                "user": ["x\", \"admin\": \"true"],
+                // Keys are not expanded.
+                "${USER}": 1,
+            })
+        );
+    }
+
+    #[test]
+    fn toml_values_keep_their_quotes() {
+        #[derive(serde::Deserialize)]
+        struct Doc {
+            password: String,
+            nested: HashMap<String, Vec<String>>,
+            port: u16,
+        }
+        let vars = vars();
+        let lookup = |name: &str| vars.get(name).map(|v| v.to_string());
+        let doc: Doc = parse_toml_with(
+            "password = \"${SECRET}\"\nport = 1080\n[nested]\nusers = [\"${INJECT}\", \"$${USER}\"]\n",
+            Path::new("spec.toml"),
+            Some(&lookup),
+        )
+        .unwrap();
+        assert_eq!(doc.password, "a\"b\\c\nd");
+        assert_eq!(doc.nested["users"], ["x\", \"admin\": \"true", "${USER}"]);
+        assert_eq!(doc.port, 1080);
+    }
+
+    #[test]
+    fn toml_is_left_alone_without_substitution() {
+        let doc: HashMap<String, String> =
+            parse_toml_with("key = \"${MISSING}\"", Path::new("spec.toml"), None).unwrap();
+        assert_eq!(doc["key"], "${MISSING}");
+    }
+}
@@ -0,0 +1,108 @@
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, PawprintError>;
+
+#[derive(Debug, Error)]
+pub enum PawprintError {
+    // The link is not a URL, or its payload (base64, JSON) is malformed.
+    #[error("Invalid {protocol} link: {reason}")]
+    InvalidLink {
+        protocol: &'static str,
+        reason: String,
+    },
+
+    #[error("Unsupported scheme: {scheme} (supported: {supported})")]
+    UnsupportedScheme { scheme: String, supported: String },
+
+    // A field the protocol cannot work without, e.g. the UUID or REALITY's pbk.
+    #[error("{param} not found in {protocol} link")]
+    MissingParam {
+        protocol: &'static str,
+        param: &'static str,
+    },
+
+    // The chosen core version cannot express part of the config.
+    #[error("Target {target} cannot {feature}")]
+    Unsupported { target: String, feature: String },
+
+    #[error("{}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("Plugin error: {0}")]
+    Plugin(String),
+
+    #[error("Network error: {0}")]
+    Network(String),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Other(String),
+}
+
+impl PawprintError {
+    pub fn file(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        PawprintError::File {
+            path: path.into(),
+            source,
+        }
+    }
+
+    // What the user can do about it, shown after the error by the CLI.
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            PawprintError::InvalidLink { .. } => Some(
+                "Check that the whole link was copied, including everything after ://".to_string(),
+            ),
+            PawprintError::UnsupportedScheme { .. } => Some(
+                "Put a parser plugin for it in ~/.config/pawprint-vpn/plugins or pass --plugins-dir"
+                    .to_string(),
+            ),
+            PawprintError::MissingParam { param: "pbk", .. } => Some(
+                "REALITY needs the server's public key as pbk=...; ask your provider for a complete link"
+                    .to_string(),
+            ),
+            PawprintError::MissingParam { .. } => {
+                Some("The link is incomplete; copy it again from your provider".to_string())
+            }
+            PawprintError::Unsupported { .. } => {
+                Some("Pick a newer core with --target, e.g. xray@25.x or sing-box@1.12".to_string())
+            }
+            PawprintError::File { source, .. } | PawprintError::Io(source) => {
+                match source.kind() {
+                    io::ErrorKind::NotFound => Some("Check the path".to_string()),
+                    io::ErrorKind::PermissionDenied => {
+                        Some("Check the file permissions or run as another user".to_string())
+                    }
+                    _ => None,
+                }
+            }
+            PawprintError::Network(_) => {
+                Some("Check the URL and your connection; a proxy may be required".to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for PawprintError {
+    fn from(message: String) -> Self {
+        PawprintError::Other(message)
+    }
+}
+
+impl From<&str> for PawprintError {
+    fn from(message: &str) -> Self {
+        PawprintError::Other(message.to_string())
+    }
+}
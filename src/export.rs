@@ -0,0 +1,235 @@
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+// Alpine based, so the healthcheck can use busybox nc.
+pub const DEFAULT_IMAGE: &str = "teddysun/xray:latest";
+pub const CONFIG_FILE: &str = "config.json";
+const CONTAINER_CONFIG_PATH: &str = "/etc/xray/config.json";
+
+#[derive(Debug, Serialize)]
+pub struct Compose {
+    services: BTreeMap<String, Service>,
+}
+
+#[derive(Debug, Serialize)]
+struct Service {
+    image: String,
+    container_name: String,
+    restart: String,
+    volumes: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ports: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    healthcheck: Option<Healthcheck>,
+}
+
+#[derive(Debug, Serialize)]
+struct Healthcheck {
+    test: Vec<String>,
+    interval: String,
+    timeout: String,
+    retries: u32,
+}
+
+// Inbound published from the container, as configured before it is moved to 0.0.0.0.
+pub struct PublishedPort {
+    pub listen: Option<String>,
+    pub port: u16,
+    pub udp: bool,
+}
+
+// Rebinds every inbound to 0.0.0.0 so it is reachable through the container network,
+// and returns what each one was originally meant to listen on.
+pub fn publish_inbounds(config: &mut serde_json::Value) -> Vec<PublishedPort> {
+    let Some(inbounds) = config["inbounds"].as_array_mut() else {
+        return Vec::new();
+    };
+
+    let mut ports = Vec::new();
+    for inbound in inbounds {
+        let Some(port) = inbound["port"].as_u64().and_then(|p| u16::try_from(p).ok()) else {
+            continue;
+        };
+        let listen = inbound["listen"]
+            .as_str()
+            .filter(|listen| *listen != "0.0.0.0" && *listen != "::")
+            .map(str::to_string);
+        let udp = inbound["protocol"] == "socks" && inbound["settings"]["udp"] == true;
+
+        inbound["listen"] = json!("0.0.0.0");
+        ports.push(PublishedPort { listen, port, udp });
+    }
+    ports
+}
+
+pub fn docker_compose(config: &mut serde_json::Value, image: &str) -> Compose {
+    let published = publish_inbounds(config);
+
+    let mut ports = Vec::new();
+    for inbound in &published {
+        let host = match &inbound.listen {
+            Some(listen) => format!("{}:{}", listen, inbound.port),
+            None => inbound.port.to_string(),
+        };
+        ports.push(format!("{}:{}", host, inbound.port));
+        if inbound.udp {
+            ports.push(format!("{}:{}/udp", host, inbound.port));
+        }
+    }
+
+    let healthcheck = published.first().map(|inbound| Healthcheck {
+        test: vec![
+            "CMD-SHELL".to_string(),
+            format!("nc -z 127.0.0.1 {} || exit 1", inbound.port),
+        ],
+        interval: "30s".to_string(),
+        timeout: "5s".to_string(),
+        retries: 3,
+    });
+
+    let service = Service {
+        image: image.to_string(),
+        container_name: "pawprint-xray".to_string(),
+        restart: "unless-stopped".to_string(),
+        volumes: vec![format!("./{}:{}:ro", CONFIG_FILE, CONTAINER_CONFIG_PATH)],
+        ports,
+        healthcheck,
+    };
+
+    Compose {
+        services: BTreeMap::from([("xray".to_string(), service)]),
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigMap {
+    api_version: String,
+    kind: String,
+    metadata: Metadata,
+    data: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Metadata {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+}
+
+// Fragment to merge into a workload's pod spec.
+#[derive(Debug, Serialize)]
+pub struct Sidecar {
+    containers: Vec<Container>,
+    volumes: Vec<Volume>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Container {
+    name: String,
+    image: String,
+    volume_mounts: Vec<VolumeMount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    readiness_probe: Option<Probe>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VolumeMount {
+    name: String,
+    mount_path: String,
+    sub_path: String,
+    read_only: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Probe {
+    exec: ExecAction,
+    period_seconds: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct ExecAction {
+    command: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Volume {
+    name: String,
+    config_map: ConfigMapRef,
+}
+
+#[derive(Debug, Serialize)]
+struct ConfigMapRef {
+    name: String,
+}
+
+// Pods share one network namespace, so inbounds without an explicit listen address
+// are bound to loopback: the app reaches the proxy on 127.0.0.1 and nothing else can.
+pub fn bind_loopback(config: &mut serde_json::Value) {
+    if let Some(inbounds) = config["inbounds"].as_array_mut() {
+        for inbound in inbounds {
+            if inbound.get("listen").is_none() {
+                inbound["listen"] = json!("127.0.0.1");
+            }
+        }
+    }
+}
+
+pub fn config_map(
+    config: &serde_json::Value,
+    name: &str,
+    namespace: Option<&str>,
+) -> Result<ConfigMap, serde_json::Error> {
+    Ok(ConfigMap {
+        api_version: "v1".to_string(),
+        kind: "ConfigMap".to_string(),
+        metadata: Metadata {
+            name: name.to_string(),
+            namespace: namespace.map(str::to_string),
+        },
+        data: BTreeMap::from([(
+            CONFIG_FILE.to_string(),
+            serde_json::to_string_pretty(config)?,
+        )]),
+    })
+}
+
+pub fn sidecar(config: &serde_json::Value, config_map_name: &str, image: &str) -> Sidecar {
+    // Loopback-bound inbounds are invisible to tcpSocket probes, which dial the pod IP.
+    let readiness_probe = config["inbounds"][0]["port"].as_u64().map(|port| Probe {
+        exec: ExecAction {
+            command: vec![
+                "nc".to_string(),
+                "-z".to_string(),
+                "127.0.0.1".to_string(),
+                port.to_string(),
+            ],
+        },
+        period_seconds: 10,
+    });
+
+    Sidecar {
+        containers: vec![Container {
+            name: "xray".to_string(),
+            image: image.to_string(),
+            volume_mounts: vec![VolumeMount {
+                name: "xray-config".to_string(),
+                mount_path: CONTAINER_CONFIG_PATH.to_string(),
+                sub_path: CONFIG_FILE.to_string(),
+                read_only: true,
+            }],
+            readiness_probe,
+        }],
+        volumes: vec![Volume {
+            name: "xray-config".to_string(),
+            config_map: ConfigMapRef {
+                name: config_map_name.to_string(),
+            },
+        }],
+    }
+}
@@ -0,0 +1,281 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+
+use crate::error::PawprintError;
+use crate::parser::Node;
+
+// Stand-in country for tags that do not name one.
+const UNKNOWN: &str = "XX";
+
+// Names airport subscriptions use for the countries they usually cover, in
+// English and Chinese. Two-letter codes in the tag are matched as well.
+const COUNTRIES: &[(&str, &[&str])] = &[
+    ("HK", &["hong kong", "hongkong", "香港"]),
+    ("TW", &["taiwan", "台湾", "臺灣"]),
+    ("MO", &["macau", "macao", "澳门"]),
+    ("CN", &["china", "中国"]),
+    ("JP", &["japan", "tokyo", "osaka", "日本", "东京", "大阪"]),
+    ("KR", &["korea", "seoul", "韩国", "首尔"]),
+    ("SG", &["singapore", "新加坡", "狮城"]),
+    ("US", &["united states", "los angeles", "seattle", "美国"]),
+    ("CA", &["canada", "加拿大"]),
+    ("GB", &["united kingdom", "london", "britain", "英国"]),
+    ("DE", &["germany", "frankfurt", "德国"]),
+    ("FR", &["france", "paris", "法国"]),
+    ("NL", &["netherlands", "amsterdam", "荷兰"]),
+    ("RU", &["russia", "moscow", "俄罗斯"]),
+    ("IN", &["india", "mumbai", "印度"]),
+    ("AU", &["australia", "sydney", "澳大利亚", "澳洲"]),
+    ("TR", &["turkey", "türkiye", "istanbul", "土耳其"]),
+    ("TH", &["thailand", "bangkok", "泰国"]),
+    ("VN", &["vietnam", "越南"]),
+    ("MY", &["malaysia", "马来西亚"]),
+    ("PH", &["philippines", "菲律宾"]),
+    ("ID", &["indonesia", "印尼", "印度尼西亚"]),
+    ("AR", &["argentina", "阿根廷"]),
+    ("BR", &["brazil", "巴西"]),
+    ("AE", &["emirates", "dubai", "阿联酋", "迪拜"]),
+];
+
+// What to do with the nodes of a subscription before building a config.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterSpec {
+    // Regexes; a node must match one of them by tag to be kept.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    // Regexes; a node matching one of them by tag is dropped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    // Two-letter codes; only nodes whose tag names one of them are kept.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub countries: Vec<String>,
+    // Drop nodes with the protocol, server and credentials of an earlier one.
+    #[serde(default)]
+    pub dedup: bool,
+    // Put the nodes of each country next to each other.
+    #[serde(default)]
+    pub group_by_country: bool,
+    // New tag, e.g. "{flag} {country}-{index}"; see `apply`.
+    pub rename: Option<String>,
+}
+
+impl FilterSpec {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty()
+            && self.exclude.is_empty()
+            && self.countries.is_empty()
+            && !self.dedup
+            && !self.group_by_country
+            && self.rename.is_none()
+    }
+}
+
+// The country a tag names, as a two-letter code: a flag emoji wins, then a
+// country or city name, then a code standing on its own like `HK 01`.
+pub fn country(tag: &str) -> Option<&'static str> {
+    let chars: Vec<char> = tag.chars().collect();
+    for pair in chars.windows(2) {
+        let letters: Option<String> = pair
+            .iter()
+            .map(|&c| {
+                let offset = (c as u32).checked_sub(0x1F1E6).filter(|o| *o < 26)?;
+                char::from_u32('A' as u32 + offset)
+            })
+            .collect();
+        // Regional indicator pairs spell the code; only known ones count.
+        if let Some(code) = letters.and_then(|l| COUNTRIES.iter().find(|(c, _)| *c == l)) {
+            return Some(code.0);
+        }
+    }
+    let lower = tag.to_lowercase();
+    if let Some((code, _)) = COUNTRIES
+        .iter()
+        .find(|(_, names)| names.iter().any(|name| lower.contains(name)))
+    {
+        return Some(code);
+    }
+    let words = tag
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .filter(|w| (2..=3).contains(&w.len()));
+    for word in words {
+        let word = match word {
+            "UK" => "GB",
+            "USA" => "US",
+            word => word,
+        };
+        if let Some((code, _)) = COUNTRIES.iter().find(|(code, _)| *code == word) {
+            return Some(code);
+        }
+    }
+    None
+}
+
+// The flag emoji of a two-letter code.
+pub fn flag(code: &str) -> String {
+    if code == UNKNOWN {
+        return "🏳".to_string();
+    }
+    code.chars()
+        .filter_map(|c| char::from_u32(0x1F1E6 + (c.to_ascii_uppercase() as u32 - 'A' as u32)))
+        .collect()
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<Regex>, PawprintError> {
+    patterns
+        .iter()
+        .map(|p| Regex::new(p).map_err(|e| format!("Invalid regex {:?}: {}", p, e).into()))
+        .collect()
+}
+
+// Filters, deduplicates, groups and renames nodes, in that order. Each node
+// carries a `T` along, like the link it was parsed from. The template
+// placeholders are {tag}, {country}, {flag}, {index} (counted per country
+// from 1), {protocol} and {server}.
+pub fn apply<T>(
+    entries: Vec<(T, Node)>,
+    spec: &FilterSpec,
+) -> Result<Vec<(T, Node)>, PawprintError> {
+    if spec.is_empty() {
+        return Ok(entries);
+    }
+    let include = compile(&spec.include)?;
+    let exclude = compile(&spec.exclude)?;
+    let countries: Vec<String> = spec
+        .countries
+        .iter()
+        .map(|c| c.trim().to_ascii_uppercase())
+        .map(|c| if c == "UK" { "GB".to_string() } else { c })
+        .collect();
+    let before = entries.len();
+
+    let mut seen = HashSet::new();
+    let mut kept: Vec<(&'static str, (T, Node))> = entries
+        .into_iter()
+        .filter(|(_, node)| include.is_empty() || include.iter().any(|r| r.is_match(node.tag())))
+        .filter(|(_, node)| !exclude.iter().any(|r| r.is_match(node.tag())))
+        .map(|entry| (country(entry.1.tag()).unwrap_or(UNKNOWN), entry))
+        .filter(|(country, _)| countries.is_empty() || countries.iter().any(|c| c == country))
+        .filter(|(_, (_, node))| {
+            !spec.dedup
+                || seen.insert((
+                    node.protocol().to_string(),
+                    node.address().to_ascii_lowercase(),
+                    node.port(),
+                    node.credential(),
+                ))
+        })
+        .collect();
+    if kept.is_empty() {
+        return Err("The subscription filters left no servers".into());
+    }
+
+    if spec.group_by_country {
+        // Stable, so each country keeps the provider's order; unknown goes last.
+        kept.sort_by_key(|(country, _)| (*country == UNKNOWN, *country));
+    }
+    if let Some(template) = &spec.rename {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (country, (_, node)) in &mut kept {
+            let index = counts.entry(country).or_default();
+            *index += 1;
+            let tag = template
+                .replace("{tag}", node.tag())
+                .replace("{country}", country)
+                .replace("{flag}", &flag(country))
+                .replace("{index}", &index.to_string())
+                .replace("{protocol}", node.protocol())
+                .replace("{server}", node.address());
+            node.set_tag(tag);
+        }
+    }
+    if kept.len() < before {
+        info!("Filters kept {} of {} servers", kept.len(), before);
+    }
+    Ok(kept.into_iter().map(|(_, entry)| entry).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Registry;
+
+    fn entries(links: &[&str]) -> Vec<((), Node)> {
+        let registry = Registry::new();
+        links
+            .iter()
+            .map(|link| ((), registry.parse(link).unwrap()))
+            .collect()
+    }
+
+    fn tags(entries: &[((), Node)]) -> Vec<&str> {
+        entries.iter().map(|(_, node)| node.tag()).collect()
+    }
+
+    #[test]
+    fn countries_are_read_from_tags() {
+        assert_eq!(country("🇯🇵 Japan 01"), Some("JP"));
+        assert_eq!(country("🇩🇪 01"), Some("DE"));
+        assert_eq!(country("Hong Kong IPLC"), Some("HK"));
+        assert_eq!(country("香港 01"), Some("HK"));
+        assert_eq!(country("UK-London 2"), Some("GB"));
+        assert_eq!(country("SG 03"), Some("SG"));
+        assert_eq!(country("fast-01"), None);
+        assert_eq!(flag("NL"), "🇳🇱");
+        assert_eq!(flag(UNKNOWN), "🏳");
+    }
+
+    #[test]
+    fn filters_pick_countries_and_drop_duplicates() {
+        let nodes = entries(&[
+            "trojan://a@jp.example:443#Japan 01",
+            "trojan://a@JP.example:443#Japan 01 copy",
+            "trojan://b@hk.example:443#HK 01",
+            "trojan://c@us.example:443#US 01 expire",
+        ]);
+        let spec = FilterSpec {
+            countries: vec!["jp".to_string(), "us".to_string()],
+            exclude: vec!["expire".to_string()],
+            dedup: true,
+            ..FilterSpec::default()
+        };
+        assert_eq!(tags(&apply(nodes.clone(), &spec).unwrap()), ["Japan 01"]);
+
+        let spec = FilterSpec {
+            include: vec!["^Mars".to_string()],
+            ..FilterSpec::default()
+        };
+        assert!(apply(nodes.clone(), &spec).is_err());
+        let spec = FilterSpec {
+            include: vec!["(".to_string()],
+            ..FilterSpec::default()
+        };
+        assert!(apply(nodes, &spec).is_err());
+    }
+
+    #[test]
+    fn renaming_counts_per_country() {
+        let nodes = entries(&[
+            "trojan://a@a.example:443#Japan A",
+            "trojan://b@b.example:443#HK A",
+            "trojan://c@c.example:443#Japan B",
+            "trojan://d@d.example:443#somewhere",
+        ]);
+        let spec = FilterSpec {
+            group_by_country: true,
+            rename: Some("{flag} {country}-{index} {protocol}".to_string()),
+            ..FilterSpec::default()
+        };
+        assert_eq!(
+            tags(&apply(nodes, &spec).unwrap()),
+            [
+                "🇭🇰 HK-1 trojan",
+                "🇯🇵 JP-1 trojan",
+                "🇯🇵 JP-2 trojan",
+                "🏳 XX-1 trojan"
+            ]
+        );
+    }
+}
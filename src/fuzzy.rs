@@ -0,0 +1,98 @@
+// Loose matching of typed names against stored ones, so `use ger` finds
+// "🇩🇪 Germany-03" without typing its flag.
+
+// Lowercase letters and digits only; flags, spaces and punctuation go.
+fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn is_subsequence(query: &str, candidate: &str) -> bool {
+    let mut chars = candidate.chars();
+    query.chars().all(|q| chars.any(|c| c == q))
+}
+
+// How well `candidate` matches, lower is better: the same, a prefix, the start
+// of a word, anywhere, then letters in order with gaps. None if it does not.
+pub fn rank(query: &str, candidate: &str) -> Option<u8> {
+    let query = normalize(query);
+    let normalized = normalize(candidate);
+    if query.is_empty() {
+        return None;
+    }
+    if normalized == query {
+        return Some(0);
+    }
+    if normalized.starts_with(&query) {
+        return Some(1);
+    }
+    let word_start = candidate
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| normalize(word).starts_with(&query));
+    if word_start {
+        return Some(2);
+    }
+    if normalized.contains(&query) {
+        return Some(3);
+    }
+    is_subsequence(&query, &normalized).then_some(4)
+}
+
+// The one candidate matching best, or all that tie for best (none if nothing
+// matches at all).
+pub fn best<'a>(query: &str, candidates: &'a [String]) -> Result<&'a str, Vec<&'a str>> {
+    if let Some(exact) = candidates.iter().find(|c| *c == query) {
+        return Ok(exact);
+    }
+    let ranked: Vec<(u8, &str)> = candidates
+        .iter()
+        .filter_map(|c| Some((rank(query, c)?, c.as_str())))
+        .collect();
+    let Some(top) = ranked.iter().map(|(rank, _)| *rank).min() else {
+        return Err(Vec::new());
+    };
+    let tied: Vec<&str> = ranked
+        .into_iter()
+        .filter(|(rank, _)| *rank == top)
+        .map(|(_, c)| c)
+        .collect();
+    match tied.as_slice() {
+        [one] => Ok(one),
+        _ => Err(tied),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn names_match_without_flags_or_case() {
+        let stored = names(&["🇩🇪 Germany-03", "🇳🇱 Amsterdam 1", "🇳🇱 Amsterdam 2", "hk"]);
+        assert_eq!(best("ger", &stored), Ok("🇩🇪 Germany-03"));
+        assert_eq!(best("germany03", &stored), Ok("🇩🇪 Germany-03"));
+        assert_eq!(best("ams2", &stored), Ok("🇳🇱 Amsterdam 2"));
+        assert_eq!(
+            best("ams", &stored),
+            Err(vec!["🇳🇱 Amsterdam 1", "🇳🇱 Amsterdam 2"])
+        );
+        assert_eq!(best("hk", &stored), Ok("hk"));
+        assert_eq!(best("tokyo", &stored), Err(Vec::new()));
+    }
+
+    #[test]
+    fn closer_matches_rank_first() {
+        assert_eq!(rank("de", "DE"), Some(0));
+        assert_eq!(rank("de", "de-frankfurt"), Some(1));
+        assert_eq!(rank("fra", "🇩🇪 DE Frankfurt"), Some(2));
+        assert_eq!(rank("man", "Germany"), Some(3));
+        assert_eq!(rank("gmy", "Germany"), Some(4));
+        assert_eq!(rank("", "Germany"), None);
+    }
+}
@@ -0,0 +1,116 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::info;
+
+use crate::error::PawprintError;
+
+// Loyalsoldier's builds, updated daily, with a `.sha256sum` next to each file.
+pub const DEFAULT_SOURCE: &str =
+    "https://github.com/Loyalsoldier/v2ray-rules-dat/releases/latest/download";
+
+pub const FILES: [&str; 2] = ["geoip.dat", "geosite.dat"];
+
+// geosite.dat is around 10 MiB; leave room for it to grow.
+const MAX_SIZE: u64 = 100 * 1024 * 1024;
+
+pub fn default_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("pawprint-vpn")
+        .join("geo")
+}
+
+// One data file after an update.
+pub struct GeoFile {
+    pub name: &'static str,
+    pub sha256: String,
+    pub size: u64,
+    // False when the local copy already matched the published checksum.
+    pub downloaded: bool,
+}
+
+pub(crate) fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub(crate) fn get(url: &str) -> Result<Vec<u8>, PawprintError> {
+    ureq::get(url)
+        .call()
+        .map_err(|e| PawprintError::Network(format!("Failed to fetch {}: {}", url, e)))?
+        .body_mut()
+        .with_config()
+        .limit(MAX_SIZE)
+        .read_to_vec()
+        .map_err(|e| PawprintError::Network(format!("Failed to read {}: {}", url, e)))
+}
+
+// Downloads whichever of geoip.dat and geosite.dat differ from the published
+// checksums into `dir`. A file is only replaced once its download verified.
+pub fn update(dir: &Path, source: &str) -> Result<Vec<GeoFile>, PawprintError> {
+    fs::create_dir_all(dir).map_err(|e| PawprintError::file(dir, e))?;
+    let source = source.trim_end_matches('/');
+    let mut files = Vec::new();
+    for name in FILES {
+        let url = format!("{}/{}", source, name);
+        let checksum = String::from_utf8_lossy(&get(&format!("{}.sha256sum", url))?).to_string();
+        // `sha256sum` output: the hex digest, then the file name.
+        let expected = checksum
+            .split_whitespace()
+            .next()
+            .filter(|sum| sum.len() == 64 && sum.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("{}.sha256sum does not contain a SHA-256 checksum", url))?
+            .to_ascii_lowercase();
+
+        let path = dir.join(name);
+        if let Ok(current) = fs::read(&path)
+            && sha256(&current) == expected
+        {
+            files.push(GeoFile {
+                name,
+                sha256: expected,
+                size: current.len() as u64,
+                downloaded: false,
+            });
+            continue;
+        }
+
+        info!("Downloading {}...", url);
+        let data = get(&url)?;
+        let actual = sha256(&data);
+        if actual != expected {
+            return Err(format!(
+                "Checksum mismatch for {}: expected {}, got {}",
+                url, expected, actual
+            )
+            .into());
+        }
+        let temp = dir.join(format!("{}.tmp", name));
+        fs::write(&temp, &data).map_err(|e| PawprintError::file(&temp, e))?;
+        fs::rename(&temp, &path).map_err(|e| PawprintError::file(&path, e))?;
+        files.push(GeoFile {
+            name,
+            sha256: actual,
+            size: data.len() as u64,
+            downloaded: true,
+        });
+    }
+    Ok(files)
+}
+
+pub fn has_files(dir: &Path) -> bool {
+    FILES.iter().all(|name| dir.join(name).is_file())
+}
+
+// Points xray at the files from `geo update` unless XRAY_LOCATION_ASSET already
+// says where to look; otherwise it uses whatever came with the binary.
+pub fn set_asset_dir(command: &mut Command) {
+    let dir = default_dir();
+    if std::env::var_os("XRAY_LOCATION_ASSET").is_none() && has_files(&dir) {
+        command.env("XRAY_LOCATION_ASSET", dir);
+    }
+}
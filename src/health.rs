@@ -0,0 +1,71 @@
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::latency;
+use crate::sysproxy::Endpoints;
+use crate::watch;
+
+// How `run --failover` decides the proxy is down.
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    // Fetched through the proxy; any response counts as healthy.
+    pub url: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+    // Checks failing in a row before giving up on the proxy.
+    pub failures: u32,
+}
+
+// The local inbound to probe through, as a proxy URL for ureq.
+pub fn proxy_url(config: &Value) -> Option<String> {
+    let endpoints = Endpoints::from_config(config);
+    match (endpoints.socks, endpoints.http) {
+        (Some(socks), _) => Some(format!("socks5://{}", socks)),
+        (None, Some(http)) => Some(format!("http://{}", http)),
+        (None, None) => None,
+    }
+}
+
+// Probes `proxy` every interval until `check.failures` probes failed in a row,
+// returning true, or until `done` is set or a stop is requested, returning
+// false. `on_success` gets the delay of every check that passed.
+pub fn monitor(
+    check: &HealthCheck,
+    proxy: &str,
+    done: &AtomicBool,
+    mut on_success: impl FnMut(Duration),
+) -> bool {
+    let mut failed = 0;
+    loop {
+        // Short naps so the monitor notices a core that exited.
+        let mut waited = Duration::ZERO;
+        while waited < check.interval {
+            let nap = Duration::from_millis(500).min(check.interval - waited);
+            if done.load(Ordering::SeqCst) || !watch::sleep(nap) {
+                return false;
+            }
+            waited += nap;
+        }
+        match latency::http_delay(proxy, &check.url, check.timeout) {
+            Ok(delay) => {
+                if failed > 0 {
+                    info!("✓ Health check passed again ({} ms)", delay.as_millis());
+                }
+                failed = 0;
+                on_success(delay);
+            }
+            Err(e) => {
+                failed += 1;
+                warn!(
+                    "Health check failed ({} of {}): {}",
+                    failed, check.failures, e
+                );
+                if failed >= check.failures {
+                    return true;
+                }
+            }
+        }
+    }
+}
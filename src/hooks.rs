@@ -0,0 +1,117 @@
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+use tracing::{debug, warn};
+
+use crate::alerts;
+use crate::profile;
+
+// Commands run through the shell when the core changes state, with what
+// happened in the environment:
+//
+//   PAWPRINT_EVENT             connected, disconnected, failover or subscription-updated
+//   PAWPRINT_PROFILE           the active profile, if any
+//   PAWPRINT_CONFIG            the config concerned
+//   PAWPRINT_PID               xray's pid (connected, disconnected)
+//   PAWPRINT_PREVIOUS_PROFILE  the profile failed over from (failover)
+//
+// Hooks run in the background; a slow one never holds up the core.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    pub connected: Option<String>,
+    pub disconnected: Option<String>,
+    pub failover: Option<String>,
+    pub subscription_updated: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    // xray started
+    Connected,
+    // xray stopped or exited
+    Disconnected,
+    // Health checks gave up on a profile and another one took over
+    Failover,
+    // The config was regenerated, fetching its subscriptions again
+    SubscriptionUpdated,
+}
+
+impl Event {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::Connected => "connected",
+            Event::Disconnected => "disconnected",
+            Event::Failover => "failover",
+            Event::SubscriptionUpdated => "subscription-updated",
+        }
+    }
+}
+
+static HOOKS: OnceLock<Hooks> = OnceLock::new();
+
+// Sets the hooks for the rest of the process; only the first call counts.
+pub fn install(hooks: Hooks) {
+    let _ = HOOKS.set(hooks);
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+fn hook(event: Event) -> Option<&'static str> {
+    let hooks = HOOKS.get()?;
+    match event {
+        Event::Connected => hooks.connected.as_deref(),
+        Event::Disconnected => hooks.disconnected.as_deref(),
+        Event::Failover => hooks.failover.as_deref(),
+        Event::SubscriptionUpdated => hooks.subscription_updated.as_deref(),
+    }
+}
+
+// Whether anything, a hook or an alert, happens on `event`.
+pub fn is_set(event: Event) -> bool {
+    hook(event).is_some() || alerts::wants(event)
+}
+
+// Runs the hook for `event`, if there is one, with `vars` added to the
+// PAWPRINT_* variables above, and sends the alerts for it.
+pub fn fire(event: Event, vars: &[(&str, String)]) {
+    alerts::event(event, vars);
+    let Some(hook) = hook(event) else {
+        return;
+    };
+
+    let mut command = shell(hook);
+    command
+        .stdin(Stdio::null())
+        .env("PAWPRINT_EVENT", event.as_str());
+    if let Ok(Some(name)) = profile::active() {
+        command.env("PAWPRINT_PROFILE", name);
+    }
+    for (key, value) in vars {
+        command.env(format!("PAWPRINT_{}", key), value);
+    }
+    debug!("Running the {} hook: {}", event.as_str(), hook);
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to run the {} hook: {}", event.as_str(), e);
+            return;
+        }
+    };
+    thread::spawn(move || match child.wait() {
+        Ok(status) if !status.success() => {
+            warn!("The {} hook exited with {}", event.as_str(), status)
+        }
+        Err(e) => warn!("The {} hook failed: {}", event.as_str(), e),
+        Ok(_) => {}
+    });
+}
@@ -0,0 +1,143 @@
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use pawprint_vpn::filter::FilterSpec;
+use pawprint_vpn::parser::Registry;
+use pawprint_vpn::spec::{InboundProtocol, InboundSpec, RoutingRules, Spec};
+use pawprint_vpn::target::CoreTarget;
+
+fn prompt(question: &str, default: Option<&str>) -> io::Result<String> {
+    match default {
+        Some(default) => eprint!("{} [{}]: ", question, default),
+        None => eprint!("{}: ", question),
+    }
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "input closed before setup finished",
+        ));
+    }
+    let answer = answer.trim();
+    Ok(match (answer.is_empty(), default) {
+        (true, Some(default)) => default.to_string(),
+        _ => answer.to_string(),
+    })
+}
+
+// Asks until `parse` accepts the answer, printing why it was rejected.
+fn ask<T>(
+    question: &str,
+    default: Option<&str>,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> io::Result<T> {
+    loop {
+        match parse(&prompt(question, default)?) {
+            Ok(value) => return Ok(value),
+            Err(e) => eprintln!("  {}", e),
+        }
+    }
+}
+
+fn parse_port(answer: &str) -> Result<u16, String> {
+    match answer.parse() {
+        Ok(0) | Err(_) => Err(format!("Not a valid port: {}", answer)),
+        Ok(port) => Ok(port),
+    }
+}
+
+// Interactive spec for `pawprint-vpn init`, covering the server and the local
+// ports. Routing rules and the rest are left for editing the spec afterwards, and
+// `service install` runs it at boot.
+pub fn wizard(registry: &Registry, output: PathBuf) -> Result<Spec, Box<dyn std::error::Error>> {
+    eprintln!("pawprint-vpn setup\n");
+
+    // An http(s) URL is a subscription, fetched when the spec is applied.
+    let (nodes, subscriptions) = ask("Share link or subscription URL", None, |answer| {
+        if answer.starts_with("http://") || answer.starts_with("https://") {
+            return url::Url::parse(answer)
+                .map(|_| (Vec::new(), vec![answer.to_string()]))
+                .map_err(|e| format!("Not a valid URL: {}", e));
+        }
+        registry
+            .parse(answer)
+            .map(|_| (vec![answer.to_string()], Vec::new()))
+            .map_err(|e| e.to_string())
+    })?;
+
+    let socks_port = ask("SOCKS port", Some("10808"), parse_port)?;
+    let http_port = ask("HTTP proxy port (empty for none)", None, |answer| {
+        if answer.is_empty() {
+            Ok(None)
+        } else {
+            parse_port(answer).map(Some)
+        }
+    })?;
+    let share_lan = ask(
+        "Allow other devices on the LAN to connect? (y/N)",
+        Some("n"),
+        |answer| match answer.to_ascii_lowercase().as_str() {
+            "y" | "yes" => Ok(true),
+            "n" | "no" => Ok(false),
+            _ => Err("Answer y or n".to_string()),
+        },
+    )?;
+    let default_target = CoreTarget::default().to_string();
+    let target = ask("Xray version", Some(&default_target), |answer| {
+        answer.parse()
+    })?;
+
+    let listen = Some(if share_lan { "0.0.0.0" } else { "127.0.0.1" }.to_string());
+    let mut inbounds = vec![InboundSpec {
+        protocol: InboundProtocol::Socks,
+        listen: listen.clone(),
+        port: socks_port,
+        tag: None,
+        username: None,
+        password: None,
+        outbound: None,
+    }];
+    if let Some(port) = http_port {
+        inbounds.push(InboundSpec {
+            protocol: InboundProtocol::Http,
+            listen,
+            port,
+            tag: None,
+            username: None,
+            password: None,
+            outbound: None,
+        });
+    }
+
+    Ok(Spec {
+        output,
+        force: false,
+        target,
+        plugins_dir: None,
+        nodes,
+        subscriptions,
+        filter: FilterSpec::default(),
+        balance: false,
+        chain: false,
+        noises: Vec::new(),
+        dns_servers: Vec::new(),
+        dns_routes: Vec::new(),
+        template: None,
+        merges: Vec::new(),
+        patches: Vec::new(),
+        json_patches: Vec::new(),
+        post_script: None,
+        jq: None,
+        routing: RoutingRules::default(),
+        tun: None,
+        mux: None,
+        fragment: None,
+        sniffing: None,
+        sockopt: None,
+        stats: None,
+        log: None,
+        inbounds,
+    })
+}
@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+use crate::error::PawprintError;
+use crate::process;
+use crate::traceroute;
+
+// Where a server's address is and whose network it is on, from ip-api.com.
+// Servers on one IP are one host, whatever their tags say.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct IpInfo {
+    pub country: Option<String>,
+    pub country_code: Option<String>,
+    pub city: Option<String>,
+    // Number and name, e.g. "AS24940 Hetzner Online GmbH".
+    pub asn: Option<String>,
+    // Unix time of the lookup.
+    #[serde(default)]
+    pub at: u64,
+}
+
+impl IpInfo {
+    // `DE Falkenstein AS24940 Hetzner Online GmbH`, leaving out what is unknown.
+    pub fn summary(&self) -> String {
+        [&self.country_code, &self.city, &self.asn]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+const API: &str = "http://ip-api.com";
+const FIELDS: &str = "fields=status,query,country,countryCode,city,as";
+// The most addresses ip-api.com takes in one batch.
+const BATCH: usize = 100;
+// Providers rarely move servers between networks; look them up weekly.
+const MAX_AGE: u64 = 7 * 86_400;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn cache_file() -> PathBuf {
+    process::state_dir().join("ipinfo.json")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn agent(proxy: Option<&str>) -> Result<ureq::Agent, PawprintError> {
+    let mut builder = ureq::Agent::config_builder().timeout_global(Some(TIMEOUT));
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(Some(
+            ureq::Proxy::new(proxy).map_err(|e| PawprintError::Network(e.to_string()))?,
+        ));
+    }
+    Ok(builder.build().into())
+}
+
+fn parse_batch(body: &str) -> Result<BTreeMap<IpAddr, IpInfo>, PawprintError> {
+    let replies: Vec<Value> = serde_json::from_str(body)
+        .map_err(|e| PawprintError::Network(format!("ip-api.com sent an invalid reply: {}", e)))?;
+    let text = |reply: &Value, key: &str| {
+        reply[key]
+            .as_str()
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+    };
+    let at = now();
+    Ok(replies
+        .iter()
+        .filter(|reply| reply["status"] == "success")
+        .filter_map(|reply| {
+            let ip = reply["query"].as_str()?.parse().ok()?;
+            let info = IpInfo {
+                country: text(reply, "country"),
+                country_code: text(reply, "countryCode"),
+                city: text(reply, "city"),
+                asn: text(reply, "as"),
+                at,
+            };
+            Some((ip, info))
+        })
+        .collect())
+}
+
+// Looks up public addresses, from the cache when it is fresh. Through `proxy`,
+// e.g. socks5://127.0.0.1:10808, ip-api.com does not learn which servers your
+// own IP uses. Private addresses and failed lookups are left out.
+pub fn lookup(
+    ips: &[IpAddr],
+    proxy: Option<&str>,
+) -> Result<BTreeMap<IpAddr, IpInfo>, PawprintError> {
+    let mut cache: BTreeMap<IpAddr, IpInfo> = fs::read_to_string(cache_file())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    let fresh = now().saturating_sub(MAX_AGE);
+    let mut missing: Vec<IpAddr> = ips
+        .iter()
+        .filter(|ip| !traceroute::is_private(ip) && !ip.is_loopback())
+        .filter(|ip| cache.get(ip).is_none_or(|info| info.at < fresh))
+        .copied()
+        .collect();
+    missing.sort();
+    missing.dedup();
+
+    if !missing.is_empty() {
+        let agent = agent(proxy)?;
+        for chunk in missing.chunks(BATCH) {
+            let body = serde_json::to_string(chunk)?;
+            let reply = agent
+                .post(format!("{}/batch?{}", API, FIELDS))
+                .header("Content-Type", "application/json")
+                .send(body)
+                .and_then(|mut response| response.body_mut().read_to_string())
+                .map_err(|e| PawprintError::Network(format!("ip-api.com: {}", e)))?;
+            cache.extend(parse_batch(&reply)?);
+        }
+        let saved = fs::create_dir_all(process::state_dir())
+            .and_then(|_| fs::write(cache_file(), serde_json::to_string_pretty(&cache)?));
+        if let Err(e) = saved {
+            debug!("Could not save {}: {}", cache_file().display(), e);
+        }
+    }
+    Ok(ips
+        .iter()
+        .filter_map(|ip| Some((*ip, cache.get(ip)?.clone())))
+        .collect())
+}
+
+// The address sites see for requests through `proxy`, i.e. where the tunnel
+// comes out.
+pub fn exit(proxy: &str) -> Result<(IpAddr, IpInfo), PawprintError> {
+    let reply = agent(Some(proxy))?
+        .get(format!("{}/json/?{}", API, FIELDS))
+        .call()
+        .and_then(|mut response| response.body_mut().read_to_string())
+        .map_err(|e| PawprintError::Network(format!("ip-api.com: {}", e)))?;
+    parse_batch(&format!("[{}]", reply))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| PawprintError::Network("ip-api.com could not place the exit IP".into()))
+}
+
+// Names sharing each server IP, for those with more than one.
+pub fn shared<'a>(servers: &[(&'a str, IpAddr)]) -> Vec<(IpAddr, Vec<&'a str>)> {
+    let mut by_ip: BTreeMap<IpAddr, Vec<&str>> = BTreeMap::new();
+    for (name, ip) in servers {
+        by_ip.entry(*ip).or_default().push(name);
+    }
+    by_ip
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_replies_are_read() {
+        let body = r#"[
+            {"status":"success","query":"1.2.3.4","country":"Germany","countryCode":"DE",
+             "city":"Falkenstein","as":"AS24940 Hetzner Online GmbH"},
+            {"status":"fail","query":"10.0.0.1"},
+            {"status":"success","query":"2001:db8::1","country":"","countryCode":"NL","city":"","as":""}
+        ]"#;
+        let infos = parse_batch(body).unwrap();
+        assert_eq!(infos.len(), 2);
+        let hetzner = &infos[&"1.2.3.4".parse::<IpAddr>().unwrap()];
+        assert_eq!(
+            hetzner.summary(),
+            "DE Falkenstein AS24940 Hetzner Online GmbH"
+        );
+        assert_eq!(
+            infos[&"2001:db8::1".parse::<IpAddr>().unwrap()].summary(),
+            "NL"
+        );
+        assert!(parse_batch("{}").is_err());
+    }
+
+    #[test]
+    fn servers_on_one_ip_are_found() {
+        let a: IpAddr = "1.2.3.4".parse().unwrap();
+        let b: IpAddr = "5.6.7.8".parse().unwrap();
+        assert_eq!(
+            shared(&[("de-1", a), ("nl-1", b), ("nl-2", a)]),
+            vec![(a, vec!["de-1", "nl-2"])]
+        );
+    }
+}
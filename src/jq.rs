@@ -0,0 +1,67 @@
+use jaq_core::load::{Arena, File, Loader};
+use jaq_core::{Compiler, Ctx, Vars, data, unwrap_valr};
+use jaq_json::{Val, read};
+
+use crate::error::PawprintError;
+
+// Applies a jq expression to the config. The expression must produce exactly one value.
+pub fn apply(config: &serde_json::Value, expr: &str) -> Result<serde_json::Value, PawprintError> {
+    let input = read::parse_single(serde_json::to_string(config)?.as_bytes())
+        .map_err(|e| format!("Failed to load config into jq: {}", e))?;
+
+    let defs = jaq_core::defs()
+        .chain(jaq_std::defs())
+        .chain(jaq_json::defs());
+    let funs = jaq_core::funs()
+        .chain(jaq_std::funs())
+        .chain(jaq_json::funs());
+
+    let loader = Loader::new(defs);
+    let arena = Arena::default();
+    let program = File {
+        code: expr,
+        path: (),
+    };
+    let modules = loader.load(&arena, program).map_err(|errs| {
+        let reasons: Vec<String> = errs
+            .into_iter()
+            .flat_map(|(_, err)| match err {
+                jaq_core::load::Error::Io(errs) => {
+                    errs.into_iter().map(|(_, msg)| msg).collect::<Vec<_>>()
+                }
+                jaq_core::load::Error::Lex(errs) => errs
+                    .into_iter()
+                    .map(|(expect, near)| format!("expected {} near `{}`", expect.as_str(), near))
+                    .collect(),
+                jaq_core::load::Error::Parse(errs) => errs
+                    .into_iter()
+                    .map(|(expect, near)| format!("expected {} near `{}`", expect.as_str(), near))
+                    .collect(),
+            })
+            .collect();
+        format!("Invalid --jq expression: {}", reasons.join("; "))
+    })?;
+
+    let filter = Compiler::default()
+        .with_funs(funs)
+        .compile(modules)
+        .map_err(|errs| {
+            let names: Vec<&str> = errs
+                .iter()
+                .flat_map(|(_, errs)| errs.iter().map(|(name, _)| *name))
+                .collect();
+            format!("Invalid --jq expression: undefined {}", names.join(", "))
+        })?;
+
+    let ctx = Ctx::<data::JustLut<Val>>::new(&filter.lut, Vars::new([]));
+    let mut outputs = filter.id.run((ctx, input)).map(unwrap_valr);
+    let output = outputs
+        .next()
+        .ok_or("--jq expression produced no output")?
+        .map_err(|e| format!("--jq expression failed: {}", e))?;
+    if outputs.next().is_some() {
+        return Err("--jq expression produced more than one value".into());
+    }
+
+    Ok(serde_json::from_str(&output.to_string())?)
+}
@@ -0,0 +1,85 @@
+// JSON with comments, as accepted by xray: `//` and `/* */` comments and trailing
+// commas are removed before parsing. Newlines are kept so parse errors still point
+// at the right line.
+pub fn strip(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                out.push(c);
+            }
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push('\n');
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = '\0';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push('\n');
+                    }
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            ('}' | ']', _) => {
+                // Drop a trailing comma left before the closing bracket.
+                let trimmed = out.trim_end().len();
+                if out[..trimmed].ends_with(',') {
+                    out.replace_range(trimmed - 1..trimmed, "");
+                }
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn parse(input: &str) -> Result<serde_json::Value, serde_json::Error> {
+    serde_json::from_str(&strip(input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn comments_and_trailing_commas_are_dropped() {
+        let input = "{\n  // the proxy\n  \"a\": [1, 2,], /* more\n  */ \"b\": {\"c\": 3,},\n}";
+        assert_eq!(parse(input).unwrap(), json!({"a": [1, 2], "b": {"c": 3}}));
+        // Newlines stay, so errors point at the right line.
+        assert_eq!(strip(input).lines().count(), input.lines().count());
+    }
+
+    #[test]
+    fn strings_are_left_alone() {
+        let input = r#"{"url": "https://example.com/a,]", "quote": "say \"//hi\""}"#;
+        assert_eq!(
+            parse(input).unwrap(),
+            json!({"url": "https://example.com/a,]", "quote": "say \"//hi\""})
+        );
+    }
+}
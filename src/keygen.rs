@@ -0,0 +1,91 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use url::form_urlencoded;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::error::PawprintError;
+
+// A REALITY keypair, both halves in the unpadded base64url form `xray x25519` prints.
+pub struct RealityKeys {
+    pub private_key: String,
+    pub public_key: String,
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N], PawprintError> {
+    let mut bytes = [0u8; N];
+    getrandom::fill(&mut bytes)
+        .map_err(|e| format!("Cannot read random bytes from the OS: {}", e))?;
+    Ok(bytes)
+}
+
+fn keys(mut private: [u8; 32]) -> RealityKeys {
+    // Clamp like xray does, so the printed private key is the one actually used.
+    private[0] &= 248;
+    private[31] &= 127;
+    private[31] |= 64;
+    let public = PublicKey::from(&StaticSecret::from(private));
+    RealityKeys {
+        private_key: URL_SAFE_NO_PAD.encode(private),
+        public_key: URL_SAFE_NO_PAD.encode(public.as_bytes()),
+    }
+}
+
+pub fn generate() -> Result<RealityKeys, PawprintError> {
+    Ok(keys(random_bytes()?))
+}
+
+// Recovers the public key of an existing server, like `xray x25519 -i`.
+pub fn from_private_key(private_key: &str) -> Result<RealityKeys, PawprintError> {
+    let private: [u8; 32] = URL_SAFE_NO_PAD
+        .decode(private_key.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("Private key must be 32 bytes of unpadded base64url")?;
+    Ok(keys(private))
+}
+
+// Hex shortId of `bytes` random bytes; REALITY allows up to 8.
+pub fn short_id(bytes: usize) -> Result<String, PawprintError> {
+    if bytes > 8 {
+        return Err("A shortId is at most 8 bytes".into());
+    }
+    let random: [u8; 8] = random_bytes()?;
+    Ok(random[..bytes]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+// The REALITY part of a VLESS share link for clients of this server.
+pub fn client_params(keys: &RealityKeys, short_id: &str, sni: Option<&str>) -> String {
+    let mut params = form_urlencoded::Serializer::new(String::new());
+    params.append_pair("security", "reality");
+    params.append_pair("pbk", &keys.public_key);
+    params.append_pair("sid", short_id);
+    params.append_pair("fp", "chrome");
+    if let Some(sni) = sni {
+        params.append_pair("sni", sni);
+    }
+    params.finish()
+}
+
+// Random (version 4) UUID for a VLESS or VMess user.
+pub fn uuid() -> Result<String, PawprintError> {
+    let mut bytes: [u8; 16] = random_bytes()?;
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+// Random password for a Trojan or Shadowsocks user.
+pub fn password() -> Result<String, PawprintError> {
+    Ok(URL_SAFE_NO_PAD.encode(random_bytes::<18>()?))
+}
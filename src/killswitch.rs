@@ -0,0 +1,393 @@
+use serde_json::Value;
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::process::Command;
+use tracing::{info, warn};
+
+use crate::elevate::{self, root};
+use crate::error::PawprintError;
+use crate::process;
+use crate::undo::{Undo, args, command};
+
+const NFT_TABLE: &str = "pawprint_killswitch";
+const IPTABLES_CHAIN: &str = "PAWPRINT_KS";
+// Anchors under com.apple/ are evaluated by the stock /etc/pf.conf.
+const PF_ANCHOR: &str = "com.apple/pawprint-killswitch";
+
+// What stays reachable while the kill switch is up.
+#[derive(Debug, Default)]
+pub struct Allowed {
+    // Addresses of the proxy servers, resolved when the switch goes up.
+    pub servers: Vec<IpAddr>,
+    // Inbound ports, so clients on the LAN still get answers.
+    pub ports: Vec<u16>,
+    // TUN interfaces the core routes through.
+    pub interfaces: Vec<String>,
+    // Servers given by name need DNS to be looked up again by the core.
+    pub dns: bool,
+}
+
+// Addresses of the proxy servers of an Xray or sing-box config, resolving the
+// ones given by name, and whether there were any of those.
+pub fn server_addresses(config: &Value) -> Result<(Vec<IpAddr>, bool), PawprintError> {
+    let mut hosts: Vec<(String, u16)> = Vec::new();
+    for outbound in config["outbounds"].as_array().into_iter().flatten() {
+        let settings = &outbound["settings"];
+        for server in [&settings["vnext"], &settings["servers"]]
+            .into_iter()
+            .filter_map(Value::as_array)
+            .flatten()
+        {
+            if let (Some(address), Some(port)) =
+                (server["address"].as_str(), server["port"].as_u64())
+            {
+                hosts.push((address.to_string(), port as u16));
+            }
+        }
+        for peer in settings["peers"].as_array().into_iter().flatten() {
+            if let Some((host, port)) = peer["endpoint"]
+                .as_str()
+                .and_then(|e| e.rsplit_once(':'))
+                .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+            {
+                hosts.push((host.to_string(), port));
+            }
+        }
+        if let (Some(address), Some(port)) = (
+            outbound["server"].as_str(),
+            outbound["server_port"].as_u64(),
+        ) {
+            hosts.push((address.to_string(), port as u16));
+        }
+    }
+    if hosts.is_empty() {
+        return Err("The config has no proxy servers".into());
+    }
+    let mut addresses = Vec::new();
+    let mut by_name = false;
+    for (host, port) in hosts {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            addresses.push(ip);
+            continue;
+        }
+        by_name = true;
+        let resolved = (host, port)
+            .to_socket_addrs()
+            .map_err(|e| PawprintError::Network(format!("Cannot resolve {}: {}", host, e)))?;
+        addresses.extend(resolved.map(|addr| addr.ip()));
+    }
+    addresses.sort();
+    addresses.dedup();
+    Ok((addresses, by_name))
+}
+
+impl Allowed {
+    // Everything an Xray or sing-box config connects to or listens on.
+    pub fn from_config(config: &Value) -> Result<Allowed, PawprintError> {
+        let (servers, dns) = server_addresses(config)?;
+        let ports = config["inbounds"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|inbound| inbound["port"].as_u64().or(inbound["listen_port"].as_u64()))
+            .map(|port| port as u16)
+            .collect();
+        Ok(Allowed {
+            servers,
+            ports,
+            interfaces: tun_interfaces(config),
+            dns,
+        })
+    }
+
+    fn servers(&self, v6: bool) -> Vec<String> {
+        self.servers
+            .iter()
+            .filter(|ip| ip.is_ipv6() == v6)
+            .map(IpAddr::to_string)
+            .collect()
+    }
+
+    fn ports(&self) -> String {
+        let ports: Vec<String> = self.ports.iter().map(u16::to_string).collect();
+        ports.join(", ")
+    }
+}
+
+// Names of the TUN interfaces an Xray or sing-box config creates.
+pub fn tun_interfaces(config: &Value) -> Vec<String> {
+    config["inbounds"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(
+            |inbound| match (inbound["protocol"].as_str(), inbound["type"].as_str()) {
+                (Some("tun"), _) => inbound["settings"]["name"].as_str(),
+                (_, Some("tun")) => inbound["interface_name"].as_str(),
+                _ => None,
+            },
+        )
+        .map(str::to_string)
+        .collect()
+}
+
+fn undo() -> Undo {
+    Undo::new("killswitch.json")
+}
+
+// Whether firewall rules are installed and not removed yet.
+pub fn is_active() -> bool {
+    undo().is_pending()
+}
+
+fn has_command(name: &str) -> bool {
+    Command::new(name)
+        .arg("--version")
+        .output()
+        .is_ok_and(|o| o.status.success())
+}
+
+// One table for both families, replaced atomically by `nft -f`.
+fn nftables(allowed: &Allowed) -> Result<(), PawprintError> {
+    let mut rules = vec!["oifname \"lo\" accept".to_string()];
+    for interface in &allowed.interfaces {
+        rules.push(format!("oifname \"{}\" accept", interface));
+    }
+    for (family, v6) in [("ip", false), ("ip6", true)] {
+        let servers = allowed.servers(v6);
+        if !servers.is_empty() {
+            rules.push(format!(
+                "{} daddr {{ {} }} accept",
+                family,
+                servers.join(", ")
+            ));
+        }
+    }
+    if !allowed.ports.is_empty() {
+        rules.push(format!("tcp sport {{ {} }} accept", allowed.ports()));
+        rules.push(format!("udp sport {{ {} }} accept", allowed.ports()));
+    }
+    // DHCP, so the lease can be renewed.
+    rules.push("udp sport 68 udp dport 67 accept".to_string());
+    if allowed.dns {
+        rules.push("meta l4proto { tcp, udp } th dport 53 accept".to_string());
+    }
+    rules.push("reject".to_string());
+
+    let script = format!(
+        "table inet {table} {{\n    chain output {{\n        type filter hook output priority 0; policy accept;\n        {rules}\n    }}\n}}\n",
+        table = NFT_TABLE,
+        rules = rules.join("\n        ")
+    );
+    let path = process::state_dir().join("killswitch.nft");
+    fs::write(&path, script).map_err(|e| PawprintError::file(&path, e))?;
+    undo().save(&[root(args(["nft", "delete", "table", "inet", NFT_TABLE]))])?;
+    let path = path.to_string_lossy();
+    command(&root(args(["nft", "-f", &path])))?;
+    Ok(())
+}
+
+fn iptables(allowed: &Allowed) -> Result<(), PawprintError> {
+    let mut families = vec![("iptables", false)];
+    if has_command("ip6tables") {
+        families.push(("ip6tables", true));
+    } else {
+        warn!("ip6tables not found, IPv6 traffic is not blocked");
+    }
+
+    let mut apply = Vec::new();
+    let mut rollback = Vec::new();
+    for (tool, v6) in families {
+        let rule = |rest: &[&str]| {
+            let mut rule = args([tool, "-A", IPTABLES_CHAIN]);
+            rule.extend(rest.iter().map(|a| a.to_string()));
+            rule
+        };
+        apply.push(args([tool, "-N", IPTABLES_CHAIN]));
+        apply.push(rule(&["-o", "lo", "-j", "ACCEPT"]));
+        for interface in &allowed.interfaces {
+            apply.push(rule(&["-o", interface, "-j", "ACCEPT"]));
+        }
+        for server in allowed.servers(v6) {
+            apply.push(rule(&["-d", &server, "-j", "ACCEPT"]));
+        }
+        for port in &allowed.ports {
+            let port = port.to_string();
+            for proto in ["tcp", "udp"] {
+                apply.push(rule(&["-p", proto, "--sport", &port, "-j", "ACCEPT"]));
+            }
+        }
+        if !v6 {
+            apply.push(rule(&[
+                "-p", "udp", "--sport", "68", "--dport", "67", "-j", "ACCEPT",
+            ]));
+        }
+        if allowed.dns {
+            for proto in ["tcp", "udp"] {
+                apply.push(rule(&["-p", proto, "--dport", "53", "-j", "ACCEPT"]));
+            }
+        }
+        apply.push(rule(&["-j", "REJECT"]));
+        apply.push(args([tool, "-I", "OUTPUT", "1", "-j", IPTABLES_CHAIN]));
+
+        rollback.push(args([tool, "-D", "OUTPUT", "-j", IPTABLES_CHAIN]));
+        rollback.push(args([tool, "-F", IPTABLES_CHAIN]));
+        rollback.push(args([tool, "-X", IPTABLES_CHAIN]));
+    }
+    undo().save(&rollback.into_iter().map(root).collect::<Vec<_>>())?;
+    for rule in apply {
+        command(&root(rule))?;
+    }
+    Ok(())
+}
+
+fn pf(allowed: &Allowed) -> Result<(), PawprintError> {
+    let mut rules = vec!["pass out quick on lo0 all".to_string()];
+    for interface in &allowed.interfaces {
+        rules.push(format!("pass out quick on {} all", interface));
+    }
+    for (family, v6) in [("inet", false), ("inet6", true)] {
+        let servers = allowed.servers(v6);
+        if !servers.is_empty() {
+            rules.push(format!(
+                "pass out quick {} to {{ {} }}",
+                family,
+                servers.join(", ")
+            ));
+        }
+    }
+    if !allowed.ports.is_empty() {
+        rules.push(format!(
+            "pass out quick proto {{ tcp, udp }} from any port {{ {} }}",
+            allowed.ports()
+        ));
+    }
+    rules.push("pass out quick proto udp from any port 68 to any port 67".to_string());
+    if allowed.dns {
+        rules.push("pass out quick proto { tcp, udp } to any port 53".to_string());
+    }
+    rules.push("block return out quick all".to_string());
+
+    let path = process::state_dir().join("killswitch.pf");
+    fs::write(&path, rules.join("\n") + "\n").map_err(|e| PawprintError::file(&path, e))?;
+    let flush = root(args(["pfctl", "-a", PF_ANCHOR, "-F", "all"]));
+    undo().save(std::slice::from_ref(&flush))?;
+    let path = path.to_string_lossy();
+    command(&root(args(["pfctl", "-a", PF_ANCHOR, "-f", &path])))?;
+
+    // Enabling takes a reference on pf, released again by its token, so pf
+    // stays in whatever state it was in before.
+    let enable = root(args(["pfctl", "-E"]));
+    let output = Command::new(&enable[0]).args(&enable[1..]).output()?;
+    let text = [output.stderr, output.stdout].concat();
+    let text = String::from_utf8_lossy(&text);
+    let token = text
+        .lines()
+        .find_map(|line| line.strip_prefix("Token : "))
+        .map(str::trim);
+    if let Some(token) = token {
+        undo().save(&[flush, root(args(["pfctl", "-X", token]))])?;
+    }
+    Ok(())
+}
+
+// Blocks all outgoing traffic except to the proxy servers, loopback and the
+// replies of the local inbounds. The firewall commands run as root.
+pub fn enable(allowed: &Allowed) -> Result<(), PawprintError> {
+    if cfg!(windows) {
+        return Err("The kill switch needs nftables or iptables (Linux) or pf (macOS)".into());
+    }
+    elevate::check("The kill switch")?;
+    // Rules left by a run that crashed, or by the core being restarted.
+    disable()?;
+    fs::create_dir_all(process::state_dir())?;
+
+    let result = if cfg!(target_os = "macos") {
+        pf(allowed)
+    } else if has_command("nft") {
+        nftables(allowed)
+    } else if has_command("iptables") {
+        iptables(allowed)
+    } else {
+        Err("The kill switch needs nft or iptables".into())
+    };
+    if let Err(e) = result {
+        // Roll back whatever part of the rules went in.
+        let _ = undo().run();
+        return Err(e);
+    }
+    if allowed.dns {
+        warn!("DNS stays open so the core can look up servers given by name");
+    }
+    info!(
+        "✓ Kill switch up: only {} server address(es) are reachable",
+        allowed.servers.len()
+    );
+    Ok(())
+}
+
+// Removes the rules installed by `enable`. Returns false if there were none.
+pub fn disable() -> Result<bool, PawprintError> {
+    let removed = undo().run()?;
+    if removed {
+        info!("✓ Kill switch down");
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn xray_servers_and_interfaces_are_found() {
+        let config = json!({
+            "inbounds": [
+                {"protocol": "socks", "port": 10808},
+                {"protocol": "tun", "settings": {"name": "xray0"}}
+            ],
+            "outbounds": [
+                {"protocol": "vless", "settings": {"vnext": [{"address": "203.0.113.7", "port": 443}]}},
+                {"protocol": "trojan", "settings": {"servers": [{"address": "[2001:db8::7]", "port": 443}]}},
+                {"protocol": "wireguard", "settings": {"peers": [{"endpoint": "203.0.113.7:51820"}]}},
+                {"protocol": "freedom", "settings": {}}
+            ]
+        });
+        let (servers, by_name) = server_addresses(&config).unwrap();
+        assert_eq!(
+            servers,
+            [
+                "203.0.113.7".parse::<IpAddr>().unwrap(),
+                "2001:db8::7".parse().unwrap()
+            ]
+        );
+        assert!(!by_name);
+        assert_eq!(tun_interfaces(&config), ["xray0"]);
+
+        let allowed = Allowed::from_config(&config).unwrap();
+        assert_eq!(allowed.ports(), "10808");
+        assert_eq!(allowed.servers(true), ["2001:db8::7"]);
+        assert!(!allowed.dns);
+    }
+
+    #[test]
+    fn singbox_servers_and_interfaces_are_found() {
+        let config = json!({
+            "inbounds": [
+                {"type": "mixed", "listen_port": 2080},
+                {"type": "tun", "interface_name": "sing0"}
+            ],
+            "outbounds": [
+                {"type": "hysteria2", "server": "198.51.100.2", "server_port": 443},
+                {"type": "direct", "tag": "direct"}
+            ]
+        });
+        let (servers, _) = server_addresses(&config).unwrap();
+        assert_eq!(servers, ["198.51.100.2".parse::<IpAddr>().unwrap()]);
+        assert_eq!(tun_interfaces(&config), ["sing0"]);
+
+        assert!(server_addresses(&json!({"outbounds": [{"type": "direct"}]})).is_err());
+    }
+}
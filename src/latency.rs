@@ -0,0 +1,315 @@
+use std::fs;
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::backend::BuildOptions;
+use crate::batch;
+use crate::error::PawprintError;
+use crate::geo;
+use crate::parser::Node;
+use crate::spec::{InboundProtocol, InboundSpec, RoutingRules};
+use crate::target::CoreTarget;
+use crate::xray;
+use crate::xraycore;
+
+// Servers probed at the same time. Real delay runs one xray per server.
+pub const TCP_WORKERS: usize = 16;
+pub const REAL_WORKERS: usize = 4;
+
+pub struct RealDelay<'a> {
+    pub xray: &'a str,
+    pub url: &'a str,
+    pub target: CoreTarget,
+}
+
+pub struct SpeedTest<'a> {
+    pub download_url: &'a str,
+    // None skips the upload.
+    pub upload_url: Option<&'a str>,
+    pub upload_bytes: u64,
+    // Each transfer stops after this long; the speed counts what made it through.
+    pub duration: Duration,
+}
+
+// Throughput in megabits per second, next to the real delay.
+pub struct Speed {
+    pub delay: Duration,
+    pub download: f64,
+    // None if the upload was skipped or failed.
+    pub upload: Option<f64>,
+}
+
+// Best of `attempts` TCP handshakes with the server.
+pub fn tcp_ping(
+    address: &str,
+    port: u16,
+    attempts: u32,
+    timeout: Duration,
+) -> Result<Duration, PawprintError> {
+    fastest(&tcp_attempts(address, port, attempts, timeout)?)
+}
+
+// Each of `attempts` TCP handshakes with the server.
+pub fn tcp_attempts(
+    address: &str,
+    port: u16,
+    attempts: u32,
+    timeout: Duration,
+) -> Result<Vec<batch::Sample>, PawprintError> {
+    let addr: SocketAddr = (address.trim_matches(['[', ']']), port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("Could not resolve {}", address))?;
+    Ok((0..attempts)
+        .map(|_| {
+            let start = Instant::now();
+            let result = TcpStream::connect_timeout(&addr, timeout)
+                .map(|_| start.elapsed())
+                .map_err(|e| e.to_string());
+            batch::Sample::new(result)
+        })
+        .collect())
+}
+
+// The best of some samples, or the last error if they all failed.
+pub fn fastest(samples: &[batch::Sample]) -> Result<Duration, PawprintError> {
+    if let Some(best) = samples.iter().filter_map(|s| s.latency).min() {
+        return Ok(best);
+    }
+    match samples.iter().rev().find_map(|s| s.error.clone()) {
+        Some(e) => Err(e.into()),
+        None => Err("No attempts made".into()),
+    }
+}
+
+fn free_port() -> std::io::Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+fn wait_for_port(port: u16, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    while Instant::now() < deadline {
+        if TcpStream::connect_timeout(&addr, Duration::from_millis(100)).is_ok() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+fn proxied_agent(proxy: &str, timeout: Duration) -> Result<ureq::Agent, PawprintError> {
+    Ok(ureq::Agent::config_builder()
+        .proxy(Some(
+            ureq::Proxy::new(proxy).map_err(|e| PawprintError::Network(e.to_string()))?,
+        ))
+        .timeout_global(Some(timeout))
+        .build()
+        .into())
+}
+
+// Time for a GET of `url` through `proxy`, e.g. socks5://127.0.0.1:10808.
+pub fn http_delay(proxy: &str, url: &str, timeout: Duration) -> Result<Duration, PawprintError> {
+    let agent = proxied_agent(proxy, timeout)?;
+    let start = Instant::now();
+    agent
+        .get(url)
+        .call()
+        .map(|_| start.elapsed())
+        .map_err(|e| e.to_string().into())
+}
+
+fn mbps(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 * 8.0 / elapsed.as_secs_f64().max(0.001) / 1_000_000.0
+}
+
+// Zeros to upload, ending early once `until` has passed.
+struct Payload {
+    remaining: u64,
+    until: Instant,
+    sent: u64,
+}
+
+impl Read for Payload {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || Instant::now() >= self.until {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.remaining as usize);
+        buf[..n].fill(0);
+        self.remaining -= n as u64;
+        self.sent += n as u64;
+        Ok(n)
+    }
+}
+
+fn download(agent: &ureq::Agent, url: &str, duration: Duration) -> Result<f64, PawprintError> {
+    let start = Instant::now();
+    let response = agent.get(url).call().map_err(|e| e.to_string())?;
+    let mut reader = response
+        .into_body()
+        .into_with_config()
+        .limit(u64::MAX)
+        .reader();
+    let mut buf = vec![0; 64 * 1024];
+    let mut received = 0;
+    while start.elapsed() < duration {
+        match reader.read(&mut buf)? {
+            0 => break,
+            n => received += n as u64,
+        }
+    }
+    if received == 0 {
+        return Err(format!("{} sent nothing", url).into());
+    }
+    Ok(mbps(received, start.elapsed()))
+}
+
+fn upload(
+    agent: &ureq::Agent,
+    url: &str,
+    bytes: u64,
+    duration: Duration,
+) -> Result<f64, PawprintError> {
+    let start = Instant::now();
+    let mut payload = Payload {
+        remaining: bytes,
+        until: start + duration,
+        sent: 0,
+    };
+    agent
+        .post(url)
+        .header("Content-Type", "application/octet-stream")
+        .send(ureq::SendBody::from_reader(&mut payload))
+        .map_err(|e| e.to_string())?;
+    Ok(mbps(payload.sent, start.elapsed()))
+}
+
+// Download and upload speed through a temporary xray using only this node,
+// after the real delay. `timeout` bounds connecting; transfers run for up to
+// `speed.duration` each.
+pub fn speed_test(
+    node: &Node,
+    options: &RealDelay,
+    speed: &SpeedTest,
+    timeout: Duration,
+) -> Result<Speed, PawprintError> {
+    with_core(node, options, |proxy| {
+        let delay = http_delay(proxy, options.url, timeout)?;
+        // The overall limit only catches a stalled transfer.
+        let agent = proxied_agent(proxy, speed.duration + timeout)?;
+        let download = download(&agent, speed.download_url, speed.duration)?;
+        let upload = match speed.upload_url {
+            Some(url) => match upload(&agent, url, speed.upload_bytes, speed.duration) {
+                Ok(upload) => Some(upload),
+                Err(e) => {
+                    warn!("Upload test through {} failed: {}", node.tag(), e);
+                    None
+                }
+            },
+            None => None,
+        };
+        Ok(Speed {
+            delay,
+            download,
+            upload,
+        })
+    })
+}
+
+// Time for an HTTP request through a temporary xray using only this node.
+pub fn real_delay(
+    node: &Node,
+    options: &RealDelay,
+    timeout: Duration,
+) -> Result<Duration, PawprintError> {
+    with_core(node, options, |proxy| {
+        http_delay(proxy, options.url, timeout)
+    })
+}
+
+// Runs `probe` with the socks5:// URL of a temporary xray using only this node.
+fn with_core<T>(
+    node: &Node,
+    options: &RealDelay,
+    probe: impl FnOnce(&str) -> Result<T, PawprintError>,
+) -> Result<T, PawprintError> {
+    let port = free_port()?;
+    let build = BuildOptions {
+        target: options.target,
+        inbounds: vec![InboundSpec {
+            protocol: InboundProtocol::Socks,
+            listen: Some("127.0.0.1".to_string()),
+            port,
+            tag: None,
+            username: None,
+            password: None,
+            outbound: None,
+        }],
+        dns_servers: Vec::new(),
+        dns_routes: Vec::new(),
+        rules: RoutingRules::default(),
+        tun: None,
+        balance: false,
+        chain: false,
+        mux: None,
+        fragment: None,
+        noises: Vec::new(),
+        sniffing: None,
+        sockopt: None,
+        stats: None,
+        log: None,
+    };
+    let config = serde_json::to_string(&xray::build_config(std::slice::from_ref(node), &build)?)?;
+    let config_path = std::env::temp_dir().join(format!(
+        "pawprint-latency-{}-{}.json",
+        std::process::id(),
+        port
+    ));
+    fs::write(&config_path, config)?;
+
+    let mut command = Command::new(xraycore::program(options.xray));
+    command
+        .arg("run")
+        .arg("-c")
+        .arg(&config_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    geo::set_asset_dir(&mut command);
+    let child = command.spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            let _ = fs::remove_file(&config_path);
+            return Err(format!("Failed to start {}: {}", options.xray, e).into());
+        }
+    };
+
+    let result = if wait_for_port(port, Duration::from_secs(3)) {
+        probe(&format!("socks5://127.0.0.1:{}", port))
+    } else {
+        Err("xray did not start".into())
+    };
+
+    let _ = child.kill();
+    let _ = child.wait();
+    let _ = fs::remove_file(&config_path);
+    result
+}
+
+// Runs `probe` over every node with `workers` threads, keeping the input order.
+pub fn probe_all<F>(
+    nodes: &[Node],
+    workers: usize,
+    probe: F,
+) -> Vec<Result<Duration, PawprintError>>
+where
+    F: Fn(&Node) -> Result<Duration, PawprintError> + Sync,
+{
+    batch::run(nodes, workers, None, probe)
+}
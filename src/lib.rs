@@ -0,0 +1,60 @@
+//! Share link parsing and proxy core config generation behind the `pawprint-vpn`
+//! CLI. Parse links with [`parser::Registry`], then build a config for a
+//! [`target::CoreTarget`] with the [`backend::Backend`] from [`backend::for_target`].
+
+pub mod alerts;
+pub mod backend;
+pub mod batch;
+pub mod bundle;
+pub mod clash;
+pub mod clipboard;
+pub mod diff;
+pub mod elevate;
+pub mod env;
+pub mod error;
+pub mod export;
+pub mod filter;
+pub mod fuzzy;
+pub mod geo;
+pub mod health;
+pub mod hooks;
+pub mod ipinfo;
+pub mod jq;
+pub mod jsonc;
+pub mod keygen;
+pub mod killswitch;
+pub mod latency;
+pub mod logfile;
+pub mod metrics;
+pub mod mqtt;
+pub mod networkmanager;
+pub mod parser;
+pub mod patch;
+pub mod process;
+pub mod profile;
+pub mod qr;
+pub mod quality;
+pub mod resolver;
+pub mod routes;
+pub mod script;
+pub mod server;
+pub mod service;
+pub mod singbox;
+pub mod spec;
+pub mod stats;
+pub mod subscription;
+pub mod sysproxy;
+pub mod target;
+pub mod traceroute;
+pub mod undo;
+pub mod update;
+pub mod validate;
+pub mod vault;
+pub mod watch;
+pub mod xray;
+pub mod xraycore;
+
+pub use backend::{Backend, BuildOptions};
+pub use error::PawprintError;
+pub use parser::{Node, Registry};
+pub use target::CoreTarget;
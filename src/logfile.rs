@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+
+use crate::error::PawprintError;
+use crate::watch;
+
+// Rotated files kept next to the active log: app.log.1 (newest) .. app.log.3.
+const KEEP_ROTATED: u32 = 3;
+
+// Log file that is rotated once it grows past `max_size` bytes.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    size: u64,
+    file: File,
+}
+
+impl RotatingFile {
+    // Opens `path` for appending, rotating it first if it is already full.
+    pub fn open(path: &Path, max_size: u64) -> io::Result<Self> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        let mut rotating = RotatingFile {
+            path: path.to_path_buf(),
+            max_size,
+            size,
+            file,
+        };
+        if size >= max_size {
+            rotating.rotate()?;
+        }
+        Ok(rotating)
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.path.display(), index))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..KEEP_ROTATED).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// The current time in the format of the log file's timestamps, e.g.
+// 2026-10-14T09:30:00.123456Z.
+pub fn timestamp() -> String {
+    let mut now = String::new();
+    let _ = SystemTime.format_time(&mut Writer::new(&mut now));
+    now
+}
+
+// The last `count` lines of a log, and the offset they end at to follow from.
+pub fn tail(path: &Path, count: usize) -> Result<(Vec<String>, u64), PawprintError> {
+    let file = File::open(path).map_err(|e| PawprintError::file(path, e))?;
+    let mut reader = BufReader::new(file);
+    let mut lines = VecDeque::with_capacity(count);
+    let mut end = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        end += read as u64;
+        if count > 0 {
+            if lines.len() == count {
+                lines.pop_front();
+            }
+            lines.push_back(String::from_utf8_lossy(&line).trim_end().to_string());
+        }
+    }
+    Ok((lines.into(), end))
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<u64> {
+    Some(std::os::unix::fs::MetadataExt::ino(metadata))
+}
+
+#[cfg(not(unix))]
+fn file_id(_: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+// Passes every line appended to `path` after `offset` to `on_line`, until a
+// stop is requested. A rotated or truncated log is followed from its start.
+pub fn follow(
+    path: &Path,
+    offset: u64,
+    mut on_line: impl FnMut(&str),
+) -> Result<(), PawprintError> {
+    let open = |offset| -> Result<_, PawprintError> {
+        let mut file = File::open(path).map_err(|e| PawprintError::file(path, e))?;
+        let id = file_id(&file.metadata()?);
+        file.seek(SeekFrom::Start(offset))?;
+        Ok((BufReader::new(file), id))
+    };
+    let (mut reader, mut id) = open(offset)?;
+    let mut position = offset;
+    // A line xray has not finished writing yet.
+    let mut pending = Vec::new();
+    loop {
+        let read = reader.read_until(b'\n', &mut pending)?;
+        position += read as u64;
+        if pending.ends_with(b"\n") {
+            on_line(String::from_utf8_lossy(&pending).trim_end());
+            pending.clear();
+            continue;
+        }
+        if read > 0 {
+            continue;
+        }
+        // Gone for a moment while it is being rotated.
+        if let Ok(metadata) = fs::metadata(path)
+            && (metadata.len() < position || file_id(&metadata) != id)
+        {
+            (reader, id) = open(0)?;
+            position = 0;
+            pending.clear();
+            continue;
+        }
+        if !watch::sleep(Duration::from_millis(250)) {
+            return Ok(());
+        }
+    }
+}
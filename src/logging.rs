@@ -0,0 +1,99 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use pawprint_vpn::logfile::RotatingFile;
+
+// Console output stays as terse as the old println!s: just the message, prefixed
+// with the level when it is not INFO. Timestamps and spans go to the log file.
+struct ConsoleFormat;
+
+impl<S, N> FormatEvent<S, N> for ConsoleFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let level = *event.metadata().level();
+        if level != Level::INFO {
+            write!(writer, "{}: ", level)?;
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+// While the TUI owns the terminal, console output is collected here instead and
+// shown inside it.
+static CAPTURED: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+pub fn capture(enabled: bool) {
+    *CAPTURED.lock().unwrap() = enabled.then(Vec::new);
+}
+
+// Lines captured since the last call.
+pub fn take_captured() -> Vec<String> {
+    CAPTURED
+        .lock()
+        .unwrap()
+        .as_mut()
+        .map(std::mem::take)
+        .unwrap_or_default()
+}
+
+struct Console;
+
+impl Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(lines) = CAPTURED.lock().unwrap().as_mut() {
+            lines.extend(String::from_utf8_lossy(buf).lines().map(str::to_string));
+            return Ok(buf.len());
+        }
+        io::stderr().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+// Installs the global subscriber. RUST_LOG overrides the default `info` filter.
+pub fn init(log_file: Option<&Path>, max_size: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let console = tracing_subscriber::fmt::layer()
+        .event_format(ConsoleFormat)
+        .with_writer(|| Console);
+
+    let file = match log_file {
+        Some(path) => Some(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(Mutex::new(RotatingFile::open(path, max_size).map_err(
+                    |e| format!("Failed to open log file {}: {}", path.display(), e),
+                )?))
+                .boxed(),
+        ),
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(console)
+        .with(file)
+        .try_init()?;
+    Ok(())
+}
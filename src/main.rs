@@ -1,209 +1,2861 @@
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::path::PathBuf;
-use std::{collections::HashMap, fs};
-use url::Url;
-
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    // Start default config.
-    // #[arg(short, long)]
-    // vpn_start: bool,
-
-    // Config key to parse it
-    #[arg(short, long)]
-    config: String,
-
-    // Path to output json
-    #[arg(short, long)]
-    output: PathBuf,
-
-    // Replace existing config
-    #[arg(short, long)]
+use clap::{CommandFactory, FromArgMatches};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, info_span, warn};
+
+mod bot;
+mod cli;
+mod daemon;
+mod defaults;
+mod init;
+mod logging;
+mod tui;
+
+use cli::{
+    Args, BuildArgs, ClashArgs, Command, CoreAction, DockerArgs, ExportFormat, FilterArgs,
+    GenerateArgs, GeoAction, HookArgs, IntegrateTarget, K8sArgs, LatencyArgs, LogArgs, OutputArgs,
+    ProfileAction, QrArgs, ServerArgs, ServiceAction, TestKind, Transforms, TuningArgs, UnitArgs,
+    UpdateArgs, UserAction,
+};
+use pawprint_vpn::filter::{self, FilterSpec};
+use pawprint_vpn::health::{self, HealthCheck};
+use pawprint_vpn::parser::WireguardConfig;
+use pawprint_vpn::spec::{
+    FragmentSpec, InboundProtocol, InboundSpec, LogSpec, MuxSpec, RoutingRules, SniffingSpec,
+    SockoptSpec, Spec, StatsSpec, TunSpec,
+};
+use pawprint_vpn::{
+    BuildOptions, CoreTarget, Node, PawprintError, Registry, alerts, backend, batch, bundle, clash,
+    clipboard, diff, elevate, export, geo, hooks, ipinfo, jq, jsonc, keygen, killswitch, latency,
+    logfile, networkmanager, patch, process, profile, qr, resolver, routes, script, server,
+    service, stats, subscription, sysproxy, traceroute, update, validate, vault, watch, xray,
+    xraycore,
+};
+
+fn write_file(
+    output_path: &Path,
+    content: impl AsRef<[u8]>,
     force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if output_path.exists() && !force {
+        return Err(format!(
+            "File already exists: {}. Use --force to overwrite.",
+            output_path.display()
+        )
+        .into());
+    }
+
+    if let Some(parent) = output_path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = PathBuf::from(format!("{}.tmp", output_path.display()));
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, output_path)?;
+    Ok(())
 }
 
-#[derive(Debug, Clone)]
-struct VlessConfig {
-    uuid: String,
-    address: String,
-    port: u16,
-    params: HashMap<String, String>,
-    tag: String,
+fn save_config(
+    config: &serde_json::Value,
+    output_path: &Path,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let _span = info_span!("save").entered();
+    let json_content = serde_json::to_string_pretty(config)?;
+    if output_path == Path::new("-") {
+        println!("{}", json_content);
+        return Ok(());
+    }
+    // What --force is about to replace, so it does not happen unnoticed.
+    if force
+        && let Ok(old) = fs::read_to_string(output_path)
+        && let Ok(old) = jsonc::parse(&old)
+    {
+        match diff::diff(&old, config).len() {
+            0 => info!("{} is unchanged", output_path.display()),
+            count => info!(
+                "Replacing {} ({} change(s); see `pawprint-vpn diff`)",
+                output_path.display(),
+                count
+            ),
+        }
+    }
+    write_file(output_path, &json_content, force)?;
+
+    info!("✓ Config saved to: {}", output_path.display());
+    Ok(())
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct XrayConfig {
-    inbounds: Vec<serde_json::Value>,
-    outbounds: Vec<serde_json::Value>,
+fn parse_nodes(
+    registry: &Registry,
+    links: &[String],
+) -> Result<Vec<Node>, Box<dyn std::error::Error>> {
+    let _span = info_span!("parse").entered();
+    info!("Parsing share links...");
+    let mut nodes = Vec::new();
+    for link in links {
+        let node = registry.parse(link)?;
+        match &node {
+            Node::Vless(config) => info!("UUID: {}", config.uuid),
+            Node::Vmess(config) => info!("UUID: {}", config.uuid),
+            Node::Tuic(config) => info!("UUID: {}", config.uuid),
+            Node::Shadowsocks(config) => info!("Method: {}", config.method),
+            Node::Trojan(_) | Node::Hysteria2(_) | Node::Wireguard(_) | Node::Plugin(_) => {}
+        }
+        info!("Protocol: {}", node.protocol());
+        info!("Server: {}:{}", node.address(), node.port());
+        info!("Tag: {}", node.tag());
+        nodes.push(node);
+    }
+    Ok(nodes)
 }
 
-fn parse_config(config: &str) -> Result<VlessConfig, Box<dyn std::error::Error>> {
-    if !config.starts_with("vless://") {
-        return Err("URL must start with vless://".into());
+fn apply_transforms(
+    mut output: serde_json::Value,
+    transforms: &Transforms,
+    env_subst: bool,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let _span = info_span!("transform").entered();
+    if let Some(path) = &transforms.template {
+        info!("Merging template {}...", path.display());
+        patch::deep_merge(&mut output, &patch::load(path, env_subst)?, false);
+    }
+    for path in &transforms.merges {
+        info!("Merging {}...", path.display());
+        patch::deep_merge(&mut output, &patch::load(path, env_subst)?, true);
     }
-    let url = Url::parse(config)?;
 
-    let uuid = url.username().to_string();
-    if uuid.is_empty() {
-        return Err("UUID not found in URL".into());
+    for path in &transforms.patches {
+        info!("Applying merge patch {}...", path.display());
+        patch::merge(&mut output, &patch::load(path, env_subst)?);
     }
 
-    let address = url.host_str().ok_or("Host not found in URL")?.to_string();
-    let port = url.port().ok_or("Port not found in URL")?;
+    for path in &transforms.json_patches {
+        info!("Applying JSON patch {}...", path.display());
+        patch::apply_operations(&mut output, &patch::load_operations(path, env_subst)?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
 
-    let mut params = HashMap::new();
-    for (key, value) in url.query_pairs() {
-        params.insert(key.to_string(), value.to_string());
+    if let Some(script) = &transforms.post_script {
+        info!("Running post script {}...", script.display());
+        output = script::run_post_script(output, script)?;
     }
 
-    let tag = url.fragment().unwrap_or("VLESS-Config").to_string();
+    if let Some(expr) = &transforms.jq {
+        output = jq::apply(&output, expr)?;
+    }
 
-    Ok(VlessConfig {
-        uuid,
-        address,
+    Ok(output)
+}
+
+fn generate(
+    nodes: &[Node],
+    options: &BuildOptions,
+    transforms: &Transforms,
+    env_subst: bool,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let backend = backend::for_target(&options.target);
+    let config = {
+        let _span = info_span!("build", target = %options.target).entered();
+        info!(
+            "🔨 Building {} configuration for {}...",
+            backend.name(),
+            options.target
+        );
+        if options.balance && nodes.len() < 2 {
+            warn!("Balancing needs at least two servers, using the only one");
+        }
+        if options.chain && nodes.len() < 2 {
+            warn!("A chain needs an entry and an exit server, connecting directly");
+        }
+        backend.build(nodes, options)?
+    };
+    let config = apply_transforms(config, transforms, env_subst)?;
+
+    // Catch what the core would only reject when it starts.
+    let problems = validate::check(&config);
+    if !problems.is_empty() {
+        for problem in &problems {
+            warn!("{}", problem);
+        }
+        return Err(format!(
+            "Generated config has {} problem(s), not saving it",
+            problems.len()
+        )
+        .into());
+    }
+    Ok(config)
+}
+
+// The spec's own nodes followed by the entries of its subscriptions.
+fn spec_links(spec: &Spec) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut links = spec.nodes.clone();
+    for url in &spec.subscriptions {
+        links.extend(subscription::fetch(url)?);
+    }
+    Ok(links)
+}
+
+// Parses the result of `spec_links`. Broken subscription entries are skipped,
+// broken nodes written into the spec are not.
+fn spec_nodes(
+    spec: &Spec,
+    links: &[String],
+) -> Result<Vec<(String, Node)>, Box<dyn std::error::Error>> {
+    let registry = Registry::with_plugins(spec.plugins_dir.as_deref())?;
+    let (own, fetched) = links.split_at(spec.nodes.len());
+    let mut nodes: Vec<_> = own
+        .iter()
+        .cloned()
+        .zip(parse_nodes(&registry, own)?)
+        .collect();
+    if !fetched.is_empty() {
+        let _span = info_span!("parse").entered();
+        let mut entries = Vec::new();
+        for (index, link) in fetched.iter().enumerate() {
+            match registry.parse(link) {
+                Ok(node) => entries.push((link.clone(), node)),
+                Err(e) => warn!("Skipping subscription entry {}: {}", index + 1, e),
+            }
+        }
+        if entries.is_empty() {
+            return Err("No usable share links in the subscriptions".into());
+        }
+        info!(
+            "Parsed {} of {} subscription entries",
+            entries.len(),
+            fetched.len()
+        );
+        nodes.extend(filter::apply(entries, &spec.filter)?);
+    }
+    Ok(nodes)
+}
+
+fn apply(
+    spec_path: &Path,
+    force: bool,
+    dry_run: bool,
+    env_subst: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Loading spec {}...", spec_path.display());
+    let mut spec = Spec::load(spec_path, env_subst)?;
+    if dry_run {
+        spec.output = PathBuf::from("-");
+    }
+    apply_spec(spec, force, env_subst)
+}
+
+fn apply_spec(spec: Spec, force: bool, env_subst: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let links = spec_links(&spec)?;
+    let nodes: Vec<Node> = spec_nodes(&spec, &links)?
+        .into_iter()
+        .map(|(_, node)| node)
+        .collect();
+    build_spec(spec, &nodes, force, env_subst)
+}
+
+fn build_spec(
+    spec: Spec,
+    nodes: &[Node],
+    force: bool,
+    env_subst: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let transforms = Transforms {
+        template: spec.template,
+        merges: spec.merges,
+        patches: spec.patches,
+        json_patches: spec.json_patches,
+        post_script: spec.post_script,
+        jq: spec.jq,
+    };
+    let options = BuildOptions {
+        target: spec.target,
+        inbounds: spec.inbounds,
+        dns_servers: spec.dns_servers,
+        dns_routes: spec.dns_routes,
+        rules: spec.routing,
+        tun: spec.tun,
+        balance: spec.balance,
+        chain: spec.chain,
+        mux: spec.mux,
+        fragment: spec.fragment,
+        noises: spec.noises,
+        sniffing: spec.sniffing,
+        sockopt: spec.sockopt,
+        stats: spec.stats,
+        log: spec.log,
+    };
+    let output = generate(nodes, &options, &transforms, env_subst)?;
+
+    info!("Saving configuration...");
+    save_config(&output, &spec.output, force || spec.force)
+}
+
+// One round of `watch`: regenerates the config if the server list changed and
+// restarts xray if it runs that config. Returns whether anything changed.
+fn watch_update(
+    spec_path: &Path,
+    state_file: &Path,
+    pid_file: &Path,
+    env_subst: bool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let spec = Spec::load(spec_path, env_subst)?;
+    let links = spec_links(&spec)?;
+    let nodes = spec_nodes(&spec, &links)?;
+    let servers: Vec<_> = nodes
+        .iter()
+        .map(|(link, node)| watch::ServerEntry::new(link, node))
+        .collect();
+
+    let state = watch::WatchState::load(state_file)?;
+    let (added, removed) = watch::diff(&state.servers, &servers);
+    if state.output == spec.output && spec.output.exists() && added.is_empty() && removed.is_empty()
+    {
+        info!("No changes in {} servers", servers.len());
+        return Ok(false);
+    }
+    for entry in &added {
+        info!("+ {}", entry.name);
+    }
+    for entry in &removed {
+        info!("- {}", entry.name);
+    }
+
+    let output = spec.output.clone();
+    let nodes: Vec<Node> = nodes.into_iter().map(|(_, node)| node).collect();
+    build_spec(spec, &nodes, true, env_subst)?;
+    watch::WatchState {
+        output: output.clone(),
+        servers,
+    }
+    .save(state_file)?;
+    let vars = [("CONFIG", output.display().to_string())];
+    hooks::fire(hooks::Event::SubscriptionUpdated, &vars);
+
+    if let Some(running) = process::running(pid_file)?
+        && running.config.canonicalize().ok() == output.canonicalize().ok()
+    {
+        info!(
+            "Restarting xray (pid {}) with the new config...",
+            running.pid
+        );
+        restart_core(pid_file)?;
+    }
+    Ok(true)
+}
+
+fn watch_spec(
+    spec_path: &Path,
+    interval: u64,
+    state_file: &Path,
+    pid_file: &Path,
+    env_subst: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    watch::install_signal_handlers();
+    info!(
+        "Watching {} every {}s (Ctrl-C to stop)...",
+        spec_path.display(),
+        interval
+    );
+    loop {
+        // A failed update keeps the previous config and is retried next round.
+        if let Err(e) = watch_update(spec_path, state_file, pid_file, env_subst) {
+            warn!("Update failed: {}", e);
+        }
+        if !watch::sleep(Duration::from_secs(interval)) {
+            info!("Stopping");
+            return Ok(());
+        }
+    }
+}
+
+// Parses every entry of a subscription, skipping the ones that fail.
+fn parse_subscription(
+    registry: &Registry,
+    links: &[String],
+) -> Result<Vec<Node>, Box<dyn std::error::Error>> {
+    let _span = info_span!("parse").entered();
+    let mut nodes = Vec::new();
+    for (index, link) in links.iter().enumerate() {
+        match registry.parse(link) {
+            Ok(node) => {
+                info!(
+                    "{} {} ({}:{})",
+                    node.protocol(),
+                    node.tag(),
+                    node.address(),
+                    node.port()
+                );
+                nodes.push(node);
+            }
+            // The link itself is not logged since it carries credentials.
+            Err(e) => warn!("Skipping subscription entry {}: {}", index + 1, e),
+        }
+    }
+    if nodes.is_empty() {
+        return Err("No usable share links in the subscription".into());
+    }
+    info!("Parsed {} of {} entries", nodes.len(), links.len());
+    Ok(nodes)
+}
+
+// One share link per line; blank lines and # comments are skipped.
+fn link_lines(content: &str) -> impl Iterator<Item = String> + '_ {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+}
+
+fn read_stdin() -> Result<String, Box<dyn std::error::Error>> {
+    Ok(io::read_to_string(io::stdin()).map_err(|e| format!("Failed to read stdin: {}", e))?)
+}
+
+fn filter_spec(args: &FilterArgs) -> FilterSpec {
+    FilterSpec {
+        include: args.include.clone(),
+        exclude: args.exclude.clone(),
+        countries: args.countries.clone(),
+        dedup: args.dedup,
+        group_by_country: args.group_by_country,
+        rename: args.rename.clone(),
+    }
+}
+
+fn filter_nodes(nodes: Vec<Node>, spec: &FilterSpec) -> Result<Vec<Node>, PawprintError> {
+    let entries = filter::apply(nodes.into_iter().map(|node| ((), node)).collect(), spec)?;
+    Ok(entries.into_iter().map(|(_, node)| node).collect())
+}
+
+fn load_nodes(args: &GenerateArgs) -> Result<Vec<Node>, Box<dyn std::error::Error>> {
+    let nodes = read_nodes(args)?;
+    Ok(filter_nodes(nodes, &filter_spec(&args.build.filter))?)
+}
+
+fn read_nodes(args: &GenerateArgs) -> Result<Vec<Node>, Box<dyn std::error::Error>> {
+    let registry = Registry::with_plugins(args.build.plugins_dir.as_deref())?;
+    if let Some(url) = &args.subscription {
+        return parse_subscription(&registry, &subscription::fetch(url)?);
+    }
+    let mut links = Vec::new();
+    for link in &args.config {
+        if link == "-" {
+            links.extend(link_lines(&read_stdin()?));
+        } else {
+            links.push(link.clone());
+        }
+    }
+    if let Some(path) = &args.links_file {
+        let content = if path == Path::new("-") {
+            read_stdin()?
+        } else {
+            fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        };
+        links.extend(link_lines(&content));
+    }
+    for path in &args.wireguard {
+        let content = fs::read_to_string(path).map_err(|e| PawprintError::file(path, e))?;
+        let tag = path
+            .file_stem()
+            .map_or("WireGuard".into(), |stem| stem.to_string_lossy());
+        links.push(WireguardConfig::from_conf(&content, &tag)?.to_link());
+    }
+    if links.is_empty() {
+        return Err("No share links given".into());
+    }
+    parse_nodes(&registry, &links)
+}
+
+fn build_options(
+    args: &BuildArgs,
+    env_subst: bool,
+) -> Result<BuildOptions, Box<dyn std::error::Error>> {
+    let mut rules = match &args.rules {
+        Some(path) => RoutingRules::load(path, env_subst)?,
+        None => RoutingRules::default(),
+    };
+    rules.extend(RoutingRules {
+        direct: args.direct.clone(),
+        block: args.block.clone(),
+        proxy: args.proxy.clone(),
+    });
+    let inbound = |protocol, port| InboundSpec {
+        protocol,
+        listen: args.inbounds.listen.clone(),
         port,
-        params,
-        tag,
+        tag: None,
+        username: args.inbounds.auth.as_ref().map(|(user, _)| user.clone()),
+        password: args.inbounds.auth.as_ref().map(|(_, pass)| pass.clone()),
+        outbound: None,
+    };
+    let mut inbounds = vec![inbound(InboundProtocol::Socks, args.inbounds.socks_port)];
+    if let Some(port) = args.inbounds.http_port {
+        inbounds.push(inbound(InboundProtocol::Http, port));
+    }
+
+    let tun = args.tun.tun.then(|| {
+        let mut tun = TunSpec::default();
+        if let Some(name) = &args.tun.tun_name {
+            tun.name = name.clone();
+        }
+        if let Some(mtu) = args.tun.tun_mtu {
+            tun.mtu = mtu;
+        }
+        if !args.tun.tun_address.is_empty() {
+            tun.address = args.tun.tun_address.clone();
+        }
+        tun.auto_route = !args.tun.no_auto_route;
+        tun.stack = args.tun.tun_stack;
+        tun
+    });
+
+    let mux = args.tuning.mux.then(|| {
+        let mut mux = MuxSpec::default();
+        if let Some(concurrency) = args.tuning.mux_concurrency {
+            mux.concurrency = concurrency;
+        }
+        mux.xudp_concurrency = args.tuning.xudp_concurrency;
+        mux
+    });
+    let fragment = args.tuning.fragment.then(|| {
+        let mut fragment = FragmentSpec::default();
+        if let Some(packets) = &args.tuning.fragment_packets {
+            fragment.packets = packets.clone();
+        }
+        if let Some(length) = &args.tuning.fragment_length {
+            fragment.length = length.clone();
+        }
+        if let Some(interval) = &args.tuning.fragment_interval {
+            fragment.interval = interval.clone();
+        }
+        fragment
+    });
+
+    Ok(BuildOptions {
+        target: args.target,
+        inbounds,
+        tun,
+        dns_servers: args.dns_servers.clone(),
+        dns_routes: args.dns_routes.clone(),
+        rules,
+        balance: args.balance,
+        chain: args.chain,
+        mux,
+        fragment,
+        noises: args.tuning.noises.clone(),
+        sniffing: args.inbounds.sniff.then(|| SniffingSpec {
+            protocols: args.inbounds.sniff_protocols.clone(),
+            route_only: args.inbounds.sniff_route_only,
+        }),
+        sockopt: sockopt_spec(&args.tuning),
+        stats: args.stats.then_some(StatsSpec {
+            port: args.stats_port,
+        }),
+        log: log_spec(&args.log),
     })
 }
 
-fn build_config(vless_config: &VlessConfig) -> XrayConfig {
-    let network_type = vless_config
-        .params
-        .get("type")
-        .cloned()
-        .unwrap_or_else(|| "tcp".to_string());
+fn sockopt_spec(args: &TuningArgs) -> Option<SockoptSpec> {
+    let spec = SockoptSpec {
+        tcp_fast_open: args.tcp_fast_open,
+        mark: args.mark,
+        domain_strategy: args.domain_strategy,
+        tcp_keep_alive_idle: args.tcp_keep_alive_idle,
+    };
+    (spec.tcp_fast_open
+        || spec.mark.is_some()
+        || spec.domain_strategy.is_some()
+        || spec.tcp_keep_alive_idle.is_some())
+    .then_some(spec)
+}
 
-    let security = vless_config
-        .params
-        .get("security")
-        .cloned()
-        .unwrap_or_else(|| "tls".to_string());
+fn log_spec(args: &LogArgs) -> Option<LogSpec> {
+    if args.log_level.is_none()
+        && args.access_log.is_none()
+        && args.error_log.is_none()
+        && !args.dns_log
+    {
+        return None;
+    }
+    Some(LogSpec {
+        level: args.log_level.unwrap_or_default(),
+        access: args.access_log.clone(),
+        error: args.error_log.clone(),
+        dns: args.dns_log,
+    })
+}
+
+fn generate_from_args(
+    args: GenerateArgs,
+    env_subst: bool,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let nodes = load_nodes(&args)?;
+    generate(
+        &nodes,
+        &build_options(&args.build, env_subst)?,
+        &args.build.transforms,
+        env_subst,
+    )
+}
+
+fn update_config(args: UpdateArgs, env_subst: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let UpdateArgs {
+        file,
+        links,
+        tag,
+        prune,
+        dry_run,
+        mut generate,
+    } = args;
+    generate.config.extend(links);
+    let content = fs::read_to_string(&file).map_err(|e| PawprintError::file(&file, e))?;
+    let mut config =
+        jsonc::parse(&content).map_err(|e| format!("Invalid JSON in {}: {}", file.display(), e))?;
+    if serde_json::from_str::<serde_json::Value>(&content).is_err() {
+        warn!("Comments in {} are not kept", file.display());
+    }
+    let target = generate.build.target;
+    if update::is_xray(&config) != matches!(target, CoreTarget::Xray(_)) {
+        return Err(format!(
+            "{} is not a config for {}; pass its core with --target",
+            file.display(),
+            target
+        )
+        .into());
+    }
+    if generate.build.balance || generate.build.chain || generate.build.tuning.fragment {
+        warn!("The config's own routing is kept; --balance, --chain and --fragment are ignored");
+    }
+
+    let nodes = load_nodes(&generate)?;
+    let mut options = build_options(&generate.build, env_subst)?;
+    options.balance = false;
+    options.chain = false;
+    options.fragment = None;
+    options.noises.clear();
+    // The server outbounds come first, in the order of the nodes.
+    let built = backend::for_target(&target).build(&nodes, &options)?;
+    let fresh: Vec<serde_json::Value> = built["outbounds"]
+        .as_array()
+        .map(|outbounds| outbounds.iter().take(nodes.len()).cloned().collect())
+        .unwrap_or_default();
+
+    let changes = update::apply(&mut config, fresh, tag.as_deref(), prune)?;
+    for name in &changes.replaced {
+        info!("Updated {}", name);
+    }
+    for name in &changes.added {
+        info!("Added {}", name);
+    }
+    for name in &changes.removed {
+        info!("Removed {}", name);
+    }
+    for name in &changes.kept {
+        warn!(
+            "Keeping {}: the links no longer have it, but the config still uses it",
+            name
+        );
+    }
+    if changes.replaced.is_empty() && changes.added.is_empty() && changes.removed.is_empty() {
+        info!("✓ {} is up to date", file.display());
+        return Ok(());
+    }
+
+    let problems = validate::check(&config);
+    if !problems.is_empty() {
+        for problem in &problems {
+            warn!("{}", problem);
+        }
+        return Err(format!(
+            "Updated config has {} problem(s), not saving it",
+            problems.len()
+        )
+        .into());
+    }
+    if dry_run {
+        println!("{}", serde_json::to_string_pretty(&config)?);
+        return Ok(());
+    }
+    write_file(&file, serde_json::to_string_pretty(&config)?, true)?;
+    info!("✓ Config updated: {}", file.display());
+    Ok(())
+}
+
+fn diff_config(args: cli::DiffArgs, env_subst: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let cli::DiffArgs {
+        file,
+        links,
+        mut generate,
+    } = args;
+    generate.config.extend(links);
+    let content = fs::read_to_string(&file).map_err(|e| PawprintError::file(&file, e))?;
+    let existing =
+        jsonc::parse(&content).map_err(|e| format!("Invalid JSON in {}: {}", file.display(), e))?;
+    let config = generate_from_args(generate, env_subst)?;
+    let changes = diff::diff(&existing, &config);
+    if changes.is_empty() {
+        info!("✓ {} matches the generated config", file.display());
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    info!("{} change(s) to {}", changes.len(), file.display());
+    Ok(())
+}
+
+// Tag reduced to characters that are safe in a file name.
+fn file_stem(tag: &str) -> String {
+    let stem: String = tag
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let stem = stem.trim_matches(['_', '.']);
+    if stem.is_empty() {
+        "node".to_string()
+    } else {
+        stem.to_string()
+    }
+}
+
+// File name for a node's config, derived from its tag.
+fn config_file_name(tag: &str, taken: &[String]) -> String {
+    let stem = file_stem(tag);
+    let mut name = format!("{}.json", stem);
+    let mut suffix = 2;
+    while taken.contains(&name) {
+        name = format!("{}-{}.json", stem, suffix);
+        suffix += 1;
+    }
+    name
+}
+
+fn convert(
+    args: GenerateArgs,
+    output: &OutputArgs,
+    env_subst: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if output.per_server && args.build.balance {
+        return Err(
+            "--balance puts every server in one config and cannot be used with --per-server".into(),
+        );
+    }
+    if output.per_server && args.build.chain {
+        return Err(
+            "--chain puts every server in one config and cannot be used with --per-server".into(),
+        );
+    }
+    let path = if output.dry_run {
+        Path::new("-")
+    } else {
+        output.output.as_path()
+    };
+    if !output.per_server {
+        let config = generate_from_args(args, env_subst)?;
+        info!("Saving configuration...");
+        return save_config(&config, path, output.force);
+    }
 
-    let mut stream_settings = json!({
-        "network": network_type,
-        "security": security,
+    let nodes = load_nodes(&args)?;
+    let options = build_options(&args.build, env_subst)?;
+    let workers = output
+        .batch
+        .workers
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, usize::from));
+    info!("Building {} configs...", nodes.len());
+    let configs = batch::run(&nodes, workers, Some("Building"), |node| {
+        generate(
+            std::slice::from_ref(node),
+            &options,
+            &args.build.transforms,
+            env_subst,
+        )
+        .map_err(|e| PawprintError::from(e.to_string()))
     });
 
-    if security == "reality" {
-        let pbk = vless_config.params.get("pbk").cloned().unwrap_or_default();
-        let sni = vless_config.params.get("sni").cloned().unwrap_or_default();
-        let fp = vless_config
-            .params
-            .get("fp")
-            .cloned()
-            .unwrap_or_else(|| "chrome".to_string());
-        let sid = vless_config.params.get("sid").cloned().unwrap_or_default();
-
-        stream_settings["realitySettings"] = json!({
-            "publicKey": pbk,
-            "password": pbk,
-            "fingerprint": fp,
-            "serverName": sni,
-            "shortId": sid,
-            "spiderX": "/"
+    // Written one by one, so file names stay unique and NDJSON lines whole.
+    let stdout = path == Path::new("-");
+    let mut written = Vec::new();
+    let mut entries = Vec::new();
+    for (node, config) in nodes.iter().zip(configs) {
+        let result = config.and_then(|config| {
+            // On stdout the configs form an NDJSON stream, one per line.
+            if stdout {
+                println!("{}", serde_json::to_string(&config)?);
+                return Ok(serde_json::Value::Null);
+            }
+            let name = config_file_name(node.tag(), &written);
+            let file = path.join(&name);
+            save_config(&config, &file, output.force)
+                .map_err(|e| PawprintError::from(e.to_string()))?;
+            written.push(name);
+            Ok(serde_json::json!({ "file": file }))
         });
-    } else if security == "tls" {
-        let sni = vless_config
-            .params
-            .get("sni")
+        if let Err(e) = &result {
+            warn!("Skipping {}: {}", node.tag(), e);
+        }
+        let details = result.as_ref().cloned().unwrap_or_default();
+        entries.push(batch::Entry::new(node, &result, details));
+    }
+    let report = batch::Report::new(entries);
+    if let Some(file) = &output.batch.report {
+        report.save(file)?;
+        info!("✓ Report saved to: {}", file.display());
+    }
+    if !stdout {
+        info!(
+            "✓ Wrote {} configs to {}",
+            written.len(),
+            output.output.display()
+        );
+    }
+    if report.failed > 0 {
+        return Err(format!("{} of {} servers failed", report.failed, report.total).into());
+    }
+    Ok(())
+}
+
+// Deployment files and real delay tests run xray.
+fn check_xray_target(target: &CoreTarget) -> Result<(), Box<dyn std::error::Error>> {
+    if let CoreTarget::SingBox(_) = target {
+        return Err(format!("This command runs xray and cannot use target {}", target).into());
+    }
+    Ok(())
+}
+
+fn export_docker(args: DockerArgs, env_subst: bool) -> Result<(), Box<dyn std::error::Error>> {
+    check_xray_target(&args.generate.build.target)?;
+    let mut config = generate_from_args(args.generate, env_subst)?;
+    let compose = export::docker_compose(&mut config, &args.image);
+
+    info!("Saving deployment...");
+    save_config(&config, &args.dir.join(export::CONFIG_FILE), args.force)?;
+    let compose_path = args.dir.join("docker-compose.yml");
+    write_file(&compose_path, &serde_yaml::to_string(&compose)?, args.force)?;
+    info!("✓ Compose file saved to: {}", compose_path.display());
+    Ok(())
+}
+
+fn export_clash(args: ClashArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let registry = Registry::with_plugins(args.plugins_dir.as_deref())?;
+    let mut nodes = parse_nodes(&registry, &args.links)?;
+    if let Some(url) = &args.subscription {
+        nodes.extend(parse_subscription(&registry, &subscription::fetch(url)?)?);
+    }
+
+    let group = args.group.as_deref().map(|name| (name, args.group_type));
+    let yaml = serde_yaml::to_string(&clash::build_document(&nodes, group)?)?;
+    match &args.output {
+        Some(path) => {
+            write_file(path, &yaml, args.force)?;
+            info!("✓ Clash proxies saved to: {}", path.display());
+        }
+        None => print!("{}", yaml),
+    }
+    Ok(())
+}
+
+fn show_qr(link: &str, args: &QrArgs, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if args.qr {
+        println!("{}", qr::terminal(link)?);
+    }
+    if let Some(path) = &args.qr_png {
+        write_file(path, qr::png(link)?, force)?;
+        info!("✓ QR code saved to: {}", path.display());
+    }
+    Ok(())
+}
+
+fn export_link(
+    config: &Path,
+    tag: Option<&str>,
+    qr: &QrArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(config)
+        .map_err(|e| format!("Failed to read {}: {}", config.display(), e))?;
+    let value = jsonc::parse(&content)
+        .map_err(|e| format!("Invalid JSON in {}: {}", config.display(), e))?;
+    let outbounds = match &value {
+        serde_json::Value::Array(outbounds) => outbounds.clone(),
+        value if value.get("outbounds").is_some() => value["outbounds"]
+            .as_array()
             .cloned()
-            .unwrap_or_else(|| vless_config.address.clone());
+            .ok_or("`outbounds` is not a list")?,
+        value => vec![value.clone()],
+    };
 
-        stream_settings["tlsSettings"] = json!({
-            "serverName": sni,
-            "allowInsecure": false
-        });
+    let mut links = Vec::new();
+    for outbound in &outbounds {
+        if let Some(tag) = tag
+            && outbound["tag"] != tag
+        {
+            continue;
+        }
+        match xray::parse_outbound(outbound) {
+            Ok(Some(node)) => links.push(node.to_link()?),
+            Ok(None) => {}
+            Err(e) => warn!("Skipping {}", e),
+        }
+    }
+    match (links.len(), tag) {
+        (0, Some(tag)) => return Err(format!("No proxy outbound tagged {}", tag).into()),
+        (0, None) => return Err(format!("No proxy outbounds in {}", config.display()).into()),
+        (1, _) => {}
+        _ if qr.qr_png.is_some() => {
+            return Err("--qr-png takes a single link; pick an outbound with --tag".into());
+        }
+        _ => {}
     }
+    for link in &links {
+        println!("{}", link);
+        show_qr(link, qr, false)?;
+    }
+    Ok(())
+}
 
-    let mut user = json!({
-        "id": vless_config.uuid,
-        "encryption": "none",
-        "level": 0
-    });
+fn validate_configs(configs: &[PathBuf]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut failed = 0;
+    for path in configs {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let config = jsonc::parse(&content)
+            .map_err(|e| format!("Invalid JSON in {}: {}", path.display(), e))?;
+        let problems = validate::check(&config);
+        if problems.is_empty() {
+            info!("✓ {} is valid", path.display());
+            continue;
+        }
+        failed += 1;
+        for problem in &problems {
+            println!("{}: {}", path.display(), problem);
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} configs have problems", failed, configs.len()).into());
+    }
+    Ok(())
+}
+
+fn server_config(args: ServerArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let options = server::ServerOptions {
+        protocol: args.protocol,
+        address: args.address,
+        port: args.port,
+        tag: args.tag,
+        secret: args.secret,
+        dest: args.dest,
+        server_names: args.server_names,
+        private_key: args.private_key,
+        certificate: args.cert,
+        key: args.key,
+        ws_path: args.ws_path,
+        fallback: args.fallback,
+    };
+    let (config, node) = server::build(&options)?;
+    save_config(&config, &args.output, args.force)?;
 
-    let flow = vless_config.params.get("flow").cloned();
+    let link = node.to_link()?;
+    info!("Client share link:");
+    println!("{}", link);
+    show_qr(&link, &args.qr, args.force)
+}
+
+fn load_server_config(path: &Path) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path).map_err(|e| PawprintError::file(path, e))?;
+    Ok(jsonc::parse(&content).map_err(|e| format!("Invalid JSON in {}: {}", path.display(), e))?)
+}
 
-    if let Some(flow_val) = flow {
-        user["flow"] = json!(flow_val);
+fn print_user_link(
+    config: &serde_json::Value,
+    name: &str,
+    link: &cli::UserLinkArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tag = link.tag.as_deref().unwrap_or(name);
+    let link_text = server::client_node(config, Some(name), &link.address, tag)?.to_link()?;
+    info!("Share link of {}:", name);
+    println!("{}", link_text);
+    show_qr(&link_text, &link.qr, false)
+}
+
+fn user_command(action: UserAction) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        UserAction::Add {
+            name,
+            secret,
+            server,
+            link,
+        } => {
+            let mut config = load_server_config(&server.config)?;
+            server::add_user(&mut config, &name, secret)?;
+            save_config(&config, &server.config, true)?;
+            info!("✓ User {} added; restart xray on the server to apply", name);
+            print_user_link(&config, &name, &link)
+        }
+        UserAction::Remove { name, server } => {
+            let mut config = load_server_config(&server.config)?;
+            if !server::remove_user(&mut config, &name)? {
+                return Err(format!("No such user: {}", name).into());
+            }
+            save_config(&config, &server.config, true)?;
+            info!(
+                "✓ User {} removed; restart xray on the server to apply",
+                name
+            );
+            Ok(())
+        }
+        UserAction::List { server } => {
+            let config = load_server_config(&server.config)?;
+            for user in server::users(&config)? {
+                println!(
+                    "{:<20} {}",
+                    user.name.as_deref().unwrap_or("-"),
+                    user.secret
+                );
+            }
+            Ok(())
+        }
+        UserAction::Link { name, server, link } => {
+            print_user_link(&load_server_config(&server.config)?, &name, &link)
+        }
     }
+}
 
-    let outbound = json!({
-        "protocol": "vless",
-        "settings": {
-            "vnext": [{
-                "address": vless_config.address,
-                "port": vless_config.port,
-                "users": [user]
-            }]
-        },
-        "streamSettings": stream_settings,
-        "tag": vless_config.tag
-    });
+fn reality_keygen(
+    private_key: Option<&str>,
+    short_ids: usize,
+    short_id_bytes: usize,
+    sni: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let keys = match private_key {
+        Some(private_key) => keygen::from_private_key(private_key)?,
+        None => keygen::generate()?,
+    };
+    let short_ids = (0..short_ids.max(1))
+        .map(|_| keygen::short_id(short_id_bytes))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let inbound = json!({
-        "port": 10808,
-        "protocol": "socks",
-        "settings": {
-            "auth": "noauth",
-            "udp": true
-        },
-        "tag": "socks-in"
-    });
+    println!("Private key: {}", keys.private_key);
+    println!("Public key:  {}", keys.public_key);
+    println!();
+    println!("Server realitySettings:");
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&serde_json::json!({
+            "privateKey": keys.private_key,
+            "shortIds": short_ids,
+        }))?
+    );
+    println!();
+    println!("Client link parameters:");
+    println!("{}", keygen::client_params(&keys, &short_ids[0], sni));
+    Ok(())
+}
+
+fn export_k8s(args: K8sArgs, env_subst: bool) -> Result<(), Box<dyn std::error::Error>> {
+    check_xray_target(&args.generate.build.target)?;
+    let mut config = generate_from_args(args.generate, env_subst)?;
+    export::bind_loopback(&mut config);
+    let config_map = export::config_map(&config, &args.name, args.namespace.as_deref())?;
+    let sidecar = export::sidecar(&config, &args.name, &args.image);
+
+    info!("Saving manifests...");
+    let config_map_path = args.dir.join("configmap.yaml");
+    write_file(
+        &config_map_path,
+        &serde_yaml::to_string(&config_map)?,
+        args.force,
+    )?;
+    info!("✓ ConfigMap saved to: {}", config_map_path.display());
+
+    let sidecar_path = args.dir.join("sidecar.yaml");
+    let snippet = format!(
+        "# Merge into the pod template spec of the workload that should egress through xray.\n{}",
+        serde_yaml::to_string(&sidecar)?
+    );
+    write_file(&sidecar_path, &snippet, args.force)?;
+    info!("✓ Sidecar snippet saved to: {}", sidecar_path.display());
+    Ok(())
+}
+
+fn test_route(
+    node: &str,
+    max_hops: u32,
+    plugins_dir: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let host = if node.contains("://") {
+        Registry::with_plugins(plugins_dir)?
+            .parse(node)?
+            .address()
+            .to_string()
+    } else {
+        node.to_string()
+    };
+    let target = traceroute::resolve(&host)?;
+    info!("Tracing route to {} ({})...", host, target);
+
+    let hops = traceroute::trace(target, max_hops)?;
+    let notes = traceroute::annotate(&hops, target);
 
-    XrayConfig {
-        inbounds: vec![inbound],
-        outbounds: vec![outbound],
+    println!(
+        "{:>3}  {:<40} {:>9} {:>6}  notes",
+        "hop", "host", "avg", "loss"
+    );
+    for (hop, notes) in hops.iter().zip(&notes) {
+        let host = hop
+            .host
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "*".to_string());
+        let avg = hop
+            .avg_ms
+            .map(|ms| format!("{:.1} ms", ms))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:>3}  {:<40} {:>9} {:>5.0}%  {}",
+            hop.index,
+            host,
+            avg,
+            hop.loss * 100.0,
+            notes.join(", ")
+        );
     }
+
+    // Loss that begins at one hop and persists to the end points at that hop;
+    // loss on a single intermediate hop is usually just ICMP rate limiting.
+    let last_clean = hops
+        .iter()
+        .rposition(|hop| hop.host.is_some() && hop.loss == 0.0);
+    let lossy_from = hops
+        .iter()
+        .skip(last_clean.map_or(0, |i| i + 1))
+        .find(|hop| hop.host.is_some());
+    if let Some(hop) = lossy_from {
+        warn!(
+            "Packet loss starts at hop {} and continues to the end of the path",
+            hop.index
+        );
+    }
+
+    match hops.last() {
+        Some(last) if last.host == Some(target) => {
+            info!("✓ Server reached in {} hops", last.index)
+        }
+        _ => warn!("Server did not answer; it may drop ICMP/UDP probes, or the path is blocked"),
+    }
+    Ok(())
 }
 
-fn save_config(
-    config: &XrayConfig,
-    output_path: &PathBuf,
+// Arguments that are not share links name stored profiles, matched loosely,
+// and stand for the profile's share links.
+fn expand_profiles(links: &[String]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut expanded = Vec::new();
+    for link in links {
+        if link.contains("://") {
+            expanded.push(link.clone());
+            continue;
+        }
+        let name = profile::resolve(link)?;
+        let spec = profile::load_raw(&name)?;
+        if spec.nodes.is_empty() {
+            return Err(format!(
+                "Profile {} has no share links; test its subscription with --subscription",
+                name
+            )
+            .into());
+        }
+        info!("Testing profile {}", name);
+        expanded.extend(spec.nodes);
+    }
+    Ok(expanded)
+}
+
+fn test_latency(args: LatencyArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let registry = Registry::with_plugins(args.plugins_dir.as_deref())?;
+    let mut nodes = parse_nodes(&registry, &expand_profiles(&args.links)?)?;
+    if let Some(url) = &args.subscription {
+        nodes.extend(parse_subscription(&registry, &subscription::fetch(url)?)?);
+    }
+
+    let timeout = Duration::from_secs(args.timeout);
+    if args.speed {
+        check_xray_target(&args.target)?;
+        return test_speed(&args, &nodes, timeout);
+    }
+    let samples = if args.real {
+        check_xray_target(&args.target)?;
+        info!(
+            "Measuring real delay of {} servers via {}...",
+            nodes.len(),
+            args.url
+        );
+        let real = latency::RealDelay {
+            xray: &args.xray,
+            url: &args.url,
+            target: args.target,
+        };
+        let workers = args.batch.workers.unwrap_or(latency::REAL_WORKERS);
+        batch::run(&nodes, workers, Some("Testing"), |node| {
+            let result = latency::real_delay(node, &real, timeout).map_err(|e| e.to_string());
+            Ok(vec![batch::Sample::new(result)])
+        })
+    } else {
+        info!("Measuring TCP latency of {} servers...", nodes.len());
+        let workers = args.batch.workers.unwrap_or(latency::TCP_WORKERS);
+        batch::run(&nodes, workers, Some("Testing"), |node| {
+            latency::tcp_attempts(node.address(), node.port(), args.attempts, timeout)
+        })
+    };
+    let results: Vec<Result<Duration, PawprintError>> = samples
+        .iter()
+        .map(|samples| match samples {
+            Ok(samples) => latency::fastest(samples),
+            Err(e) => Err(e.to_string().into()),
+        })
+        .collect();
+    if let Some(file) = &args.batch.report {
+        let entries = nodes
+            .iter()
+            .zip(&results)
+            .zip(samples)
+            .map(|((node, result), samples)| {
+                let details = match result {
+                    Ok(delay) => serde_json::json!({ "latency_ms": delay.as_millis() }),
+                    Err(_) => serde_json::Value::Null,
+                };
+                batch::Entry::new(node, result, details).with_samples(samples.unwrap_or_default())
+            })
+            .collect();
+        batch::Report::new(entries).save(file)?;
+        info!("✓ Report saved to: {}", file.display());
+    }
+
+    // Fastest first, failures last.
+    let mut rows: Vec<_> = nodes.iter().zip(results).collect();
+    rows.sort_by_key(|(_, result)| result.as_ref().map_or(Duration::MAX, |d| *d));
+
+    println!("{:>8}  {:<10} {:<30} server", "latency", "protocol", "tag");
+    for (node, result) in rows {
+        let server = format!("{}:{}", node.address(), node.port());
+        match result {
+            Ok(delay) => println!(
+                "{:>5} ms  {:<10} {:<30} {}",
+                delay.as_millis(),
+                node.protocol(),
+                node.tag(),
+                server
+            ),
+            Err(e) => println!(
+                "{:>8}  {:<10} {:<30} {} ({})",
+                "failed",
+                node.protocol(),
+                node.tag(),
+                server,
+                e
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn test_speed(
+    args: &LatencyArgs,
+    nodes: &[Node],
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let real = latency::RealDelay {
+        xray: &args.xray,
+        url: &args.url,
+        target: args.target,
+    };
+    let speed = latency::SpeedTest {
+        download_url: &args.download_url,
+        upload_url: (!args.no_upload).then_some(args.upload_url.as_str()),
+        upload_bytes: args.upload_bytes,
+        duration: Duration::from_secs(args.speed_time.max(1)),
+    };
+    let mut rows = Vec::new();
+    for (index, node) in nodes.iter().enumerate() {
+        info!(
+            "Testing speed of {} ({} of {})...",
+            node.tag(),
+            index + 1,
+            nodes.len()
+        );
+        rows.push((node, latency::speed_test(node, &real, &speed, timeout)));
+    }
+    if let Some(file) = &args.batch.report {
+        let entries = rows
+            .iter()
+            .map(|(node, result)| {
+                let details = match result {
+                    Ok(speed) => serde_json::json!({
+                        "latency_ms": speed.delay.as_millis(),
+                        "download_mbps": speed.download,
+                        "upload_mbps": speed.upload,
+                    }),
+                    Err(_) => serde_json::Value::Null,
+                };
+                batch::Entry::new(node, result, details)
+            })
+            .collect();
+        batch::Report::new(entries).save(file)?;
+        info!("✓ Report saved to: {}", file.display());
+    }
+
+    // Fastest download first, failures last.
+    rows.sort_by(|(_, a), (_, b)| {
+        let download =
+            |r: &Result<latency::Speed, PawprintError>| r.as_ref().map_or(-1.0, |s| s.download);
+        download(b).total_cmp(&download(a))
+    });
+    let mbps = |value: Option<f64>| match value {
+        Some(value) => format!("{:.1} Mbps", value),
+        None => "-".to_string(),
+    };
+    println!(
+        "{:>8}  {:>11} {:>11}  {:<10} {:<30} server",
+        "latency", "download", "upload", "protocol", "tag"
+    );
+    for (node, result) in rows {
+        let server = format!("{}:{}", node.address(), node.port());
+        match result {
+            Ok(speed) => println!(
+                "{:>5} ms  {:>11} {:>11}  {:<10} {:<30} {}",
+                speed.delay.as_millis(),
+                mbps(Some(speed.download)),
+                mbps(speed.upload),
+                node.protocol(),
+                node.tag(),
+                server
+            ),
+            Err(e) => println!(
+                "{:>8}  {:>11} {:>11}  {:<10} {:<30} {} ({})",
+                "failed",
+                "-",
+                "-",
+                node.protocol(),
+                node.tag(),
+                server,
+                e
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn init(
+    dir: &Path,
     force: bool,
+    plugins_dir: Option<&Path>,
+    env_subst: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if output_path.exists() && !force {
+    let spec_path = dir.join("pawprint.toml");
+    if spec_path.exists() && !force {
         return Err(format!(
             "File already exists: {}. Use --force to overwrite.",
-            output_path.display()
+            spec_path.display()
         )
         .into());
     }
 
-    let json_content = serde_json::to_string_pretty(config)?;
+    let registry = Registry::with_plugins(plugins_dir)?;
+    let mut spec = init::wizard(&registry, PathBuf::from("config.json"))?;
+    spec.plugins_dir = plugins_dir.map(Path::to_path_buf);
 
-    if let Some(parent) = output_path.parent() {
-        if !parent.as_os_str().is_empty() {
-            fs::create_dir_all(parent)?;
+    write_file(&spec_path, &toml::to_string(&spec)?, force)?;
+    info!("✓ Spec saved to: {}", spec_path.display());
+
+    apply(&spec_path, force, false, env_subst)
+}
+
+fn geo_update(dir: &Path, source: &str) -> Result<(), Box<dyn std::error::Error>> {
+    for file in geo::update(dir, source)? {
+        if file.downloaded {
+            info!(
+                "✓ {} updated ({}, sha256 {})",
+                file.name,
+                stats::human_bytes(file.size),
+                file.sha256
+            );
+        } else {
+            info!("✓ {} is up to date", file.name);
         }
     }
+    if dir != geo::default_dir() {
+        info!(
+            "Set XRAY_LOCATION_ASSET={} so xray loads these files",
+            dir.display()
+        );
+    }
+    Ok(())
+}
 
-    let temp_path = PathBuf::from(format!("{}.tmp", output_path.display()));
-    fs::write(&temp_path, &json_content)?;
-    fs::rename(&temp_path, output_path)?;
+fn core_command(action: CoreAction) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        CoreAction::Install {
+            version,
+            force,
+            dir,
+            source,
+        } => {
+            match xraycore::install(&dir, &source, version.as_deref(), force)? {
+                Some(installed) => info!("✓ xray {} installed in {}", installed, dir.display()),
+                None => info!(
+                    "✓ xray {} is already installed",
+                    xraycore::State::load(&dir)?.active.unwrap_or_default()
+                ),
+            }
+            xraycore::set_pinned(&dir, version.is_some())?;
+            if version.is_some() {
+                info!("Pinned; `core install` without a version lets `core update` move it again");
+            }
+            Ok(())
+        }
+        CoreAction::Update { dir, source } => {
+            let state = xraycore::State::load(&dir)?;
+            if state.pinned {
+                info!(
+                    "xray {} is pinned, not updating",
+                    state.active.unwrap_or_default()
+                );
+                return Ok(());
+            }
+            match xraycore::install(&dir, &source, None, false)? {
+                Some(installed) => info!("✓ xray updated to {}", installed),
+                None => info!("✓ xray {} is up to date", state.active.unwrap_or_default()),
+            }
+            Ok(())
+        }
+        CoreAction::Setcap { xray } => {
+            let binary = service::resolve(&xraycore::program(&xray).to_string_lossy());
+            Ok(elevate::grant_net_admin(Path::new(&binary))?)
+        }
+        CoreAction::List { dir } => {
+            let state = xraycore::State::load(&dir)?;
+            let versions = xraycore::installed(&dir)?;
+            if versions.is_empty() {
+                info!("No xray installed yet; run `pawprint-vpn core install`");
+                return Ok(());
+            }
+            for version in versions {
+                let active = state.active.as_deref() == Some(version.as_str());
+                println!(
+                    "{} {}{}",
+                    if active { "*" } else { " " },
+                    version,
+                    if active && state.pinned {
+                        " (pinned)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+fn service_command(action: ServiceAction) -> Result<(), Box<dyn std::error::Error>> {
+    let unit_of = |args: &UnitArgs| service::Unit::new(&args.name, args.system);
+    match action {
+        ServiceAction::Install {
+            unit,
+            mode,
+            profile,
+            xray,
+            failover,
+            kill_switch,
+            tun_routes,
+            tun_dns,
+            system_proxy,
+            print,
+            force,
+        } => {
+            let unit = unit_of(&unit)?;
+            let options = service::ServiceOptions {
+                mode,
+                profile,
+                xray: service::resolve(&xray),
+                failover,
+                kill_switch,
+                tun_routes,
+                tun_dns,
+                system_proxy,
+            };
+            if !options.xray.starts_with('/') {
+                warn!("{} is not in PATH, the unit may not find it", options.xray);
+            }
+            let content = service::unit_file(&unit, &std::env::current_exe()?, &options)?;
+            if print {
+                print!("{}", content);
+                return Ok(());
+            }
+            let path = service::install(&unit, &content, force)?;
+            info!("✓ Unit saved to: {}", path.display());
+            let user = if unit.system { "" } else { " --user" };
+            info!(
+                "Start it with `systemctl{} start {}`, or `service enable` to start it at {} as well",
+                user,
+                unit.file_name(),
+                if unit.system { "boot" } else { "login" }
+            );
+            Ok(())
+        }
+        ServiceAction::Uninstall { unit } => {
+            let unit = unit_of(&unit)?;
+            if service::uninstall(&unit)? {
+                info!("✓ {} removed", unit.file_name());
+            } else {
+                info!("{} is not installed", unit.file_name());
+            }
+            Ok(())
+        }
+        ServiceAction::Enable { unit } => {
+            let unit = unit_of(&unit)?;
+            unit.systemctl("enable", true)?;
+            unit.systemctl("start", true)?;
+            info!("✓ {} enabled and started", unit.file_name());
+            Ok(())
+        }
+        ServiceAction::Disable { unit } => {
+            let unit = unit_of(&unit)?;
+            unit.systemctl("disable", true)?;
+            unit.systemctl("stop", true)?;
+            info!("✓ {} disabled and stopped", unit.file_name());
+            Ok(())
+        }
+        ServiceAction::Start { unit } => {
+            let unit = unit_of(&unit)?;
+            unit.systemctl("start", true)?;
+            info!("✓ {} started", unit.file_name());
+            Ok(())
+        }
+        ServiceAction::Stop { unit } => {
+            let unit = unit_of(&unit)?;
+            unit.systemctl("stop", true)?;
+            info!("✓ {} stopped", unit.file_name());
+            Ok(())
+        }
+    }
+}
+
+fn integrate_command(target: IntegrateTarget) -> Result<(), Box<dyn std::error::Error>> {
+    match target {
+        IntegrateTarget::Networkmanager {
+            unit,
+            system,
+            pid_file,
+            print,
+            force,
+            remove,
+        } => {
+            if remove {
+                if networkmanager::uninstall()? {
+                    info!("✓ {} removed", networkmanager::DISPATCHER);
+                } else {
+                    info!("{} is not installed", networkmanager::DISPATCHER);
+                }
+                return Ok(());
+            }
+            let restart = match unit {
+                Some(name) => networkmanager::Restart::Unit(service::Unit::new(&name, system)?),
+                // The script runs from another directory.
+                None => networkmanager::Restart::Core {
+                    pid_file: std::path::absolute(
+                        pid_file.unwrap_or_else(process::default_pid_file),
+                    )?,
+                },
+            };
+            let dispatcher =
+                networkmanager::Dispatcher::for_current_user(std::env::current_exe()?, restart)?;
+            let content = networkmanager::script(&dispatcher);
+            if print {
+                print!("{}", content);
+                return Ok(());
+            }
+            let path = networkmanager::install(&content, force)?;
+            info!(
+                "✓ Dispatcher script saved to: {}; the tunnel restarts when the network changes",
+                path.display()
+            );
+            Ok(())
+        }
+    }
+}
+
+// A profile spec generating the active config from a single share link.
+fn link_spec(
+    url: &str,
+    target: CoreTarget,
+    plugins_dir: Option<PathBuf>,
+) -> Result<Spec, Box<dyn std::error::Error>> {
+    Ok(Spec {
+        output: profile::active_config()?,
+        force: true,
+        target,
+        plugins_dir,
+        nodes: vec![url.to_string()],
+        subscriptions: Vec::new(),
+        filter: FilterSpec::default(),
+        balance: false,
+        chain: false,
+        noises: Vec::new(),
+        dns_servers: Vec::new(),
+        dns_routes: Vec::new(),
+        template: None,
+        merges: Vec::new(),
+        patches: Vec::new(),
+        json_patches: Vec::new(),
+        post_script: None,
+        jq: None,
+        routing: RoutingRules::default(),
+        tun: None,
+        mux: None,
+        fragment: None,
+        sniffing: None,
+        sockopt: None,
+        stats: None,
+        log: None,
+        inbounds: Vec::new(),
+    })
+}
+
+fn profile_add(
+    url: String,
+    name: Option<String>,
+    target: CoreTarget,
+    plugins_dir: Option<PathBuf>,
+    force: bool,
+    qr: &QrArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let node = Registry::with_plugins(plugins_dir.as_deref())?.parse(&url)?;
+    let name = name.unwrap_or_else(|| file_stem(node.tag()));
+    let spec = link_spec(&url, target, plugins_dir)?;
+    if profile::exists(&name)? && !force {
+        return Err(format!(
+            "Profile {} already exists. Use --force to replace it.",
+            name
+        )
+        .into());
+    }
+    profile::save(&name, &toml::to_string(&spec)?)?;
+    info!(
+        "✓ Profile {} saved ({} {}:{})",
+        name,
+        node.protocol(),
+        node.address(),
+        node.port()
+    );
+    show_qr(&url, qr, force)
+}
+
+// The share link among the QR codes of an image.
+fn qr_link(image: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let codes = qr::decode(image)?;
+    let mut links = codes.iter().filter(|code| code.contains("://"));
+    let link = links
+        .next()
+        .ok_or_else(|| format!("The QR code in {} is not a share link", image.display()))?;
+    if links.next().is_some() {
+        warn!(
+            "{} has several share links, using the first",
+            image.display()
+        );
+    }
+    Ok(link.clone())
+}
+
+// A link copied as text, or else a copied image of its QR code.
+fn clipboard_link() -> Result<String, Box<dyn std::error::Error>> {
+    let text = clipboard::text().unwrap_or_default();
+    if let Some(link) = text.split_whitespace().find(|word| word.contains("://")) {
+        return Ok(link.to_string());
+    }
+    let image =
+        clipboard::image().map_err(|e| format!("The clipboard holds no share link ({})", e))?;
+    let path = std::env::temp_dir().join(format!("pawprint-clipboard-{}.png", std::process::id()));
+    write_file(&path, &image, true)?;
+    let link = qr_link(&path);
+    let _ = fs::remove_file(&path);
+    link.map_err(|_| "The clipboard holds no share link or QR code of one".into())
+}
+
+// The country of a profile's server: from its tag, else the profile name.
+fn profile_country(name: &str, node: Option<&Node>) -> Option<&'static str> {
+    node.and_then(|node| filter::country(node.tag()))
+        .or_else(|| filter::country(name))
+}
+
+// What a profile sorts by under `sort`, lowest first. Sorting is stable and
+// names come sorted, so ties stay in name order.
+fn profile_sort_key(
+    sort: profile::Sort,
+    name: &str,
+    spec: Option<&Spec>,
+    node: Option<&Node>,
+    latency: Option<Option<u64>>,
+) -> (u8, u64, String) {
+    match sort {
+        profile::Sort::Name => (0, 0, name.to_string()),
+        profile::Sort::Latency => match latency {
+            Some(Some(ms)) => (0, ms, String::new()),
+            Some(None) => (1, 0, String::new()),
+            None => (2, 0, String::new()),
+        },
+        profile::Sort::Country => match profile_country(name, node) {
+            Some(code) => (0, 0, code.to_string()),
+            None => (1, 0, String::new()),
+        },
+        profile::Sort::Quota => {
+            let percent = spec
+                .and_then(|spec| spec.subscriptions.first())
+                .and_then(|url| alerts::last_usage().remove(url))
+                .and_then(|usage| usage.percent());
+            match percent {
+                Some(percent) => (0, percent, String::new()),
+                None => (1, 0, String::new()),
+            }
+        }
+        profile::Sort::LastUsed => {
+            match profile::last_used().ok().and_then(|u| u.get(name).copied()) {
+                Some(at) => (0, u64::MAX - at, String::new()),
+                None => (1, 0, String::new()),
+            }
+        }
+    }
+}
+
+fn profile_list(
+    group_by: Option<profile::Group>,
+    group_pattern: Option<&str>,
+    sort: profile::Sort,
+    geo: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pattern = group_pattern
+        .map(regex::Regex::new)
+        .transpose()
+        .map_err(|e| format!("Invalid --group-pattern: {}", e))?;
+    let active = profile::active()?;
+    let latencies = profile::latencies()?;
+    let names = profile::names()?;
+    if names.is_empty() {
+        info!("No profiles yet, add one with `profile add <url>`");
+        return Ok(());
+    }
+    let mut rows: Vec<(String, Result<Spec, PawprintError>, Option<Node>)> = names
+        .into_iter()
+        .map(|name| {
+            let spec = profile::load_raw(&name);
+            let node = spec.as_ref().ok().and_then(first_node);
+            (name, spec, node)
+        })
+        .collect();
+    rows.sort_by_cached_key(|(name, spec, node)| {
+        let latency = latencies.get(name).copied();
+        profile_sort_key(sort, name, spec.as_ref().ok(), node.as_ref(), latency)
+    });
+    let addresses: Vec<Option<std::net::IpAddr>> = rows
+        .iter()
+        .map(|(_, _, node)| {
+            node.as_ref()
+                .filter(|_| geo)
+                .and_then(|node| traceroute::resolve(node.address()).ok())
+        })
+        .collect();
+    let places = if geo {
+        lookup_places(&addresses.iter().flatten().copied().collect::<Vec<_>>())
+    } else {
+        Default::default()
+    };
+
+    // Headings in the order their first profile sorts.
+    let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+    for (index, (name, spec, node)) in rows.iter().enumerate() {
+        let key = match (group_by, &pattern) {
+            (Some(profile::Group::Subscription), _) => spec
+                .as_ref()
+                .ok()
+                .and_then(|spec| spec.subscriptions.first())
+                .map(|url| {
+                    url::Url::parse(url)
+                        .ok()
+                        .and_then(|u| u.host_str().map(str::to_string))
+                        .unwrap_or_else(|| "?".to_string())
+                })
+                .unwrap_or_else(|| "share links".to_string()),
+            (Some(profile::Group::Country), _) => match profile_country(name, node.as_ref()) {
+                Some(code) => format!("{} {}", filter::flag(code), code),
+                None => "unknown country".to_string(),
+            },
+            (None, Some(pattern)) => {
+                let tag = node.as_ref().map_or(name.as_str(), |node| node.tag());
+                pattern
+                    .captures(tag)
+                    .and_then(|c| c.get(1).or_else(|| c.get(0)))
+                    .map_or("other".to_string(), |m| m.as_str().to_string())
+            }
+            (None, None) => String::new(),
+        };
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, members)) => members.push(index),
+            None => groups.push((key, vec![index])),
+        }
+    }
+
+    for (number, (key, members)) in groups.iter().enumerate() {
+        if group_by.is_some() || pattern.is_some() {
+            if number > 0 {
+                println!();
+            }
+            println!("{} ({})", key, members.len());
+        }
+        for &index in members {
+            let (name, spec, node) = &rows[index];
+            let marker = if active.as_deref() == Some(name.as_str()) {
+                '*'
+            } else {
+                ' '
+            };
+            let summary = match spec {
+                Ok(spec) => {
+                    let server = node
+                        .as_ref()
+                        .map(|node| {
+                            format!("{} {}:{}", node.protocol(), node.address(), node.port())
+                        })
+                        .unwrap_or_else(|| "?".to_string());
+                    let latency = match latencies.get(name) {
+                        Some(Some(ms)) => format!("  {} ms", ms),
+                        Some(None) => "  failed".to_string(),
+                        None => String::new(),
+                    };
+                    format!("{:<12} {}{}", spec.target, server, latency)
+                }
+                Err(e) => format!("error: {}", e),
+            };
+            let place = match addresses[index] {
+                Some(ip) => match places.get(&ip) {
+                    Some(info) => format!("  {} {}", ip, info.summary()),
+                    None => format!("  {}", ip),
+                },
+                None if geo => "  unresolved".to_string(),
+                None => String::new(),
+            };
+            println!("{} {:<20} {}{}", marker, name, summary, place);
+        }
+    }
+    let servers: Vec<(&str, std::net::IpAddr)> = rows
+        .iter()
+        .zip(&addresses)
+        .filter_map(|((name, _, _), ip)| Some((name.as_str(), (*ip)?)))
+        .collect();
+    for (ip, names) in ipinfo::shared(&servers) {
+        info!("{} share the server IP {}", names.join(", "), ip);
+    }
+    Ok(())
+}
+
+// The SOCKS or HTTP inbound of the running core, to send lookups through.
+fn tunnel_proxy(pid_file: &Path) -> Option<String> {
+    let state = process::running(pid_file).ok().flatten()?;
+    health::proxy_url(&read_config(&state.config).ok()?)
+}
+
+// Where each address is, through the tunnel when the core runs. A failed
+// lookup leaves the places out rather than failing the listing.
+fn lookup_places(
+    ips: &[std::net::IpAddr],
+) -> std::collections::BTreeMap<std::net::IpAddr, ipinfo::IpInfo> {
+    let proxy = tunnel_proxy(&process::default_pid_file());
+    if proxy.is_none() {
+        info!("The core is not running; asking ip-api.com directly");
+    }
+    ipinfo::lookup(ips, proxy.as_deref()).unwrap_or_else(|e| {
+        warn!("Could not look up server locations: {}", e);
+        Default::default()
+    })
+}
+
+fn profile_use(name: &str, env_subst: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if !profile::exists(name)? {
+        return Err(format!("No such profile: {}", name).into());
+    }
+    let spec = profile::load(name, env_subst)?;
+    let output = spec.output.clone();
+    apply_spec(spec, true, env_subst)?;
+    // The config holds the same secrets as the profile it came from.
+    #[cfg(unix)]
+    if vault::recipient()?.is_some() {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&output, fs::Permissions::from_mode(0o600))?;
+    }
+    profile::set_active(name)?;
+    info!("✓ Switched to profile {}", name);
+    Ok(())
+}
+
+// xray fails to create a TUN interface without root or CAP_NET_ADMIN, and its
+// error does not say so.
+fn check_tun_permission(config: &serde_json::Value, xray: &str) {
+    if killswitch::tun_interfaces(config).is_empty() {
+        return;
+    }
+    let binary = service::resolve(&xraycore::program(xray).to_string_lossy());
+    if !elevate::can_create_tun(Path::new(&binary)) {
+        warn!(
+            "{} cannot create the TUN interface as this user; run `pawprint-vpn core setcap` once, or run as root",
+            binary
+        );
+    }
+}
+
+// What `run` changes around xray besides starting it, undone when it stops.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Setup {
+    system_proxy: bool,
+    kill_switch: bool,
+    tun_routes: bool,
+    tun_dns: bool,
+}
 
-    println!("✓ Config saved to: {}", output_path.display());
+impl Setup {
+    // For `stop`: undoing what was never set up does nothing.
+    const ALL: Setup = Setup {
+        system_proxy: true,
+        kill_switch: true,
+        tun_routes: true,
+        tun_dns: true,
+    };
+
+    // What a running core was set up with, to set it up again after a restart.
+    fn active() -> Setup {
+        Setup {
+            system_proxy: sysproxy::is_active(),
+            kill_switch: killswitch::is_active(),
+            tun_routes: routes::is_active(),
+            tun_dns: resolver::is_active(),
+        }
+    }
+
+    fn reads_config(&self) -> bool {
+        self.system_proxy || self.kill_switch || self.tun_routes || self.tun_dns
+    }
+
+    // Before xray starts. The firewall goes up first so nothing leaks in between.
+    // Whatever went in already is left for `tear_down` when a step fails.
+    fn enable(&self, config: &serde_json::Value) -> Result<(), PawprintError> {
+        if self.kill_switch {
+            killswitch::enable(&killswitch::Allowed::from_config(config)?)?;
+        }
+        if self.tun_routes {
+            routes::enable(config)?;
+        }
+        if self.system_proxy {
+            sysproxy::enable(&sysproxy::Endpoints::from_config(config))?;
+        }
+        Ok(())
+    }
+
+    // Once xray runs, until `done` is set.
+    fn started(&self, config: &serde_json::Value, done: &AtomicBool) {
+        if self.tun_routes
+            && let Err(e) = routes::capture(config, done)
+        {
+            warn!("Traffic does not go through the TUN interface: {}", e);
+        }
+        if self.tun_dns
+            && let Err(e) = resolver::enable(config, done)
+        {
+            warn!("DNS queries do not go through the TUN interface: {}", e);
+        }
+    }
+
+    // Undoes the steps of `enable`, trying all of them.
+    fn tear_down(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let restored = if self.system_proxy {
+            sysproxy::restore().map(drop)
+        } else {
+            Ok(())
+        };
+        let routed = if self.tun_routes {
+            routes::disable().map(drop)
+        } else {
+            Ok(())
+        };
+        let resolving = if self.tun_dns {
+            resolver::restore().map(drop)
+        } else {
+            Ok(())
+        };
+        if self.kill_switch {
+            killswitch::disable()?;
+        }
+        routed?;
+        resolving?;
+        Ok(restored?)
+    }
+}
+
+// xray in the foreground, set up further once it started.
+fn run_foreground(
+    xray: &str,
+    config: &Path,
+    pid_file: &Path,
+    setup: Setup,
+    parsed: &serde_json::Value,
+) -> Result<(), PawprintError> {
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        scope.spawn(|| setup.started(parsed, &done));
+        let result = process::run_foreground(xray, config, pid_file);
+        done.store(true, Ordering::SeqCst);
+        result
+    })
+}
+
+fn read_config(config: &Path) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(config).map_err(|e| PawprintError::file(config, e))?;
+    Ok(jsonc::parse(&content)
+        .map_err(|e| format!("Invalid JSON in {}: {}", config.display(), e))?)
+}
+
+fn run_core(
+    config: &Path,
+    xray: &str,
+    detach: bool,
+    pid_file: &Path,
+    setup: Setup,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !config.exists() {
+        return Err(format!("Config not found: {}", config.display()).into());
+    }
+    let parsed = if setup.reads_config() {
+        read_config(config)?
+    } else {
+        // Only for the TUN check; xray reports a broken config better.
+        read_config(config).unwrap_or_default()
+    };
+    check_tun_permission(&parsed, xray);
+    if let Err(e) = setup.enable(&parsed) {
+        setup.tear_down()?;
+        return Err(e.into());
+    }
+
+    if !detach {
+        if setup.reads_config() || hooks::is_set(hooks::Event::Disconnected) {
+            // Ctrl-C reaches xray too; staying alive until it exits lets the
+            // changes be undone and the disconnect be reported.
+            watch::install_signal_handlers();
+        }
+        let result = run_foreground(xray, config, pid_file, setup, &parsed);
+        setup.tear_down()?;
+        return Ok(result?);
+    }
+    let state = match process::start_detached(xray, config, pid_file) {
+        Ok(state) => state,
+        Err(e) => {
+            setup.tear_down()?;
+            return Err(e.into());
+        }
+    };
+    setup.started(&parsed, &AtomicBool::new(false));
+    info!("✓ xray started in the background (pid {})", state.pid);
+    if let Some(log) = &state.log {
+        info!("Logs: {}", log.display());
+    }
+    Ok(())
+}
+
+// `run --failover`: runs the active profile in the foreground and moves on to
+// the next fastest profile whenever the health checks give up on the current one.
+fn run_failover(
+    xray: &str,
+    pid_file: &Path,
+    setup: Setup,
+    check: &HealthCheck,
+    env_subst: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut current =
+        profile::active()?.ok_or("No active profile to run, pick one with `profile use <name>`")?;
+    let config = profile::active_config()?;
+    if !config.exists() {
+        return Err(format!("Config not found: {}", config.display()).into());
+    }
+    watch::install_signal_handlers();
+    let mut failed = Vec::new();
+    let result = loop {
+        match run_monitored(&config, xray, pid_file, setup, check, &current) {
+            Ok(true) => {}
+            other => break other.map(drop),
+        }
+        warn!("Profile {} is not responding, failing over", current);
+        let _ = profile::record_latency(&current, None);
+        failed.push(current.clone());
+        match switch_profile(&mut failed, setup.kill_switch, env_subst) {
+            Ok(next) => {
+                let vars = [
+                    ("PREVIOUS_PROFILE", current),
+                    ("CONFIG", config.display().to_string()),
+                ];
+                hooks::fire(hooks::Event::Failover, &vars);
+                current = next;
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    setup.tear_down()?;
+    result
+}
+
+// Runs `config` in the foreground under health checks. Returns true if xray
+// was stopped because the checks kept failing.
+fn run_monitored(
+    config: &Path,
+    xray: &str,
+    pid_file: &Path,
+    setup: Setup,
+    check: &HealthCheck,
+    name: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let parsed = read_config(config)?;
+    let proxy = health::proxy_url(&parsed)
+        .ok_or("The config has no SOCKS or HTTP inbound to run health checks through")?;
+    // This replaces what the previous profile set up, as its servers and
+    // inbounds may differ.
+    setup.enable(&parsed)?;
+
+    let done = AtomicBool::new(false);
+    let (result, unhealthy) = thread::scope(|scope| {
+        let monitor = scope.spawn(|| {
+            let unhealthy = health::monitor(check, &proxy, &done, |delay| {
+                let _ = profile::record_latency(name, Some(delay));
+            });
+            if unhealthy && let Err(e) = process::stop(pid_file) {
+                warn!("{}", e);
+            }
+            unhealthy
+        });
+        let result = run_foreground(xray, config, pid_file, setup, &parsed);
+        done.store(true, Ordering::SeqCst);
+        (result, monitor.join().unwrap_or(false))
+    });
+    // xray exits with an error when the monitor stops it.
+    if unhealthy {
+        return Ok(true);
+    }
+    result?;
+    Ok(false)
+}
+
+// `run --reload`: runs the active profile in the foreground and restarts xray on
+// a fresh config whenever the profile, a file it uses or the config changes.
+fn run_reloading(
+    xray: &str,
+    pid_file: &Path,
+    setup: Setup,
+    env_subst: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = profile::active_config()?;
+    if !config.exists() {
+        return Err(format!("Config not found: {}", config.display()).into());
+    }
+    watch::install_signal_handlers();
+    watch::install_reload_handler();
+    let result = loop {
+        // `profile use` in another terminal changes what to follow.
+        let name = profile::active()?
+            .ok_or("No active profile to run, pick one with `profile use <name>`")?;
+        match run_watched(&config, xray, pid_file, setup, &name, env_subst) {
+            Ok(true) => info!("Restarting xray on the new config..."),
+            other => break other.map(drop),
+        }
+    };
+    setup.tear_down()?;
+    result
+}
+
+// Runs `config` in the foreground until xray exits or a reload replaced the
+// config. Returns true in the second case.
+fn run_watched(
+    config: &Path,
+    xray: &str,
+    pid_file: &Path,
+    setup: Setup,
+    name: &str,
+    env_subst: bool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    // The new config may bring other servers and inbounds.
+    let parsed = if setup.reads_config() {
+        read_config(config)?
+    } else {
+        serde_json::Value::Null
+    };
+    setup.enable(&parsed)?;
+
+    let mut files = profile::sources(name, env_subst)?;
+    files.push(config.to_path_buf());
+    let mut changes = watch::Changes::new(files);
+    let done = AtomicBool::new(false);
+    let (result, reloaded) = thread::scope(|scope| {
+        let watcher = scope.spawn(|| {
+            while !done.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(500));
+                // A SIGTERM meant for both of us may only have reached this process.
+                if watch::stop_requested() {
+                    if let Err(e) = process::stop(pid_file) {
+                        warn!("{}", e);
+                    }
+                    return false;
+                }
+                let hangup = watch::take_reload();
+                let changed = changes.poll();
+                if let Some(path) = changed.first() {
+                    info!("{} changed, reloading", path.display());
+                } else if hangup {
+                    info!("SIGHUP received, reloading");
+                } else {
+                    continue;
+                }
+                // A config rewritten by someone else is run as it is.
+                let regenerate = hangup || changed.iter().any(|path| path != config);
+                if regenerate {
+                    if let Err(e) = profile_use(name, env_subst) {
+                        warn!("Keeping the running config: {}", e);
+                        continue;
+                    }
+                    let vars = [("CONFIG", config.display().to_string())];
+                    hooks::fire(hooks::Event::SubscriptionUpdated, &vars);
+                }
+                if let Err(e) = process::stop(pid_file) {
+                    warn!("{}", e);
+                }
+                return true;
+            }
+            false
+        });
+        let result = run_foreground(xray, config, pid_file, setup, &parsed);
+        done.store(true, Ordering::SeqCst);
+        (result, watcher.join().unwrap_or(false))
+    });
+    // xray exits with an error when the watcher stops it.
+    if reloaded {
+        return Ok(true);
+    }
+    result?;
+    Ok(false)
+}
+
+fn first_node(spec: &Spec) -> Option<Node> {
+    let link = spec.nodes.first()?;
+    Registry::with_plugins(spec.plugins_dir.as_deref())
+        .ok()?
+        .parse(link)
+        .ok()
+}
+
+// Records the TCP latency of the first server of each profile.
+fn measure_profiles(names: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let nodes: Vec<(String, Node)> = names
+        .iter()
+        .filter_map(|name| {
+            let node = first_node(&profile::load_raw(name).ok()?)?;
+            Some((name.clone(), node))
+        })
+        .collect();
+    info!("Measuring TCP latency of {} profiles...", nodes.len());
+    let servers: Vec<Node> = nodes.iter().map(|(_, node)| node.clone()).collect();
+    let results = latency::probe_all(&servers, latency::TCP_WORKERS, |node| {
+        latency::tcp_ping(node.address(), node.port(), 3, Duration::from_secs(5))
+    });
+    for ((name, _), result) in nodes.iter().zip(results) {
+        profile::record_latency(name, result.ok())?;
+    }
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+// Regenerates the active config from the fastest profile that has not failed
+// yet and returns its name.
+fn switch_profile(
+    failed: &mut Vec<String>,
+    kill_switch: bool,
+    env_subst: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let names = profile::names()?;
+    if names.iter().all(|name| failed.contains(name)) {
+        // Everything failed once; outages pass, so go round again.
+        failed.drain(..failed.len().saturating_sub(1));
+    }
+    let candidates: Vec<String> = names
+        .into_iter()
+        .filter(|name| !failed.contains(name))
+        .collect();
+    if candidates.is_empty() {
+        return Err("No other profile to fail over to".into());
+    }
+
+    // The kill switch blocks every server but the current one, so only the
+    // latency recorded earlier is known then.
+    if !kill_switch {
+        measure_profiles(&candidates)?;
+    }
+    let latencies = profile::latencies()?;
+    let mut ranked = candidates;
+    // Fastest first, then never measured, then failed.
+    ranked.sort_by_key(|name| match latencies.get(name) {
+        Some(Some(ms)) => (0, *ms),
+        None => (1, 0),
+        Some(None) => (2, 0),
+    });
+
+    for name in ranked {
+        match profile_use(&name, env_subst) {
+            Ok(()) => return Ok(name),
+            Err(e) => {
+                warn!("Cannot switch to profile {}: {}", name, e);
+                failed.push(name);
+            }
+        }
+    }
+    Err("No profile could be switched to".into())
+}
+
+fn install_hooks(args: HookArgs) {
+    hooks::install(hooks::Hooks {
+        connected: args.on_connect,
+        disconnected: args.on_disconnect,
+        failover: args.on_failover,
+        subscription_updated: args.on_subscription_update,
+    });
+}
+
+fn stop_core(pid_file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    match process::stop(pid_file)? {
+        Some(state) => info!("✓ Stopped xray (pid {})", state.pid),
+        None => info!("xray is not running"),
+    }
+    Setup::ALL.tear_down()
+}
 
-    println!("Parsing VLESS URL...");
-    let vless_config = parse_config(&args.config)?;
-    println!("UUID: {}", vless_config.uuid);
-    println!("Server: {}:{}", vless_config.address, vless_config.port);
-    println!("Tag: {}", vless_config.tag);
+fn show_stats(server: &str, xray: &str, reset: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let traffic = stats::query(xray, server, reset)?;
+    if traffic.is_empty() {
+        info!("No traffic counted yet");
+        return Ok(());
+    }
+    println!("{:<9} {:<30} {:>12} {:>12}", "kind", "tag", "up", "down");
+    for entry in &traffic {
+        println!(
+            "{:<9} {:<30} {:>12} {:>12}",
+            entry.kind,
+            entry.tag,
+            stats::human_bytes(entry.uplink),
+            stats::human_bytes(entry.downlink)
+        );
+    }
+    if reset {
+        info!("Counters reset");
+    }
+    Ok(())
+}
 
-    println!("\n🔨 Building Xray configuration...");
-    let xray_config = build_config(&vless_config);
+fn core_status(pid_file: &Path, geo: bool) -> Result<(), Box<dyn std::error::Error>> {
+    match process::running(pid_file)? {
+        Some(state) => {
+            println!("running (pid {})", state.pid);
+            println!("config: {}", state.config.display());
+            if let Some(log) = &state.log {
+                println!("log:    {}", log.display());
+            }
+        }
+        None => println!("not running"),
+    }
+    if !geo {
+        return Ok(());
+    }
+    let node = profile::active()?
+        .and_then(|name| profile::load_raw(&name).ok())
+        .and_then(|spec| first_node(&spec));
+    if let Some(node) = node {
+        let ip = traceroute::resolve(node.address())
+            .map_err(|e| format!("Could not resolve {}: {}", node.address(), e))?;
+        let place = lookup_places(&[ip])
+            .get(&ip)
+            .map(|info| format!(" {}", info.summary()))
+            .unwrap_or_default();
+        println!("server: {} ({}){}", node.address(), ip, place);
+    }
+    match tunnel_proxy(pid_file) {
+        Some(proxy) => {
+            let (ip, info) = ipinfo::exit(&proxy)?;
+            println!("exit:   {} {}", ip, info.summary());
+        }
+        None => info!("Start the core to see where the tunnel comes out"),
+    }
+    Ok(())
+}
 
-    let output_path = args.output;
-    println!("\nSaving configuration...");
-    save_config(&xray_config, &output_path, args.force)?;
+// The log `logs` shows: the access or error log named in the config of the
+// running core (or the active profile), or the output captured next to the pid file.
+fn log_path(pid_file: &Path, section: Option<&str>) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let state = process::running(pid_file)?;
+    let Some(key) = section else {
+        return Ok(state
+            .and_then(|s| s.log)
+            .unwrap_or_else(|| process::log_file(pid_file)));
+    };
+    let config = match state {
+        Some(state) => state.config,
+        None => profile::active_config()?,
+    };
+    let log = &read_config(&config)?["log"];
+    // sing-box writes all of it to `output`.
+    let path = match key {
+        "error" => log["error"].as_str().or(log["output"].as_str()),
+        _ => log[key].as_str(),
+    };
+    match path {
+        Some(path) if path != "none" => Ok(PathBuf::from(path)),
+        _ => Err(format!(
+            "{} sets no {} log; generate it with --{}-log",
+            config.display(),
+            key,
+            key
+        )
+        .into()),
+    }
+}
 
+fn show_logs(
+    pid_file: &Path,
+    section: Option<&str>,
+    lines: usize,
+    follow: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = log_path(pid_file, section)?;
+    if !path.exists() {
+        return Err(format!("No log at {} yet", path.display()).into());
+    }
+    let (last, end) = logfile::tail(&path, lines)?;
+    for line in last {
+        println!("{}", line);
+    }
+    if follow {
+        watch::install_signal_handlers();
+        logfile::follow(&path, end, |line| println!("{}", line))?;
+    }
     Ok(())
 }
+
+fn restart_core(pid_file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let state = process::PidFile::load(pid_file)?
+        .ok_or("xray was not started with `run`, nothing to restart")?;
+    let setup = Setup::active();
+    if let Some(stopped) = process::stop(pid_file)? {
+        info!("✓ Stopped xray (pid {})", stopped.pid);
+    }
+    // All of it is set up again from the config, which may list new servers.
+    run_core(&state.config, &state.xray, true, pid_file, setup)
+}
+
+fn main() -> ExitCode {
+    let defaults = match defaults::Defaults::load().and_then(|d| d.install_alerts().map(|_| d)) {
+        Ok(defaults) => defaults,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let matches = defaults.apply(Args::command()).get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    defaults.fill(&mut args);
+
+    if let Err(e) = logging::init(args.log_file.as_deref(), args.log_max_size) {
+        eprintln!("Error: {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            if let Some(hint) = e.downcast_ref::<PawprintError>().and_then(|e| e.hint()) {
+                info!("Hint: {}", hint);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let env_subst = !args.no_env_subst;
+
+    if let Some(command) = args.command {
+        return match command {
+            Command::Convert(convert_args) => {
+                let cli::ConvertArgs {
+                    links,
+                    mut generate,
+                    output,
+                } = *convert_args;
+                generate.config.extend(links);
+                convert(generate, &output, env_subst)
+            }
+            Command::Update(update_args) => update_config(*update_args, env_subst),
+            Command::Diff(diff_args) => diff_config(*diff_args, env_subst),
+            Command::Subscribe(subscribe) => {
+                let cli::SubscribeArgs { url, build, output } = *subscribe;
+                let generate = GenerateArgs {
+                    config: Vec::new(),
+                    links_file: None,
+                    wireguard: Vec::new(),
+                    subscription: Some(url),
+                    build,
+                };
+                convert(generate, &output, env_subst)
+            }
+            Command::Profile { action } => match action {
+                ProfileAction::Add {
+                    url,
+                    from_clipboard: _,
+                    from_qr,
+                    name,
+                    target,
+                    plugins_dir,
+                    force,
+                    qr,
+                } => {
+                    let url = match (url, from_qr) {
+                        (Some(url), _) => url,
+                        (None, Some(image)) => qr_link(&image)?,
+                        // clap requires one of the three.
+                        (None, None) => clipboard_link()?,
+                    };
+                    profile_add(url, name, target, plugins_dir, force, &qr)
+                }
+                ProfileAction::List {
+                    group_by,
+                    group_pattern,
+                    sort,
+                    geo,
+                } => profile_list(group_by, group_pattern.as_deref(), sort, geo),
+                ProfileAction::Remove { name } => {
+                    profile::remove(&name)?;
+                    info!("✓ Profile {} removed", name);
+                    Ok(())
+                }
+                ProfileAction::Use { name } => profile::resolve(&name)
+                    .map_err(Into::into)
+                    .and_then(|name| profile_use(&name, env_subst)),
+                ProfileAction::Encrypt { key } => {
+                    let count = profile::set_encryption(Some(key))?;
+                    info!("✓ {} profiles encrypted; new ones will be too", count);
+                    Ok(())
+                }
+                ProfileAction::Decrypt => {
+                    let count = profile::set_encryption(None)?;
+                    info!("✓ {} profiles stored in plain text again", count);
+                    Ok(())
+                }
+            },
+            Command::Geo { action } => match action {
+                GeoAction::Update { dir, source } => geo_update(&dir, &source),
+            },
+            Command::Core { action } => core_command(action),
+            Command::Service { action } => service_command(action),
+            Command::Integrate { target } => integrate_command(target),
+            Command::Apply {
+                spec,
+                force,
+                dry_run,
+            } => apply(&spec, force, dry_run, env_subst),
+            Command::Validate { configs } => validate_configs(&configs),
+            Command::Watch {
+                spec,
+                interval,
+                state_file,
+                pid_file,
+                hooks,
+            } => {
+                install_hooks(hooks);
+                watch_spec(
+                    &spec,
+                    interval,
+                    &state_file.unwrap_or_else(watch::default_state_file),
+                    &pid_file.unwrap_or_else(process::default_pid_file),
+                    env_subst,
+                )
+            }
+            Command::Init {
+                dir,
+                force,
+                plugins_dir,
+            } => init(&dir, force, plugins_dir.as_deref(), env_subst),
+            Command::Server(server) => server_config(*server),
+            Command::User { action } => user_command(action),
+            Command::Keygen {
+                private_key,
+                short_ids,
+                short_id_bytes,
+                sni,
+            } => reality_keygen(
+                private_key.as_deref(),
+                short_ids,
+                short_id_bytes,
+                sni.as_deref(),
+            ),
+            Command::Bundle {
+                output,
+                config_files,
+                xray,
+            } => {
+                let inputs = bundle::BundleInputs {
+                    config_files: &config_files,
+                    log_file: args.log_file.as_deref(),
+                    xray: &xray,
+                };
+                bundle::create(&output, &inputs)?;
+                info!("✓ Bundle saved to: {}", output.display());
+                Ok(())
+            }
+            Command::Run {
+                config,
+                xray,
+                detach,
+                system_proxy,
+                kill_switch,
+                tun_routes,
+                tun_dns,
+                health,
+                reload,
+                pid_file,
+                hooks,
+            } => {
+                install_hooks(hooks);
+                let pid_file = pid_file.unwrap_or_else(process::default_pid_file);
+                let setup = Setup {
+                    system_proxy,
+                    kill_switch,
+                    tun_routes,
+                    tun_dns,
+                };
+                if reload {
+                    run_reloading(&xray, &pid_file, setup, env_subst)
+                } else if health.failover {
+                    let check = HealthCheck {
+                        url: health.health_url,
+                        interval: Duration::from_secs(health.health_interval.max(1)),
+                        timeout: Duration::from_secs(health.health_timeout.max(1)),
+                        failures: health.health_failures.max(1),
+                    };
+                    run_failover(&xray, &pid_file, setup, &check, env_subst)
+                } else {
+                    run_core(&config, &xray, detach, &pid_file, setup)
+                }
+            }
+            Command::Stop { pid_file, hooks } => {
+                install_hooks(hooks);
+                stop_core(&pid_file.unwrap_or_else(process::default_pid_file))
+            }
+            Command::Status { pid_file, geo } => {
+                core_status(&pid_file.unwrap_or_else(process::default_pid_file), geo)
+            }
+            Command::Logs {
+                follow,
+                lines,
+                access,
+                error,
+                pid_file,
+            } => {
+                let section = if access {
+                    Some("access")
+                } else {
+                    error.then_some("error")
+                };
+                show_logs(
+                    &pid_file.unwrap_or_else(process::default_pid_file),
+                    section,
+                    lines,
+                    follow,
+                )
+            }
+            Command::Restart { pid_file, hooks } => {
+                install_hooks(hooks);
+                restart_core(&pid_file.unwrap_or_else(process::default_pid_file))
+            }
+            Command::Stats {
+                server,
+                xray,
+                reset,
+            } => show_stats(&server, &xray, reset),
+            Command::Tui {
+                subscription,
+                xray,
+                target,
+                plugins_dir,
+                stats_server,
+                pid_file,
+            } => tui::run(tui::Options {
+                subscription,
+                xray,
+                target,
+                plugins_dir,
+                stats_server,
+                pid_file: pid_file.unwrap_or_else(process::default_pid_file),
+                env_subst,
+            }),
+            Command::Daemon {
+                listen,
+                socket,
+                xray,
+                stats_server,
+                pid_file,
+                probe_url,
+                probe_interval,
+                probe_window,
+                telegram_bot,
+                mqtt,
+                hooks,
+            } => {
+                install_hooks(hooks);
+                let defaults = defaults::Defaults::load()?;
+                let bot = match (telegram_bot, defaults.bot()) {
+                    (true, None) => {
+                        return Err("--telegram-bot needs a [bot] table in config.toml".into());
+                    }
+                    (true, bot) => bot,
+                    (false, _) => None,
+                };
+                let mqtt = match (mqtt, defaults.mqtt()) {
+                    (true, None) => {
+                        return Err("--mqtt needs an [mqtt] table in config.toml".into());
+                    }
+                    (true, mqtt) => mqtt,
+                    (false, _) => None,
+                };
+                daemon::run(daemon::Options {
+                    listen,
+                    socket,
+                    xray,
+                    stats_server,
+                    pid_file: pid_file.unwrap_or_else(process::default_pid_file),
+                    env_subst,
+                    probe_url,
+                    probe_interval: Duration::from_secs(probe_interval),
+                    probe_window,
+                    bot,
+                    mqtt,
+                })
+            }
+            Command::Test { kind } => match kind {
+                TestKind::Latency(latency_args) => test_latency(*latency_args),
+                TestKind::Route {
+                    node,
+                    max_hops,
+                    plugins_dir,
+                } => test_route(&node, max_hops, plugins_dir.as_deref()),
+            },
+            Command::Export { format } => match format {
+                ExportFormat::Docker(docker) => export_docker(*docker, env_subst),
+                ExportFormat::K8s(k8s) => export_k8s(*k8s, env_subst),
+                ExportFormat::Clash(clash) => export_clash(*clash),
+                ExportFormat::Link { config, tag, qr } => export_link(&config, tag.as_deref(), &qr),
+            },
+        };
+    }
+
+    // Compatibility with the flat `-c ... -o ...` usage from before subcommands.
+    let output = OutputArgs {
+        output: args.output.unwrap_or_else(|| {
+            assert!(args.dry_run, "clap requires --output without a subcommand");
+            PathBuf::from("-")
+        }),
+        force: args.force,
+        per_server: args.per_server,
+        dry_run: args.dry_run,
+        batch: args.batch,
+    };
+    convert(args.generate, &output, env_subst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Each step appends to `trail`, or checks what the steps before it left.
+    #[test]
+    fn transforms_run_in_order() {
+        let dir = std::env::temp_dir().join(format!("pawprint-transforms-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str, content: &str| {
+            let path = dir.join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let transforms = Transforms {
+            template: Some(file(
+                "template.json",
+                r#"{"port": 10, "trail": ["template"], "log": "template"}"#,
+            )),
+            merges: vec![file(
+                "merge.json",
+                r#"{"trail": ["merge"], "log": "merge", "dropped": true}"#,
+            )],
+            patches: vec![file("patch.json", r#"{"log": "patch", "dropped": null}"#)],
+            json_patches: vec![file(
+                "ops.json",
+                r#"[
+                    {"op": "test", "path": "/log", "value": "patch"},
+                    {"op": "test", "path": "/trail", "value": ["template", "merge"]},
+                    {"op": "add", "path": "/trail/-", "value": "json-patch"}
+                ]"#,
+            )],
+            post_script: Some(file("post.rhai", r#"config.trail.push("post-script");"#)),
+            jq: Some(r#".trail += ["jq"]"#.to_string()),
+        };
+
+        let output = apply_transforms(json!({"port": 1, "trail": []}), &transforms, false);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            output.unwrap(),
+            json!({
+                // The template only fills in what the generated config lacks.
+                "port": 1,
+                "trail": ["template", "merge", "json-patch", "post-script", "jq"],
+                "log": "patch",
+            })
+        );
+    }
+
+    #[test]
+    fn a_failed_json_patch_test_stops_the_build() {
+        let path = std::env::temp_dir().join(format!("pawprint-ops-{}.json", std::process::id()));
+        fs::write(&path, r#"[{"op": "test", "path": "/port", "value": 2}]"#).unwrap();
+        let transforms = Transforms {
+            template: None,
+            merges: Vec::new(),
+            patches: Vec::new(),
+            json_patches: vec![path.clone()],
+            post_script: None,
+            jq: Some(".port = 3".to_string()),
+        };
+        let result = apply_transforms(json!({"port": 1}), &transforms, false);
+        fs::remove_file(&path).unwrap();
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("expected 2"), "{}", error);
+    }
+}
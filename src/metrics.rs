@@ -0,0 +1,82 @@
+use std::fmt::Write;
+
+// Gauges go up and down; counters only grow until the process that owns them
+// restarts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Gauge,
+    Counter,
+}
+
+#[derive(Debug)]
+struct Family {
+    name: String,
+    help: String,
+    kind: Kind,
+    samples: Vec<(String, f64)>,
+}
+
+// Samples in the Prometheus text exposition format, grouped by metric name.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    families: Vec<Family>,
+}
+
+// Backslashes, quotes and newlines are the only characters escaped in label values.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    pub fn add(&mut self, name: &str, help: &str, kind: Kind, labels: &[(&str, &str)], value: f64) {
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            let pairs: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                .collect();
+            format!("{{{}}}", pairs.join(","))
+        };
+        let index = match self.families.iter().position(|f| f.name == name) {
+            Some(index) => index,
+            None => {
+                self.families.push(Family {
+                    name: name.to_string(),
+                    help: help.to_string(),
+                    kind,
+                    samples: Vec::new(),
+                });
+                self.families.len() - 1
+            }
+        };
+        self.families[index].samples.push((labels, value));
+    }
+
+    pub fn gauge(&mut self, name: &str, help: &str, labels: &[(&str, &str)], value: f64) {
+        self.add(name, help, Kind::Gauge, labels, value);
+    }
+
+    pub fn counter(&mut self, name: &str, help: &str, labels: &[(&str, &str)], value: f64) {
+        self.add(name, help, Kind::Counter, labels, value);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for family in &self.families {
+            let kind = match family.kind {
+                Kind::Gauge => "gauge",
+                Kind::Counter => "counter",
+            };
+            let _ = writeln!(out, "# HELP {} {}", family.name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", family.name, kind);
+            for (labels, value) in &family.samples {
+                let _ = writeln!(out, "{}{} {}", family.name, labels, value);
+            }
+        }
+        out
+    }
+}
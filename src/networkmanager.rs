@@ -0,0 +1,199 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::elevate::{self, root};
+use crate::error::PawprintError;
+use crate::process;
+use crate::service::Unit;
+use crate::undo::{args, command};
+
+pub const DISPATCHER: &str = "/etc/NetworkManager/dispatcher.d/90-pawprint-vpn";
+
+// What the dispatcher script restarts when the network changes.
+pub enum Restart {
+    // The core `run --detach` started, through `restart`
+    Core { pid_file: PathBuf },
+    // A unit from `service install`
+    Unit(Unit),
+}
+
+pub struct Dispatcher {
+    // pawprint-vpn itself, for Restart::Core.
+    pub exe: PathBuf,
+    // Who `restart` runs as, with the variables locating their state and
+    // config; None when that is root.
+    pub user: Option<(String, Vec<(String, String)>)>,
+    pub restart: Restart,
+}
+
+impl Dispatcher {
+    // Restarting as the current user, keeping the HOME and XDG directories that
+    // locate the pid file and profiles.
+    pub fn for_current_user(exe: PathBuf, restart: Restart) -> Result<Dispatcher, PawprintError> {
+        let user = if elevate::is_root() {
+            None
+        } else {
+            let name = std::env::var("USER")
+                .or_else(|_| std::env::var("LOGNAME"))
+                .map_err(|_| "Cannot tell the user name, set USER")?;
+            let vars = ["HOME", "XDG_STATE_HOME", "XDG_CONFIG_HOME", "XDG_DATA_HOME"]
+                .into_iter()
+                .filter_map(|var| Some((var.to_string(), std::env::var(var).ok()?)))
+                .collect();
+            Some((name, vars))
+        };
+        Ok(Dispatcher { exe, user, restart })
+    }
+}
+
+// Single quotes keep everything literal in sh.
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+fn command_line(words: &[String]) -> String {
+    words
+        .iter()
+        .map(|word| quote(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// NetworkManager runs dispatcher scripts as root with the interface and the
+// action. The tunnel is restarted when a connection comes up or full
+// connectivity returns, so it does not keep the sockets and routes of a network
+// that is gone. TUN interfaces, like the core's own, are left out.
+pub fn script(dispatcher: &Dispatcher) -> String {
+    let (guard, restart) = match &dispatcher.restart {
+        Restart::Core { pid_file } => {
+            let pid_file = pid_file.to_string_lossy();
+            let mut words = Vec::new();
+            if let Some((user, vars)) = &dispatcher.user {
+                words.extend(args(["runuser", "-u", user, "--", "env"]));
+                words.extend(vars.iter().map(|(var, value)| format!("{}={}", var, value)));
+            }
+            words.push(dispatcher.exe.to_string_lossy().into_owned());
+            words.extend(args(["restart", "--pid-file", &pid_file]));
+            // Only a core that was started is restarted.
+            let guard = format!("[ -e {} ] || exit 0\n", quote(&pid_file));
+            (guard, command_line(&words))
+        }
+        Restart::Unit(unit) => {
+            let mut words = args(["systemctl"]);
+            if !unit.system
+                && let Some((user, _)) = &dispatcher.user
+            {
+                words.extend(args(["--user", "-M", &format!("{}@", user)]));
+            }
+            words.extend(args(["try-restart", &unit.file_name()]));
+            (String::new(), command_line(&words))
+        }
+    };
+    format!(
+        "#!/bin/sh\n\
+         # Written by `pawprint-vpn integrate networkmanager`.\n\
+         [ -e \"/sys/class/net/$1/tun_flags\" ] && exit 0\n\
+         case \"$2\" in\n\
+         \x20   up) ;;\n\
+         \x20   connectivity-change) [ \"$CONNECTIVITY_STATE\" = FULL ] || exit 0 ;;\n\
+         \x20   *) exit 0 ;;\n\
+         esac\n\
+         {}{}\n",
+        guard, restart
+    )
+}
+
+// Installs the script as root, as NetworkManager ignores scripts others can write.
+pub fn install(content: &str, force: bool) -> Result<PathBuf, PawprintError> {
+    let path = Path::new(DISPATCHER);
+    if path.exists() && !force {
+        return Err(format!(
+            "{} already exists. Use --force to replace it.",
+            path.display()
+        )
+        .into());
+    }
+    if !path.parent().is_some_and(Path::is_dir) {
+        return Err("NetworkManager's dispatcher.d directory was not found".into());
+    }
+    elevate::check("Installing a dispatcher script")?;
+    let dir = process::state_dir();
+    fs::create_dir_all(&dir)?;
+    let staged = dir.join("networkmanager-dispatcher");
+    fs::write(&staged, content).map_err(|e| PawprintError::file(&staged, e))?;
+    let result = command(&root(args([
+        "install",
+        "-m",
+        "0755",
+        "-o",
+        "root",
+        "-g",
+        "root",
+        &staged.to_string_lossy(),
+        DISPATCHER,
+    ])));
+    let _ = fs::remove_file(&staged);
+    result?;
+    Ok(path.to_path_buf())
+}
+
+// Removes the script. Returns false if it was not installed.
+pub fn uninstall() -> Result<bool, PawprintError> {
+    if !Path::new(DISPATCHER).exists() {
+        return Ok(false);
+    }
+    elevate::check("Removing the dispatcher script")?;
+    command(&root(args(["rm", "-f", DISPATCHER])))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_script_restarts_the_core_as_its_user() {
+        let dispatcher = Dispatcher {
+            exe: PathBuf::from("/usr/bin/pawprint-vpn"),
+            user: Some((
+                "fox".to_string(),
+                vec![("HOME".to_string(), "/home/fox's".to_string())],
+            )),
+            restart: Restart::Core {
+                pid_file: PathBuf::from("/home/fox/.local/state/pawprint-vpn/xray.pid"),
+            },
+        };
+        let script = script(&dispatcher);
+        assert!(script.starts_with("#!/bin/sh\n"), "{}", script);
+        assert!(script.contains("    up) ;;\n"), "{}", script);
+        assert!(
+            script.contains("[ -e '/home/fox/.local/state/pawprint-vpn/xray.pid' ] || exit 0\n"),
+            "{}",
+            script
+        );
+        assert!(
+            script.contains(
+                "'runuser' '-u' 'fox' '--' 'env' 'HOME=/home/fox'\\''s' '/usr/bin/pawprint-vpn' 'restart' '--pid-file' "
+            ),
+            "{}",
+            script
+        );
+    }
+
+    #[test]
+    fn the_script_can_restart_a_unit() {
+        let unit = |system| Dispatcher {
+            exe: PathBuf::from("/usr/bin/pawprint-vpn"),
+            user: Some(("fox".to_string(), Vec::new())),
+            restart: Restart::Unit(Unit::new("pawprint-vpn", system).unwrap()),
+        };
+        assert!(
+            script(&unit(false)).ends_with(
+                "'systemctl' '--user' '-M' 'fox@' 'try-restart' 'pawprint-vpn.service'\n"
+            )
+        );
+        assert!(
+            script(&unit(true)).ends_with("'systemctl' 'try-restart' 'pawprint-vpn.service'\n")
+        );
+    }
+}
@@ -0,0 +1,145 @@
+// Share link parsers loaded from dynamic libraries.
+//
+// A plugin is a cdylib exporting the following C ABI:
+//
+//   uint32_t    pawprint_plugin_abi_version(void);   // must return 1
+//   const char *pawprint_plugin_schemes(void);       // comma separated, e.g. "foo,bar"
+//   char       *pawprint_plugin_parse(const char *link);
+//   void        pawprint_plugin_free(char *json);
+//
+// `pawprint_plugin_parse` returns a JSON object owned by the plugin, released through
+// `pawprint_plugin_free`: either `{"error": "..."}` or
+// `{"address": "...", "port": 443, "tag": "...", "outbound": {...}}` where `outbound`
+// is a complete Xray outbound object (protocol, settings, streamSettings).
+
+use libloading::Library;
+use serde::Deserialize;
+use std::ffi::{CStr, CString, c_char};
+use std::path::Path;
+use std::sync::Arc;
+use std::{env, fs};
+
+use super::{Node, ShareLinkParser};
+use crate::error::PawprintError;
+
+const ABI_VERSION: u32 = 1;
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type SchemesFn = unsafe extern "C" fn() -> *const c_char;
+type ParseFn = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+type FreeFn = unsafe extern "C" fn(*mut c_char);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginNode {
+    #[serde(skip)]
+    pub scheme: String,
+    pub address: String,
+    pub port: u16,
+    pub tag: String,
+    pub outbound: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PluginResponse {
+    Error { error: String },
+    Node(PluginNode),
+}
+
+struct PluginParser {
+    scheme: String,
+    parse_fn: ParseFn,
+    free_fn: FreeFn,
+    // Keeps the function pointers above valid.
+    _library: Arc<Library>,
+}
+
+impl ShareLinkParser for PluginParser {
+    fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    fn parse(&self, link: &str) -> Result<Node, PawprintError> {
+        let link = CString::new(link)
+            .map_err(|_| PawprintError::Other("Share link contains a NUL byte".to_string()))?;
+        // SAFETY: the plugin promised the documented ABI when it reported ABI_VERSION.
+        let json = unsafe {
+            let raw = (self.parse_fn)(link.as_ptr());
+            if raw.is_null() {
+                return Err(PawprintError::Plugin(format!(
+                    "{}:// parser returned nothing",
+                    self.scheme
+                )));
+            }
+            let json = CStr::from_ptr(raw).to_string_lossy().into_owned();
+            (self.free_fn)(raw);
+            json
+        };
+
+        match serde_json::from_str(&json)? {
+            PluginResponse::Error { error } => Err(PawprintError::Plugin(format!(
+                "{}:// parser failed: {}",
+                self.scheme, error
+            ))),
+            PluginResponse::Node(mut node) => {
+                node.scheme = self.scheme.clone();
+                Ok(Node::Plugin(node))
+            }
+        }
+    }
+}
+
+pub fn load_dir(dir: &Path) -> Result<Vec<Box<dyn ShareLinkParser>>, PawprintError> {
+    let mut parsers = Vec::new();
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == env::consts::DLL_EXTENSION))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        parsers.extend(load_library(&path).map_err(|e| {
+            PawprintError::Plugin(format!("Failed to load {}: {}", path.display(), e))
+        })?);
+    }
+    Ok(parsers)
+}
+
+fn load_library(path: &Path) -> Result<Vec<Box<dyn ShareLinkParser>>, Box<dyn std::error::Error>> {
+    // SAFETY: loading a plugin runs its initialisers; plugins are trusted by being
+    // placed in the plugins directory. Symbols are only used while `library` is alive.
+    unsafe {
+        let library = Arc::new(Library::new(path)?);
+        let abi_version = library.get::<AbiVersionFn>(b"pawprint_plugin_abi_version")?;
+        if abi_version() != ABI_VERSION {
+            return Err(format!(
+                "ABI version {} is not supported (expected {})",
+                abi_version(),
+                ABI_VERSION
+            )
+            .into());
+        }
+
+        let schemes = library.get::<SchemesFn>(b"pawprint_plugin_schemes")?();
+        if schemes.is_null() {
+            return Err("Plugin reported no schemes".into());
+        }
+        let schemes = CStr::from_ptr(schemes).to_string_lossy().into_owned();
+        let parse_fn = *library.get::<ParseFn>(b"pawprint_plugin_parse")?;
+        let free_fn = *library.get::<FreeFn>(b"pawprint_plugin_free")?;
+
+        Ok(schemes
+            .split(',')
+            .map(str::trim)
+            .filter(|scheme| !scheme.is_empty())
+            .map(|scheme| {
+                Box::new(PluginParser {
+                    scheme: scheme.to_string(),
+                    parse_fn,
+                    free_fn,
+                    _library: Arc::clone(&library),
+                }) as Box<dyn ShareLinkParser>
+            })
+            .collect())
+    }
+}
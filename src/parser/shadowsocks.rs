@@ -0,0 +1,320 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use std::collections::HashMap;
+use url::form_urlencoded;
+
+use super::{Node, ShareLinkParser, invalid, missing, percent_decode};
+use crate::error::PawprintError;
+
+#[derive(Debug, Clone)]
+pub struct ShadowsocksConfig {
+    pub method: String,
+    pub password: String,
+    pub address: String,
+    pub port: u16,
+    // SIP003 plugin as given in the link, e.g. `obfs-local;obfs=http;obfs-host=example.com`.
+    pub plugin: Option<String>,
+    pub tag: String,
+}
+
+// Shadowsocks 2022 ciphers with the size of the key they take as the password.
+const SS2022_METHODS: [(&str, usize); 3] = [
+    ("2022-blake3-aes-128-gcm", 16),
+    ("2022-blake3-aes-256-gcm", 32),
+    ("2022-blake3-chacha20-poly1305", 32),
+];
+
+// A SIP003 plugin split up: `v2ray-plugin;tls;host=example.com` is named
+// v2ray-plugin with the options tls (an empty value) and host.
+#[derive(Debug)]
+pub struct Sip003<'a> {
+    pub name: &'a str,
+    pub options: HashMap<&'a str, &'a str>,
+}
+
+impl Sip003<'_> {
+    pub fn option(&self, key: &str) -> Option<&str> {
+        self.options.get(key).copied().filter(|v| !v.is_empty())
+    }
+
+    pub fn flag(&self, key: &str) -> bool {
+        self.options.contains_key(key)
+    }
+}
+
+impl ShadowsocksConfig {
+    pub fn is_2022(&self) -> bool {
+        self.method.starts_with("2022-")
+    }
+
+    pub fn sip003(&self) -> Option<Sip003<'_>> {
+        let mut parts = self.plugin.as_deref()?.split(';');
+        let name = parts.next().unwrap_or_default();
+        let options = parts
+            .map(|part| part.split_once('=').unwrap_or((part, "")))
+            .collect();
+        Some(Sip003 { name, options })
+    }
+
+    // SIP002 form with base64url user info.
+    pub fn to_link(&self) -> String {
+        let user_info = URL_SAFE_NO_PAD.encode(format!("{}:{}", self.method, self.password));
+        let query = match &self.plugin {
+            Some(plugin) => format!(
+                "/?{}",
+                form_urlencoded::Serializer::new(String::new())
+                    .append_pair("plugin", plugin)
+                    .finish()
+            ),
+            None => String::new(),
+        };
+        format!(
+            "ss://{}@{}{}#{}",
+            user_info,
+            super::authority(&self.address, self.port),
+            query,
+            super::encode_component(&self.tag)
+        )
+    }
+}
+
+pub struct ShadowsocksParser;
+
+impl ShareLinkParser for ShadowsocksParser {
+    fn scheme(&self) -> &str {
+        "ss"
+    }
+
+    fn parse(&self, link: &str) -> Result<Node, PawprintError> {
+        Ok(Node::Shadowsocks(parse_config(link)?))
+    }
+}
+
+fn decode_utf8(input: &[u8]) -> Result<String, PawprintError> {
+    String::from_utf8(input.to_vec())
+        .map_err(|_| invalid("Shadowsocks", "user info is not valid UTF-8"))
+}
+
+fn split_host_port(host_port: &str) -> Result<(String, u16), PawprintError> {
+    let (host, port) = host_port
+        .rsplit_once(':')
+        .ok_or_else(|| missing("Shadowsocks", "Port"))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(missing("Shadowsocks", "Host"));
+    }
+    let port = port
+        .parse()
+        .map_err(|_| invalid("Shadowsocks", format!("invalid port {}", port)))?;
+    Ok((host.to_string(), port))
+}
+
+// 2022 ciphers take base64 keys of a fixed size instead of a free-form password;
+// multi-user servers want the server key and the user key joined by colons.
+fn check_2022_key(method: &str, password: &str) -> Result<(), PawprintError> {
+    let Some((_, size)) = SS2022_METHODS.iter().find(|(name, _)| *name == method) else {
+        if method.starts_with("2022-") {
+            return Err(invalid(
+                "Shadowsocks",
+                format!("unknown Shadowsocks 2022 cipher {}", method),
+            ));
+        }
+        return Ok(());
+    };
+    let valid = password
+        .split(':')
+        .all(|key| super::decode_base64(key).is_ok_and(|key| key.len() == *size));
+    if !valid {
+        return Err(invalid(
+            "Shadowsocks",
+            format!(
+                "{} needs a base64 key of {} bytes as the password (generate one with `openssl rand -base64 {}`)",
+                method, size, size
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Accepts SIP002 links, `ss://<userinfo>@host:port/?plugin=...#tag` where the user
+// info is base64url or percent-encoded `method:password`, and the legacy form
+// `ss://<base64 of method:password@host:port>#tag`.
+pub fn parse_config(config: &str) -> Result<ShadowsocksConfig, PawprintError> {
+    let rest = config
+        .strip_prefix("ss://")
+        .ok_or_else(|| invalid("Shadowsocks", "URL must start with ss://"))?;
+    let (rest, fragment) = match rest.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (rest, None),
+    };
+    let (rest, query) = match rest.split_once('?') {
+        Some((rest, query)) => (rest, Some(query)),
+        None => (rest, None),
+    };
+    let rest = rest.trim_end_matches('/');
+
+    let (user_info, host_port) = match rest.rsplit_once('@') {
+        Some((encoded, host_port)) => {
+            let decoded = percent_decode("Shadowsocks", encoded)?;
+            let user_info = if decoded.contains(':') {
+                decoded
+            } else {
+                decode_utf8(&super::decode_base64(&decoded).map_err(|e| {
+                    invalid(
+                        "Shadowsocks",
+                        format!("user info is not valid base64: {}", e),
+                    )
+                })?)?
+            };
+            (user_info, host_port.to_string())
+        }
+        None => {
+            let decoded = decode_utf8(
+                &super::decode_base64(rest)
+                    .map_err(|e| invalid("Shadowsocks", format!("not valid base64: {}", e)))?,
+            )?;
+            let (user_info, host_port) = decoded
+                .rsplit_once('@')
+                .ok_or_else(|| missing("Shadowsocks", "Server"))?;
+            (user_info.to_string(), host_port.to_string())
+        }
+    };
+
+    let (method, password) = user_info
+        .split_once(':')
+        .ok_or_else(|| missing("Shadowsocks", "method:password"))?;
+    let (address, port) = split_host_port(&host_port)?;
+    check_2022_key(method, password)?;
+
+    let plugin = query.and_then(|query| {
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "plugin")
+            .map(|(_, value)| value.into_owned())
+            .filter(|plugin| !plugin.is_empty())
+    });
+
+    let tag = match fragment {
+        Some(fragment) => percent_decode("Shadowsocks", fragment)?,
+        None => "SS-Config".to_string(),
+    };
+
+    Ok(ShadowsocksConfig {
+        method: method.to_string(),
+        password: password.to_string(),
+        address,
+        port,
+        plugin,
+        tag,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+
+    #[test]
+    fn parses_sip002_with_base64url_user_info() {
+        let user_info = URL_SAFE_NO_PAD.encode("chacha20-ietf-poly1305:pa:ss");
+        let config = parse_config(&format!(
+            "ss://{}@198.51.100.7:8388/?plugin=obfs-local%3Bobfs%3Dhttp%3Bobfs-host%3Dexample.com#SG",
+            user_info
+        ))
+        .unwrap();
+        assert_eq!(config.method, "chacha20-ietf-poly1305");
+        assert_eq!(config.password, "pa:ss");
+        assert_eq!(config.address, "198.51.100.7");
+        assert_eq!(config.port, 8388);
+        assert_eq!(
+            config.plugin.as_deref(),
+            Some("obfs-local;obfs=http;obfs-host=example.com")
+        );
+        assert_eq!(config.tag, "SG");
+
+        let plugin = config.sip003().unwrap();
+        assert_eq!(plugin.name, "obfs-local");
+        assert_eq!(plugin.option("obfs"), Some("http"));
+        assert!(!plugin.flag("tls"));
+    }
+
+    #[test]
+    fn parses_percent_encoded_user_info() {
+        let key = STANDARD.encode([7u8; 16]);
+        let config = parse_config(&format!(
+            "ss://2022-blake3-aes-128-gcm:{}@example.com:443#2022",
+            key.replace('+', "%2B")
+                .replace('/', "%2F")
+                .replace('=', "%3D")
+        ))
+        .unwrap();
+        assert!(config.is_2022());
+        assert_eq!(config.password, key);
+    }
+
+    #[test]
+    fn parses_the_legacy_form() {
+        let encoded = STANDARD.encode("aes-256-gcm:secret@[2001:db8::2]:8389");
+        let config = parse_config(&format!("ss://{}#Old%20style", encoded)).unwrap();
+        assert_eq!(config.method, "aes-256-gcm");
+        assert_eq!(config.password, "secret");
+        assert_eq!(config.address, "2001:db8::2");
+        assert_eq!(config.port, 8389);
+        assert_eq!(config.plugin, None);
+        assert_eq!(config.tag, "Old style");
+    }
+
+    #[test]
+    fn round_trips_through_to_link() {
+        let config = ShadowsocksConfig {
+            method: "aes-128-gcm".to_string(),
+            password: "p@ss/word:1".to_string(),
+            address: "2001:db8::3".to_string(),
+            port: 443,
+            plugin: Some("v2ray-plugin;tls;host=cdn.example.com;path=/ws".to_string()),
+            tag: "東京 & more".to_string(),
+        };
+        let link = config.to_link();
+        let parsed = parse_config(&link).unwrap();
+        assert_eq!(parsed.method, config.method);
+        assert_eq!(parsed.password, config.password);
+        assert_eq!(parsed.address, config.address);
+        assert_eq!(parsed.port, config.port);
+        assert_eq!(parsed.plugin, config.plugin);
+        assert_eq!(parsed.tag, config.tag);
+        assert_eq!(parsed.to_link(), link);
+    }
+
+    #[test]
+    fn checks_2022_keys() {
+        let short = STANDARD.encode([1u8; 16]);
+        let long = STANDARD.encode([1u8; 32]);
+        let link = |method: &str, password: &str| {
+            format!(
+                "ss://{}@example.com:443",
+                URL_SAFE_NO_PAD.encode(format!("{}:{}", method, password))
+            )
+        };
+        assert!(parse_config(&link("2022-blake3-aes-256-gcm", &long)).is_ok());
+        // Multi-user: the server key, then the user key.
+        assert!(
+            parse_config(&link(
+                "2022-blake3-aes-256-gcm",
+                &format!("{}:{}", long, long)
+            ))
+            .is_ok()
+        );
+        assert!(parse_config(&link("2022-blake3-aes-256-gcm", &short)).is_err());
+        assert!(parse_config(&link("2022-blake3-aes-128-gcm", "password")).is_err());
+        assert!(parse_config(&link("2022-blake3-unknown", &long)).is_err());
+        // Other ciphers take any password.
+        assert!(parse_config(&link("aes-256-gcm", "password")).is_ok());
+    }
+
+    #[test]
+    fn needs_a_method_and_port() {
+        let no_method = URL_SAFE_NO_PAD.encode("password");
+        assert!(parse_config(&format!("ss://{}@example.com:443", no_method)).is_err());
+        let user_info = URL_SAFE_NO_PAD.encode("aes-256-gcm:password");
+        assert!(parse_config(&format!("ss://{}@example.com", user_info)).is_err());
+    }
+}
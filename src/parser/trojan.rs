@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use url::Url;
+
+use super::{Node, ShareLinkParser, check_reality, invalid, missing, percent_decode};
+use crate::error::PawprintError;
+
+#[derive(Debug, Clone)]
+pub struct TrojanConfig {
+    pub password: String,
+    pub address: String,
+    pub port: u16,
+    pub params: HashMap<String, String>,
+    pub tag: String,
+}
+
+impl TrojanConfig {
+    pub fn to_link(&self) -> String {
+        super::url_link(
+            "trojan",
+            &self.password,
+            &self.address,
+            self.port,
+            &self.params,
+            &self.tag,
+        )
+    }
+}
+
+pub struct TrojanParser;
+
+impl ShareLinkParser for TrojanParser {
+    fn scheme(&self) -> &str {
+        "trojan"
+    }
+
+    fn parse(&self, link: &str) -> Result<Node, PawprintError> {
+        Ok(Node::Trojan(parse_config(link)?))
+    }
+}
+
+pub fn parse_config(config: &str) -> Result<TrojanConfig, PawprintError> {
+    if !config.starts_with("trojan://") {
+        return Err(invalid("Trojan", "URL must start with trojan://"));
+    }
+    let url = Url::parse(config).map_err(|e| invalid("Trojan", e))?;
+
+    // Passwords are arbitrary strings, so they arrive percent-encoded.
+    let password = percent_decode("Trojan", url.username())?;
+    if password.is_empty() {
+        return Err(missing("Trojan", "Password"));
+    }
+
+    let address = url
+        .host_str()
+        .ok_or_else(|| missing("Trojan", "Host"))?
+        .to_string();
+    let port = url.port().ok_or_else(|| missing("Trojan", "Port"))?;
+
+    let mut params = HashMap::new();
+    for (key, value) in url.query_pairs() {
+        params.insert(key.to_string(), value.to_string());
+    }
+    check_reality("Trojan", &params)?;
+
+    let tag = match url.fragment() {
+        Some(fragment) => percent_decode("Trojan", fragment)?,
+        None => "Trojan-Config".to_string(),
+    };
+
+    Ok(TrojanConfig {
+        password,
+        address,
+        port,
+        params,
+        tag,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_the_password() {
+        let config =
+            parse_config("trojan://p%40ss%3Aw%2Frd@example.com:443?sni=example.com#Trojan")
+                .unwrap();
+        assert_eq!(config.password, "p@ss:w/rd");
+        assert_eq!(config.params["sni"], "example.com");
+        assert_eq!(config.tag, "Trojan");
+    }
+
+    #[test]
+    fn round_trips_through_to_link() {
+        let link = "trojan://p%40ss%20word@example.com:443?allowInsecure=0&security=tls&sni=a.example.com&type=grpc#HK%2001";
+        let config = parse_config(link).unwrap();
+        assert_eq!(config.password, "p@ss word");
+        assert_eq!(config.tag, "HK 01");
+        assert_eq!(config.to_link(), link);
+    }
+
+    #[test]
+    fn defaults_the_tag() {
+        let config = parse_config("trojan://secret@example.com:443").unwrap();
+        assert_eq!(config.tag, "Trojan-Config");
+    }
+
+    #[test]
+    fn needs_a_password() {
+        assert!(matches!(
+            parse_config("trojan://@example.com:443"),
+            Err(PawprintError::MissingParam {
+                param: "Password",
+                ..
+            })
+        ));
+    }
+}
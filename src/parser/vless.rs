@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use url::Url;
+
+use super::{Node, ShareLinkParser, check_reality, invalid, missing, percent_decode};
+use crate::error::PawprintError;
+
+#[derive(Debug, Clone)]
+pub struct VlessConfig {
+    pub uuid: String,
+    pub address: String,
+    pub port: u16,
+    pub params: HashMap<String, String>,
+    pub tag: String,
+}
+
+impl VlessConfig {
+    pub fn to_link(&self) -> String {
+        super::url_link(
+            "vless",
+            &self.uuid,
+            &self.address,
+            self.port,
+            &self.params,
+            &self.tag,
+        )
+    }
+}
+
+pub struct VlessParser;
+
+impl ShareLinkParser for VlessParser {
+    fn scheme(&self) -> &str {
+        "vless"
+    }
+
+    fn parse(&self, link: &str) -> Result<Node, PawprintError> {
+        Ok(Node::Vless(parse_config(link)?))
+    }
+}
+
+pub fn parse_config(config: &str) -> Result<VlessConfig, PawprintError> {
+    if !config.starts_with("vless://") {
+        return Err(invalid("VLESS", "URL must start with vless://"));
+    }
+    let url = Url::parse(config).map_err(|e| invalid("VLESS", e))?;
+
+    let uuid = url.username().to_string();
+    if uuid.is_empty() {
+        return Err(missing("VLESS", "UUID"));
+    }
+
+    let address = url
+        .host_str()
+        .ok_or_else(|| missing("VLESS", "Host"))?
+        .to_string();
+    let port = url.port().ok_or_else(|| missing("VLESS", "Port"))?;
+
+    let mut params = HashMap::new();
+    for (key, value) in url.query_pairs() {
+        params.insert(key.to_string(), value.to_string());
+    }
+    check_reality("VLESS", &params)?;
+
+    let tag = match url.fragment() {
+        Some(fragment) => percent_decode("VLESS", fragment)?,
+        None => "VLESS-Config".to_string(),
+    };
+
+    Ok(VlessConfig {
+        uuid,
+        address,
+        port,
+        params,
+        tag,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "b831381d-6324-4d53-ad4f-8cda48b30811";
+
+    #[test]
+    fn parses_a_reality_link() {
+        let config = parse_config(&format!(
+            "vless://{}@example.com:443?security=reality&pbk=abc&sid=01ab&flow=xtls-rprx-vision#My%20Server",
+            UUID
+        ))
+        .unwrap();
+        assert_eq!(config.uuid, UUID);
+        assert_eq!(config.address, "example.com");
+        assert_eq!(config.port, 443);
+        assert_eq!(config.params["security"], "reality");
+        assert_eq!(config.params["flow"], "xtls-rprx-vision");
+        assert_eq!(config.tag, "My Server");
+    }
+
+    #[test]
+    fn round_trips_through_to_link() {
+        let link = format!(
+            "vless://{}@[2001:db8::1]:8443?host=cdn.example.com&path=%2Fws%3Fed%3D2048&security=tls&type=ws#%E6%97%A5%E6%9C%AC%20%231",
+            UUID
+        );
+        let config = parse_config(&link).unwrap();
+        assert_eq!(config.params["path"], "/ws?ed=2048");
+        assert_eq!(config.tag, "日本 #1");
+        assert_eq!(config.to_link(), link);
+    }
+
+    #[test]
+    fn rejects_reality_without_a_public_key() {
+        let link = format!("vless://{}@example.com:443?security=reality", UUID);
+        assert!(matches!(
+            parse_config(&link),
+            Err(PawprintError::MissingParam { param: "pbk", .. })
+        ));
+    }
+
+    #[test]
+    fn needs_a_port() {
+        let link = format!("vless://{}@example.com?security=tls", UUID);
+        assert!(parse_config(&link).is_err());
+    }
+}
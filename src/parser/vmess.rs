@@ -0,0 +1,253 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+use super::{Node, ShareLinkParser, invalid, missing};
+use crate::error::PawprintError;
+
+// `params` mirrors the VLESS query parameter names (type, security, sni, host,
+// path, serviceName, mode, seed, headerType, alpn, fp) so both protocols share
+// the stream settings builder.
+#[derive(Debug, Clone)]
+pub struct VmessConfig {
+    pub uuid: String,
+    pub alter_id: u16,
+    // Body cipher: auto, aes-128-gcm, chacha20-poly1305, none or zero.
+    pub security: String,
+    pub address: String,
+    pub port: u16,
+    pub params: HashMap<String, String>,
+    pub tag: String,
+}
+
+impl VmessConfig {
+    // v2rayN JSON with every value as a string, which is what most clients write.
+    pub fn to_link(&self) -> String {
+        let param = |key: &str| self.params.get(key).cloned().unwrap_or_default();
+        let network = self
+            .params
+            .get("type")
+            .cloned()
+            .unwrap_or_else(|| "tcp".to_string());
+        let (path_key, type_key) = match network.as_str() {
+            "grpc" => ("serviceName", "mode"),
+            "kcp" => ("seed", "headerType"),
+            _ => ("path", "headerType"),
+        };
+        let security = param("security");
+        let link = json!({
+            "v": "2",
+            "ps": self.tag,
+            "add": self.address,
+            "port": self.port.to_string(),
+            "id": self.uuid,
+            "aid": self.alter_id.to_string(),
+            "scy": self.security,
+            "net": network,
+            "type": param(type_key),
+            "host": param("host"),
+            "path": param(path_key),
+            "tls": if security == "none" { String::new() } else { security },
+            "sni": param("sni"),
+            "alpn": param("alpn"),
+            "fp": param("fp"),
+        });
+        format!("vmess://{}", STANDARD.encode(link.to_string()))
+    }
+}
+
+pub struct VmessParser;
+
+impl ShareLinkParser for VmessParser {
+    fn scheme(&self) -> &str {
+        "vmess"
+    }
+
+    fn parse(&self, link: &str) -> Result<Node, PawprintError> {
+        Ok(Node::Vmess(parse_config(link)?))
+    }
+}
+
+// Clients disagree on whether numbers are JSON numbers or strings.
+fn field(link: &Value, key: &str) -> Option<String> {
+    match &link[key] {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+// Links are `vmess://` followed by base64 of the v2rayN JSON object.
+pub fn parse_config(config: &str) -> Result<VmessConfig, PawprintError> {
+    let encoded = config
+        .strip_prefix("vmess://")
+        .ok_or_else(|| invalid("VMess", "URL must start with vmess://"))?;
+    let decoded = super::decode_base64(encoded)
+        .map_err(|e| invalid("VMess", format!("not valid base64: {}", e)))?;
+    let link: Value = serde_json::from_slice(&decoded)
+        .map_err(|e| invalid("VMess", format!("does not contain a JSON object: {}", e)))?;
+
+    let uuid = field(&link, "id").ok_or_else(|| missing("VMess", "UUID"))?;
+    let address = field(&link, "add").ok_or_else(|| missing("VMess", "Host"))?;
+    let port = field(&link, "port")
+        .ok_or_else(|| missing("VMess", "Port"))?
+        .parse()
+        .map_err(|_| invalid("VMess", "invalid port"))?;
+    let alter_id = match field(&link, "aid") {
+        Some(aid) => aid
+            .parse()
+            .map_err(|_| invalid("VMess", "invalid alterId"))?,
+        None => 0,
+    };
+    let security = field(&link, "scy").unwrap_or_else(|| "auto".to_string());
+
+    let network = field(&link, "net").unwrap_or_else(|| "tcp".to_string());
+    let mut params = HashMap::new();
+    // Unlike VLESS links, a missing `tls` means a plain connection.
+    params.insert(
+        "security".to_string(),
+        field(&link, "tls").unwrap_or_else(|| "none".to_string()),
+    );
+    // vmess links overload `path` (gRPC service name, mKCP seed) and `type`
+    // (header type, gRPC mode).
+    let (path_key, type_key) = match network.as_str() {
+        "grpc" => ("serviceName", "mode"),
+        "kcp" => ("seed", "headerType"),
+        _ => ("path", "headerType"),
+    };
+    for (from, to) in [
+        ("type", type_key),
+        ("path", path_key),
+        ("host", "host"),
+        ("sni", "sni"),
+        ("alpn", "alpn"),
+        ("fp", "fp"),
+    ] {
+        if let Some(value) = field(&link, from) {
+            params.insert(to.to_string(), value);
+        }
+    }
+    params.insert("type".to_string(), network);
+
+    let tag = field(&link, "ps").unwrap_or_else(|| "VMess-Config".to_string());
+
+    Ok(VmessConfig {
+        uuid,
+        alter_id,
+        security,
+        address,
+        port,
+        params,
+        tag,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(json: Value) -> String {
+        format!("vmess://{}", STANDARD.encode(json.to_string()))
+    }
+
+    #[test]
+    fn maps_the_v2rayn_fields() {
+        // Numbers as numbers, as some clients write them.
+        let config = parse_config(&link(json!({
+            "v": "2",
+            "ps": "WS node",
+            "add": "example.com",
+            "port": 443,
+            "id": "b831381d-6324-4d53-ad4f-8cda48b30811",
+            "aid": 0,
+            "scy": "aes-128-gcm",
+            "net": "ws",
+            "host": "cdn.example.com",
+            "path": "/ray",
+            "tls": "tls",
+            "sni": "cdn.example.com",
+        })))
+        .unwrap();
+        assert_eq!(config.port, 443);
+        assert_eq!(config.alter_id, 0);
+        assert_eq!(config.security, "aes-128-gcm");
+        assert_eq!(config.params["type"], "ws");
+        assert_eq!(config.params["security"], "tls");
+        assert_eq!(config.params["path"], "/ray");
+        assert_eq!(config.params["host"], "cdn.example.com");
+        assert_eq!(config.tag, "WS node");
+    }
+
+    #[test]
+    fn maps_grpc_and_kcp_overloads() {
+        let grpc = parse_config(&link(json!({
+            "add": "example.com", "port": "443", "id": "id", "net": "grpc",
+            "path": "svc", "type": "multi",
+        })))
+        .unwrap();
+        assert_eq!(grpc.params["serviceName"], "svc");
+        assert_eq!(grpc.params["mode"], "multi");
+        assert!(!grpc.params.contains_key("path"));
+
+        let kcp = parse_config(&link(json!({
+            "add": "example.com", "port": "443", "id": "id", "net": "kcp",
+            "path": "seed", "type": "wechat-video",
+        })))
+        .unwrap();
+        assert_eq!(kcp.params["seed"], "seed");
+        assert_eq!(kcp.params["headerType"], "wechat-video");
+    }
+
+    #[test]
+    fn missing_tls_is_a_plain_connection() {
+        let config = parse_config(&link(json!({
+            "add": "example.com", "port": "80", "id": "id",
+        })))
+        .unwrap();
+        assert_eq!(config.params["security"], "none");
+        assert_eq!(config.params["type"], "tcp");
+        assert_eq!(config.security, "auto");
+    }
+
+    #[test]
+    fn round_trips_through_to_link() {
+        for net in ["ws", "grpc", "kcp", "tcp"] {
+            let original = parse_config(&link(json!({
+                "ps": "節點 1", "add": "example.com", "port": "8443",
+                "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "aid": "2", "scy": "chacha20-poly1305",
+                "net": net, "type": "none", "host": "h.example.com", "path": "/p",
+                "tls": "tls", "sni": "s.example.com", "alpn": "h2,http/1.1", "fp": "chrome",
+            })))
+            .unwrap();
+            let parsed = parse_config(&original.to_link()).unwrap();
+            assert_eq!(parsed.uuid, original.uuid);
+            assert_eq!(parsed.alter_id, 2);
+            assert_eq!(parsed.security, original.security);
+            assert_eq!(parsed.address, original.address);
+            assert_eq!(parsed.port, original.port);
+            assert_eq!(parsed.params, original.params, "{}", net);
+            assert_eq!(parsed.tag, original.tag);
+        }
+    }
+
+    #[test]
+    fn accepts_unpadded_url_safe_base64() {
+        let json = json!({"add": "example.com", "port": "443", "id": "id", "ps": "??>"});
+        let encoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json.to_string());
+        let config = parse_config(&format!("vmess://{}", encoded)).unwrap();
+        assert_eq!(config.tag, "??>");
+    }
+
+    #[test]
+    fn rejects_bad_payloads() {
+        assert!(parse_config("vmess://not base64!").is_err());
+        assert!(parse_config(&format!("vmess://{}", STANDARD.encode("[]"))).is_err());
+        assert!(
+            parse_config(&link(
+                json!({"add": "example.com", "port": "x", "id": "id"})
+            ))
+            .is_err()
+        );
+    }
+}
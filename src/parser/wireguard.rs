@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use url::Url;
+
+use super::{Node, ShareLinkParser, decode_base64, invalid, missing, percent_decode};
+use crate::error::PawprintError;
+
+// A WireGuard peer in the `wireguard://` link form v2rayN uses: the private
+// key as user info and the interface settings as parameters (`publickey`,
+// `address`, `reserved`, `mtu`, `presharedkey`, `keepalive`).
+#[derive(Debug, Clone)]
+pub struct WireguardConfig {
+    pub private_key: String,
+    // The peer's endpoint.
+    pub address: String,
+    pub port: u16,
+    pub params: HashMap<String, String>,
+    pub tag: String,
+}
+
+impl WireguardConfig {
+    pub fn to_link(&self) -> String {
+        super::url_link(
+            "wireguard",
+            &self.private_key,
+            &self.address,
+            self.port,
+            &self.params,
+            &self.tag,
+        )
+    }
+
+    // Addresses of the local interface, e.g. 172.16.0.2/32.
+    pub fn local_addresses(&self) -> Vec<&str> {
+        self.params
+            .get("address")
+            .into_iter()
+            .flat_map(|a| a.split(','))
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .collect()
+    }
+
+    // The three bytes Cloudflare WARP expects after the message type.
+    pub fn reserved(&self) -> Option<Vec<u8>> {
+        let reserved = self.params.get("reserved")?;
+        reserved.split(',').map(|b| b.trim().parse().ok()).collect()
+    }
+
+    // Reads a standard wg-quick file with one [Peer]. A `Reserved = 1,2,3`
+    // line, as written by WARP tools, is picked up from either section.
+    pub fn from_conf(conf: &str, tag: &str) -> Result<WireguardConfig, PawprintError> {
+        let mut section = "";
+        let mut peers = 0;
+        let mut private_key = None;
+        let mut endpoint = None;
+        let mut addresses: Vec<String> = Vec::new();
+        let mut params = HashMap::new();
+        for line in conf.lines() {
+            let line = line.split(['#', ';']).next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                section = match line.to_ascii_lowercase().as_str() {
+                    "[interface]" => "interface",
+                    "[peer]" => {
+                        peers += 1;
+                        "peer"
+                    }
+                    _ => return Err(invalid("WireGuard", format!("unknown section {}", line))),
+                };
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(invalid(
+                    "WireGuard",
+                    format!("expected key = value: {}", line),
+                ));
+            };
+            let value = value.trim().to_string();
+            match (section, key.trim().to_ascii_lowercase().as_str()) {
+                ("interface", "privatekey") => private_key = Some(value),
+                ("interface", "address") => addresses.push(value),
+                ("interface", "mtu") => {
+                    params.insert("mtu".to_string(), value);
+                }
+                ("peer", "publickey") => {
+                    params.insert("publickey".to_string(), value);
+                }
+                ("peer", "presharedkey") => {
+                    params.insert("presharedkey".to_string(), value);
+                }
+                ("peer", "persistentkeepalive") => {
+                    params.insert("keepalive".to_string(), value);
+                }
+                ("peer", "endpoint") => endpoint = Some(value),
+                (_, "reserved") => {
+                    params.insert("reserved".to_string(), value);
+                }
+                // DNS, AllowedIPs, routing table and hook settings only matter to wg-quick.
+                _ => {}
+            }
+        }
+        if peers > 1 {
+            return Err(invalid(
+                "WireGuard",
+                "only configs with a single [Peer] can be converted",
+            ));
+        }
+        let private_key = private_key.ok_or_else(|| missing("WireGuard", "PrivateKey"))?;
+        let endpoint = endpoint.ok_or_else(|| missing("WireGuard", "Endpoint"))?;
+        let (host, port) = endpoint
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+            .ok_or_else(|| invalid("WireGuard", "Endpoint must be host:port"))?;
+        if !addresses.is_empty() {
+            params.insert("address".to_string(), addresses.join(","));
+        }
+
+        let config = WireguardConfig {
+            private_key,
+            address: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            params,
+            tag: tag.to_string(),
+        };
+        check(config)
+    }
+}
+
+// Registered for both `wireguard://` and the short `wg://`.
+pub struct WireguardParser(pub &'static str);
+
+impl ShareLinkParser for WireguardParser {
+    fn scheme(&self) -> &str {
+        self.0
+    }
+
+    fn parse(&self, link: &str) -> Result<Node, PawprintError> {
+        Ok(Node::Wireguard(parse_config(link)?))
+    }
+}
+
+pub fn parse_config(config: &str) -> Result<WireguardConfig, PawprintError> {
+    let url = Url::parse(config).map_err(|e| invalid("WireGuard", e))?;
+    if url.scheme() != "wireguard" && url.scheme() != "wg" {
+        return Err(invalid(
+            "WireGuard",
+            "URL must start with wireguard:// or wg://",
+        ));
+    }
+
+    let private_key = percent_decode("WireGuard", url.username())?;
+    let address = url
+        .host_str()
+        .ok_or_else(|| missing("WireGuard", "Host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let port = url.port().ok_or_else(|| missing("WireGuard", "Port"))?;
+
+    let mut params = HashMap::new();
+    for (key, value) in url.query_pairs() {
+        params.insert(key.to_string(), value.to_string());
+    }
+
+    let tag = match url.fragment() {
+        Some(fragment) => percent_decode("WireGuard", fragment)?,
+        None => "WireGuard-Config".to_string(),
+    };
+
+    check(WireguardConfig {
+        private_key,
+        address,
+        port,
+        params,
+        tag,
+    })
+}
+
+// Keys must be 32 bytes; reserved bytes are normalized to `a,b,c` since some
+// tools write them as base64 instead.
+fn check(mut config: WireguardConfig) -> Result<WireguardConfig, PawprintError> {
+    let is_key = |key: &str| decode_base64(key).is_ok_and(|k| k.len() == 32);
+    if config.private_key.is_empty() {
+        return Err(missing("WireGuard", "PrivateKey"));
+    }
+    if !is_key(&config.private_key) {
+        return Err(invalid(
+            "WireGuard",
+            "private key is not 32 bytes of base64",
+        ));
+    }
+    match config.params.get("publickey").filter(|k| !k.is_empty()) {
+        None => return Err(missing("WireGuard", "PublicKey")),
+        Some(key) if !is_key(key) => {
+            return Err(invalid("WireGuard", "public key is not 32 bytes of base64"));
+        }
+        Some(_) => {}
+    }
+    if let Some(reserved) = config.params.get("reserved").filter(|r| !r.is_empty()) {
+        let bytes: Option<Vec<u8>> = if reserved.contains(',') || reserved.parse::<u8>().is_ok() {
+            reserved.split(',').map(|b| b.trim().parse().ok()).collect()
+        } else {
+            decode_base64(reserved).ok()
+        };
+        let Some(bytes) = bytes.filter(|b| b.len() == 3) else {
+            return Err(invalid(
+                "WireGuard",
+                format!("reserved {:?} must be three bytes, e.g. 1,2,3", reserved),
+            ));
+        };
+        let normalized: Vec<String> = bytes.iter().map(u8::to_string).collect();
+        config
+            .params
+            .insert("reserved".to_string(), normalized.join(","));
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+
+    use crate::parser::encode_component as urlencode;
+
+    fn key(byte: u8) -> String {
+        STANDARD.encode([byte; 32])
+    }
+
+    #[test]
+    fn round_trips_through_to_link() {
+        let config = WireguardConfig {
+            private_key: key(1),
+            address: "2606:4700:d0::a29f:c001".to_string(),
+            port: 2408,
+            params: HashMap::from([
+                ("publickey".to_string(), key(2)),
+                (
+                    "address".to_string(),
+                    "172.16.0.2/32,2606:4700::2/128".to_string(),
+                ),
+                ("reserved".to_string(), "1,2,3".to_string()),
+                ("mtu".to_string(), "1280".to_string()),
+            ]),
+            tag: "WARP".to_string(),
+        };
+        let link = config.to_link();
+        let parsed = parse_config(&link).unwrap();
+        assert_eq!(parsed.private_key, config.private_key);
+        assert_eq!(parsed.address, config.address);
+        assert_eq!(parsed.port, config.port);
+        assert_eq!(parsed.params, config.params);
+        assert_eq!(parsed.tag, config.tag);
+        assert_eq!(parsed.to_link(), link);
+        assert_eq!(
+            parsed.local_addresses(),
+            ["172.16.0.2/32", "2606:4700::2/128"]
+        );
+        assert_eq!(parsed.reserved(), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn normalizes_base64_reserved_bytes() {
+        let link = format!(
+            "wg://{}@engage.cloudflareclient.com:2408?publickey={}&reserved=AQID",
+            urlencode(&key(1)),
+            urlencode(&key(2))
+        );
+        let config = parse_config(&link).unwrap();
+        assert_eq!(config.params["reserved"], "1,2,3");
+    }
+
+    #[test]
+    fn checks_keys() {
+        let link = format!(
+            "wireguard://{}@example.com:51820?publickey=short",
+            urlencode(&key(1))
+        );
+        assert!(parse_config(&link).is_err());
+        let link = format!("wireguard://{}@example.com:51820", urlencode(&key(1)));
+        assert!(matches!(
+            parse_config(&link),
+            Err(PawprintError::MissingParam {
+                param: "PublicKey",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn reads_wg_quick_files() {
+        let conf = format!(
+            "[Interface]\nPrivateKey = {}\nAddress = 10.0.0.2/32\nAddress = fd00::2/128\nDNS = 1.1.1.1\nMTU = 1420\n\n[Peer]\nPublicKey = {}\nEndpoint = [2001:db8::1]:51820\nPersistentKeepalive = 25 # keep NAT open\n",
+            key(1),
+            key(2)
+        );
+        let config = WireguardConfig::from_conf(&conf, "home").unwrap();
+        assert_eq!(config.address, "2001:db8::1");
+        assert_eq!(config.port, 51820);
+        assert_eq!(config.params["address"], "10.0.0.2/32,fd00::2/128");
+        assert_eq!(config.params["keepalive"], "25");
+        assert_eq!(config.params["mtu"], "1420");
+        assert_eq!(config.tag, "home");
+
+        let two_peers = format!("{}[Peer]\nPublicKey = {}\n", conf, key(3));
+        assert!(WireguardConfig::from_conf(&two_peers, "home").is_err());
+    }
+}
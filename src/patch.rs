@@ -0,0 +1,375 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+use crate::error::PawprintError;
+use crate::{env, jsonc};
+
+pub fn load(path: &Path, env_subst: bool) -> Result<Value, PawprintError> {
+    let content = fs::read_to_string(path).map_err(|e| PawprintError::file(path, e))?;
+    let mut value =
+        jsonc::parse(&content).map_err(|e| format!("Invalid JSON in {}: {}", path.display(), e))?;
+    if env_subst {
+        env::substitute_json(&mut value, path)?;
+    }
+    Ok(value)
+}
+
+// RFC 7386 JSON Merge Patch: objects merge recursively, `null` removes a key and
+// anything else (including arrays) replaces the target value.
+pub fn merge(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let target = target.as_object_mut().expect("target is an object");
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+// Deep merge of a hand-written fragment into a generated config. Unlike a merge
+// patch, arrays are combined: entries whose `tag` the config already has are
+// merged into that entry, other entries are added after the generated ones, and
+// `rules` go first so they win over the generated rules. With `overwrite` the
+// fragment's values replace the config's, otherwise they only fill gaps, which
+// is how a template behaves.
+pub fn deep_merge(config: &mut Value, fragment: &Value, overwrite: bool) {
+    merge_value(config, fragment, overwrite, "");
+}
+
+fn merge_value(config: &mut Value, fragment: &Value, overwrite: bool, key: &str) {
+    match (config, fragment) {
+        (Value::Object(config), Value::Object(fragment)) => {
+            for (key, value) in fragment {
+                match config.get_mut(key) {
+                    Some(existing) => merge_value(existing, value, overwrite, key),
+                    None => {
+                        config.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (Value::Array(config), Value::Array(fragment)) => {
+            let mut added = Vec::new();
+            for entry in fragment {
+                let existing = entry["tag"]
+                    .as_str()
+                    .and_then(|tag| config.iter().position(|e| e["tag"] == tag));
+                match existing {
+                    Some(index) => merge_value(&mut config[index], entry, overwrite, ""),
+                    None if !config.contains(entry) => added.push(entry.clone()),
+                    None => {}
+                }
+            }
+            if key == "rules" {
+                config.splice(0..0, added);
+            } else {
+                config.extend(added);
+            }
+        }
+        (config, fragment) => {
+            if overwrite || config.is_null() {
+                *config = fragment.clone();
+            }
+        }
+    }
+}
+
+// RFC 6902 operation. Only the operations pawprint supports are accepted.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Test { path: String, value: Value },
+}
+
+pub fn load_operations(path: &Path, env_subst: bool) -> Result<Value, PawprintError> {
+    let ops = load(path, env_subst)?;
+    if !ops.is_array() {
+        return Err(format!("{} must contain an array of operations", path.display()).into());
+    }
+    Ok(ops)
+}
+
+// Applies an RFC 6902 JSON Patch. The patch is all-or-nothing: on any failure,
+// including a `test` operation that does not match, `target` is left untouched.
+pub fn apply_operations(target: &mut Value, ops: &Value) -> Result<(), PawprintError> {
+    let ops = ops
+        .as_array()
+        .ok_or("A JSON patch must be an array of operations")?;
+    let mut patched = target.clone();
+
+    for (index, op) in ops.iter().enumerate() {
+        Operation::deserialize(op)
+            .map_err(|e| e.to_string())
+            .and_then(|op| apply_operation(&mut patched, &op))
+            .map_err(|e| format!("JSON patch operation {} failed: {}", index, e))?;
+    }
+
+    *target = patched;
+    Ok(())
+}
+
+fn apply_operation(target: &mut Value, op: &Operation) -> Result<(), String> {
+    match op {
+        Operation::Add { path, value } => add(target, path, value.clone()),
+        Operation::Remove { path } => remove(target, path).map(drop),
+        Operation::Replace { path, value } => {
+            let slot = target
+                .pointer_mut(path)
+                .ok_or_else(|| format!("replace: path {} does not exist", path))?;
+            *slot = value.clone();
+            Ok(())
+        }
+        Operation::Test { path, value } => match target.pointer(path) {
+            Some(actual) if actual == value => Ok(()),
+            Some(actual) => Err(format!("test: {} is {}, expected {}", path, actual, value)),
+            None => Err(format!("test: path {} does not exist", path)),
+        },
+    }
+}
+
+// Splits a JSON pointer into its parent pointer and unescaped last token.
+fn split_pointer(path: &str) -> Result<(&str, String), String> {
+    let (parent, last) = path
+        .rsplit_once('/')
+        .ok_or_else(|| format!("invalid JSON pointer: {}", path))?;
+    if !parent.is_empty() && !parent.starts_with('/') {
+        return Err(format!("invalid JSON pointer: {}", path));
+    }
+    Ok((parent, last.replace("~1", "/").replace("~0", "~")))
+}
+
+fn add(target: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    if path.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+    let (parent, key) = split_pointer(path)?;
+    match target.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.insert(key, value);
+            Ok(())
+        }
+        Some(Value::Array(items)) => {
+            let index = if key == "-" {
+                items.len()
+            } else {
+                array_index(&key, items.len() + 1)?
+            };
+            items.insert(index, value);
+            Ok(())
+        }
+        Some(_) => Err(format!("add: parent of {} is not a container", path)),
+        None => Err(format!("add: parent of {} does not exist", path)),
+    }
+}
+
+fn remove(target: &mut Value, path: &str) -> Result<Value, String> {
+    let (parent, key) = split_pointer(path)?;
+    match target.pointer_mut(parent) {
+        Some(Value::Object(map)) => map
+            .remove(&key)
+            .ok_or_else(|| format!("remove: path {} does not exist", path)),
+        Some(Value::Array(items)) => {
+            let index = array_index(&key, items.len())?;
+            Ok(items.remove(index))
+        }
+        _ => Err(format!("remove: path {} does not exist", path)),
+    }
+}
+
+fn array_index(token: &str, len: usize) -> Result<usize, String> {
+    match token.parse::<usize>() {
+        Ok(index) if index < len && (token == "0" || !token.starts_with('0')) => Ok(index),
+        _ => Err(format!("array index {} is out of bounds", token)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merge_patch_follows_rfc_7386() {
+        let mut target = json!({
+            "log": {"loglevel": "info", "access": "/var/log/access.log"},
+            "dns": {"servers": ["1.1.1.1", "8.8.8.8"]},
+            "stats": {},
+        });
+        merge(
+            &mut target,
+            &json!({
+                "log": {"loglevel": "warning", "access": null},
+                // Arrays are replaced, not appended to.
+                "dns": {"servers": ["9.9.9.9"]},
+                "stats": null,
+                "api": {"tag": "api"},
+            }),
+        );
+        assert_eq!(
+            target,
+            json!({
+                "log": {"loglevel": "warning"},
+                "dns": {"servers": ["9.9.9.9"]},
+                "api": {"tag": "api"},
+            })
+        );
+    }
+
+    #[test]
+    fn merge_patch_replaces_non_objects() {
+        let mut target = json!({"a": [1, 2]});
+        merge(&mut target, &json!({"a": {"b": 1, "c": null}}));
+        assert_eq!(target, json!({"a": {"b": 1}}));
+
+        let mut target = json!({"a": 1});
+        merge(&mut target, &json!(["whole"]));
+        assert_eq!(target, json!(["whole"]));
+
+        // Deleting a key that is not there changes nothing.
+        let mut target = json!({"a": 1});
+        merge(&mut target, &json!({"b": null}));
+        assert_eq!(target, json!({"a": 1}));
+    }
+
+    #[test]
+    fn deep_merge_combines_arrays() {
+        let mut config = json!({
+            "outbounds": [
+                {"tag": "proxy", "protocol": "vless"},
+                {"tag": "direct", "protocol": "freedom"},
+            ],
+            "routing": {"rules": [{"outboundTag": "direct", "ip": ["geoip:private"]}]},
+            "dns": {"servers": ["1.1.1.1"]},
+        });
+        deep_merge(
+            &mut config,
+            &json!({
+                "outbounds": [
+                    {"tag": "proxy", "mux": {"enabled": true}},
+                    {"tag": "warp", "protocol": "wireguard"},
+                ],
+                "routing": {"rules": [{"outboundTag": "block", "domain": ["geosite:ads"]}]},
+                // Entries already there are not added twice.
+                "dns": {"servers": ["1.1.1.1", "8.8.8.8"]},
+            }),
+            true,
+        );
+        assert_eq!(
+            config,
+            json!({
+                "outbounds": [
+                    {"tag": "proxy", "protocol": "vless", "mux": {"enabled": true}},
+                    {"tag": "direct", "protocol": "freedom"},
+                    {"tag": "warp", "protocol": "wireguard"},
+                ],
+                "routing": {"rules": [
+                    {"outboundTag": "block", "domain": ["geosite:ads"]},
+                    {"outboundTag": "direct", "ip": ["geoip:private"]},
+                ]},
+                "dns": {"servers": ["1.1.1.1", "8.8.8.8"]},
+            })
+        );
+    }
+
+    #[test]
+    fn deep_merge_without_overwrite_only_fills_gaps() {
+        let mut config = json!({"log": {"loglevel": "warning", "access": null}, "port": 1080});
+        deep_merge(
+            &mut config,
+            &json!({"log": {"loglevel": "debug", "access": "none", "error": "e.log"}, "port": 1}),
+            false,
+        );
+        assert_eq!(
+            config,
+            json!({"log": {"loglevel": "warning", "access": "none", "error": "e.log"}, "port": 1080})
+        );
+
+        let mut config = json!({"port": 1080});
+        deep_merge(&mut config, &json!({"port": 1}), true);
+        assert_eq!(config, json!({"port": 1}));
+    }
+
+    #[test]
+    fn json_patch_applies_operations_in_order() {
+        let mut target = json!({"inbounds": [{"port": 1080}], "a/b": {"~c": 1}});
+        apply_operations(
+            &mut target,
+            &json!([
+                {"op": "test", "path": "/inbounds/0/port", "value": 1080},
+                {"op": "replace", "path": "/inbounds/0/port", "value": 2080},
+                {"op": "add", "path": "/inbounds/-", "value": {"port": 3080}},
+                {"op": "add", "path": "/inbounds/0", "value": {"port": 80}},
+                {"op": "remove", "path": "/a~1b/~0c"},
+                {"op": "add", "path": "/log", "value": {"loglevel": "none"}},
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            target,
+            json!({
+                "inbounds": [{"port": 80}, {"port": 2080}, {"port": 3080}],
+                "a/b": {},
+                "log": {"loglevel": "none"},
+            })
+        );
+    }
+
+    #[test]
+    fn json_patch_is_all_or_nothing() {
+        let original = json!({"inbounds": [{"port": 1080}]});
+        let failing = [
+            json!([
+                {"op": "replace", "path": "/inbounds/0/port", "value": 2080},
+                {"op": "test", "path": "/inbounds/0/port", "value": 1080},
+            ]),
+            json!([{"op": "remove", "path": "/outbounds"}]),
+            json!([{"op": "replace", "path": "/missing", "value": 1}]),
+            json!([{"op": "add", "path": "/inbounds/2", "value": 1}]),
+            json!([{"op": "add", "path": "/inbounds/01", "value": 1}]),
+            json!([{"op": "add", "path": "/missing/key", "value": 1}]),
+            json!([{"op": "move", "from": "/inbounds", "path": "/x"}]),
+        ];
+        for ops in failing {
+            let mut target = original.clone();
+            assert!(apply_operations(&mut target, &ops).is_err(), "{}", ops);
+            assert_eq!(target, original, "{}", ops);
+        }
+    }
+
+    #[test]
+    fn json_patch_errors_name_the_operation() {
+        let mut target = json!({"a": 1});
+        let ops = json!([
+            {"op": "test", "path": "/a", "value": 1},
+            {"op": "move", "from": "/a", "path": "/b"},
+        ]);
+        let error = apply_operations(&mut target, &ops).unwrap_err().to_string();
+        assert!(error.contains("operation 1"), "{}", error);
+        assert!(apply_operations(&mut target, &json!({"op": "test"})).is_err());
+    }
+
+    #[test]
+    fn json_patch_add_at_the_root_replaces_everything() {
+        let mut target = json!({"a": 1});
+        apply_operations(
+            &mut target,
+            &json!([{"op": "add", "path": "", "value": [1]}]),
+        )
+        .unwrap();
+        assert_eq!(target, json!([1]));
+    }
+}
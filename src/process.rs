@@ -0,0 +1,371 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::error::PawprintError;
+use crate::geo;
+use crate::hooks::{self, Event};
+use crate::logfile::{self, RotatingFile};
+use crate::xraycore;
+
+// How long `stop` waits for xray to exit after SIGTERM before killing it.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Cores started in the background by this process, as the TUI and the daemon do.
+// One that exits stays a zombie, which still looks alive to a signal check, until
+// its Child is waited on.
+static STARTED: Mutex<Vec<Child>> = Mutex::new(Vec::new());
+
+// xray's captured output is rotated past this size, keeping three old files.
+const LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+// Written next to the running core so stop/status/restart can find it again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PidFile {
+    pub pid: u32,
+    pub xray: String,
+    pub config: PathBuf,
+    // Where xray's output goes; None if the log could not be opened.
+    pub log: Option<PathBuf>,
+    // Nothing waits on a detached xray, so whoever stops it reports the disconnect.
+    #[serde(default)]
+    pub detached: bool,
+    // When the process started, so a pid reused after a crash is not taken for
+    // xray. Files written before this was recorded have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started: Option<String>,
+}
+
+pub fn state_dir() -> PathBuf {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("pawprint-vpn")
+}
+
+pub fn default_pid_file() -> PathBuf {
+    state_dir().join("xray.pid")
+}
+
+// xray's output is kept next to its pid file: xray.pid logs to xray.log.
+pub fn log_file(pid_file: &Path) -> PathBuf {
+    pid_file.with_extension("log")
+}
+
+impl PidFile {
+    pub fn load(path: &Path) -> Result<Option<PidFile>, PawprintError> {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                Ok(Some(serde_json::from_str(&content).map_err(|e| {
+                    format!("Invalid pid file {}: {}", path.display(), e)
+                })?))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read pid file {}: {}", path.display(), e).into()),
+        }
+    }
+
+    // Whether the recorded core is still running, as opposed to another process
+    // that got its pid.
+    fn is_running(&self) -> bool {
+        is_alive(self.pid)
+            && match &self.started {
+                Some(started) => start_time(self.pid).as_ref() == Some(started),
+                None => true,
+            }
+    }
+
+    fn hook_vars(&self) -> [(&'static str, String); 2] {
+        [
+            ("PID", self.pid.to_string()),
+            ("CONFIG", self.config.display().to_string()),
+        ]
+    }
+
+    fn save(&self, path: &Path) -> Result<(), PawprintError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+// The pid file of a core that is still alive; stale files are removed.
+pub fn running(pid_file: &Path) -> Result<Option<PidFile>, PawprintError> {
+    match PidFile::load(pid_file)? {
+        Some(state) if state.is_running() => Ok(Some(state)),
+        Some(_) => {
+            fs::remove_file(pid_file)?;
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
+
+fn spawn(
+    xray: &str,
+    config: &Path,
+    stdout: Stdio,
+    stderr: Stdio,
+    detach: bool,
+) -> Result<Child, String> {
+    let mut command = Command::new(xraycore::program(xray));
+    command
+        .arg("run")
+        .arg("-c")
+        .arg(config)
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(stderr);
+    geo::set_asset_dir(&mut command);
+    // Keep a detached core alive when the terminal that started it closes. In the
+    // foreground it stays in our group so Ctrl-C reaches it too.
+    #[cfg(unix)]
+    if detach {
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    }
+    #[cfg(windows)]
+    if detach {
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        std::os::windows::process::CommandExt::creation_flags(&mut command, DETACHED_PROCESS);
+    }
+    command.spawn().map_err(|e| {
+        format!(
+            "Failed to start {} (install it with `pawprint-vpn core install` or see --xray): {}",
+            xray, e
+        )
+    })
+}
+
+fn check_not_running(pid_file: &Path) -> Result<(), PawprintError> {
+    if let Some(state) = running(pid_file)? {
+        return Err(format!(
+            "xray is already running (pid {}, config {}). Stop it first.",
+            state.pid,
+            state.config.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn forward_lines(
+    stream: impl Read + Send + 'static,
+    log: Option<Arc<Mutex<RotatingFile>>>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            info!(target: "xray", "{}", line);
+            if let Some(log) = &log {
+                let mut log = log.lock().unwrap();
+                let _ = writeln!(log, "{} {}", logfile::timestamp(), line);
+            }
+        }
+    })
+}
+
+// Runs xray in the foreground until it exits, streaming its output through the
+// log and into the timestamped log file next to the pid file.
+pub fn run_foreground(xray: &str, config: &Path, pid_file: &Path) -> Result<(), PawprintError> {
+    check_not_running(pid_file)?;
+    let log_path = log_file(pid_file);
+    let log = match RotatingFile::open(&log_path, LOG_MAX_SIZE) {
+        Ok(log) => Some(Arc::new(Mutex::new(log))),
+        Err(e) => {
+            warn!("Not saving xray's output to {}: {}", log_path.display(), e);
+            None
+        }
+    };
+    let mut child = spawn(xray, config, Stdio::piped(), Stdio::piped(), false)?;
+    let state = PidFile {
+        pid: child.id(),
+        xray: xray.to_string(),
+        config: config.to_path_buf(),
+        log: log.is_some().then_some(log_path),
+        detached: false,
+        started: start_time(child.id()),
+    };
+    state.save(pid_file)?;
+    info!(
+        "Started xray (pid {}) with {}",
+        child.id(),
+        config.display()
+    );
+    hooks::fire(Event::Connected, &state.hook_vars());
+
+    let readers = [
+        forward_lines(child.stdout.take().expect("stdout is piped"), log.clone()),
+        forward_lines(child.stderr.take().expect("stderr is piped"), log),
+    ];
+    let status = child.wait()?;
+    for reader in readers {
+        let _ = reader.join();
+    }
+    let _ = fs::remove_file(pid_file);
+    hooks::fire(Event::Disconnected, &state.hook_vars());
+
+    if status.success() {
+        info!("xray exited");
+        Ok(())
+    } else {
+        Err(format!("xray exited with {}", status).into())
+    }
+}
+
+// Starts xray in the background with its output appended to the log file next
+// to the pid file. xray writes it directly, so lines are only timestamped by
+// xray itself and the file is rotated when the next core starts.
+pub fn start_detached(
+    xray: &str,
+    config: &Path,
+    pid_file: &Path,
+) -> Result<PidFile, PawprintError> {
+    check_not_running(pid_file)?;
+    let log = log_file(pid_file);
+    drop(RotatingFile::open(&log, LOG_MAX_SIZE)?);
+    let output = OpenOptions::new().create(true).append(true).open(&log)?;
+    let mut child = spawn(
+        xray,
+        config,
+        output.try_clone()?.into(),
+        output.into(),
+        true,
+    )?;
+
+    // A bad config makes xray exit right away; report that instead of a stale pid.
+    thread::sleep(Duration::from_millis(300));
+    if let Some(status) = child.try_wait()? {
+        return Err(format!("xray exited with {}, see {}", status, log.display()).into());
+    }
+
+    let state = PidFile {
+        pid: child.id(),
+        xray: xray.to_string(),
+        config: config.to_path_buf(),
+        log: Some(log),
+        detached: true,
+        started: start_time(child.id()),
+    };
+    state.save(pid_file)?;
+    STARTED.lock().unwrap().push(child);
+    hooks::fire(Event::Connected, &state.hook_vars());
+    Ok(state)
+}
+
+// Stops the running core. Returns its pid file, or None if nothing was running.
+pub fn stop(pid_file: &Path) -> Result<Option<PidFile>, PawprintError> {
+    let Some(state) = running(pid_file)? else {
+        return Ok(None);
+    };
+
+    terminate(state.pid, false)?;
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while state.is_running() {
+        if Instant::now() > deadline {
+            warn!("xray did not exit in time, killing it");
+            terminate(state.pid, true)?;
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    // A foreground `run` reports xray exiting and removes the file itself.
+    if state.detached {
+        hooks::fire(Event::Disconnected, &state.hook_vars());
+    }
+    match fs::remove_file(pid_file) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(Some(state)),
+    }
+}
+
+// Cores of this process are checked through their Child, which also reaps them.
+// Nothing else is waited on here: a foreground `run` may be blocked waiting on
+// the pid, and reaping it first would leave that wait failing.
+fn is_alive(pid: u32) -> bool {
+    let mut started = STARTED.lock().unwrap();
+    let ours = started.iter().any(|child| child.id() == pid);
+    // Reaped ones are forgotten, as their pids may be reused from here on.
+    started.retain_mut(|child| !matches!(child.try_wait(), Ok(Some(_))));
+    if ours {
+        return started.iter().any(|child| child.id() == pid);
+    }
+    drop(started);
+    pid_alive(pid)
+}
+
+// The start time of `pid` as the OS reports it, in whatever unit it uses; only
+// compared with itself.
+#[cfg(target_os = "linux")]
+fn start_time(pid: u32) -> Option<String> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name in parentheses may contain spaces; field 22 is the start
+    // time in clock ticks since boot.
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19).map(str::to_string)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn start_time(pid: u32) -> Option<String> {
+    let output = Command::new("ps")
+        .args(["-o", "lstart=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    let started = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !started.is_empty()).then_some(started)
+}
+
+#[cfg(windows)]
+fn start_time(_pid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+fn pid_alive(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists and may be signalled.
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32, force: bool) -> Result<(), PawprintError> {
+    let signal = if force { libc::SIGKILL } else { libc::SIGTERM };
+    // SAFETY: plain syscall on a pid read from our own pid file.
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+        return Err(format!(
+            "Failed to signal pid {}: {}",
+            pid,
+            std::io::Error::last_os_error()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn pid_alive(pid: u32) -> bool {
+    Command::new("tasklist")
+        .args(["/NH", "/FI", &format!("PID eq {}", pid)])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+}
+
+#[cfg(windows)]
+fn terminate(pid: u32, force: bool) -> Result<(), PawprintError> {
+    let mut command = Command::new("taskkill");
+    command.args(["/PID", &pid.to_string()]);
+    if force {
+        command.arg("/F");
+    }
+    let status = command.status()?;
+    if !status.success() {
+        return Err(format!("taskkill failed for pid {}: {}", pid, status).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,334 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::PawprintError;
+use crate::fuzzy;
+use crate::logfile;
+use crate::spec::Spec;
+use crate::vault;
+
+// Profiles are specs kept in ~/.config/pawprint-vpn/profiles/<name>.toml, or
+// <name>.toml.age once `profile encrypt` ran. The one last generated with
+// `profile use` is recorded in `active-profile` next to it.
+pub fn base_dir() -> Result<PathBuf, PawprintError> {
+    Ok(dirs::config_dir()
+        .ok_or("Could not determine the config directory")?
+        .join("pawprint-vpn"))
+}
+
+fn profiles_dir() -> Result<PathBuf, PawprintError> {
+    Ok(base_dir()?.join("profiles"))
+}
+
+// Config regenerated by `profile use`.
+pub fn active_config() -> Result<PathBuf, PawprintError> {
+    Ok(base_dir()?.join("config.json"))
+}
+
+pub fn check_name(name: &str) -> Result<(), PawprintError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(format!(
+            "Invalid profile name: {} (use letters, digits, '-', '_' and '.')",
+            name
+        )
+        .into());
+    }
+    Ok(())
+}
+
+pub fn path(name: &str) -> Result<PathBuf, PawprintError> {
+    check_name(name)?;
+    Ok(profiles_dir()?.join(format!("{}.toml", name)))
+}
+
+// `<name>.toml.age` when the profile is stored encrypted.
+fn encrypted_path(name: &str) -> Result<PathBuf, PawprintError> {
+    check_name(name)?;
+    Ok(profiles_dir()?.join(format!("{}.toml{}", name, vault::SUFFIX)))
+}
+
+pub fn exists(name: &str) -> Result<bool, PawprintError> {
+    Ok(path(name)?.is_file() || encrypted_path(name)?.is_file())
+}
+
+// The TOML of a stored profile, decrypted if need be.
+pub fn read(name: &str) -> Result<String, PawprintError> {
+    let encrypted = encrypted_path(name)?;
+    if encrypted.is_file() {
+        return vault::decrypt(&encrypted);
+    }
+    let path = path(name)?;
+    fs::read_to_string(&path).map_err(|e| PawprintError::file(&path, e))
+}
+
+// Stores a profile, encrypted when the store is.
+pub fn save(name: &str, content: &str) -> Result<(), PawprintError> {
+    let plain = path(name)?;
+    let encrypted = encrypted_path(name)?;
+    let dir = profiles_dir()?;
+    fs::create_dir_all(&dir).map_err(|e| PawprintError::file(&dir, e))?;
+    let stale = match vault::recipient()? {
+        Some(recipient) => {
+            let data = vault::encrypt(content, &recipient)?;
+            fs::write(&encrypted, data).map_err(|e| PawprintError::file(&encrypted, e))?;
+            plain
+        }
+        None => {
+            fs::write(&plain, content).map_err(|e| PawprintError::file(&plain, e))?;
+            encrypted
+        }
+    };
+    // A copy in the other form would shadow or outlive the new one.
+    if stale.exists() {
+        fs::remove_file(&stale).map_err(|e| PawprintError::file(&stale, e))?;
+    }
+    Ok(())
+}
+
+// Reads a stored profile without expanding ${VARS}, for listing.
+pub fn load_raw(name: &str) -> Result<Spec, PawprintError> {
+    let content = read(name)?;
+    Ok(toml::from_str(&content).map_err(|e| format!("Invalid profile {}: {}", name, e))?)
+}
+
+// Reads a stored profile like `Spec::load` reads a spec file.
+pub fn load(name: &str, env_subst: bool) -> Result<Spec, PawprintError> {
+    Spec::parse(read(name)?, &path(name)?, env_subst)
+}
+
+// The profile's file in either form and the files its spec pulls in, which a
+// reload should follow. Only the profile itself if it cannot be read right now.
+pub fn sources(name: &str, env_subst: bool) -> Result<Vec<PathBuf>, PawprintError> {
+    let mut files = vec![path(name)?, encrypted_path(name)?];
+    if let Ok(spec) = load(name, env_subst) {
+        files.extend(spec.template);
+        files.extend(spec.merges);
+        files.extend(spec.patches);
+        files.extend(spec.json_patches);
+        files.extend(spec.post_script);
+    }
+    Ok(files)
+}
+
+// Encrypts every profile to a new key kept in `store`, or with None decrypts
+// them all and forgets the key. Returns how many profiles were rewritten.
+pub fn set_encryption(store: Option<vault::KeyStore>) -> Result<usize, PawprintError> {
+    let names = names()?;
+    let profiles = names
+        .iter()
+        .map(|name| Ok((name, read(name)?)))
+        .collect::<Result<Vec<_>, PawprintError>>()?;
+    match store {
+        Some(store) => {
+            if vault::recipient()?.is_some() {
+                return Err("The profiles are already encrypted".into());
+            }
+            vault::create_key(store)?;
+        }
+        None => {
+            if vault::recipient()?.is_none() {
+                return Err("The profiles are not encrypted".into());
+            }
+            // Saving in plain text needs the recipient gone first.
+            vault::remove_key()?;
+        }
+    }
+    for (name, content) in &profiles {
+        save(name, content)?;
+    }
+    Ok(profiles.len())
+}
+
+pub fn names() -> Result<Vec<String>, PawprintError> {
+    let dir = profiles_dir()?;
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names: Vec<String> = fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter_map(|path| {
+            let file = path.file_name()?.to_string_lossy().into_owned();
+            let file = file.strip_suffix(vault::SUFFIX).unwrap_or(&file);
+            Some(file.strip_suffix(".toml")?.to_string())
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+// The stored profile `query` names exactly, or the one it matches best
+// loosely, e.g. "ger" for "🇩🇪 Germany-03".
+pub fn resolve(query: &str) -> Result<String, PawprintError> {
+    let names = names()?;
+    match fuzzy::best(query, &names) {
+        Ok(name) => Ok(name.to_string()),
+        Err(tied) if tied.is_empty() => Err(format!("No such profile: {}", query).into()),
+        Err(tied) => Err(format!("{} matches several profiles: {}", query, tied.join(", ")).into()),
+    }
+}
+
+pub fn remove(name: &str) -> Result<(), PawprintError> {
+    if !exists(name)? {
+        return Err(format!("No such profile: {}", name).into());
+    }
+    for path in [path(name)?, encrypted_path(name)?] {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+    }
+    if active()?.as_deref() == Some(name) {
+        fs::remove_file(base_dir()?.join("active-profile"))?;
+    }
+    Ok(())
+}
+
+pub fn active() -> Result<Option<String>, PawprintError> {
+    match fs::read_to_string(base_dir()?.join("active-profile")) {
+        Ok(name) => Ok(Some(name.trim().to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn set_active(name: &str) -> Result<(), PawprintError> {
+    let dir = base_dir()?;
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("active-profile"), format!("{}\n", name))?;
+    let mut used = last_used()?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    used.insert(name.to_string(), now);
+    fs::write(
+        dir.join("last-used.json"),
+        serde_json::to_string_pretty(&used)?,
+    )?;
+    Ok(())
+}
+
+// Unix time each profile was last made active, from `last-used.json`.
+pub fn last_used() -> Result<BTreeMap<String, u64>, PawprintError> {
+    let path = base_dir()?.join("last-used.json");
+    match fs::read_to_string(&path) {
+        Ok(content) => Ok(serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid last-used record {}: {}", path.display(), e))?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(PawprintError::file(&path, e)),
+    }
+}
+
+// How `profile list` and the TUI order profiles. Profiles missing the value
+// sort last, in name order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Sort {
+    #[default]
+    Name,
+    // Fastest first, then failed, then untested
+    Latency,
+    // By two-letter code, from the server's tag or the profile name
+    Country,
+    // Most subscription traffic left first
+    Quota,
+    // Most recently used first
+    LastUsed,
+}
+
+impl Sort {
+    // The next order, for cycling through them in the TUI.
+    pub fn next(self) -> Sort {
+        match self {
+            Sort::Name => Sort::Latency,
+            Sort::Latency => Sort::Country,
+            Sort::Country => Sort::Quota,
+            Sort::Quota => Sort::LastUsed,
+            Sort::LastUsed => Sort::Name,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Sort::Name => "name",
+            Sort::Latency => "latency",
+            Sort::Country => "country",
+            Sort::Quota => "quota",
+            Sort::LastUsed => "last used",
+        }
+    }
+}
+
+// What `profile list --group-by` puts under one heading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Group {
+    // The host of the profile's first subscription; share-link-only profiles
+    // are grouped together
+    Subscription,
+    // The country of the server, from its tag or the profile name
+    Country,
+}
+
+// Milliseconds of the last latency measured per profile, None if it failed.
+// Kept in `latency.json` next to the profiles to order failover candidates.
+pub fn latencies() -> Result<BTreeMap<String, Option<u64>>, PawprintError> {
+    let path = base_dir()?.join("latency.json");
+    match fs::read_to_string(&path) {
+        Ok(content) => Ok(serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid latency record {}: {}", path.display(), e))?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(PawprintError::file(&path, e)),
+    }
+}
+
+pub fn record_latency(name: &str, latency: Option<Duration>) -> Result<(), PawprintError> {
+    let mut latencies = latencies()?;
+    latencies.insert(name.to_string(), latency.map(|d| d.as_millis() as u64));
+    let dir = base_dir()?;
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join("latency.json"),
+        serde_json::to_string_pretty(&latencies)?,
+    )?;
+    if let Some(latency) = latency {
+        let mut successes = successes()?;
+        successes.insert(
+            name.to_string(),
+            Success {
+                latency_ms: latency.as_millis() as u64,
+                at: logfile::timestamp(),
+            },
+        );
+        fs::write(
+            dir.join("last-success.json"),
+            serde_json::to_string_pretty(&successes)?,
+        )?;
+    }
+    Ok(())
+}
+
+// The last measurement of a profile that passed, kept when later ones fail.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Success {
+    pub latency_ms: u64,
+    // RFC 3339, UTC.
+    pub at: String,
+}
+
+// Per profile, from `last-success.json` next to `latency.json`.
+pub fn successes() -> Result<BTreeMap<String, Success>, PawprintError> {
+    let path = base_dir()?.join("last-success.json");
+    match fs::read_to_string(&path) {
+        Ok(content) => Ok(serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid success record {}: {}", path.display(), e))?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(PawprintError::file(&path, e)),
+    }
+}
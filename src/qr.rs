@@ -0,0 +1,81 @@
+use qrcode::render::unicode::Dense1x2;
+use qrcode::{Color, QrCode};
+use std::path::Path;
+use std::process::Command;
+
+use crate::error::PawprintError;
+
+// Pixels per module and modules of white border in PNG output.
+const PNG_SCALE: usize = 8;
+const QUIET_ZONE: usize = 4;
+
+// QR code drawn with half-block characters, two modules per line. Colours are
+// inverted because most terminals draw light text on a dark background.
+pub fn terminal(data: &str) -> Result<String, PawprintError> {
+    Ok(QrCode::new(data)
+        .map_err(|e| format!("Cannot encode QR code: {}", e))?
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
+
+// The text of every QR code in an image, read with zbarimg from zbar.
+pub fn decode(path: &Path) -> Result<Vec<String>, PawprintError> {
+    let output = Command::new("zbarimg")
+        .args(["--quiet", "--raw", "-Sdisable", "-Sqrcode.enable"])
+        .arg(path)
+        .output()
+        .map_err(|e| {
+            format!(
+                "Failed to run zbarimg (install zbar to read QR images): {}",
+                e
+            )
+        })?;
+    let codes: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    // zbarimg exits with 4 when the image has no codes.
+    if codes.is_empty() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => format!("No QR code found in {}", path.display()),
+            error => format!("Cannot read {}: {}", path.display(), error),
+        }
+        .into());
+    }
+    Ok(codes)
+}
+
+// Grayscale PNG of the QR code.
+pub fn png(data: &str) -> Result<Vec<u8>, PawprintError> {
+    let code = QrCode::new(data).map_err(|e| format!("Cannot encode QR code: {}", e))?;
+    let modules = code.width();
+    let colors = code.to_colors();
+    let size = (modules + 2 * QUIET_ZONE) * PNG_SCALE;
+
+    let mut pixels = vec![255u8; size * size];
+    for (index, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let x = (index % modules + QUIET_ZONE) * PNG_SCALE;
+        let y = (index / modules + QUIET_ZONE) * PNG_SCALE;
+        for row in y..y + PNG_SCALE {
+            pixels[row * size + x..row * size + x + PNG_SCALE].fill(0);
+        }
+    }
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|e| format!("Cannot write PNG: {}", e))?;
+    Ok(output)
+}
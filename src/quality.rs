@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// The last probes through the tunnel, failed ones as None, for telling a slow
+// link from a jittery or lossy one.
+#[derive(Debug)]
+pub struct Window {
+    samples: VecDeque<Option<Duration>>,
+    capacity: usize,
+}
+
+// What a window of probes says about the link. Jitter is the mean difference
+// between consecutive round trips that passed, as RFC 3550 estimates it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Quality {
+    pub samples: usize,
+    pub lost: usize,
+    // Lost probes as a fraction of all.
+    pub loss: f64,
+    // None when every probe in the window was lost.
+    pub rtt_ms: Option<f64>,
+    pub rtt_min_ms: Option<f64>,
+    pub rtt_max_ms: Option<f64>,
+    pub jitter_ms: Option<f64>,
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl Window {
+    pub const fn new(capacity: usize) -> Window {
+        Window {
+            samples: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    // Adds a probe, dropping the oldest once the window is full.
+    pub fn push(&mut self, sample: Option<Duration>) {
+        if self.samples.len() >= self.capacity.max(1) {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    // Forgets the probes, as when the core restarts on another server.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    // None before the first probe.
+    pub fn quality(&self) -> Option<Quality> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let passed: Vec<f64> = self.samples.iter().flatten().copied().map(ms).collect();
+        let lost = self.samples.len() - passed.len();
+        let mean = |values: &[f64]| {
+            (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+        };
+        let differences: Vec<f64> = passed.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+        Some(Quality {
+            samples: self.samples.len(),
+            lost,
+            loss: lost as f64 / self.samples.len() as f64,
+            rtt_ms: mean(&passed),
+            rtt_min_ms: passed.iter().copied().reduce(f64::min),
+            rtt_max_ms: passed.iter().copied().reduce(f64::max),
+            jitter_ms: mean(&differences),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(samples: &[Option<u64>]) -> Window {
+        let mut window = Window::new(4);
+        for sample in samples {
+            window.push(sample.map(Duration::from_millis));
+        }
+        window
+    }
+
+    #[test]
+    fn quality_sums_up_the_window() {
+        assert_eq!(window(&[]).quality(), None);
+        let quality = window(&[Some(100), None, Some(120), Some(90)])
+            .quality()
+            .unwrap();
+        assert_eq!(quality.samples, 4);
+        assert_eq!(quality.lost, 1);
+        assert_eq!(quality.loss, 0.25);
+        assert_eq!(quality.rtt_min_ms, Some(90.0));
+        assert_eq!(quality.rtt_max_ms, Some(120.0));
+        // |120 - 100| and |90 - 120|
+        assert_eq!(quality.jitter_ms, Some(25.0));
+        assert!((quality.rtt_ms.unwrap() - 103.333).abs() < 0.001);
+    }
+
+    #[test]
+    fn old_samples_leave_the_window() {
+        let quality = window(&[None, None, Some(50), Some(50), Some(50), Some(50)])
+            .quality()
+            .unwrap();
+        assert_eq!((quality.samples, quality.lost), (4, 0));
+        assert_eq!(quality.jitter_ms, Some(0.0));
+
+        let quality = window(&[None, None]).quality().unwrap();
+        assert_eq!(quality.loss, 1.0);
+        assert_eq!((quality.rtt_ms, quality.jitter_ms), (None, None));
+    }
+}
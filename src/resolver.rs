@@ -0,0 +1,199 @@
+use serde_json::Value;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use tracing::{info, warn};
+
+use crate::elevate::{self, root};
+use crate::error::PawprintError;
+use crate::killswitch;
+use crate::process;
+use crate::routes;
+use crate::undo::{Undo, args, command};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+// Points the system resolver at the config's DNS server through its TUN
+// interface while the core runs (Linux). With systemd-resolved the interface
+// becomes the resolver for every domain, and those settings go away with it.
+// Otherwise /etc/resolv.conf is replaced and made immutable so DHCP clients
+// leave it alone; the original is kept in the state directory until `stop` or
+// the next `run` puts it back, even after a crash.
+fn undo() -> Undo {
+    Undo::new("resolver.json")
+}
+
+// Whether the resolver was changed and not restored yet.
+pub fn is_active() -> bool {
+    undo().is_pending()
+}
+
+// The host of a DNS server as configs write it: 1.1.1.1, tcp://[::1]:53 or
+// https://1.1.1.1/dns-query.
+fn server_ip(server: &str) -> Option<IpAddr> {
+    let rest = server.split_once("://").map_or(server, |(_, rest)| rest);
+    let authority = rest.split('/').next()?;
+    let host = match authority.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next()?,
+        None if authority.matches(':').count() == 1 => authority.split(':').next()?,
+        None => authority,
+    };
+    host.parse().ok()
+}
+
+// The upstream server of an Xray or sing-box config, skipping the ones scoped
+// to some domains; 1.1.1.1 like the generated configs when there is none.
+fn tunnel_server(config: &Value) -> IpAddr {
+    let dns = &config["dns"];
+    let servers = dns["servers"].as_array().into_iter().flatten();
+    let upstream = servers
+        .filter(|server| server["domains"].is_null() && server["detour"] != "direct")
+        .filter(|server| match dns["final"].as_str() {
+            Some(tag) => server["tag"] == tag,
+            None => true,
+        })
+        .filter_map(|server| {
+            server
+                .as_str()
+                .or(server["address"].as_str())
+                .or(server["server"].as_str())
+        })
+        .find_map(server_ip);
+    upstream.unwrap_or(IpAddr::from([1, 1, 1, 1]))
+}
+
+fn uses_resolved() -> bool {
+    command(&args(["resolvectl", "status"])).is_ok()
+}
+
+fn resolved(interface: &str, server: &str) -> Result<(), PawprintError> {
+    // Answers cached from the old servers would outlive the switch.
+    undo().save(&[root(args(["resolvectl", "flush-caches"]))])?;
+    for step in [
+        args(["resolvectl", "dns", interface, server]),
+        args(["resolvectl", "domain", interface, "~."]),
+        args(["resolvectl", "default-route", interface, "yes"]),
+    ] {
+        command(&root(step))?;
+    }
+    Ok(())
+}
+
+fn resolv_conf(server: &str) -> Result<(), PawprintError> {
+    let dir = process::state_dir();
+    fs::create_dir_all(&dir)?;
+    let current = Path::new(RESOLV_CONF);
+    // A symlink, as to a resolver stub, is restored as one.
+    let restore = match fs::read_link(current) {
+        Ok(target) => args(["ln", "-sfn", &target.to_string_lossy(), RESOLV_CONF]),
+        Err(_) => {
+            let backup = dir.join("resolv.conf.orig");
+            fs::copy(current, &backup).map_err(|e| PawprintError::file(current, e))?;
+            args(["cp", &backup.to_string_lossy(), RESOLV_CONF])
+        }
+    };
+    let replacement = dir.join("resolv.conf");
+    let content = format!(
+        "# Written by pawprint-vpn while the tunnel is up\nnameserver {}\n",
+        server
+    );
+    fs::write(&replacement, content).map_err(|e| PawprintError::file(&replacement, e))?;
+
+    let restore = root(restore);
+    undo().save(&[root(args(["chattr", "-i", RESOLV_CONF])), restore.clone()])?;
+    let replacement = replacement.to_string_lossy();
+    command(&root(args([
+        "cp",
+        "--remove-destination",
+        &replacement,
+        RESOLV_CONF,
+    ])))?;
+    // Not every file system supports the flag; unlocking would fail then too.
+    if let Err(e) = command(&root(args(["chattr", "+i", RESOLV_CONF]))) {
+        warn!("Other programs may rewrite {}: {}", RESOLV_CONF, e);
+        undo().save(&[restore])?;
+    }
+    Ok(())
+}
+
+// Once the core runs: waits for its TUN interface, until `done` is set, and
+// sends the system's DNS queries through it.
+pub fn enable(config: &Value, done: &AtomicBool) -> Result<(), PawprintError> {
+    if !cfg!(target_os = "linux") {
+        return Err("The resolver is only managed on Linux".into());
+    }
+    let interface = killswitch::tun_interfaces(config)
+        .into_iter()
+        .next()
+        .ok_or("The config has no TUN inbound to send DNS queries through")?;
+    elevate::check("Changing the resolver")?;
+    // Settings left by a run that crashed.
+    restore()?;
+    if !routes::wait_for_interface(&interface, done)? {
+        return Ok(());
+    }
+
+    let server = tunnel_server(config).to_string();
+    let result = if uses_resolved() {
+        resolved(&interface, &server)
+    } else {
+        resolv_conf(&server)
+    };
+    if let Err(e) = result {
+        let _ = restore();
+        return Err(e);
+    }
+    info!("✓ DNS queries go to {} through {}", server, interface);
+    Ok(())
+}
+
+// Puts back the resolver settings saved by `enable`. Returns false if nothing
+// was saved.
+pub fn restore() -> Result<bool, PawprintError> {
+    let restored = undo().run()?;
+    if restored {
+        info!("✓ Resolver restored");
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn server_addresses_are_taken_from_urls() {
+        assert_eq!(server_ip("8.8.8.8"), "8.8.8.8".parse().ok());
+        assert_eq!(
+            server_ip("tcp://[2606:4700::1111]:53"),
+            "2606:4700::1111".parse().ok()
+        );
+        assert_eq!(
+            server_ip("https://1.0.0.1/dns-query"),
+            "1.0.0.1".parse().ok()
+        );
+        assert_eq!(server_ip("udp://9.9.9.9:53"), "9.9.9.9".parse().ok());
+        assert_eq!(server_ip("2001:db8::53"), "2001:db8::53".parse().ok());
+        assert_eq!(server_ip("https://dns.google/dns-query"), None);
+    }
+
+    #[test]
+    fn the_upstream_server_is_used_not_the_scoped_ones() {
+        let xray = json!({"dns": {"servers": [
+            {"address": "10.0.0.53", "domains": ["domain:corp.example"]},
+            "https://dns.google/dns-query",
+            "tcp://8.8.4.4",
+        ]}});
+        assert_eq!(tunnel_server(&xray), IpAddr::from([8, 8, 4, 4]));
+
+        let sing_box = json!({"dns": {"final": "dns-remote", "servers": [
+            {"type": "udp", "tag": "dns-1", "server": "10.0.0.53", "detour": "direct"},
+            {"type": "local", "tag": "dns-local"},
+            {"type": "udp", "tag": "dns-remote", "server": "9.9.9.9"},
+        ]}});
+        assert_eq!(tunnel_server(&sing_box), IpAddr::from([9, 9, 9, 9]));
+        assert_eq!(tunnel_server(&json!({})), IpAddr::from([1, 1, 1, 1]));
+    }
+}
@@ -0,0 +1,222 @@
+use serde_json::Value;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::elevate::{self, root};
+use crate::error::PawprintError;
+use crate::killswitch;
+use crate::undo::{Undo, args, command};
+
+// Routes sending everything into the TUN interface of the core, for Xray, which
+// leaves routing to the OS, and sing-box without auto_route. The proxy servers
+// keep the path they had before. Two halves of the address space win over the
+// default route without replacing it, and go away with the interface, so only
+// the server routes need undoing. Linux only.
+const HALVES: [(&str, [&str; 2]); 2] = [
+    ("-4", ["0.0.0.0/1", "128.0.0.0/1"]),
+    ("-6", ["::/1", "8000::/1"]),
+];
+
+// How long the core gets to create its interface.
+const INTERFACE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn undo() -> Undo {
+    Undo::new("routes.json")
+}
+
+// Whether server routes are in place and not removed yet.
+pub fn is_active() -> bool {
+    undo().is_pending()
+}
+
+// The TUN interface to route into; None if sing-box routes it itself.
+fn interface(config: &Value) -> Result<Option<String>, PawprintError> {
+    let auto_route = config["inbounds"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|inbound| inbound["type"] == "tun" && inbound["auto_route"] == true);
+    if auto_route {
+        return Ok(None);
+    }
+    let name = killswitch::tun_interfaces(config)
+        .into_iter()
+        .next()
+        .ok_or("The config has no TUN inbound to route through")?;
+    Ok(Some(name))
+}
+
+// The `via` gateway, if any, and `dev` of a route as `ip route` prints it, e.g.
+// "1.2.3.4 via 192.168.1.1 dev wlan0 src 192.168.1.5 uid 1000".
+fn parse_route(line: &str) -> Option<(Option<String>, String)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let after = |key: &str| {
+        let index = words.iter().position(|word| *word == key)?;
+        words.get(index + 1).map(|word| word.to_string())
+    };
+    Some((after("via"), after("dev")?))
+}
+
+// The path the kernel takes to `server` right now.
+fn current_route(server: IpAddr) -> Result<(Option<String>, String), PawprintError> {
+    let output = command(&args(["ip", "route", "get", &server.to_string()]))?;
+    parse_route(output.lines().next().unwrap_or_default())
+        .ok_or_else(|| format!("No route to {}", server).into())
+}
+
+fn has_default_route(family: &str) -> bool {
+    command(&args(["ip", family, "route", "show", "default"])).is_ok_and(|out| !out.is_empty())
+}
+
+// Before the core starts: pins each proxy server to the route it has now, so
+// the core still reaches them once everything else goes into the interface.
+pub fn enable(config: &Value) -> Result<(), PawprintError> {
+    if !cfg!(target_os = "linux") {
+        return Err("TUN routes are only managed on Linux".into());
+    }
+    let Some(name) = interface(config)? else {
+        warn!("The TUN inbound sets auto_route, so sing-box adds the routes itself");
+        return Ok(());
+    };
+    elevate::check("Changing routes")?;
+    // Routes left by a run that crashed.
+    disable()?;
+
+    let (servers, _) = killswitch::server_addresses(config)?;
+    let mut apply = Vec::new();
+    let mut rollback = Vec::new();
+    for server in servers {
+        let (gateway, device) = match current_route(server) {
+            Ok(route) => route,
+            // Typically an IPv6 address without IPv6 connectivity.
+            Err(e) => {
+                warn!("{}", e);
+                continue;
+            }
+        };
+        if device == name {
+            continue;
+        }
+        let destination = server.to_string();
+        let mut route = args(["ip", "route", "replace", &destination]);
+        if let Some(gateway) = &gateway {
+            route.extend(args(["via", gateway]));
+        }
+        route.extend(args(["dev", &device]));
+        apply.push(root(route));
+        rollback.push(root(args(["ip", "route", "del", &destination])));
+    }
+    undo().save(&rollback)?;
+    for route in &apply {
+        if let Err(e) = command(route) {
+            let _ = disable();
+            return Err(e);
+        }
+    }
+
+    let direct = config["routing"]["rules"]
+        .as_array()
+        .or(config["route"]["rules"].as_array())
+        .into_iter()
+        .flatten()
+        .any(|rule| rule["outboundTag"] == "direct" || rule["outbound"] == "direct");
+    if direct {
+        warn!(
+            "Traffic routed to `direct` goes back into {} too; bind the direct outbound to the real interface (sockopt.interface in Xray, bind_interface in sing-box)",
+            name
+        );
+    }
+    Ok(())
+}
+
+// Waits for the core to create interface `name`. Returns false if `done` was
+// set first.
+pub fn wait_for_interface(name: &str, done: &AtomicBool) -> Result<bool, PawprintError> {
+    let device = Path::new("/sys/class/net").join(name);
+    let deadline = Instant::now() + INTERFACE_TIMEOUT;
+    while !device.exists() {
+        if done.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        if Instant::now() >= deadline {
+            return Err(format!("The core did not create the TUN interface {}", name).into());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(true)
+}
+
+// Once the core runs: waits for it to create the interface, until `done` is set,
+// then routes everything into it.
+pub fn capture(config: &Value, done: &AtomicBool) -> Result<(), PawprintError> {
+    let Some(name) = interface(config)? else {
+        return Ok(());
+    };
+    if !wait_for_interface(&name, done)? {
+        return Ok(());
+    }
+
+    command(&root(args(["ip", "link", "set", "dev", &name, "up"])))?;
+    for (family, halves) in HALVES {
+        if !has_default_route(family) {
+            continue;
+        }
+        for half in halves {
+            let route = root(args(["ip", family, "route", "replace", half, "dev", &name]));
+            // Without IPv6 on the interface only IPv4 is captured.
+            match command(&route) {
+                Ok(_) => {}
+                Err(e) if family == "-6" => {
+                    warn!("IPv6 traffic does not go through {}: {}", name, e);
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    info!("✓ Routing all traffic through {}", name);
+    Ok(())
+}
+
+// Removes the server routes added by `enable`. Returns false if there were none.
+pub fn disable() -> Result<bool, PawprintError> {
+    let removed = undo().run()?;
+    if removed {
+        info!("✓ Server routes removed");
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn routes_are_read_from_ip_output() {
+        assert_eq!(
+            parse_route("1.2.3.4 via 192.168.1.1 dev wlan0 src 192.168.1.5 uid 1000"),
+            Some((Some("192.168.1.1".to_string()), "wlan0".to_string()))
+        );
+        assert_eq!(
+            parse_route("10.0.0.1 dev ppp0 src 10.0.0.2 uid 0"),
+            Some((None, "ppp0".to_string()))
+        );
+        assert_eq!(parse_route("unreachable"), None);
+    }
+
+    #[test]
+    fn sing_box_auto_route_is_left_alone() {
+        let xray = json!({"inbounds": [{"protocol": "tun", "settings": {"name": "tun9"}}]});
+        assert_eq!(interface(&xray).unwrap().as_deref(), Some("tun9"));
+        let sing_box = json!({"inbounds": [
+            {"type": "tun", "interface_name": "tun9", "auto_route": true}
+        ]});
+        assert_eq!(interface(&sing_box).unwrap(), None);
+        assert!(interface(&json!({"inbounds": []})).is_err());
+    }
+}
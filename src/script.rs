@@ -0,0 +1,31 @@
+use rhai::{Dynamic, Engine, Scope};
+use std::path::Path;
+
+use crate::error::PawprintError;
+
+// Runs a Rhai script with the built config bound to the `config` variable and
+// returns whatever `config` holds once the script finishes, e.g.
+//
+//   config.inbounds[0].port = 1080;
+//   config.log = #{ loglevel: "warning" };
+pub fn run_post_script(
+    config: serde_json::Value,
+    path: &Path,
+) -> Result<serde_json::Value, PawprintError> {
+    let engine = Engine::new();
+    let mut scope = Scope::new();
+    scope.push_dynamic(
+        "config",
+        rhai::serde::to_dynamic(&config).map_err(|e| e.to_string())?,
+    );
+
+    engine
+        .run_file_with_scope(&mut scope, path.to_path_buf())
+        .map_err(|e| format!("Post script {} failed: {}", path.display(), e))?;
+
+    let config = scope
+        .get_value::<Dynamic>("config")
+        .ok_or("Post script removed the `config` variable")?;
+    Ok(rhai::serde::from_dynamic(&config)
+        .map_err(|e| format!("Post script left an invalid config: {}", e))?)
+}
@@ -0,0 +1,369 @@
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::error::PawprintError;
+use crate::keygen;
+use crate::parser::{Node, TrojanConfig, VlessConfig};
+
+// Loopback port of the WebSocket inbound the Trojan inbound falls back to.
+const WS_FALLBACK_PORT: u16 = 10001;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ServerProtocol {
+    // VLESS with Vision over REALITY, borrowing the TLS handshake of --dest
+    VlessReality,
+    // Trojan over WebSocket and TLS with your own certificate
+    TrojanWs,
+}
+
+// What to build a server config for. Missing credentials are generated.
+pub struct ServerOptions {
+    pub protocol: ServerProtocol,
+    // Public address clients connect to.
+    pub address: String,
+    pub port: u16,
+    pub tag: String,
+    // VLESS UUID or Trojan password.
+    pub secret: Option<String>,
+    // REALITY: site whose handshake is borrowed, and the names clients may ask for.
+    pub dest: String,
+    pub server_names: Vec<String>,
+    pub private_key: Option<String>,
+    // Trojan: certificate for `address`.
+    pub certificate: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub ws_path: String,
+    // Trojan: where non-Trojan traffic goes, e.g. 80 for a local web server.
+    pub fallback: String,
+}
+
+// Xray accepts a port number, host:port or a unix socket path as a fallback `dest`.
+fn fallback_dest(dest: &str) -> Value {
+    match dest.parse::<u16>() {
+        Ok(port) => json!(port),
+        Err(_) => json!(dest),
+    }
+}
+
+fn routing_and_outbounds(config: &mut Value) {
+    config["outbounds"] = json!([
+        { "tag": "direct", "protocol": "freedom" },
+        { "tag": "block", "protocol": "blackhole" },
+    ]);
+    // Keep clients from reaching the server's own network.
+    config["routing"] = json!({
+        "rules": [{ "type": "field", "ip": ["geoip:private"], "outboundTag": "block" }],
+    });
+}
+
+fn vless_reality(options: &ServerOptions) -> Result<(Value, Node), PawprintError> {
+    let uuid = match &options.secret {
+        Some(uuid) => uuid.clone(),
+        None => keygen::uuid()?,
+    };
+    let keys = match &options.private_key {
+        Some(private_key) => keygen::from_private_key(private_key)?,
+        None => keygen::generate()?,
+    };
+    let short_id = keygen::short_id(8)?;
+    let server_names = if options.server_names.is_empty() {
+        let host = options
+            .dest
+            .rsplit_once(':')
+            .map_or(options.dest.as_str(), |(host, _)| host);
+        vec![host.to_string()]
+    } else {
+        options.server_names.clone()
+    };
+
+    let mut config = json!({
+        "log": { "loglevel": "warning" },
+        "inbounds": [{
+            "tag": "vless-in",
+            "listen": "0.0.0.0",
+            "port": options.port,
+            "protocol": "vless",
+            "settings": {
+                "clients": [{ "id": uuid, "flow": "xtls-rprx-vision" }],
+                "decryption": "none",
+            },
+            "streamSettings": {
+                "network": "tcp",
+                "security": "reality",
+                // Connections that fail REALITY authentication are passed to
+                // `dest`, which serves as the fallback site.
+                "realitySettings": {
+                    "dest": options.dest,
+                    "serverNames": server_names,
+                    "privateKey": keys.private_key,
+                    "shortIds": [short_id],
+                },
+            },
+            "sniffing": { "enabled": true, "destOverride": ["http", "tls", "quic"] },
+        }],
+    });
+    routing_and_outbounds(&mut config);
+    let node = client_node(&config, None, &options.address, &options.tag)?;
+    Ok((config, node))
+}
+
+// Trojan over TCP+TLS on the public port. WebSocket requests on `ws_path` fall
+// back to a second Trojan inbound on loopback, anything else to `fallback`.
+fn trojan_ws(options: &ServerOptions) -> Result<(Value, Node), PawprintError> {
+    let (Some(certificate), Some(key)) = (&options.certificate, &options.key) else {
+        return Err("Trojan needs --cert and --key for the server's TLS certificate".into());
+    };
+    let password = match &options.secret {
+        Some(password) => password.clone(),
+        None => keygen::password()?,
+    };
+
+    let mut config = json!({
+        "log": { "loglevel": "warning" },
+        "inbounds": [
+            {
+                "tag": "trojan-in",
+                "listen": "0.0.0.0",
+                "port": options.port,
+                "protocol": "trojan",
+                "settings": {
+                    "clients": [{ "password": password }],
+                    "fallbacks": [
+                        { "path": options.ws_path, "dest": WS_FALLBACK_PORT },
+                        { "dest": fallback_dest(&options.fallback) },
+                    ],
+                },
+                "streamSettings": {
+                    "network": "tcp",
+                    "security": "tls",
+                    // WebSocket upgrades only work over HTTP/1.1.
+                    "tlsSettings": {
+                        "alpn": ["http/1.1"],
+                        "certificates": [{
+                            "certificateFile": certificate,
+                            "keyFile": key,
+                        }],
+                    },
+                },
+            },
+            {
+                "tag": "trojan-ws-in",
+                "listen": "127.0.0.1",
+                "port": WS_FALLBACK_PORT,
+                "protocol": "trojan",
+                "settings": { "clients": [{ "password": password }] },
+                "streamSettings": {
+                    "network": "ws",
+                    "security": "none",
+                    "wsSettings": { "path": options.ws_path },
+                },
+            },
+        ],
+    });
+    routing_and_outbounds(&mut config);
+    let node = client_node(&config, None, &options.address, &options.tag)?;
+    Ok((config, node))
+}
+
+// Inbounds of a server config that clients log in to, as `server` writes them:
+// the public one first, then any loopback fallback sharing its clients.
+fn client_inbounds(config: &Value) -> Vec<usize> {
+    config["inbounds"]
+        .as_array()
+        .map(|inbounds| {
+            inbounds
+                .iter()
+                .enumerate()
+                .filter(|(_, inbound)| {
+                    matches!(inbound["protocol"].as_str(), Some("vless" | "trojan"))
+                        && inbound["settings"]["clients"].is_array()
+                })
+                .map(|(index, _)| index)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn public_inbound(config: &Value) -> Result<&Value, PawprintError> {
+    client_inbounds(config)
+        .into_iter()
+        .map(|index| &config["inbounds"][index])
+        .find(|inbound| inbound["listen"].as_str() != Some("127.0.0.1"))
+        .ok_or_else(|| "The config has no VLESS or Trojan inbound with clients".into())
+}
+
+// A client of the public inbound: the VLESS UUID or Trojan password, and the
+// email Xray names it by in logs and stats.
+pub struct User {
+    pub name: Option<String>,
+    pub secret: String,
+}
+
+fn secret_key(inbound: &Value) -> &'static str {
+    if inbound["protocol"] == "trojan" {
+        "password"
+    } else {
+        "id"
+    }
+}
+
+pub fn users(config: &Value) -> Result<Vec<User>, PawprintError> {
+    let inbound = public_inbound(config)?;
+    let key = secret_key(inbound);
+    Ok(inbound["settings"]["clients"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|client| User {
+            name: client["email"].as_str().map(str::to_string),
+            secret: client[key].as_str().unwrap_or_default().to_string(),
+        })
+        .collect())
+}
+
+// Adds a client named `name` to every client inbound, with a generated secret
+// unless one is given. Returns the secret.
+pub fn add_user(
+    config: &mut Value,
+    name: &str,
+    secret: Option<String>,
+) -> Result<String, PawprintError> {
+    let inbound = public_inbound(config)?;
+    if users(config)?
+        .iter()
+        .any(|u| u.name.as_deref() == Some(name))
+    {
+        return Err(format!("User {} already exists", name).into());
+    }
+    let trojan = inbound["protocol"] == "trojan";
+    let flow = inbound["settings"]["clients"][0]["flow"].clone();
+    let secret = match secret {
+        Some(secret) => secret,
+        None if trojan => keygen::password()?,
+        None => keygen::uuid()?,
+    };
+    for index in client_inbounds(config) {
+        let inbound = &mut config["inbounds"][index];
+        let mut client = json!({ secret_key(inbound): secret, "email": name });
+        // Vision is only set on the public VLESS inbound, and all its clients use it.
+        if !flow.is_null() && inbound["protocol"] == "vless" {
+            client["flow"] = flow.clone();
+        }
+        if let Some(clients) = inbound["settings"]["clients"].as_array_mut() {
+            clients.push(client);
+        }
+    }
+    Ok(secret)
+}
+
+// Removes the client named `name` from every client inbound. Returns false if
+// there was none.
+pub fn remove_user(config: &mut Value, name: &str) -> Result<bool, PawprintError> {
+    public_inbound(config)?;
+    let mut removed = false;
+    for index in client_inbounds(config) {
+        if let Some(clients) = config["inbounds"][index]["settings"]["clients"].as_array_mut() {
+            let before = clients.len();
+            clients.retain(|client| client["email"].as_str() != Some(name));
+            removed |= clients.len() < before;
+        }
+    }
+    if removed && users(config)?.is_empty() {
+        return Err("Cannot remove the last user; the inbound would accept nobody".into());
+    }
+    Ok(removed)
+}
+
+// The node a client connects with: user `name`, or the first one when None.
+// `address` is the server's public address, which the config does not record.
+pub fn client_node(
+    config: &Value,
+    name: Option<&str>,
+    address: &str,
+    tag: &str,
+) -> Result<Node, PawprintError> {
+    let inbound = public_inbound(config)?;
+    let clients = inbound["settings"]["clients"]
+        .as_array()
+        .into_iter()
+        .flatten();
+    let client = match name {
+        Some(name) => clients
+            .into_iter()
+            .find(|client| client["email"].as_str() == Some(name))
+            .ok_or_else(|| format!("No such user: {}", name))?,
+        None => clients
+            .into_iter()
+            .next()
+            .ok_or("The inbound has no clients")?,
+    };
+    let port = inbound["port"]
+        .as_u64()
+        .and_then(|port| u16::try_from(port).ok())
+        .ok_or("The inbound has no port")?;
+    let stream = &inbound["streamSettings"];
+    let string = |value: &Value| value.as_str().unwrap_or_default().to_string();
+
+    match (inbound["protocol"].as_str(), stream["security"].as_str()) {
+        (Some("vless"), Some("reality")) => {
+            let reality = &stream["realitySettings"];
+            let keys =
+                keygen::from_private_key(reality["privateKey"].as_str().unwrap_or_default())?;
+            let mut params = HashMap::from([
+                ("type".to_string(), string(&stream["network"])),
+                ("encryption".to_string(), "none".to_string()),
+                ("security".to_string(), "reality".to_string()),
+                ("pbk".to_string(), keys.public_key),
+                ("sid".to_string(), string(&reality["shortIds"][0])),
+                ("sni".to_string(), string(&reality["serverNames"][0])),
+                ("fp".to_string(), "chrome".to_string()),
+            ]);
+            if let Some(flow) = client["flow"].as_str() {
+                params.insert("flow".to_string(), flow.to_string());
+            }
+            Ok(Node::Vless(VlessConfig {
+                uuid: string(&client["id"]),
+                address: address.to_string(),
+                port,
+                params,
+                tag: tag.to_string(),
+            }))
+        }
+        (Some("trojan"), Some("tls")) => {
+            // Clients use the WebSocket fallback, which has the path.
+            let ws = client_inbounds(config)
+                .into_iter()
+                .map(|index| &config["inbounds"][index])
+                .find(|inbound| inbound["streamSettings"]["network"] == "ws")
+                .ok_or("The Trojan inbound has no WebSocket fallback")?;
+            let params = HashMap::from([
+                ("type".to_string(), "ws".to_string()),
+                ("security".to_string(), "tls".to_string()),
+                (
+                    "path".to_string(),
+                    string(&ws["streamSettings"]["wsSettings"]["path"]),
+                ),
+                ("host".to_string(), address.to_string()),
+                ("sni".to_string(), address.to_string()),
+                ("alpn".to_string(), "http/1.1".to_string()),
+            ]);
+            Ok(Node::Trojan(TrojanConfig {
+                password: string(&client["password"]),
+                address: address.to_string(),
+                port,
+                params,
+                tag: tag.to_string(),
+            }))
+        }
+        _ => Err("Only configs from `pawprint-vpn server` can be turned into share links".into()),
+    }
+}
+
+// Xray server config plus the node clients need to connect to it.
+pub fn build(options: &ServerOptions) -> Result<(Value, Node), PawprintError> {
+    match options.protocol {
+        ServerProtocol::VlessReality => vless_reality(options),
+        ServerProtocol::TrojanWs => trojan_ws(options),
+    }
+}
@@ -0,0 +1,226 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::PawprintError;
+use crate::profile;
+use crate::undo::command;
+use crate::xraycore;
+
+pub const DEFAULT_NAME: &str = "pawprint-vpn";
+
+// What the unit keeps running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ServiceMode {
+    // `run` on the profile's config, in the foreground
+    Run,
+    // `daemon`, the control API
+    Daemon,
+}
+
+pub struct ServiceOptions {
+    pub mode: ServiceMode,
+    // Switched to before every start; the active profile otherwise.
+    pub profile: Option<String>,
+    pub xray: String,
+    pub failover: bool,
+    pub kill_switch: bool,
+    pub tun_routes: bool,
+    pub tun_dns: bool,
+    pub system_proxy: bool,
+}
+
+// A unit under the user's or the system's systemd instance.
+pub struct Unit {
+    pub name: String,
+    pub system: bool,
+}
+
+impl Unit {
+    pub fn new(name: &str, system: bool) -> Result<Unit, PawprintError> {
+        profile::check_name(name).map_err(|_| format!("Invalid service name: {}", name))?;
+        Ok(Unit {
+            name: name.to_string(),
+            system,
+        })
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.service", self.name)
+    }
+
+    pub fn path(&self) -> Result<PathBuf, PawprintError> {
+        let dir = if self.system {
+            PathBuf::from("/etc/systemd/system")
+        } else {
+            dirs::config_dir()
+                .ok_or("Could not determine the config directory")?
+                .join("systemd")
+                .join("user")
+        };
+        Ok(dir.join(self.file_name()))
+    }
+
+    // Runs `systemctl [--user] <verb> [unit]`.
+    pub fn systemctl(&self, verb: &str, with_unit: bool) -> Result<String, PawprintError> {
+        let mut args = vec!["systemctl".to_string()];
+        if !self.system {
+            args.push("--user".to_string());
+        }
+        args.push(verb.to_string());
+        if with_unit {
+            args.push(self.file_name());
+        }
+        command(&args)
+    }
+}
+
+// The absolute path of a bare program name found in PATH, so the unit does
+// not depend on systemd's own PATH. Anything else is returned as is.
+pub fn resolve(program: &str) -> String {
+    // The installed core is found by name when the unit starts, so it follows
+    // `core update`.
+    if program.contains('/') || (program == "xray" && xraycore::active_binary().is_some()) {
+        return program.to_string();
+    }
+    std::env::var_os("PATH")
+        .iter()
+        .flat_map(std::env::split_paths)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|| program.to_string())
+}
+
+// systemd splits command lines like a shell but expands `%` specifiers and
+// `$VARS`, so both are escaped along with quotes.
+fn quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    if escaped.is_empty() || escaped.contains(char::is_whitespace) || escaped != arg {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+fn command_line(exe: &Path, args: &[&str]) -> String {
+    let exe = exe.to_string_lossy();
+    std::iter::once(exe.as_ref())
+        .chain(args.iter().copied())
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// The unit file starting `exe` with `options`.
+pub fn unit_file(
+    unit: &Unit,
+    exe: &Path,
+    options: &ServiceOptions,
+) -> Result<String, PawprintError> {
+    if let Some(name) = &options.profile {
+        profile::check_name(name)?;
+    }
+    let verb = match options.mode {
+        ServiceMode::Run => "run",
+        ServiceMode::Daemon => "daemon",
+    };
+    let mut run = vec![verb.to_string(), "--xray".to_string(), options.xray.clone()];
+    if options.mode == ServiceMode::Run {
+        if options.failover {
+            run.push("--failover".to_string());
+        } else {
+            run.push(profile::active_config()?.to_string_lossy().into_owned());
+        }
+        if options.kill_switch {
+            run.push("--kill-switch".to_string());
+        }
+        if options.tun_routes {
+            run.push("--tun-routes".to_string());
+        }
+        if options.tun_dns {
+            run.push("--tun-dns".to_string());
+        }
+        if options.system_proxy {
+            run.push("--system-proxy".to_string());
+        }
+    }
+    let run: Vec<&str> = run.iter().map(String::as_str).collect();
+
+    let mut lines = vec![
+        "[Unit]".to_string(),
+        format!(
+            "Description=pawprint-vpn {}",
+            match options.mode {
+                ServiceMode::Run => "proxy",
+                ServiceMode::Daemon => "control API",
+            }
+        ),
+        "Wants=network-online.target".to_string(),
+        "After=network-online.target".to_string(),
+        String::new(),
+        "[Service]".to_string(),
+        "Type=simple".to_string(),
+    ];
+    if let Some(name) = &options.profile {
+        lines.push(format!(
+            "ExecStartPre={}",
+            command_line(exe, &["profile", "use", name])
+        ));
+    }
+    lines.extend([
+        format!("ExecStart={}", command_line(exe, &run)),
+        // The whole group gets SIGTERM; once xray exits, `run` still needs a
+        // moment to take down the kill switch and system proxy.
+        "TimeoutStopSec=15".to_string(),
+        "Restart=on-failure".to_string(),
+        "RestartSec=5".to_string(),
+        String::new(),
+        "[Install]".to_string(),
+        format!(
+            "WantedBy={}",
+            if unit.system {
+                "multi-user.target"
+            } else {
+                "default.target"
+            }
+        ),
+    ]);
+    Ok(lines.join("\n") + "\n")
+}
+
+// Writes the unit file and reloads systemd so it sees it.
+pub fn install(unit: &Unit, content: &str, force: bool) -> Result<PathBuf, PawprintError> {
+    let path = unit.path()?;
+    if path.exists() && !force {
+        return Err(format!(
+            "{} already exists. Use --force to replace it.",
+            path.display()
+        )
+        .into());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| PawprintError::file(parent, e))?;
+    }
+    fs::write(&path, content).map_err(|e| PawprintError::file(&path, e))?;
+    unit.systemctl("daemon-reload", false)?;
+    Ok(path)
+}
+
+// Stops and disables the unit, then removes its file. Returns false if there
+// was no unit file.
+pub fn uninstall(unit: &Unit) -> Result<bool, PawprintError> {
+    let path = unit.path()?;
+    if !path.exists() {
+        return Ok(false);
+    }
+    // Either fails harmlessly if the unit was never enabled or started.
+    let _ = unit.systemctl("disable", true);
+    let _ = unit.systemctl("stop", true);
+    fs::remove_file(&path).map_err(|e| PawprintError::file(&path, e))?;
+    unit.systemctl("daemon-reload", false)?;
+    Ok(true)
+}
@@ -0,0 +1,1006 @@
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use tracing::warn;
+use url::Url;
+
+use crate::backend::BuildOptions;
+use crate::error::PawprintError;
+use crate::parser::{Node, ShadowsocksConfig};
+use crate::spec::{
+    self, DomainStrategy, FragmentSpec, InboundProtocol, InboundSpec, LogLevel, SniffingSpec,
+    SockoptSpec, TunSpec,
+};
+use crate::target::CoreTarget;
+
+// Rule sets published by the sing-box authors, named geoip-<code> and geosite-<name>.
+const GEOIP_RULE_SETS: &str = "https://raw.githubusercontent.com/SagerNet/sing-geoip/rule-set";
+const GEOSITE_RULE_SETS: &str = "https://raw.githubusercontent.com/SagerNet/sing-geosite/rule-set";
+const PROBE_URL: &str = "http://cp.cloudflare.com/generate_204";
+
+// Tags of the outbounds the config adds itself.
+const RESERVED_TAGS: &[&str] = &["direct", "block", "dns-out", "balancer"];
+
+fn build_tls(
+    params: &HashMap<String, String>,
+    address: &str,
+    default_security: &str,
+) -> Option<Value> {
+    let security = params
+        .get("security")
+        .map(String::as_str)
+        .unwrap_or(default_security);
+    if security != "tls" && security != "reality" {
+        return None;
+    }
+
+    let mut tls = json!({
+        "enabled": true,
+        "server_name": params.get("sni").map(String::as_str).unwrap_or(address),
+    });
+    if let Some(alpn) = params.get("alpn").filter(|a| !a.is_empty()) {
+        tls["alpn"] = json!(alpn.split(',').collect::<Vec<_>>());
+    }
+    // REALITY only works with a uTLS fingerprint.
+    let fingerprint = params
+        .get("fp")
+        .map(String::as_str)
+        .filter(|fp| !fp.is_empty())
+        .or((security == "reality").then_some("chrome"));
+    if let Some(fp) = fingerprint {
+        tls["utls"] = json!({ "enabled": true, "fingerprint": fp });
+    }
+    if ["allowInsecure", "insecure"]
+        .iter()
+        .any(|key| params.get(*key).is_some_and(|v| v == "1" || v == "true"))
+    {
+        tls["insecure"] = json!(true);
+    }
+    if let Some(ech) = params.get("ech").filter(|e| !e.is_empty()) {
+        tls["ech"] = build_ech(ech);
+    }
+    if security == "reality" {
+        tls["reality"] = json!({
+            "enabled": true,
+            "public_key": params.get("pbk").cloned().unwrap_or_default(),
+            "short_id": params.get("sid").cloned().unwrap_or_default(),
+        });
+    }
+    Some(tls)
+}
+
+// The `ech` link parameter is a base64 ECHConfigList, which sing-box wants as
+// PEM, or a DNS query like `example.com+https://1.1.1.1/dns-query` (base64 has
+// no dots). For the latter sing-box looks the config up itself.
+fn build_ech(ech: &str) -> Value {
+    if ech.contains('.') {
+        return json!({ "enabled": true });
+    }
+    json!({
+        "enabled": true,
+        "config": [format!(
+            "-----BEGIN ECH CONFIGS-----\n{}\n-----END ECH CONFIGS-----",
+            ech
+        )],
+    })
+}
+
+fn build_transport(params: &HashMap<String, String>) -> Result<Option<Value>, String> {
+    let param = |key: &str| params.get(key).filter(|v| !v.is_empty());
+    let path = param("path").map(String::as_str).unwrap_or("/");
+    let host = param("host");
+
+    let network = params.get("type").map(String::as_str).unwrap_or("tcp");
+    let transport = match network {
+        "tcp" | "raw" if param("headerType").is_some_and(|t| t == "http") => {
+            return Err("sing-box has no TCP HTTP header obfuscation".to_string());
+        }
+        "tcp" | "raw" => return Ok(None),
+        "ws" => {
+            let mut ws = json!({ "type": "ws", "path": path });
+            if let Some(host) = host {
+                ws["headers"] = json!({ "Host": host });
+            }
+            ws
+        }
+        "httpupgrade" => {
+            let mut upgrade = json!({ "type": "httpupgrade", "path": path });
+            if let Some(host) = host {
+                upgrade["host"] = json!(host);
+            }
+            upgrade
+        }
+        "grpc" => json!({
+            "type": "grpc",
+            "service_name": param("serviceName").cloned().unwrap_or_default()
+        }),
+        "h2" | "http" => json!({
+            "type": "http",
+            "host": host.map(|h| h.split(',').collect::<Vec<_>>()).unwrap_or_default(),
+            "path": path
+        }),
+        "quic" => json!({ "type": "quic" }),
+        other => return Err(format!("sing-box does not support the {} transport", other)),
+    };
+    Ok(Some(transport))
+}
+
+// QUIC protocols always use TLS; links mark self-signed servers with
+// `insecure_key=1`.
+fn quic_tls(
+    params: &HashMap<String, String>,
+    address: &str,
+    insecure_key: &str,
+    default_alpn: Option<&str>,
+) -> Value {
+    let mut tls = build_tls(params, address, "tls").unwrap_or_else(|| json!({ "enabled": true }));
+    tls["enabled"] = json!(true);
+    if tls.get("alpn").is_none()
+        && let Some(alpn) = default_alpn
+    {
+        tls["alpn"] = json!([alpn]);
+    }
+    if params
+        .get(insecure_key)
+        .is_some_and(|v| v == "1" || v == "true")
+    {
+        tls["insecure"] = json!(true);
+    }
+    tls
+}
+
+// Xray sniffer names sing-box knows too; it has no fakedns sniffer.
+fn sniffers(protocols: &[String]) -> Vec<&str> {
+    protocols
+        .iter()
+        .map(String::as_str)
+        .filter(|protocol| {
+            let known = *protocol != "fakedns";
+            if !known {
+                warn!("sing-box has no fakedns sniffer, leaving it out");
+            }
+            known
+        })
+        .collect()
+}
+
+fn resolve_strategy(strategy: DomainStrategy) -> Option<&'static str> {
+    match strategy {
+        DomainStrategy::AsIs => None,
+        DomainStrategy::UseIp | DomainStrategy::ForceIp => Some("prefer_ipv4"),
+        DomainStrategy::UseIpv4 | DomainStrategy::ForceIpv4 => Some("ipv4_only"),
+        DomainStrategy::UseIpv6 | DomainStrategy::ForceIpv6 => Some("ipv6_only"),
+    }
+}
+
+// Dial fields of every outbound that opens connections itself.
+fn add_dial_fields(outbounds: &mut [Value], spec: &SockoptSpec, target: &CoreTarget) {
+    if spec.tcp_keep_alive_idle.is_some() {
+        warn!("The TCP keep-alive setting only applies to Xray and is ignored for sing-box");
+    }
+    let strategy = spec.domain_strategy.and_then(resolve_strategy);
+    for outbound in outbounds.iter_mut().filter(|o| {
+        !matches!(
+            o["type"].as_str(),
+            Some("block" | "dns" | "urltest" | "selector")
+        )
+    }) {
+        if spec.tcp_fast_open {
+            outbound["tcp_fast_open"] = json!(true);
+        }
+        if let Some(mark) = spec.mark {
+            outbound["routing_mark"] = json!(mark);
+        }
+        if let Some(strategy) = strategy
+            && !target.supports_typed_dns_servers()
+        {
+            outbound["domain_strategy"] = json!(strategy);
+        }
+    }
+}
+
+// Common shape of the VLESS, VMess and Trojan outbounds.
+fn stream_outbound(
+    mut outbound: Value,
+    params: &HashMap<String, String>,
+    address: &str,
+    default_security: &str,
+) -> Result<Value, String> {
+    if let Some(tls) = build_tls(params, address, default_security) {
+        outbound["tls"] = tls;
+    }
+    if let Some(transport) = build_transport(params)? {
+        outbound["transport"] = transport;
+    }
+    Ok(outbound)
+}
+
+fn build_shadowsocks_outbound(ss: &ShadowsocksConfig) -> Result<Value, String> {
+    let mut outbound = json!({
+        "type": "shadowsocks",
+        "tag": ss.tag,
+        "server": ss.address,
+        "server_port": ss.port,
+        "method": ss.method,
+        "password": ss.password,
+    });
+    // sing-box runs obfs-local and v2ray-plugin itself, configured like SIP003,
+    // but v2ray-plugin only in its websocket mode.
+    if let Some(plugin) = ss.sip003() {
+        let name = match plugin.name {
+            "obfs-local" | "simple-obfs" => "obfs-local",
+            "v2ray-plugin" if plugin.option("mode").is_none_or(|mode| mode == "websocket") => {
+                "v2ray-plugin"
+            }
+            _ => {
+                return Err(format!(
+                    "sing-box cannot run the SIP003 plugin {} ({})",
+                    ss.plugin.as_deref().unwrap_or_default(),
+                    ss.tag
+                ));
+            }
+        };
+        let opts = ss
+            .plugin
+            .as_deref()
+            .and_then(|p| p.split_once(';'))
+            .map_or("", |(_, opts)| opts);
+        outbound["plugin"] = json!(name);
+        outbound["plugin_opts"] = json!(opts);
+    }
+    Ok(outbound)
+}
+
+fn build_outbound(node: &Node) -> Result<Value, String> {
+    match node {
+        Node::Vless(vless) => {
+            let mut outbound = json!({
+                "type": "vless",
+                "tag": vless.tag,
+                "server": vless.address,
+                "server_port": vless.port,
+                "uuid": vless.uuid,
+                "packet_encoding": "xudp",
+            });
+            if let Some(flow) = vless.params.get("flow").filter(|f| !f.is_empty()) {
+                outbound["flow"] = json!(flow);
+            }
+            stream_outbound(outbound, &vless.params, &vless.address, "tls")
+        }
+        Node::Vmess(vmess) => stream_outbound(
+            json!({
+                "type": "vmess",
+                "tag": vmess.tag,
+                "server": vmess.address,
+                "server_port": vmess.port,
+                "uuid": vmess.uuid,
+                "alter_id": vmess.alter_id,
+                "security": vmess.security,
+            }),
+            &vmess.params,
+            &vmess.address,
+            "none",
+        ),
+        Node::Trojan(trojan) => stream_outbound(
+            json!({
+                "type": "trojan",
+                "tag": trojan.tag,
+                "server": trojan.address,
+                "server_port": trojan.port,
+                "password": trojan.password,
+            }),
+            &trojan.params,
+            &trojan.address,
+            "tls",
+        ),
+        Node::Shadowsocks(ss) => build_shadowsocks_outbound(ss),
+        Node::Hysteria2(hy2) => {
+            let param = |key: &str| hy2.params.get(key).filter(|v| !v.is_empty());
+            let mut outbound = json!({
+                "type": "hysteria2",
+                "tag": hy2.tag,
+                "server": hy2.address,
+                "server_port": hy2.port,
+                "password": hy2.auth,
+                "tls": quic_tls(&hy2.params, &hy2.address, "insecure", None),
+            });
+            if let Some(obfs) = param("obfs") {
+                outbound["obfs"] = json!({
+                    "type": obfs,
+                    "password": param("obfs-password").cloned().unwrap_or_default(),
+                });
+            }
+            for (key, field) in [("upmbps", "up_mbps"), ("downmbps", "down_mbps")] {
+                if let Some(mbps) = param(key).and_then(|v| v.parse::<u32>().ok()) {
+                    outbound[field] = json!(mbps);
+                }
+            }
+            Ok(outbound)
+        }
+        Node::Tuic(tuic) => {
+            let param = |key: &str| tuic.params.get(key).filter(|v| !v.is_empty());
+            let mut outbound = json!({
+                "type": "tuic",
+                "tag": tuic.tag,
+                "server": tuic.address,
+                "server_port": tuic.port,
+                "uuid": tuic.uuid,
+                "password": tuic.password,
+                "tls": quic_tls(&tuic.params, &tuic.address, "allow_insecure", Some("h3")),
+            });
+            if let Some(congestion) = param("congestion_control") {
+                outbound["congestion_control"] = json!(congestion);
+            }
+            if let Some(mode) = param("udp_relay_mode") {
+                outbound["udp_relay_mode"] = json!(mode);
+            }
+            Ok(outbound)
+        }
+        Node::Wireguard(_) => Err(
+            "WireGuard outbounds are only generated for Xray; sing-box 1.11 moved them to endpoints"
+                .to_string(),
+        ),
+        Node::Plugin(plugin) => Err(format!(
+            "{}:// nodes come from a plugin as Xray outbounds and cannot be converted to sing-box",
+            plugin.scheme
+        )),
+    }
+}
+
+fn build_inbound(inbound: &InboundSpec, tag: &str) -> Value {
+    let kind = match inbound.protocol {
+        InboundProtocol::Socks => "socks",
+        InboundProtocol::Http => "http",
+    };
+    // Xray listens on every interface when no address is given; keep that meaning.
+    let mut value = json!({
+        "type": kind,
+        "tag": tag,
+        "listen": inbound.listen.as_deref().unwrap_or("::"),
+        "listen_port": inbound.port,
+    });
+    if let (Some(user), Some(pass)) = (&inbound.username, &inbound.password) {
+        value["users"] = json!([{ "username": user, "password": pass }]);
+    }
+    value
+}
+
+fn build_tun_inbound(tun: &TunSpec, target: &CoreTarget) -> Value {
+    let mut value = json!({
+        "type": "tun",
+        "tag": "tun-in",
+        "interface_name": tun.name,
+        "address": tun.address,
+        "mtu": tun.mtu,
+        "auto_route": tun.auto_route,
+        "strict_route": tun.auto_route,
+    });
+    if let Some(stack) = tun.stack {
+        value["stack"] = json!(stack);
+    }
+    // Inbound sniffing became a route rule action in 1.11.
+    if !target.supports_rule_actions() {
+        value["sniff"] = json!(true);
+    }
+    value
+}
+
+fn build_dns_server(tag: &str, address: &str, port: Option<u16>, target: &CoreTarget) -> Value {
+    if target.supports_typed_dns_servers() {
+        let mut server = json!({ "type": "udp", "tag": tag, "server": address });
+        if let Some(port) = port {
+            server["server_port"] = json!(port);
+        }
+        server
+    } else {
+        let address = match (port, address.contains(':')) {
+            (Some(port), true) => format!("udp://[{}]:{}", address, port),
+            (Some(port), false) => format!("udp://{}:{}", address, port),
+            (None, _) => address.to_string(),
+        };
+        json!({ "tag": tag, "address": address })
+    }
+}
+
+// Upstream server given as a plain address or a tcp://, tls://, https://, quic://
+// or h3:// URL. Before 1.12 sing-box takes the URL as is.
+fn build_upstream_dns_server(
+    tag: &str,
+    server: &str,
+    target: &CoreTarget,
+) -> Result<Value, String> {
+    if !server.contains("://") {
+        return Ok(build_dns_server(tag, server, None, target));
+    }
+    if !target.supports_typed_dns_servers() {
+        return Ok(json!({ "tag": tag, "address": server }));
+    }
+
+    let url = Url::parse(server).map_err(|e| format!("Invalid DNS server {}: {}", server, e))?;
+    let kind = match url.scheme() {
+        "udp" | "tcp" | "tls" | "https" | "quic" | "h3" => url.scheme(),
+        other => return Err(format!("Unsupported DNS server scheme {}://", other)),
+    };
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("DNS server {} has no host", server))?;
+    let mut value = json!({
+        "type": kind,
+        "tag": tag,
+        "server": host.trim_matches(['[', ']']),
+    });
+    if let Some(port) = url.port() {
+        value["server_port"] = json!(port);
+    }
+    if matches!(kind, "https" | "h3") && url.path() != "/dns-query" {
+        value["path"] = json!(url.path());
+    }
+    Ok(value)
+}
+
+// sing-box has one log for everything; connections show up at info level.
+fn build_log(options: &BuildOptions) -> Value {
+    let Some(spec) = &options.log else {
+        return json!({ "level": "info" });
+    };
+    if spec.access.is_some() {
+        warn!("sing-box has no separate access log, connections go to the main log at info level");
+    }
+    if spec.dns {
+        warn!("sing-box logs DNS queries at debug level, ignoring the DNS log option");
+    }
+    let mut log = match spec.level {
+        LogLevel::None => json!({ "disabled": true }),
+        LogLevel::Warning => json!({ "level": "warn" }),
+        level => json!({ "level": level.as_str() }),
+    };
+    if let Some(error) = &spec.error {
+        log["output"] = json!(error);
+        log["timestamp"] = json!(true);
+    }
+    log
+}
+
+// Same split as the Xray output: scoped servers reached directly, the rest
+// resolved by the upstream servers (1.1.1.1 unless chosen) through the proxy.
+fn build_dns(options: &BuildOptions) -> Result<Option<Value>, String> {
+    let target = &options.target;
+    let routes = &options.dns_routes;
+    let typed = target.supports_typed_dns_servers();
+    if routes.is_empty() && options.dns_servers.is_empty() && !typed {
+        return Ok(None);
+    }
+
+    let mut servers = Vec::new();
+    let mut rules = Vec::new();
+    for (index, route) in routes.iter().enumerate() {
+        let tag = format!("dns-{}", index + 1);
+        let mut server = build_dns_server(&tag, &route.address, route.port, target);
+        server["detour"] = json!("direct");
+        servers.push(server);
+        rules.push(json!({ "domain_suffix": [route.domain], "server": tag }));
+    }
+    if options.dns_servers.is_empty() {
+        servers.push(build_dns_server("dns-remote", "1.1.1.1", None, target));
+    } else if options.dns_servers.len() > 1 {
+        warn!("sing-box only queries the first --dns server; the others are kept for custom rules");
+    }
+    for (index, server) in options.dns_servers.iter().enumerate() {
+        // sing-box has no fallback between servers: `final` answers everything
+        // else, and the rest are only there for hand-written rules.
+        let tag = match index {
+            0 => "dns-remote".to_string(),
+            n => format!("dns-remote-{}", n + 1),
+        };
+        servers.push(build_upstream_dns_server(&tag, server, target)?);
+    }
+    // 1.12 resolves server names through an explicit resolver instead of the system one.
+    if typed {
+        servers.push(json!({ "type": "local", "tag": "dns-local" }));
+    }
+
+    Ok(Some(json!({
+        "servers": servers,
+        "rules": rules,
+        "final": "dns-remote",
+    })))
+}
+
+// Splits Xray-style matchers into sing-box rules. Fields of different kinds are
+// ANDed within one sing-box rule, so each kind gets its own rule.
+fn build_rules(
+    matchers: &[String],
+    action: &Value,
+    rule_sets: &mut Vec<String>,
+) -> Result<Vec<Value>, String> {
+    let mut domain_fields: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut ip_cidr = Vec::new();
+    let mut private = false;
+    let mut sets = Vec::new();
+
+    for matcher in matchers {
+        let (kind, value) = matcher.split_once(':').unwrap_or(("", matcher));
+        match kind {
+            "geoip" if value == "private" => private = true,
+            "geoip" | "geosite" => sets.push(format!("{}-{}", kind, value)),
+            "domain" => domain_fields
+                .entry("domain_suffix")
+                .or_default()
+                .push(value),
+            "full" => domain_fields.entry("domain").or_default().push(value),
+            "keyword" => domain_fields
+                .entry("domain_keyword")
+                .or_default()
+                .push(value),
+            "regexp" => domain_fields.entry("domain_regex").or_default().push(value),
+            _ if matcher
+                .split_once('/')
+                .map_or(matcher.as_str(), |(ip, _)| ip)
+                .parse::<IpAddr>()
+                .is_ok() =>
+            {
+                ip_cidr.push(matcher.as_str())
+            }
+            // A bare string is a substring match in Xray.
+            "" => domain_fields
+                .entry("domain_keyword")
+                .or_default()
+                .push(value),
+            _ => return Err(format!("Matcher {} is not supported for sing-box", matcher)),
+        }
+    }
+
+    let with_action = |mut rule: Value| {
+        for (key, value) in action.as_object().expect("rule action is an object") {
+            rule[key] = value.clone();
+        }
+        rule
+    };
+    let mut rules = Vec::new();
+    if !domain_fields.is_empty() {
+        let mut keys: Vec<_> = domain_fields.keys().copied().collect();
+        keys.sort();
+        let mut rule = json!({});
+        for key in keys {
+            rule[key] = json!(domain_fields[key]);
+        }
+        rules.push(with_action(rule));
+    }
+    if !sets.is_empty() {
+        rules.push(with_action(json!({ "rule_set": sets })));
+        rule_sets.extend(sets);
+    }
+    if !ip_cidr.is_empty() || private {
+        let mut rule = json!({});
+        if !ip_cidr.is_empty() {
+            rule["ip_cidr"] = json!(ip_cidr);
+        }
+        if private {
+            rule["ip_is_private"] = json!(true);
+        }
+        rules.push(with_action(rule));
+    }
+    Ok(rules)
+}
+
+fn rule_set_definition(tag: &str) -> Value {
+    let base = if tag.starts_with("geoip-") {
+        GEOIP_RULE_SETS
+    } else {
+        GEOSITE_RULE_SETS
+    };
+    json!({
+        "tag": tag,
+        "type": "remote",
+        "format": "binary",
+        "url": format!("{}/{}.srs", base, tag),
+    })
+}
+
+pub fn build_config(nodes: &[Node], options: &BuildOptions) -> Result<Value, PawprintError> {
+    let target = &options.target;
+    let actions = target.supports_rule_actions();
+
+    let mut outbounds: Vec<Value> = Vec::new();
+    for node in nodes {
+        let mut outbound = build_outbound(node)?;
+        // Tags must be unique here too, also against the config's own.
+        let mut tag = node.tag().to_string();
+        let mut suffix = 2;
+        while RESERVED_TAGS.contains(&tag.as_str())
+            || outbounds.iter().any(|o| o["tag"] == tag.as_str())
+        {
+            tag = format!("{}-{}", node.tag(), suffix);
+            suffix += 1;
+        }
+        outbound["tag"] = json!(tag);
+        outbounds.push(outbound);
+    }
+    if options.chain {
+        for hop in 1..outbounds.len() {
+            outbounds[hop]["detour"] = outbounds[hop - 1]["tag"].clone();
+        }
+        outbounds.reverse();
+    }
+    for outbound in &mut outbounds {
+        // Hysteria2 and TUIC multiplex over QUIC themselves and have no TLS over TCP.
+        if matches!(outbound["type"].as_str(), Some("hysteria2" | "tuic")) {
+            continue;
+        }
+        if let Some(mux) = &options.mux {
+            // Vision cannot run inside a multiplexed stream.
+            if outbound["flow"].as_str().is_some_and(|f| !f.is_empty()) {
+                warn!(
+                    "Not enabling mux on {}: it cannot be combined with flow {}",
+                    outbound["tag"].as_str().unwrap_or_default(),
+                    outbound["flow"].as_str().unwrap_or_default()
+                );
+            } else {
+                // sing-box speaks its own smux-based mux; the server must run sing-box too.
+                outbound["multiplex"] = json!({
+                    "enabled": true,
+                    "protocol": "smux",
+                    "max_streams": mux.concurrency,
+                });
+            }
+        }
+        // Within a chain only the entry's handshake is visible on the network.
+        if options.fragment.is_some() && outbound["detour"].is_null() {
+            if outbound["tls"]["enabled"] == true {
+                outbound["tls"]["fragment"] = json!(true);
+            } else {
+                warn!(
+                    "Not fragmenting {}: it does not use TLS",
+                    outbound["tag"].as_str().unwrap_or_default()
+                );
+            }
+        }
+    }
+    if options
+        .fragment
+        .as_ref()
+        .is_some_and(|f| *f != FragmentSpec::default())
+    {
+        warn!("sing-box picks fragment sizes itself; packets, length and interval are ignored");
+    }
+    let mut proxy_tag = outbounds[0]["tag"].as_str().unwrap_or_default().to_string();
+    if options.balance && outbounds.len() > 1 {
+        // sing-box's counterpart of an Xray leastPing balancer.
+        let tags: Vec<Value> = outbounds.iter().map(|o| o["tag"].clone()).collect();
+        outbounds.push(json!({
+            "type": "urltest",
+            "tag": "balancer",
+            "outbounds": tags,
+            "url": PROBE_URL,
+            "interval": "1m",
+        }));
+        proxy_tag = "balancer".to_string();
+    }
+
+    let default_inbounds = [InboundSpec::default()];
+    let inbound_specs: &[InboundSpec] = if options.inbounds.is_empty() {
+        &default_inbounds
+    } else {
+        &options.inbounds
+    };
+    let mut inbounds: Vec<Value> = inbound_specs
+        .iter()
+        .zip(InboundSpec::tags(inbound_specs))
+        .map(|(inbound, tag)| build_inbound(inbound, &tag))
+        .collect();
+    if let Some(tun) = &options.tun {
+        inbounds.push(build_tun_inbound(tun, target));
+    }
+
+    let mut rules = Vec::new();
+    if actions {
+        // Since 1.11 a sniffed domain is only used for routing, whatever route_only says.
+        let mut sniff = json!({ "action": "sniff" });
+        if let Some(sniffing) = &options.sniffing {
+            sniff["sniffer"] = json!(sniffers(&sniffing.protocols));
+        }
+        rules.push(sniff);
+    } else if let Some(sniffing) = &options.sniffing {
+        if sniffing.protocols != SniffingSpec::default_protocols() {
+            warn!(
+                "sing-box before 1.11 sniffs every protocol it knows; the sniffing protocols are ignored"
+            );
+        }
+        for inbound in &mut inbounds {
+            inbound["sniff"] = json!(true);
+            inbound["sniff_override_destination"] = json!(!sniffing.route_only);
+        }
+    }
+    let hijack_dns = |mut rule: Value| {
+        if actions {
+            rule["action"] = json!("hijack-dns");
+        } else {
+            rule["outbound"] = json!("dns-out");
+        }
+        rule
+    };
+    if options.tun.is_some() {
+        rules.push(hijack_dns(json!({ "protocol": "dns" })));
+    }
+    // Like Xray's dns-out: client queries are answered by the chosen servers.
+    if !options.dns_servers.is_empty() {
+        rules.push(hijack_dns(json!({ "port": 53 })));
+    }
+    if !options.dns_routes.is_empty() {
+        // Servers given by name cannot go in `ip_cidr`.
+        let (resolvers, named): (Vec<&str>, Vec<&str>) = options
+            .dns_routes
+            .iter()
+            .map(|r| r.address.as_str())
+            .partition(|address| address.parse::<IpAddr>().is_ok());
+        let domains: Vec<&str> = options
+            .dns_routes
+            .iter()
+            .map(|r| r.domain.as_str())
+            .collect();
+        if !resolvers.is_empty() {
+            rules.push(json!({ "ip_cidr": resolvers, "outbound": "direct" }));
+        }
+        if !named.is_empty() {
+            rules.push(json!({ "domain": named, "outbound": "direct" }));
+        }
+        rules.push(json!({ "domain_suffix": domains, "outbound": "direct" }));
+    }
+
+    let block = if actions {
+        json!({ "action": "reject" })
+    } else {
+        json!({ "outbound": "block" })
+    };
+    // Inbounds bound to an outbound bypass the matchers below.
+    for (tag, outbound) in InboundSpec::routes(inbound_specs) {
+        let mut rule = match outbound {
+            spec::BLOCK => block.clone(),
+            spec::PROXY => json!({ "outbound": proxy_tag }),
+            spec::DIRECT => json!({ "outbound": spec::DIRECT }),
+            node if outbounds
+                .iter()
+                .any(|o| o["tag"] == node && o["type"] != "urltest") =>
+            {
+                json!({ "outbound": node })
+            }
+            node => {
+                let tags: Vec<&str> = outbounds
+                    .iter()
+                    .filter(|o| o["type"] != "urltest")
+                    .filter_map(|o| o["tag"].as_str())
+                    .collect();
+                return Err(format!(
+                    "Inbound {} is routed to {}, which is not a node; use direct, block, proxy or one of: {}",
+                    tag,
+                    node,
+                    tags.join(", ")
+                )
+                .into());
+            }
+        };
+        rule["inbound"] = json!([tag]);
+        rules.push(rule);
+    }
+    let mut rule_sets = Vec::new();
+    let mut matcher_rules = Vec::new();
+    matcher_rules.extend(build_rules(&options.rules.block, &block, &mut rule_sets)?);
+    matcher_rules.extend(build_rules(
+        &options.rules.proxy,
+        &json!({ "outbound": proxy_tag }),
+        &mut rule_sets,
+    )?);
+    matcher_rules.extend(build_rules(
+        &options.rules.direct,
+        &json!({ "outbound": "direct" }),
+        &mut rule_sets,
+    )?);
+    // IP rules only see domains once they are resolved, like Xray's IPIfNonMatch.
+    if actions
+        && let Some(first_ip) = matcher_rules.iter().position(|rule| {
+            rule.get("ip_cidr").is_some()
+                || rule.get("ip_is_private").is_some()
+                || rule["rule_set"].as_array().is_some_and(|sets| {
+                    sets.iter()
+                        .any(|s| s.as_str().is_some_and(|s| s.starts_with("geoip-")))
+                })
+        })
+    {
+        matcher_rules.insert(first_ip, json!({ "action": "resolve" }));
+    }
+    rules.extend(matcher_rules);
+
+    let uses = |outbound: &str| rules.iter().any(|rule| rule["outbound"] == outbound);
+    if uses("direct") || !options.dns_routes.is_empty() {
+        outbounds.push(json!({ "type": "direct", "tag": "direct" }));
+    }
+    if uses("block") {
+        outbounds.push(json!({ "type": "block", "tag": "block" }));
+    }
+    if uses("dns-out") {
+        outbounds.push(json!({ "type": "dns", "tag": "dns-out" }));
+    }
+    if let Some(sockopt) = &options.sockopt {
+        add_dial_fields(&mut outbounds, sockopt, target);
+    }
+
+    rule_sets.sort();
+    rule_sets.dedup();
+    let mut route = json!({
+        "rules": rules,
+        "final": proxy_tag,
+    });
+    if !rule_sets.is_empty() {
+        route["rule_set"] = json!(
+            rule_sets
+                .iter()
+                .map(|tag| rule_set_definition(tag))
+                .collect::<Vec<_>>()
+        );
+    }
+    if options.tun.as_ref().is_some_and(|tun| tun.auto_route) {
+        // Keeps the proxy's own connections off the TUN interface.
+        route["auto_detect_interface"] = json!(true);
+    }
+    if target.supports_typed_dns_servers() {
+        route["default_domain_resolver"] = json!("dns-local");
+        // 1.12 moved the strategy from the outbounds to the resolver.
+        if let Some(strategy) = options
+            .sockopt
+            .as_ref()
+            .and_then(|s| s.domain_strategy)
+            .and_then(resolve_strategy)
+        {
+            route["default_domain_resolver"] =
+                json!({ "server": "dns-local", "strategy": strategy });
+        }
+    }
+
+    let mut config = json!({
+        "log": build_log(options),
+        "inbounds": inbounds,
+        "outbounds": outbounds,
+        "route": route,
+    });
+    if let Some(dns) = build_dns(options)? {
+        config["dns"] = dns;
+    }
+    // Xray-compatible stats API; only in sing-box builds with the with_v2ray_api tag.
+    if let Some(stats) = &options.stats {
+        let tags = |entries: &[Value]| -> Vec<Value> {
+            entries.iter().map(|entry| entry["tag"].clone()).collect()
+        };
+        config["experimental"] = json!({
+            "v2ray_api": {
+                "listen": format!("127.0.0.1:{}", stats.port),
+                "stats": {
+                    "enabled": true,
+                    "inbounds": tags(config["inbounds"].as_array().map(Vec::as_slice).unwrap_or_default()),
+                    "outbounds": tags(config["outbounds"].as_array().map(Vec::as_slice).unwrap_or_default()),
+                },
+            },
+        });
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Registry;
+    use crate::spec::RoutingRules;
+
+    fn nodes(links: &[&str]) -> Vec<Node> {
+        let registry = Registry::new();
+        links.iter().map(|l| registry.parse(l).unwrap()).collect()
+    }
+
+    fn options() -> BuildOptions {
+        BuildOptions {
+            target: "sing-box@1.12".parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tags_stay_unique() {
+        let nodes = nodes(&[
+            "trojan://a@a.example:443#direct",
+            "trojan://b@b.example:443#nl",
+            "trojan://c@c.example:443#nl",
+        ]);
+        let config = build_config(&nodes, &options()).unwrap();
+        let tags: Vec<&str> = config["outbounds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|o| o["tag"].as_str())
+            .collect();
+        assert_eq!(&tags[..3], ["direct-2", "nl", "nl-2"]);
+    }
+
+    #[test]
+    fn blocking_follows_the_target() {
+        let nodes = nodes(&["trojan://a@a.example:443#nl"]);
+        let rules = RoutingRules {
+            block: vec!["geosite:category-ads-all".to_string()],
+            direct: vec![
+                "geoip:private".to_string(),
+                "domain:lan.example".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let legacy = BuildOptions {
+            target: "sing-box@1.10".parse().unwrap(),
+            rules: rules.clone(),
+            ..Default::default()
+        };
+        let config = build_config(&nodes, &legacy).unwrap();
+        let route = &config["route"]["rules"];
+        assert_eq!(route[0]["outbound"], "block");
+        assert_eq!(route[0]["rule_set"], json!(["geosite-category-ads-all"]));
+        let types: Vec<&str> = config["outbounds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|o| o["type"].as_str())
+            .collect();
+        assert_eq!(types, ["trojan", "direct", "block"]);
+
+        let current = BuildOptions { rules, ..options() };
+        let config = build_config(&nodes, &current).unwrap();
+        let route = config["route"]["rules"].as_array().unwrap();
+        assert!(route.iter().any(|r| r["action"] == "reject"));
+        // Domains are resolved before the first IP rule, like IPIfNonMatch.
+        let resolve = route.iter().position(|r| r["action"] == "resolve").unwrap();
+        let private = route
+            .iter()
+            .position(|r| r["ip_is_private"] == true)
+            .unwrap();
+        assert_eq!(resolve + 1, private);
+        assert!(
+            config["outbounds"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .all(|o| o["type"] != "block")
+        );
+    }
+
+    #[test]
+    fn dns_servers_are_typed_from_1_12() {
+        let nodes = nodes(&["trojan://a@a.example:443#nl"]);
+        let routes = vec!["corp.example=10.0.0.53".parse().unwrap()];
+        let typed = BuildOptions {
+            dns_routes: routes.clone(),
+            ..options()
+        };
+        let dns = &build_config(&nodes, &typed).unwrap()["dns"];
+        assert_eq!(dns["final"], "dns-remote");
+        assert_eq!(dns["servers"][0]["type"], "udp");
+        assert_eq!(dns["servers"][0]["server"], "10.0.0.53");
+        assert_eq!(dns["servers"][0]["detour"], "direct");
+        assert!(
+            dns["servers"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|s| s["tag"] == "dns-local")
+        );
+
+        let legacy = BuildOptions {
+            target: "sing-box@1.11".parse().unwrap(),
+            dns_routes: routes,
+            ..Default::default()
+        };
+        let dns = &build_config(&nodes, &legacy).unwrap()["dns"];
+        assert!(dns["servers"][0].get("type").is_none());
+        assert!(
+            dns["servers"][0]["address"]
+                .as_str()
+                .unwrap()
+                .contains("10.0.0.53")
+        );
+    }
+}
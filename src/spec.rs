@@ -0,0 +1,774 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::env;
+use crate::error::PawprintError;
+use crate::filter::FilterSpec;
+use crate::target::CoreTarget;
+
+// Declarative description of a config, usually kept in pawprint.toml. ${VARS} are
+// expanded from the environment before parsing:
+//
+//   output = "config.json"
+//   target = "xray@25.x"
+//   nodes = ["vless://...", "vless://..."]
+//   subscriptions = ["https://provider.example/sub?token=${SUB_TOKEN}"]
+//   balance = true            # or `chain = true` to dial the last node through the others
+//   template = "base.json"     # generated sections are merged into it
+//   merges = ["rules.json"]    # deep-merged into the generated config
+//   patches = ["site.json"]
+//   dns_servers = ["https://1.1.1.1/dns-query"]
+//   dns_routes = ["corp.example=10.0.0.53"]
+//
+//   [routing]
+//   direct = ["geoip:private", "geoip:cn", "geosite:cn"]
+//   block = ["geosite:category-ads-all"]
+//
+//   [tun]
+//   mtu = 1400
+//
+//   [mux]
+//   concurrency = 8
+//
+//   [fragment]
+//   length = "100-200"
+//
+//   [sniffing]
+//   route_only = true
+//
+//   [sockopt]
+//   mark = 255
+//
+//   [stats]
+//   port = 10085
+//
+//   [log]
+//   level = "info"
+//   access = "/var/log/pawprint/access.log"
+//
+//   [[inbounds]]
+//   protocol = "socks"
+//   listen = "127.0.0.1"
+//   port = 10808
+//   username = "me"
+//   password = "${SOCKS_PASSWORD}"
+//
+//   [[inbounds]]               # everything on this port skips the tunnel
+//   protocol = "socks"
+//   port = 10809
+//   outbound = "direct"
+//
+// Tables such as `inbounds` come last so the struct serializes to valid TOML.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    pub output: PathBuf,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub force: bool,
+    #[serde(default)]
+    pub target: CoreTarget,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plugins_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub nodes: Vec<String>,
+    // Fetched on every `apply` and `watch` update; entries that fail to parse are skipped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subscriptions: Vec<String>,
+    // Applied to the subscription entries; `nodes` are kept as written.
+    #[serde(default, skip_serializing_if = "FilterSpec::is_empty")]
+    pub filter: FilterSpec,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub balance: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub chain: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns_servers: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns_routes: Vec<DnsRoute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub noises: Vec<NoiseSpec>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merges: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub json_patches: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_script: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jq: Option<String>,
+    #[serde(default, skip_serializing_if = "RoutingRules::is_empty")]
+    pub routing: RoutingRules,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tun: Option<TunSpec>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mux: Option<MuxSpec>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fragment: Option<FragmentSpec>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sniffing: Option<SniffingSpec>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sockopt: Option<SockoptSpec>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<StatsSpec>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<LogSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inbounds: Vec<InboundSpec>,
+}
+
+// System-wide TUN interface in addition to the proxy inbounds.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TunSpec {
+    #[serde(default = "TunSpec::default_name")]
+    pub name: String,
+    #[serde(default = "TunSpec::default_mtu")]
+    pub mtu: u32,
+    // Interface addresses with prefix, e.g. 172.19.0.1/30.
+    #[serde(default = "TunSpec::default_address")]
+    pub address: Vec<String>,
+    // Route all traffic into the interface (sing-box only; Xray leaves routes to the OS).
+    #[serde(default = "TunSpec::default_auto_route")]
+    pub auto_route: bool,
+    // Network stack (sing-box only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack: Option<TunStack>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TunStack {
+    System,
+    Gvisor,
+    Mixed,
+}
+
+impl TunSpec {
+    fn default_name() -> String {
+        "pawprint0".to_string()
+    }
+
+    fn default_mtu() -> u32 {
+        1500
+    }
+
+    fn default_address() -> Vec<String> {
+        vec![
+            "172.19.0.1/30".to_string(),
+            "fdfe:dcba:9876::1/126".to_string(),
+        ]
+    }
+
+    fn default_auto_route() -> bool {
+        true
+    }
+}
+
+impl Default for TunSpec {
+    fn default() -> Self {
+        TunSpec {
+            name: TunSpec::default_name(),
+            mtu: TunSpec::default_mtu(),
+            address: TunSpec::default_address(),
+            auto_route: TunSpec::default_auto_route(),
+            stack: None,
+        }
+    }
+}
+
+// Carries several connections to the server over one, hiding the connection
+// pattern and saving handshakes. The server has to accept mux too.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MuxSpec {
+    // Streams per connection.
+    #[serde(default = "MuxSpec::default_concurrency")]
+    pub concurrency: u32,
+    // Separate connection pool for UDP (Xray XUDP only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xudp_concurrency: Option<u32>,
+}
+
+impl MuxSpec {
+    fn default_concurrency() -> u32 {
+        8
+    }
+}
+
+impl Default for MuxSpec {
+    fn default() -> Self {
+        MuxSpec {
+            concurrency: MuxSpec::default_concurrency(),
+            xudp_concurrency: None,
+        }
+    }
+}
+
+// Traffic counters per inbound and outbound, served on a loopback API port
+// that `pawprint-vpn stats` reads.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatsSpec {
+    #[serde(default = "StatsSpec::default_port")]
+    pub port: u16,
+}
+
+impl StatsSpec {
+    pub fn default_port() -> u16 {
+        10085
+    }
+}
+
+impl Default for StatsSpec {
+    fn default() -> Self {
+        StatsSpec {
+            port: StatsSpec::default_port(),
+        }
+    }
+}
+
+// Reads the domain out of connections on the inbounds, so domain rules also
+// match apps that connect to an IP they resolved themselves.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SniffingSpec {
+    // http, tls, quic, and for Xray fakedns.
+    #[serde(default = "SniffingSpec::default_protocols")]
+    pub protocols: Vec<String>,
+    // Route by the sniffed domain but still connect to the original address.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub route_only: bool,
+}
+
+impl SniffingSpec {
+    pub fn default_protocols() -> Vec<String> {
+        vec!["http".to_string(), "tls".to_string(), "quic".to_string()]
+    }
+}
+
+impl Default for SniffingSpec {
+    fn default() -> Self {
+        SniffingSpec {
+            protocols: SniffingSpec::default_protocols(),
+            route_only: false,
+        }
+    }
+}
+
+// Socket options of the connections the core makes to servers and, for
+// `direct` traffic, to destinations.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SockoptSpec {
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub tcp_fast_open: bool,
+    // SO_MARK for policy routing (Linux, needs CAP_NET_ADMIN).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mark: Option<u32>,
+    // How server and destination names are resolved before dialing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain_strategy: Option<DomainStrategy>,
+    // Seconds a connection idles before the first keep-alive probe (Xray only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_keep_alive_idle: Option<u32>,
+}
+
+// Xray's names; sing-box gets the closest of its strategies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+pub enum DomainStrategy {
+    #[value(name = "AsIs")]
+    AsIs,
+    #[serde(rename = "UseIP")]
+    #[value(name = "UseIP")]
+    UseIp,
+    #[serde(rename = "UseIPv4")]
+    #[value(name = "UseIPv4")]
+    UseIpv4,
+    #[serde(rename = "UseIPv6")]
+    #[value(name = "UseIPv6")]
+    UseIpv6,
+    #[serde(rename = "ForceIP")]
+    #[value(name = "ForceIP")]
+    ForceIp,
+    #[serde(rename = "ForceIPv4")]
+    #[value(name = "ForceIPv4")]
+    ForceIpv4,
+    #[serde(rename = "ForceIPv6")]
+    #[value(name = "ForceIPv6")]
+    ForceIpv6,
+}
+
+// The core's own `log` section. Without paths it logs to stdout, which `run`
+// captures; relative paths are resolved by the core from where it starts.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogSpec {
+    #[serde(default)]
+    pub level: LogLevel,
+    // Every connection with its destination (Xray only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<PathBuf>,
+    // Also log DNS queries (Xray only).
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub dns: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    #[default]
+    Warning,
+    Error,
+    None,
+}
+
+impl LogLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warning => "warning",
+            LogLevel::Error => "error",
+            LogLevel::None => "none",
+        }
+    }
+}
+
+// Splits the first packets to the server into pieces so DPI cannot read the
+// TLS ClientHello in one go. Lengths and intervals are ranges like "10-20".
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FragmentSpec {
+    // `tlshello`, or a range of TCP packets such as "1-3".
+    #[serde(default = "FragmentSpec::default_packets")]
+    pub packets: String,
+    // Bytes per fragment.
+    #[serde(default = "FragmentSpec::default_length")]
+    pub length: String,
+    // Milliseconds between fragments.
+    #[serde(default = "FragmentSpec::default_interval")]
+    pub interval: String,
+}
+
+impl FragmentSpec {
+    fn default_packets() -> String {
+        "tlshello".to_string()
+    }
+
+    fn default_length() -> String {
+        "100-200".to_string()
+    }
+
+    fn default_interval() -> String {
+        "10-20".to_string()
+    }
+}
+
+impl Default for FragmentSpec {
+    fn default() -> Self {
+        FragmentSpec {
+            packets: FragmentSpec::default_packets(),
+            length: FragmentSpec::default_length(),
+            interval: FragmentSpec::default_interval(),
+        }
+    }
+}
+
+// A junk UDP packet sent before the real ones, written as `type:packet` or
+// `type:packet:delay`, e.g. `rand:10-20:10-16` or `str:hello`. Xray only.
+#[derive(Debug, Clone)]
+pub struct NoiseSpec {
+    // `rand` (random bytes, packet is a length range), `str` or `base64`.
+    pub kind: String,
+    pub packet: String,
+    pub delay: Option<String>,
+}
+
+impl FromStr for NoiseSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(kind), Some(packet)) = (parts.next(), parts.next()) else {
+            return Err(format!(
+                "Noise must look like type:packet[:delay], got: {}",
+                s
+            ));
+        };
+        if !matches!(kind, "rand" | "str" | "base64") || packet.is_empty() {
+            return Err(format!(
+                "Noise type must be rand, str or base64 followed by a packet, got: {}",
+                s
+            ));
+        }
+        Ok(NoiseSpec {
+            kind: kind.to_string(),
+            packet: packet.to_string(),
+            delay: parts.next().map(str::to_string),
+        })
+    }
+}
+
+impl fmt::Display for NoiseSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.delay {
+            Some(delay) => write!(f, "{}:{}:{}", self.kind, self.packet, delay),
+            None => write!(f, "{}:{}", self.kind, self.packet),
+        }
+    }
+}
+
+impl Serialize for NoiseSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for NoiseSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+// Split tunneling matchers such as `geoip:cn`, `geosite:category-ads-all`,
+// `domain:example.com` or `10.0.0.0/8`, grouped by where the traffic goes.
+// Anything unmatched goes through the first node.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingRules {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub direct: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub block: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proxy: Vec<String>,
+}
+
+impl RoutingRules {
+    pub fn is_empty(&self) -> bool {
+        self.direct.is_empty() && self.block.is_empty() && self.proxy.is_empty()
+    }
+
+    // Loads a rules file holding the same `direct`, `block` and `proxy` lists as
+    // the spec's `[routing]` table.
+    pub fn load(path: &Path, env_subst: bool) -> Result<RoutingRules, PawprintError> {
+        let content = fs::read_to_string(path).map_err(|e| PawprintError::file(path, e))?;
+        env::parse_toml(&content, path, env_subst)
+    }
+
+    pub fn extend(&mut self, other: RoutingRules) {
+        self.direct.extend(other.direct);
+        self.block.extend(other.block);
+        self.proxy.extend(other.proxy);
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InboundSpec {
+    pub protocol: InboundProtocol,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen: Option<String>,
+    pub port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    // Require these credentials from clients; both must be set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    // Where everything arriving here goes, ahead of the routing rules: `direct`,
+    // `block`, `proxy` (the usual node or balancer) or the tag of a node.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outbound: Option<String>,
+}
+
+// Reserved `outbound` names; any other is taken for a node tag.
+pub const DIRECT: &str = "direct";
+pub const BLOCK: &str = "block";
+pub const PROXY: &str = "proxy";
+
+impl InboundSpec {
+    // The tag of every inbound: its own, or socks-in / http-in, with the port
+    // appended when an earlier inbound already took that. Routing by port needs
+    // several inbounds of the same kind, which must not share a tag.
+    pub fn tags(inbounds: &[InboundSpec]) -> Vec<String> {
+        let mut tags: Vec<String> = Vec::new();
+        for inbound in inbounds {
+            let tag = match &inbound.tag {
+                Some(tag) => tag.clone(),
+                None => {
+                    let default = match inbound.protocol {
+                        InboundProtocol::Socks => "socks-in",
+                        InboundProtocol::Http => "http-in",
+                    };
+                    let taken = |tag: &str| {
+                        tags.iter().any(|t| t == tag)
+                            || inbounds.iter().any(|i| i.tag.as_deref() == Some(tag))
+                    };
+                    if taken(default) {
+                        format!("{}-{}", default, inbound.port)
+                    } else {
+                        default.to_string()
+                    }
+                }
+            };
+            tags.push(tag);
+        }
+        tags
+    }
+
+    // Inbound tags with the `outbound` their traffic is sent to.
+    pub fn routes(inbounds: &[InboundSpec]) -> Vec<(String, &str)> {
+        InboundSpec::tags(inbounds)
+            .into_iter()
+            .zip(inbounds)
+            .filter_map(|(tag, inbound)| Some((tag, inbound.outbound.as_deref()?)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InboundProtocol {
+    Socks,
+    Http,
+}
+
+// Resolve `domain` (and its subdomains) through `server` instead of the tunnel DNS,
+// written as `corp.example=10.0.0.53` or `corp.example=10.0.0.53:5353`.
+#[derive(Debug, Clone)]
+pub struct DnsRoute {
+    pub domain: String,
+    pub address: String,
+    pub port: Option<u16>,
+}
+
+impl FromStr for DnsRoute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (domain, server) = s
+            .split_once('=')
+            .ok_or_else(|| format!("DNS route must look like domain=server, got: {}", s))?;
+        if domain.is_empty() || server.is_empty() {
+            return Err(format!(
+                "DNS route must look like domain=server, got: {}",
+                s
+            ));
+        }
+        let (address, port) = match server.rsplit_once(':') {
+            // Bare IPv6 addresses contain colons too; only split `[v6]:port` or `v4:port`.
+            Some((address, port)) if !address.contains(':') || address.ends_with(']') => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("Invalid DNS server port in: {}", s))?;
+                (address.trim_matches(['[', ']']), Some(port))
+            }
+            _ => (server, None),
+        };
+        Ok(DnsRoute {
+            domain: domain.to_string(),
+            address: address.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for DnsRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.port, self.address.contains(':')) {
+            (Some(port), true) => write!(f, "{}=[{}]:{}", self.domain, self.address, port),
+            (Some(port), false) => write!(f, "{}={}:{}", self.domain, self.address, port),
+            (None, _) => write!(f, "{}={}", self.domain, self.address),
+        }
+    }
+}
+
+impl Serialize for DnsRoute {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DnsRoute {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Default for InboundSpec {
+    fn default() -> Self {
+        InboundSpec {
+            protocol: InboundProtocol::Socks,
+            listen: None,
+            port: 10808,
+            tag: None,
+            username: None,
+            password: None,
+            outbound: None,
+        }
+    }
+}
+
+impl Spec {
+    // Loads a spec file. Relative paths inside it are resolved against its directory.
+    pub fn load(path: &Path, env_subst: bool) -> Result<Spec, PawprintError> {
+        let content = fs::read_to_string(path).map_err(|e| PawprintError::file(path, e))?;
+        Spec::parse(content, path, env_subst)
+    }
+
+    // Parses the content of the spec file at `path`, as `load` does.
+    pub fn parse(content: String, path: &Path, env_subst: bool) -> Result<Spec, PawprintError> {
+        let mut spec: Spec = env::parse_toml(&content, path, env_subst)?;
+
+        if spec.nodes.is_empty() && spec.subscriptions.is_empty() {
+            return Err(format!(
+                "{} does not list any nodes or subscriptions",
+                path.display()
+            )
+            .into());
+        }
+        if spec.balance && spec.chain {
+            return Err(format!("{}: balance and chain cannot be combined", path.display()).into());
+        }
+        for inbound in &spec.inbounds {
+            if inbound.username.is_some() != inbound.password.is_some() {
+                return Err(format!(
+                    "{}: inbound on port {} needs both username and password",
+                    path.display(),
+                    inbound.port
+                )
+                .into());
+            }
+            if inbound.outbound.as_deref().is_some_and(str::is_empty) {
+                return Err(format!(
+                    "{}: inbound on port {} has an empty outbound",
+                    path.display(),
+                    inbound.port
+                )
+                .into());
+            }
+        }
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        spec.output = base.join(&spec.output);
+        spec.plugins_dir = spec.plugins_dir.map(|dir| base.join(dir));
+        spec.post_script = spec.post_script.map(|script| base.join(script));
+        spec.template = spec.template.map(|template| base.join(template));
+        for patch in spec
+            .merges
+            .iter_mut()
+            .chain(spec.patches.iter_mut())
+            .chain(spec.json_patches.iter_mut())
+        {
+            *patch = base.join(&*patch);
+        }
+        Ok(spec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<Spec, PawprintError> {
+        Spec::parse(content.to_string(), Path::new("specs/pawprint.toml"), false)
+    }
+
+    #[test]
+    fn specs_resolve_paths_against_their_directory() {
+        let spec = parse(
+            r#"
+            output = "out/config.json"
+            nodes = ["trojan://secret@example.com:443#nl"]
+            dns_routes = ["corp.example=10.0.0.53:5353"]
+            noises = ["rand:10-20:5"]
+
+            [[inbounds]]
+            protocol = "http"
+            port = 8080
+            "#,
+        )
+        .unwrap();
+        assert_eq!(spec.output, Path::new("specs/out/config.json"));
+        assert_eq!(spec.dns_routes[0].port, Some(5353));
+        assert_eq!(spec.noises[0].delay.as_deref(), Some("5"));
+        assert_eq!(spec.inbounds[0].protocol, InboundProtocol::Http);
+    }
+
+    #[test]
+    fn specs_reject_unknown_fields_and_contradictions() {
+        let node = r#"output = "c.json"
+            nodes = ["trojan://secret@example.com:443"]
+            "#;
+        assert!(parse(&format!("{}balanse = true", node)).is_err());
+        assert!(parse(&format!("{}[tun]\nnmae = \"tun0\"", node)).is_err());
+        assert!(parse(&format!("{}balance = true\nchain = true", node)).is_err());
+        assert!(parse("output = \"c.json\"").is_err());
+        let half_auth = format!(
+            "{}[[inbounds]]\nprotocol = \"socks\"\nport = 1080\nusername = \"u\"",
+            node
+        );
+        assert!(parse(&half_auth).is_err());
+    }
+
+    #[test]
+    fn dns_routes_are_domain_equals_server() {
+        let route: DnsRoute = "corp.example=10.0.0.53".parse().unwrap();
+        assert_eq!(
+            (route.domain.as_str(), route.address.as_str(), route.port),
+            ("corp.example", "10.0.0.53", None)
+        );
+        let route: DnsRoute = "corp.example=[2001:db8::53]:5353".parse().unwrap();
+        assert_eq!(
+            (route.address.as_str(), route.port),
+            ("2001:db8::53", Some(5353))
+        );
+        assert_eq!(route.to_string(), "corp.example=[2001:db8::53]:5353");
+        let route: DnsRoute = "corp.example=2001:db8::53".parse().unwrap();
+        assert_eq!((route.address.as_str(), route.port), ("2001:db8::53", None));
+
+        assert!("corp.example".parse::<DnsRoute>().is_err());
+        assert!("=10.0.0.53".parse::<DnsRoute>().is_err());
+        assert!("corp.example=10.0.0.53:dns".parse::<DnsRoute>().is_err());
+    }
+
+    #[test]
+    fn inbound_tags_do_not_collide() {
+        let inbound = |protocol, port, tag: Option<&str>| InboundSpec {
+            protocol,
+            port,
+            tag: tag.map(str::to_string),
+            ..InboundSpec::default()
+        };
+        let inbounds = [
+            inbound(InboundProtocol::Socks, 1080, None),
+            inbound(InboundProtocol::Socks, 1081, None),
+            inbound(InboundProtocol::Http, 8080, Some("http-in")),
+            inbound(InboundProtocol::Http, 8081, None),
+        ];
+        assert_eq!(
+            InboundSpec::tags(&inbounds),
+            ["socks-in", "socks-in-1081", "http-in", "http-in-8081"]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use serde_json::Value;
+use std::process::Command;
+
+use crate::error::PawprintError;
+use crate::xraycore;
+
+// Bytes through one inbound or outbound since xray started or was last reset.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    // `inbound` or `outbound`.
+    pub kind: String,
+    pub tag: String,
+    pub uplink: u64,
+    pub downlink: u64,
+}
+
+// Asks a running core for its counters through `xray api statsquery`, which
+// speaks the gRPC API of both Xray and sing-box's v2ray_api.
+pub fn query(xray: &str, server: &str, reset: bool) -> Result<Vec<Traffic>, PawprintError> {
+    let mut command = Command::new(xraycore::program(xray));
+    command
+        .arg("api")
+        .arg("statsquery")
+        .arg(format!("--server={}", server));
+    if reset {
+        command.arg("-reset");
+    }
+    let output = command.output().map_err(|e| {
+        format!(
+            "Failed to run {} (install it with `pawprint-vpn core install` or see --xray): {}",
+            xray, e
+        )
+    })?;
+    if !output.status.success() {
+        return Err(PawprintError::Network(format!(
+            "Stats API at {} did not answer: {}",
+            server,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    parse(&serde_json::from_slice(&output.stdout)?)
+}
+
+// Counters are named `inbound>>>TAG>>>traffic>>>uplink` and so on; the value
+// is missing while it is zero.
+fn parse(response: &Value) -> Result<Vec<Traffic>, PawprintError> {
+    let mut traffic: Vec<Traffic> = Vec::new();
+    for stat in response["stat"].as_array().into_iter().flatten() {
+        let name = stat["name"].as_str().unwrap_or_default();
+        let parts: Vec<&str> = name.split(">>>").collect();
+        let [kind @ ("inbound" | "outbound"), tag, "traffic", direction] = parts[..] else {
+            continue;
+        };
+        let value = match &stat["value"] {
+            Value::Number(n) => n.as_u64().unwrap_or_default(),
+            Value::String(s) => s
+                .parse()
+                .map_err(|_| format!("Invalid counter value for {}: {}", name, s))?,
+            _ => 0,
+        };
+
+        let index = match traffic.iter().position(|t| t.kind == kind && t.tag == tag) {
+            Some(index) => index,
+            None => {
+                traffic.push(Traffic {
+                    kind: kind.to_string(),
+                    tag: tag.to_string(),
+                    ..Traffic::default()
+                });
+                traffic.len() - 1
+            }
+        };
+        match direction {
+            "uplink" => traffic[index].uplink = value,
+            "downlink" => traffic[index].downlink = value,
+            _ => {}
+        }
+    }
+    traffic.sort_by(|a, b| (&a.kind, &a.tag).cmp(&(&b.kind, &b.tag)));
+    Ok(traffic)
+}
+
+// 1536 -> "1.5 KiB".
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
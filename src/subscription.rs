@@ -0,0 +1,42 @@
+use tracing::info;
+
+use crate::alerts::{self, Usage};
+use crate::error::PawprintError;
+use crate::parser;
+
+// Downloads a subscription and returns the share links it lists. The traffic
+// and expiry the provider reports along with it are checked for alerts.
+pub fn fetch(url: &str) -> Result<Vec<String>, PawprintError> {
+    info!("Fetching subscription {}...", url);
+    let mut response = ureq::get(url)
+        .call()
+        .map_err(|e| format!("Failed to fetch subscription {}: {}", url, e))?;
+    let usage = response
+        .headers()
+        .get("subscription-userinfo")
+        .and_then(|header| header.to_str().ok())
+        .and_then(Usage::parse);
+    if let Some(usage) = usage {
+        alerts::usage(url, &usage);
+    }
+    let body = response
+        .body_mut()
+        .read_to_string()
+        .map_err(|e| format!("Failed to read subscription {}: {}", url, e))?;
+    Ok(decode(&body))
+}
+
+// Subscriptions are usually a base64 encoded list of links, one per line, but
+// some providers serve the plain list.
+pub fn decode(body: &str) -> Vec<String> {
+    let compact: String = body.split_whitespace().collect();
+    let text = match parser::decode_base64(&compact).map(String::from_utf8) {
+        Ok(Ok(text)) if text.contains("://") => text,
+        _ => body.to_string(),
+    };
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
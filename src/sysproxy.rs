@@ -0,0 +1,345 @@
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::error::PawprintError;
+use crate::undo::{Undo, args, command as run};
+
+const WINDOWS_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+
+// Local inbounds the OS should send traffic to, as host:port.
+#[derive(Debug, Default)]
+pub struct Endpoints {
+    pub socks: Option<String>,
+    pub http: Option<String>,
+}
+
+impl Endpoints {
+    // The first SOCKS and HTTP inbound of an Xray or sing-box config. A mixed
+    // inbound serves both.
+    pub fn from_config(config: &Value) -> Endpoints {
+        let mut endpoints = Endpoints::default();
+        for inbound in config["inbounds"].as_array().into_iter().flatten() {
+            let protocol = inbound["protocol"]
+                .as_str()
+                .or(inbound["type"].as_str())
+                .unwrap_or_default();
+            let Some(port) = inbound["port"].as_u64().or(inbound["listen_port"].as_u64()) else {
+                continue;
+            };
+            let host = match inbound["listen"].as_str() {
+                None | Some("0.0.0.0" | "::" | "") => "127.0.0.1",
+                Some(host) => host,
+            };
+            let address = crate::parser::authority(host, port as u16);
+            if matches!(protocol, "socks" | "mixed") && endpoints.socks.is_none() {
+                endpoints.socks = Some(address.clone());
+            }
+            if matches!(protocol, "http" | "mixed") && endpoints.http.is_none() {
+                endpoints.http = Some(address);
+            }
+        }
+        endpoints
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.socks.is_none() && self.http.is_none()
+    }
+}
+
+// Commands putting the old settings back, and the ones applying the new.
+type Change = (Vec<Vec<String>>, Vec<Vec<String>>);
+
+fn undo() -> Undo {
+    Undo::new("sysproxy.json")
+}
+
+// Whether the system proxy was changed and not restored yet.
+pub fn is_active() -> bool {
+    undo().is_pending()
+}
+
+fn split_host_port(address: &str) -> (&str, &str) {
+    let (host, port) = address.rsplit_once(':').unwrap_or((address, ""));
+    (host.trim_start_matches('[').trim_end_matches(']'), port)
+}
+
+// GNOME and desktops that follow its proxy settings.
+fn gnome(endpoints: &Endpoints) -> Result<Change, PawprintError> {
+    let mut restore = Vec::new();
+    let keys = [
+        ("org.gnome.system.proxy.socks", "host"),
+        ("org.gnome.system.proxy.socks", "port"),
+        ("org.gnome.system.proxy.http", "host"),
+        ("org.gnome.system.proxy.http", "port"),
+        ("org.gnome.system.proxy.https", "host"),
+        ("org.gnome.system.proxy.https", "port"),
+        // Last, so the old mode comes back once the hosts are restored.
+        ("org.gnome.system.proxy", "mode"),
+    ];
+    for (schema, key) in keys {
+        let value = run(&args(["gsettings", "get", schema, key]))?;
+        restore.push(args(["gsettings", "set", schema, key, &value]));
+    }
+
+    let mut apply = Vec::new();
+    let mut set = |schema: &str, address: &str| {
+        let (host, port) = split_host_port(address);
+        apply.push(args(["gsettings", "set", schema, "host", host]));
+        apply.push(args(["gsettings", "set", schema, "port", port]));
+    };
+    if let Some(socks) = &endpoints.socks {
+        set("org.gnome.system.proxy.socks", socks);
+    }
+    if let Some(http) = &endpoints.http {
+        set("org.gnome.system.proxy.http", http);
+        set("org.gnome.system.proxy.https", http);
+    }
+    apply.push(args([
+        "gsettings",
+        "set",
+        "org.gnome.system.proxy",
+        "mode",
+        "manual",
+    ]));
+    Ok((restore, apply))
+}
+
+// Every enabled network service, since macOS keeps proxies per service.
+fn macos(endpoints: &Endpoints) -> Result<Change, PawprintError> {
+    let listing = run(&args(["networksetup", "-listallnetworkservices"]))?;
+    // The first line explains that an asterisk marks disabled services.
+    let services: Vec<&str> = listing
+        .lines()
+        .skip(1)
+        .filter(|s| !s.starts_with('*') && !s.is_empty())
+        .collect();
+    let kinds = [
+        ("socksfirewallproxy", endpoints.socks.as_ref()),
+        ("webproxy", endpoints.http.as_ref()),
+        ("securewebproxy", endpoints.http.as_ref()),
+    ];
+
+    let mut restore = Vec::new();
+    let mut apply = Vec::new();
+    for service in services {
+        for (kind, address) in kinds {
+            let Some(address) = address else {
+                continue;
+            };
+            // "Enabled: Yes\nServer: host\nPort: 8080\n..."
+            let current = run(&args(["networksetup", &format!("-get{}", kind), service]))?;
+            let field = |name: &str| {
+                current
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .map(str::trim)
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            let (server, port) = (field("Server:"), field("Port:"));
+            if !server.is_empty() {
+                restore.push(args([
+                    "networksetup",
+                    &format!("-set{}", kind),
+                    service,
+                    &server,
+                    &port,
+                ]));
+            }
+            let state = if field("Enabled:") == "Yes" {
+                "on"
+            } else {
+                "off"
+            };
+            restore.push(args([
+                "networksetup",
+                &format!("-set{}state", kind),
+                service,
+                state,
+            ]));
+
+            let (host, port) = split_host_port(address);
+            apply.push(args([
+                "networksetup",
+                &format!("-set{}", kind),
+                service,
+                host,
+                port,
+            ]));
+        }
+    }
+    Ok((restore, apply))
+}
+
+// The WinINet settings in the registry, used by browsers and most apps for new
+// connections.
+fn windows(endpoints: &Endpoints) -> Result<Change, PawprintError> {
+    // "    ProxyEnable    REG_DWORD    0x1"; a missing value makes reg fail.
+    let query = |name: &str| {
+        run(&args(["reg", "query", WINDOWS_KEY, "/v", name]))
+            .ok()
+            .and_then(|output| {
+                let line = output.lines().find(|l| l.trim_start().starts_with(name))?;
+                let value = line
+                    .split_once("REG_")?
+                    .1
+                    .split_once(char::is_whitespace)?
+                    .1;
+                Some(value.trim().to_string())
+            })
+    };
+    let enabled = query("ProxyEnable")
+        .and_then(|v| u32::from_str_radix(v.trim_start_matches("0x"), 16).ok())
+        .unwrap_or(0);
+    let restore = vec![
+        match query("ProxyServer") {
+            Some(server) => args([
+                "reg",
+                "add",
+                WINDOWS_KEY,
+                "/v",
+                "ProxyServer",
+                "/t",
+                "REG_SZ",
+                "/d",
+                &server,
+                "/f",
+            ]),
+            None => args(["reg", "delete", WINDOWS_KEY, "/v", "ProxyServer", "/f"]),
+        },
+        args([
+            "reg",
+            "add",
+            WINDOWS_KEY,
+            "/v",
+            "ProxyEnable",
+            "/t",
+            "REG_DWORD",
+            "/d",
+            &enabled.to_string(),
+            "/f",
+        ]),
+    ];
+
+    let mut servers = Vec::new();
+    if let Some(http) = &endpoints.http {
+        servers.push(format!("http={}", http));
+        servers.push(format!("https={}", http));
+    }
+    if let Some(socks) = &endpoints.socks {
+        servers.push(format!("socks={}", socks));
+    }
+    let apply = vec![
+        args([
+            "reg",
+            "add",
+            WINDOWS_KEY,
+            "/v",
+            "ProxyServer",
+            "/t",
+            "REG_SZ",
+            "/d",
+            &servers.join(";"),
+            "/f",
+        ]),
+        args([
+            "reg",
+            "add",
+            WINDOWS_KEY,
+            "/v",
+            "ProxyEnable",
+            "/t",
+            "REG_DWORD",
+            "/d",
+            "1",
+            "/f",
+        ]),
+    ];
+    Ok((restore, apply))
+}
+
+// Points the OS proxy settings at `endpoints`, remembering the old ones.
+pub fn enable(endpoints: &Endpoints) -> Result<(), PawprintError> {
+    if endpoints.is_empty() {
+        return Err("The config has no SOCKS or HTTP inbound to use as the system proxy".into());
+    }
+    // Settings left behind by a run that crashed are the ones to go back to.
+    restore()?;
+
+    let (restore, apply) = if cfg!(windows) {
+        windows(endpoints)?
+    } else if cfg!(target_os = "macos") {
+        macos(endpoints)?
+    } else {
+        gnome(endpoints).map_err(|e| {
+            format!(
+                "Cannot change the system proxy, only GNOME settings are supported: {}",
+                e
+            )
+        })?
+    };
+    undo().save(&restore)?;
+
+    for command in &apply {
+        if let Err(e) = run(command) {
+            let _ = self::restore();
+            return Err(e);
+        }
+    }
+    let used: Vec<&str> = [&endpoints.socks, &endpoints.http]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    info!("✓ System proxy set to {}", used.join(" and "));
+    Ok(())
+}
+
+// Puts back the settings saved by `enable`. Returns false if nothing was saved.
+pub fn restore() -> Result<bool, PawprintError> {
+    match undo().run() {
+        Ok(restored) => {
+            if restored {
+                info!("✓ System proxy restored");
+            }
+            Ok(restored)
+        }
+        Err(e) => {
+            warn!("Check the system proxy settings by hand");
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn endpoints_come_from_the_first_local_inbounds() {
+        let xray = json!({"inbounds": [
+            {"protocol": "dokodemo-door", "port": 10853},
+            {"protocol": "socks", "listen": "0.0.0.0", "port": 10808},
+            {"protocol": "http", "listen": "::1", "port": 10809},
+            {"protocol": "socks", "port": 1080}
+        ]});
+        let endpoints = Endpoints::from_config(&xray);
+        assert_eq!(endpoints.socks.as_deref(), Some("127.0.0.1:10808"));
+        assert_eq!(endpoints.http.as_deref(), Some("[::1]:10809"));
+
+        let singbox =
+            json!({"inbounds": [{"type": "mixed", "listen": "127.0.0.2", "listen_port": 2080}]});
+        let endpoints = Endpoints::from_config(&singbox);
+        assert_eq!(endpoints.socks.as_deref(), Some("127.0.0.2:2080"));
+        assert_eq!(endpoints.http.as_deref(), Some("127.0.0.2:2080"));
+
+        assert!(Endpoints::from_config(&json!({"inbounds": [{"type": "tun"}]})).is_empty());
+    }
+
+    #[test]
+    fn hosts_and_ports_split_without_brackets() {
+        assert_eq!(split_host_port("127.0.0.1:10808"), ("127.0.0.1", "10808"));
+        assert_eq!(split_host_port("[::1]:10809"), ("::1", "10809"));
+    }
+}
@@ -0,0 +1,244 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+// Core version the generated config must load on, e.g. `xray@1.8` or `sing-box@1.12`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreTarget {
+    Xray(Version),
+    SingBox(Version),
+}
+
+// `minor` is None for wildcard targets such as `25.x`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Version {
+    pub major: u32,
+    pub minor: Option<u32>,
+}
+
+impl Version {
+    fn at_least(&self, major: u32, minor: u32) -> bool {
+        (self.major, self.minor.unwrap_or(0)) >= (major, minor)
+    }
+}
+
+impl CoreTarget {
+    // XHTTP replaced SplitHTTP in 24.11; older cores only know `splithttp`.
+    pub fn supports_xhttp(&self) -> bool {
+        match self {
+            CoreTarget::Xray(v) => v.at_least(24, 11),
+            CoreTarget::SingBox(_) => false,
+        }
+    }
+
+    // `wsSettings.host`; older cores take the Host header from `headers`.
+    pub fn supports_ws_host(&self) -> bool {
+        match self {
+            CoreTarget::Xray(v) => v.at_least(24, 0),
+            CoreTarget::SingBox(_) => false,
+        }
+    }
+
+    // Native `tun` inbound.
+    pub fn supports_tun(&self) -> bool {
+        match self {
+            CoreTarget::Xray(v) => v.at_least(25, 8),
+            CoreTarget::SingBox(_) => true,
+        }
+    }
+
+    // sing-box 1.11 rule actions (sniff, reject, hijack-dns, resolve) replace the
+    // block and dns outbounds and inbound sniffing.
+    pub fn supports_rule_actions(&self) -> bool {
+        match self {
+            CoreTarget::Xray(_) => false,
+            CoreTarget::SingBox(v) => v.at_least(1, 11),
+        }
+    }
+
+    // sing-box 1.12 DNS servers with `type`/`server` instead of an address URL.
+    pub fn supports_typed_dns_servers(&self) -> bool {
+        match self {
+            CoreTarget::Xray(_) => false,
+            CoreTarget::SingBox(v) => v.at_least(1, 12),
+        }
+    }
+
+    // `password` alias for the REALITY public key.
+    pub fn supports_reality_password(&self) -> bool {
+        match self {
+            CoreTarget::Xray(v) => v.at_least(25, 0),
+            CoreTarget::SingBox(_) => false,
+        }
+    }
+
+    // Post-quantum `mldsa65Verify` in realitySettings.
+    pub fn supports_mldsa65(&self) -> bool {
+        match self {
+            CoreTarget::Xray(v) => v.at_least(25, 0),
+            CoreTarget::SingBox(_) => false,
+        }
+    }
+
+    // spiderX query parameters (`/?p=...`) are ignored or rejected by 1.8 cores.
+    pub fn supports_spiderx_query(&self) -> bool {
+        match self {
+            CoreTarget::Xray(v) => v.at_least(24, 0),
+            CoreTarget::SingBox(_) => false,
+        }
+    }
+
+    // The freedom outbound's `noises` list appeared in Xray 24.9; sing-box has none.
+    pub fn supports_noises(&self) -> bool {
+        match self {
+            CoreTarget::Xray(v) => v.at_least(24, 9),
+            CoreTarget::SingBox(_) => false,
+        }
+    }
+
+    // Encrypted Client Hello in outbound tlsSettings came with 25.7.
+    pub fn supports_ech(&self) -> bool {
+        match self {
+            CoreTarget::Xray(v) => v.at_least(25, 7),
+            CoreTarget::SingBox(_) => true,
+        }
+    }
+
+    // sing-box 1.12 can split the ClientHello, though without tunable sizes.
+    pub fn supports_tls_fragment(&self) -> bool {
+        match self {
+            CoreTarget::Xray(_) => true,
+            CoreTarget::SingBox(v) => v.at_least(1, 12),
+        }
+    }
+}
+
+impl Default for CoreTarget {
+    fn default() -> Self {
+        CoreTarget::Xray(Version {
+            major: 25,
+            minor: None,
+        })
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (major, minor) = match s.split_once('.') {
+            Some((major, minor)) => (major, Some(minor)),
+            None => (s, None),
+        };
+        let major = major
+            .parse()
+            .map_err(|_| format!("Invalid major version: {}", major))?;
+        let minor = match minor {
+            None | Some("x") => None,
+            Some(minor) => Some(
+                minor
+                    .parse()
+                    .map_err(|_| format!("Invalid minor version: {}", minor))?,
+            ),
+        };
+        Ok(Version { major, minor })
+    }
+}
+
+impl FromStr for CoreTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (core, version) = s
+            .split_once('@')
+            .ok_or_else(|| format!("Target must look like <core>@<version>, got: {}", s))?;
+        match core {
+            "xray" => Ok(CoreTarget::Xray(version.parse()?)),
+            "sing-box" => {
+                let version: Version = version.parse()?;
+                // Schema handling only covers the 1.10 - 1.12 releases.
+                match (version.major, version.minor) {
+                    (1, Some(10..=12)) => Ok(CoreTarget::SingBox(version)),
+                    _ => Err(format!(
+                        "Unsupported sing-box version: {} (expected 1.10, 1.11 or 1.12)",
+                        version
+                    )),
+                }
+            }
+            _ => Err(format!("Unknown core: {}", core)),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.minor {
+            Some(minor) => write!(f, "{}.{}", self.major, minor),
+            None => write!(f, "{}.x", self.major),
+        }
+    }
+}
+
+impl fmt::Display for CoreTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoreTarget::Xray(v) => write!(f, "xray@{}", v),
+            CoreTarget::SingBox(v) => write!(f, "sing-box@{}", v),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CoreTarget {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for CoreTarget {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(s: &str) -> CoreTarget {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn targets_read_and_print_as_core_at_version() {
+        assert_eq!(CoreTarget::default().to_string(), "xray@25.x");
+        assert_eq!(target("xray@1.8").to_string(), "xray@1.8");
+        assert_eq!(target("sing-box@1.12").to_string(), "sing-box@1.12");
+        assert!("xray".parse::<CoreTarget>().is_err());
+        assert!("v2ray@5.0".parse::<CoreTarget>().is_err());
+        assert!("xray@one".parse::<CoreTarget>().is_err());
+        assert!("sing-box@1.9".parse::<CoreTarget>().is_err());
+        assert!("sing-box@2.0".parse::<CoreTarget>().is_err());
+    }
+
+    #[test]
+    fn features_follow_the_version() {
+        assert!(!target("xray@24.10").supports_xhttp());
+        assert!(target("xray@24.11").supports_xhttp());
+        assert!(!target("xray@25.7").supports_tun());
+        assert!(target("xray@25.8").supports_tun());
+        // Wildcards count as the first release of the major version.
+        assert!(!target("xray@25.x").supports_tun());
+        assert!(target("xray@25.x").supports_reality_password());
+        assert!(!target("xray@1.8").supports_ws_host());
+        assert!(!target("xray@24.8").supports_noises());
+
+        assert!(!target("sing-box@1.10").supports_rule_actions());
+        assert!(target("sing-box@1.11").supports_rule_actions());
+        assert!(!target("sing-box@1.11").supports_typed_dns_servers());
+        assert!(target("sing-box@1.12").supports_tls_fragment());
+        assert!(target("sing-box@1.10").supports_tun());
+        assert!(!target("sing-box@1.12").supports_xhttp());
+    }
+}
@@ -0,0 +1,181 @@
+use serde_json::Value;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::process::Command;
+use tracing::{info, warn};
+
+use crate::error::PawprintError;
+
+// A latency increase this large between consecutive hops is worth pointing out.
+const JUMP_MS: f64 = 50.0;
+
+#[derive(Debug)]
+pub struct Hop {
+    pub index: u32,
+    pub host: Option<IpAddr>,
+    pub avg_ms: Option<f64>,
+    pub loss: f64,
+}
+
+pub fn resolve(host: &str) -> Result<IpAddr, PawprintError> {
+    if let Ok(ip) = host.trim_matches(['[', ']']).parse() {
+        return Ok(ip);
+    }
+    (host, 0)
+        .to_socket_addrs()?
+        .next()
+        .map(|addr| addr.ip())
+        .ok_or_else(|| format!("Could not resolve {}", host).into())
+}
+
+// Runs the best available system tool. Raw-socket tracing would need root, so the
+// tools (which ship setuid helpers or capabilities) are used instead.
+pub fn trace(target: IpAddr, max_hops: u32) -> Result<Vec<Hop>, PawprintError> {
+    let max_hops = max_hops.to_string();
+    let target = target.to_string();
+
+    match Command::new("mtr")
+        .args(["--json", "-n", "-c", "3", "-m", &max_hops, &target])
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            info!("Tracing with mtr...");
+            return parse_mtr_json(&String::from_utf8_lossy(&output.stdout));
+        }
+        Ok(output) => warn!(
+            "mtr failed, falling back: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(_) => {}
+    }
+
+    let (program, args): (&str, Vec<&str>) = if cfg!(target_os = "windows") {
+        ("tracert", vec!["-d", "-h", &max_hops, &target])
+    } else {
+        (
+            "traceroute",
+            vec!["-n", "-q", "3", "-w", "2", "-m", &max_hops, &target],
+        )
+    };
+    info!("Tracing with {}...", program);
+    let output = Command::new(program)
+        .args(&args)
+        .output()
+        .map_err(|e| format!("Neither mtr nor {} is available: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(parse_text(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_mtr_json(output: &str) -> Result<Vec<Hop>, PawprintError> {
+    let report: Value = serde_json::from_str(output)?;
+    let hubs = report["report"]["hubs"]
+        .as_array()
+        .ok_or("Unexpected mtr output: no hubs")?;
+
+    Ok(hubs
+        .iter()
+        .enumerate()
+        .map(|(i, hub)| {
+            // Older mtr releases print the hop count as a string.
+            let index = hub["count"]
+                .as_u64()
+                .or_else(|| hub["count"].as_str().and_then(|c| c.parse().ok()))
+                .unwrap_or(i as u64 + 1) as u32;
+            let loss = hub["Loss%"].as_f64().unwrap_or(0.0) / 100.0;
+            Hop {
+                index,
+                host: hub["host"].as_str().and_then(|h| h.parse().ok()),
+                avg_ms: hub["Avg"].as_f64().filter(|_| loss < 1.0),
+                loss,
+            }
+        })
+        .collect())
+}
+
+// Parses traceroute and tracert output: a hop number followed by addresses,
+// `<n> ms` samples and `*` for lost probes, in tool-specific order.
+fn parse_text(output: &str) -> Vec<Hop> {
+    let mut hops = Vec::new();
+    for line in output.lines() {
+        let mut tokens = line.split_whitespace().peekable();
+        let Some(index) = tokens.next().and_then(|t| t.parse::<u32>().ok()) else {
+            continue;
+        };
+
+        let mut host = None;
+        let mut samples = Vec::new();
+        let mut lost = 0;
+        while let Some(token) = tokens.next() {
+            if token == "*" {
+                lost += 1;
+            } else if let Ok(ip) = token.trim_matches(['(', ')']).parse::<IpAddr>() {
+                host.get_or_insert(ip);
+            } else if let Ok(ms) = token.trim_start_matches('<').parse::<f64>()
+                && tokens.peek() == Some(&"ms")
+            {
+                samples.push(ms);
+                tokens.next();
+            }
+        }
+
+        let probes = samples.len() + lost;
+        hops.push(Hop {
+            index,
+            host,
+            avg_ms: (!samples.is_empty())
+                .then(|| samples.iter().sum::<f64>() / samples.len() as f64),
+            loss: if probes == 0 {
+                1.0
+            } else {
+                lost as f64 / probes as f64
+            },
+        });
+    }
+    hops
+}
+
+pub(crate) fn is_private(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_private() || v4.is_link_local(),
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xfe00) == 0xfc00 || v6.is_unicast_link_local(),
+    }
+}
+
+fn is_cgnat(ip: &IpAddr) -> bool {
+    matches!(ip, IpAddr::V4(v4) if v4.octets()[0] == 100 && (v4.octets()[1] & 0xc0) == 64)
+}
+
+// Human hints for each hop, e.g. where the ISP begins or where latency jumps.
+pub fn annotate(hops: &[Hop], target: IpAddr) -> Vec<Vec<String>> {
+    let mut previous_ms: Option<f64> = None;
+    hops.iter()
+        .map(|hop| {
+            let mut notes = Vec::new();
+            match &hop.host {
+                None => notes.push("no reply".to_string()),
+                Some(ip) if *ip == target => notes.push("server".to_string()),
+                Some(ip) if is_private(ip) => notes.push("local network".to_string()),
+                Some(ip) if is_cgnat(ip) => notes.push("carrier-grade NAT".to_string()),
+                Some(_) => {}
+            }
+            if hop.host.is_some() && hop.loss > 0.0 {
+                notes.push(format!("{:.0}% loss", hop.loss * 100.0));
+            }
+            if let Some(ms) = hop.avg_ms {
+                if let Some(prev) = previous_ms
+                    && ms - prev > JUMP_MS
+                {
+                    notes.push(format!("+{:.0} ms jump", ms - prev));
+                }
+                previous_ms = Some(ms);
+            }
+            notes
+        })
+        .collect()
+}
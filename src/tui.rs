@@ -0,0 +1,529 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pawprint_vpn::spec::StatsSpec;
+use pawprint_vpn::stats::{self, Traffic};
+use pawprint_vpn::{CoreTarget, Node, Registry, fuzzy, latency, process, profile, subscription};
+
+use crate::logging;
+
+// Lines of pawprint's own output kept for the log pane.
+const MESSAGES: usize = 50;
+// How much of the end of xray's log is read each refresh.
+const LOG_TAIL: u64 = 16 * 1024;
+const REFRESH: Duration = Duration::from_secs(2);
+
+pub struct Options {
+    pub subscription: Option<String>,
+    pub xray: String,
+    pub target: CoreTarget,
+    pub plugins_dir: Option<PathBuf>,
+    pub stats_server: String,
+    pub pid_file: PathBuf,
+    pub env_subst: bool,
+}
+
+enum Source {
+    Profile(String),
+    Subscription,
+}
+
+struct Entry {
+    source: Source,
+    node: Option<Node>,
+    // Milliseconds, None if the last test failed, absent if never tested.
+    latency: Option<Option<u64>>,
+}
+
+impl Entry {
+    fn name(&self) -> String {
+        match (&self.source, &self.node) {
+            (Source::Profile(name), _) => name.clone(),
+            (Source::Subscription, Some(node)) => node.tag().to_string(),
+            (Source::Subscription, None) => "?".to_string(),
+        }
+    }
+}
+
+struct App {
+    options: Options,
+    entries: Vec<Entry>,
+    list: ListState,
+    sort: profile::Sort,
+    // What has been typed after `/`, while searching.
+    search: Option<String>,
+    // Pid and config of the running core.
+    running: Option<(u32, PathBuf)>,
+    log: Option<PathBuf>,
+    log_lines: Vec<String>,
+    traffic: Result<Vec<Traffic>, String>,
+    querying: Option<Receiver<Result<Vec<Traffic>, String>>>,
+    messages: VecDeque<String>,
+    // Latency results of a test running in the background.
+    testing: Option<Receiver<Vec<Option<u64>>>>,
+    last_refresh: Option<Instant>,
+}
+
+fn load_entries(options: &Options) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let latencies = profile::latencies()?;
+    let mut entries = Vec::new();
+    for name in profile::names()? {
+        let node = profile::load_raw(&name)
+            .ok()
+            .and_then(|spec| crate::first_node(&spec));
+        entries.push(Entry {
+            latency: latencies.get(&name).copied(),
+            source: Source::Profile(name),
+            node,
+        });
+    }
+    if let Some(url) = &options.subscription {
+        let registry = Registry::with_plugins(options.plugins_dir.as_deref())?;
+        for node in crate::parse_subscription(&registry, &subscription::fetch(url)?)? {
+            entries.push(Entry {
+                source: Source::Subscription,
+                node: Some(node),
+                latency: None,
+            });
+        }
+    }
+    if entries.is_empty() {
+        return Err("No profiles or servers to show. Add one with `profile add <url>` or pass --subscription".into());
+    }
+    Ok(entries)
+}
+
+// The last lines of `path`, read from its end so large logs stay cheap.
+fn tail(path: &Path, lines: usize) -> Vec<String> {
+    let Ok(mut file) = File::open(path) else {
+        return Vec::new();
+    };
+    let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let _ = file.seek(SeekFrom::Start(size.saturating_sub(LOG_TAIL)));
+    let mut buffer = Vec::new();
+    let _ = file.read_to_end(&mut buffer);
+    let text = String::from_utf8_lossy(&buffer);
+    let all: Vec<&str> = text.lines().collect();
+    all[all.len().saturating_sub(lines)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+impl App {
+    fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.list.selected()?)
+    }
+
+    // Orders the profiles by `self.sort`, keeping the selection on the same
+    // entry. Subscription servers stay after them in their own order.
+    fn sort_entries(&mut self) {
+        let selected = self.selected().map(Entry::name);
+        let sort = self.sort;
+        self.entries
+            .sort_by_cached_key(|entry| match &entry.source {
+                Source::Profile(name) => {
+                    let spec = profile::load_raw(name).ok();
+                    crate::profile_sort_key(
+                        sort,
+                        name,
+                        spec.as_ref(),
+                        entry.node.as_ref(),
+                        entry.latency,
+                    )
+                }
+                Source::Subscription => (u8::MAX, 0, String::new()),
+            });
+        if let Some(name) = selected {
+            let index = self.entries.iter().position(|e| e.name() == name);
+            self.list.select(index.or(Some(0)));
+        }
+    }
+
+    // Selects the entry whose name best matches the search so far.
+    fn select_match(&mut self) {
+        let Some(query) = &self.search else {
+            return;
+        };
+        let best = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| Some((fuzzy::rank(query, &entry.name())?, index)))
+            .min();
+        if let Some((_, index)) = best {
+            self.list.select(Some(index));
+        }
+    }
+
+    fn message(&mut self, message: String) {
+        if self.messages.len() == MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+    }
+
+    fn refresh(&mut self) {
+        for line in logging::take_captured() {
+            self.message(line);
+        }
+        if let Some(receiver) = &self.testing
+            && let Ok(results) = receiver.try_recv()
+        {
+            self.testing = None;
+            for (entry, latency) in self.entries.iter_mut().zip(results) {
+                if entry.node.is_none() {
+                    continue;
+                }
+                entry.latency = Some(latency);
+                if let Source::Profile(name) = &entry.source {
+                    let _ = profile::record_latency(name, latency.map(Duration::from_millis));
+                }
+            }
+            self.message("Latency test finished".to_string());
+            if self.sort == profile::Sort::Latency {
+                self.sort_entries();
+            }
+        }
+        if let Some(receiver) = &self.querying
+            && let Ok(traffic) = receiver.try_recv()
+        {
+            self.querying = None;
+            self.traffic = traffic;
+        }
+        if self
+            .last_refresh
+            .is_some_and(|last| last.elapsed() < REFRESH)
+        {
+            return;
+        }
+        self.last_refresh = Some(Instant::now());
+
+        let state = process::running(&self.options.pid_file).ok().flatten();
+        self.running = state.as_ref().map(|s| (s.pid, s.config.clone()));
+        self.log = state.and_then(|s| s.log);
+        self.log_lines = self
+            .log
+            .as_deref()
+            .map(|l| tail(l, 100))
+            .unwrap_or_default();
+        if self.running.is_none() {
+            self.traffic = Ok(Vec::new());
+        } else if self.querying.is_none() {
+            // Off the UI thread, as a core that is not answering stalls the API.
+            let (sender, receiver) = mpsc::channel();
+            let xray = self.options.xray.clone();
+            let server = self.options.stats_server.clone();
+            thread::spawn(move || {
+                let _ = sender.send(stats::query(&xray, &server, false).map_err(|e| e.to_string()));
+            });
+            self.querying = Some(receiver);
+        }
+    }
+
+    fn test_latency(&mut self) {
+        if self.testing.is_some() {
+            return;
+        }
+        let nodes: Vec<Option<Node>> = self.entries.iter().map(|e| e.node.clone()).collect();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let servers: Vec<Node> = nodes.iter().flatten().cloned().collect();
+            let mut results = latency::probe_all(&servers, latency::TCP_WORKERS, |node| {
+                latency::tcp_ping(node.address(), node.port(), 3, Duration::from_secs(5))
+            })
+            .into_iter();
+            let latencies = nodes
+                .iter()
+                .map(|node| {
+                    node.as_ref()
+                        .and_then(|_| results.next())
+                        .and_then(|r| r.ok())
+                        .map(|d| d.as_millis() as u64)
+                })
+                .collect();
+            let _ = sender.send(latencies);
+        });
+        self.testing = Some(receiver);
+        self.message(format!(
+            "Measuring TCP latency of {} servers...",
+            self.entries.len()
+        ));
+    }
+
+    fn connect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(entry) = self.selected() else {
+            return Ok(());
+        };
+        match (&entry.source, &entry.node) {
+            (Source::Profile(name), _) => {
+                crate::profile_use(name, self.options.env_subst)?;
+            }
+            (Source::Subscription, Some(node)) => {
+                let mut spec = crate::link_spec(
+                    &node.to_link()?,
+                    self.options.target,
+                    self.options.plugins_dir.clone(),
+                )?;
+                // So the traffic pane has counters to show.
+                spec.stats = Some(StatsSpec::default());
+                crate::build_spec(
+                    spec,
+                    std::slice::from_ref(node),
+                    true,
+                    self.options.env_subst,
+                )?;
+            }
+            (Source::Subscription, None) => return Ok(()),
+        }
+        if self.running.is_some() {
+            crate::stop_core(&self.options.pid_file)?;
+        }
+        crate::run_core(
+            &profile::active_config()?,
+            &self.options.xray,
+            true,
+            &self.options.pid_file,
+            crate::Setup::default(),
+        )?;
+        self.last_refresh = None;
+        Ok(())
+    }
+
+    fn disconnect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        crate::stop_core(&self.options.pid_file)?;
+        self.last_refresh = None;
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [status, middle, logs, help] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(6),
+            Constraint::Length(12),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [servers, traffic] =
+            Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)])
+                .areas(middle);
+
+        let line = match &self.running {
+            Some((pid, config)) => Line::styled(
+                format!("● connected  pid {}  {}", pid, config.display()),
+                Style::new().fg(Color::Green),
+            ),
+            None => Line::styled("○ disconnected", Style::new().fg(Color::DarkGray)),
+        };
+        frame.render_widget(line, status);
+
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let kind = match entry.source {
+                    Source::Profile(_) => "profile",
+                    Source::Subscription => "sub",
+                };
+                let server = entry
+                    .node
+                    .as_ref()
+                    .map(|n| format!("{} {}:{}", n.protocol(), n.address(), n.port()))
+                    .unwrap_or_else(|| "?".to_string());
+                let latency = match entry.latency {
+                    Some(Some(ms)) => format!("{} ms", ms),
+                    Some(None) => "failed".to_string(),
+                    None => "-".to_string(),
+                };
+                ListItem::new(format!(
+                    "{:<8} {:<24} {:>8}  {}",
+                    kind,
+                    entry.name(),
+                    latency,
+                    server
+                ))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(" Servers "))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, servers, &mut self.list);
+
+        let lines: Vec<Line> = match &self.traffic {
+            Ok(traffic) if traffic.is_empty() => vec![Line::from("No traffic counted yet")],
+            Ok(traffic) => traffic
+                .iter()
+                .map(|t| {
+                    Line::from(format!(
+                        "{:<8} {:<14} ↑{:>9} ↓{:>9}",
+                        t.kind,
+                        t.tag,
+                        stats::human_bytes(t.uplink),
+                        stats::human_bytes(t.downlink)
+                    ))
+                })
+                .collect(),
+            Err(e) => vec![
+                Line::from("No stats API (generate with --stats)"),
+                Line::styled(e.clone(), Style::new().fg(Color::DarkGray)),
+            ],
+        };
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Traffic ")),
+            traffic,
+        );
+
+        // pawprint's own messages first, then xray's log, newest at the bottom.
+        let height = logs.height.saturating_sub(2) as usize;
+        let mut lines: Vec<Line> = self
+            .messages
+            .iter()
+            .map(|m| Line::styled(m.clone(), Style::new().fg(Color::Cyan)))
+            .collect();
+        lines.extend(self.log_lines.iter().map(|l| Line::from(l.clone())));
+        let skip = lines.len().saturating_sub(height);
+        let title = match &self.log {
+            Some(log) => format!(" Log ({}) ", log.display()),
+            None => " Log ".to_string(),
+        };
+        frame.render_widget(
+            Paragraph::new(lines.split_off(skip)).block(Block::bordered().title(title)),
+            logs,
+        );
+
+        let testing = if self.testing.is_some() {
+            "  testing..."
+        } else {
+            ""
+        };
+        let line = match &self.search {
+            Some(query) => Line::styled(
+                format!("search: {}▏  enter done  esc cancel", query),
+                Style::new().fg(Color::Yellow),
+            ),
+            None => Line::styled(
+                format!(
+                    "↑/↓ select  / search  enter connect  d disconnect  t test latency  s sort ({})  r reload  q quit{}",
+                    self.sort.as_str(),
+                    testing
+                ),
+                Style::new().fg(Color::DarkGray),
+            ),
+        };
+        frame.render_widget(line, help);
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            self.refresh();
+            terminal.draw(|frame| self.draw(frame))?;
+            if !event::poll(Duration::from_millis(250))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(query) = &mut self.search {
+                match key.code {
+                    KeyCode::Enter | KeyCode::Esc => self.search = None,
+                    KeyCode::Backspace => {
+                        query.pop();
+                    }
+                    KeyCode::Char(c) => query.push(c),
+                    _ => {}
+                }
+                self.select_match();
+                continue;
+            }
+            let result = match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.list.select_previous();
+                    Ok(())
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.list.select_next();
+                    Ok(())
+                }
+                KeyCode::Enter => {
+                    let name = self.selected().map(Entry::name).unwrap_or_default();
+                    self.message(format!("Connecting to {}...", name));
+                    terminal.draw(|frame| self.draw(frame))?;
+                    self.connect()
+                }
+                KeyCode::Char('d') => self.disconnect(),
+                KeyCode::Char('/') => {
+                    self.search = Some(String::new());
+                    Ok(())
+                }
+                // Results of a running test are matched to entries by position.
+                KeyCode::Char('s') if self.testing.is_none() => {
+                    self.sort = self.sort.next();
+                    self.sort_entries();
+                    self.message(format!("Sorted by {}", self.sort.as_str()));
+                    Ok(())
+                }
+                KeyCode::Char('t') => {
+                    self.test_latency();
+                    Ok(())
+                }
+                KeyCode::Char('r') => {
+                    self.message("Reloading...".to_string());
+                    terminal.draw(|frame| self.draw(frame))?;
+                    load_entries(&self.options).map(|entries| {
+                        self.entries = entries;
+                        self.list.select(Some(0));
+                        self.sort_entries();
+                        self.last_refresh = None;
+                    })
+                }
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                self.refresh();
+                self.message(format!("Error: {}", e));
+            }
+        }
+    }
+}
+
+pub fn run(options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = load_entries(&options)?;
+    let mut app = App {
+        options,
+        entries,
+        list: ListState::default().with_selected(Some(0)),
+        running: None,
+        log: None,
+        log_lines: Vec::new(),
+        traffic: Ok(Vec::new()),
+        querying: None,
+        messages: VecDeque::new(),
+        testing: None,
+        last_refresh: None,
+        sort: profile::Sort::Name,
+        search: None,
+    };
+
+    let mut terminal = ratatui::try_init()?;
+    logging::capture(true);
+    let result = app.run(&mut terminal);
+    logging::capture(false);
+    ratatui::restore();
+    result
+}
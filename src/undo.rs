@@ -0,0 +1,82 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use tracing::warn;
+
+use crate::error::PawprintError;
+use crate::process;
+
+// Changes to the OS that must be rolled back even if pawprint dies halfway.
+// The commands undoing a change are saved to the state directory before it is
+// made, so `stop` or the next `run` can still roll it back after a crash.
+pub struct Undo {
+    path: PathBuf,
+}
+
+impl Undo {
+    // `name` is the file in the state directory, e.g. `sysproxy.json`.
+    pub fn new(name: &str) -> Undo {
+        Undo {
+            path: process::state_dir().join(name),
+        }
+    }
+
+    // Whether a change was made and not rolled back yet.
+    pub fn is_pending(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn save(&self, commands: &[Vec<String>]) -> Result<(), PawprintError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(commands)?)
+            .map_err(|e| PawprintError::file(&self.path, e))?;
+        Ok(())
+    }
+
+    // Runs the saved commands. Returns false if nothing was saved. A failing
+    // command does not stop the rest; they are reported together at the end.
+    pub fn run(&self) -> Result<bool, PawprintError> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(PawprintError::file(&self.path, e)),
+        };
+        let commands: Vec<Vec<String>> = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid undo state {}: {}", self.path.display(), e))?;
+        let mut failed = 0;
+        for args in &commands {
+            if let Err(e) = command(args) {
+                warn!("{}", e);
+                failed += 1;
+            }
+        }
+        fs::remove_file(&self.path).map_err(|e| PawprintError::file(&self.path, e))?;
+        if failed > 0 {
+            return Err(format!("{} of {} undo steps failed", failed, commands.len()).into());
+        }
+        Ok(true)
+    }
+}
+
+// Runs `args[0]` with the remaining arguments and returns its stdout.
+pub fn command(args: &[String]) -> Result<String, PawprintError> {
+    let output = Command::new(&args[0])
+        .args(&args[1..])
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", args[0], e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+pub fn args<const N: usize>(args: [&str; N]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
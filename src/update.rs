@@ -0,0 +1,190 @@
+use serde_json::Value;
+
+use crate::error::PawprintError;
+
+// Outbounds a core ships with; everything else came from a share link.
+const XRAY_BUILTIN: &[&str] = &["freedom", "blackhole", "dns", "loopback"];
+const SINGBOX_BUILTIN: &[&str] = &["direct", "block", "dns", "selector", "urltest"];
+
+// Settings that options or hand edits add rather than the link, kept from the
+// outbound being replaced unless the new one sets them too.
+const XRAY_KEPT: &[&str] = &["mux", "proxySettings", "sendThrough"];
+const SINGBOX_KEPT: &[&str] = &[
+    "detour",
+    "multiplex",
+    "bind_interface",
+    "routing_mark",
+    "tcp_fast_open",
+    "domain_strategy",
+    "domain_resolver",
+];
+
+// What `apply` did, by outbound tag.
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub replaced: Vec<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    // Gone from the links but still used elsewhere in the config.
+    pub kept: Vec<String>,
+}
+
+// Xray outbounds name their protocol in `protocol`, sing-box ones in `type`.
+pub fn is_xray(config: &Value) -> bool {
+    outbounds(config)
+        .iter()
+        .any(|o| o.get("protocol").is_some())
+}
+
+fn outbounds(config: &Value) -> &[Value] {
+    config["outbounds"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+fn is_proxy(outbound: &Value, xray: bool) -> bool {
+    if xray {
+        let protocol = outbound["protocol"].as_str().unwrap_or_default();
+        !XRAY_BUILTIN.contains(&protocol)
+    } else {
+        let kind = outbound["type"].as_str().unwrap_or_default();
+        !SINGBOX_BUILTIN.contains(&kind)
+    }
+}
+
+fn tag(outbound: &Value) -> &str {
+    outbound["tag"].as_str().unwrap_or_default()
+}
+
+// Whether `tag` is named anywhere besides the `tag` of its own outbound: in a
+// routing rule, balancer, chain or DNS server.
+fn is_referenced(config: &Value, tag: &str) -> bool {
+    fn walk(value: &Value, tag: &str, skip_tag: bool) -> bool {
+        match value {
+            Value::String(s) => s == tag,
+            Value::Array(items) => items.iter().any(|item| walk(item, tag, false)),
+            Value::Object(map) => map
+                .iter()
+                .any(|(key, value)| !(skip_tag && key == "tag") && walk(value, tag, false)),
+            _ => false,
+        }
+    }
+    config.as_object().is_some_and(|map| {
+        map.iter().any(|(key, value)| match (key.as_str(), value) {
+            ("outbounds", Value::Array(items)) => items.iter().any(|o| walk(o, tag, true)),
+            _ => walk(value, tag, false),
+        })
+    })
+}
+
+fn merge_kept(old: &Value, new: &mut Value, xray: bool) {
+    let kept = if xray { XRAY_KEPT } else { SINGBOX_KEPT };
+    for key in kept {
+        if new.get(*key).is_none()
+            && let Some(value) = old.get(*key)
+        {
+            new[*key] = value.clone();
+        }
+    }
+    if xray {
+        // dialerProxy, mark and the like live next to each other in sockopt.
+        if let Some(Value::Object(old_sockopt)) = old["streamSettings"].get("sockopt") {
+            let sockopt = &mut new["streamSettings"]["sockopt"];
+            for (key, value) in old_sockopt {
+                if sockopt.get(key).is_none() {
+                    sockopt[key] = value.clone();
+                }
+            }
+        }
+    } else if let Some(fragment) = old["tls"].get("fragment")
+        && new["tls"].is_object()
+        && new["tls"].get("fragment").is_none()
+    {
+        new["tls"]["fragment"] = fragment.clone();
+    }
+}
+
+// Replaces the proxy outbounds of `config` that `fresh` has tags for and adds the
+// rest after them, leaving inbounds, routing and everything else alone. `fresh`
+// must be in the config's format.
+//
+// With `rename`, the only fresh outbound takes the place and tag of that one.
+// A config with a single proxy outbound has it replaced by a single fresh one
+// whatever the tags, as when a provider renames its only server. With `prune`,
+// proxy outbounds missing from `fresh` are removed unless something refers to them.
+pub fn apply(
+    config: &mut Value,
+    mut fresh: Vec<Value>,
+    rename: Option<&str>,
+    prune: bool,
+) -> Result<Changes, PawprintError> {
+    if !config["outbounds"].is_array() {
+        return Err("The config has no outbounds to update".into());
+    }
+    let xray = is_xray(config);
+    let proxies: Vec<String> = outbounds(config)
+        .iter()
+        .filter(|o| is_proxy(o, xray))
+        .map(|o| tag(o).to_string())
+        .collect();
+
+    let target = match rename {
+        Some(name) => {
+            if fresh.len() != 1 {
+                return Err("--tag needs exactly one server to put in its place".into());
+            }
+            if !proxies.iter().any(|p| p == name) {
+                return Err(format!("The config has no proxy outbound tagged {}", name).into());
+            }
+            Some(name.to_string())
+        }
+        None if proxies.len() == 1 && fresh.len() == 1 => Some(proxies[0].clone()),
+        None => None,
+    };
+    if let Some(name) = target {
+        fresh[0]["tag"] = Value::from(name);
+    }
+
+    let fresh_tags: Vec<String> = fresh.iter().map(|o| tag(o).to_string()).collect();
+    let mut changes = Changes::default();
+    let list = config["outbounds"].as_array_mut().expect("checked above");
+    // New servers go after the existing ones, before direct, block and the like.
+    let mut insert_at = list
+        .iter()
+        .rposition(|o| is_proxy(o, xray))
+        .map_or(0, |i| i + 1);
+    for mut outbound in fresh {
+        let name = tag(&outbound).to_string();
+        match list
+            .iter()
+            .position(|o| is_proxy(o, xray) && tag(o) == name)
+        {
+            Some(index) => {
+                merge_kept(&list[index], &mut outbound, xray);
+                if list[index] != outbound {
+                    changes.replaced.push(name);
+                }
+                list[index] = outbound;
+            }
+            None => {
+                list.insert(insert_at, outbound);
+                insert_at += 1;
+                changes.added.push(name);
+            }
+        }
+    }
+
+    if prune {
+        for name in proxies.into_iter().filter(|p| !fresh_tags.contains(p)) {
+            if is_referenced(config, &name) {
+                changes.kept.push(name);
+            } else {
+                let list = config["outbounds"].as_array_mut().expect("checked above");
+                list.retain(|o| tag(o) != name);
+                changes.removed.push(name);
+            }
+        }
+    }
+    Ok(changes)
+}
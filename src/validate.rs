@@ -0,0 +1,330 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+
+// One thing in a generated config the core would reject or silently misuse.
+#[derive(Debug)]
+pub struct Problem {
+    // Where in the config, e.g. `outbounds[0] (proxy)`.
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+struct Checker {
+    problems: Vec<Problem>,
+}
+
+impl Checker {
+    fn report(&mut self, location: &str, message: impl Into<String>) {
+        self.problems.push(Problem {
+            location: location.to_string(),
+            message: message.into(),
+        });
+    }
+
+    // Ports may also be Xray port ranges like "1000-2000" or "80,443".
+    fn port(&mut self, location: &str, key: &str, value: &Value) {
+        let valid = match value {
+            Value::Null => return,
+            Value::Number(n) => n.as_u64().is_some_and(|n| (1..=65535).contains(&n)),
+            Value::String(s) if s.starts_with("env:") => true,
+            Value::String(s) => s.split(',').all(|part| {
+                part.trim()
+                    .split('-')
+                    .all(|p| p.parse::<u16>().is_ok_and(|p| p != 0))
+            }),
+            _ => false,
+        };
+        if !valid {
+            self.report(location, format!("{} {} is not in 1-65535", key, value));
+        }
+    }
+
+    // Xray also accepts 1-30 byte strings as ids and maps them to a UUID, so
+    // anything longer must be a well-formed UUID.
+    fn id(&mut self, location: &str, value: &Value) {
+        let Some(id) = value.as_str() else {
+            self.report(location, "user id is missing");
+            return;
+        };
+        if !is_uuid(id) && (id.is_empty() || id.len() > 30) {
+            self.report(location, format!("{:?} is not a UUID", id));
+        }
+    }
+
+    fn reality(&mut self, location: &str, public_key: &Value, short_id: &Value) {
+        match public_key.as_str().filter(|k| !k.is_empty()) {
+            None => self.report(location, "REALITY needs the server's public key"),
+            Some(key) if URL_SAFE_NO_PAD.decode(key).map(|k| k.len()) != Ok(32) => self.report(
+                location,
+                format!("REALITY public key {:?} is not a base64url x25519 key", key),
+            ),
+            Some(_) => {}
+        }
+        if let Some(sid) = short_id.as_str()
+            && (sid.len() > 16 || sid.len() % 2 != 0 || !sid.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            self.report(
+                location,
+                format!(
+                    "REALITY shortId {:?} must be an even number of hex digits, at most 16",
+                    sid
+                ),
+            );
+        }
+    }
+
+    // Vision needs raw TCP underneath TLS or REALITY.
+    fn flow(&mut self, location: &str, flow: &str, network: &str, security: &str) {
+        if flow.is_empty() {
+            return;
+        }
+        if flow != "xtls-rprx-vision" && flow != "xtls-rprx-vision-udp443" {
+            self.report(location, format!("unknown flow {}", flow));
+        }
+        if network != "tcp" && network != "raw" {
+            self.report(
+                location,
+                format!("flow {} only works over tcp, not {}", flow, network),
+            );
+        }
+        if security != "tls" && security != "reality" {
+            self.report(
+                location,
+                format!("flow {} needs tls or reality security", flow),
+            );
+        }
+    }
+
+    fn tags<'a>(&mut self, section: &str, entries: impl Iterator<Item = &'a Value>) {
+        let mut seen = HashSet::new();
+        for (index, entry) in entries.enumerate() {
+            if let Some(tag) = entry["tag"].as_str()
+                && !seen.insert(tag)
+            {
+                self.report(
+                    &format!("{}[{}]", section, index),
+                    format!("duplicate tag {}", tag),
+                );
+            }
+        }
+    }
+}
+
+fn is_uuid(id: &str) -> bool {
+    let groups: Vec<&str> = id.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn location(section: &str, index: usize, entry: &Value) -> String {
+    match entry["tag"].as_str() {
+        Some(tag) => format!("{}[{}] ({})", section, index, tag),
+        None => format!("{}[{}]", section, index),
+    }
+}
+
+fn entries<'a>(config: &'a Value, section: &str) -> &'a [Value] {
+    config[section]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+fn check_xray(checker: &mut Checker, config: &Value) {
+    for (index, inbound) in entries(config, "inbounds").iter().enumerate() {
+        checker.port(
+            &location("inbounds", index, inbound),
+            "port",
+            &inbound["port"],
+        );
+    }
+
+    for (index, outbound) in entries(config, "outbounds").iter().enumerate() {
+        let location = location("outbounds", index, outbound);
+        let protocol = outbound["protocol"].as_str().unwrap_or_default();
+        let stream = &outbound["streamSettings"];
+        let network = stream["network"].as_str().unwrap_or("tcp");
+        let security = stream["security"].as_str().unwrap_or("none");
+        let servers = match protocol {
+            "vless" | "vmess" => &outbound["settings"]["vnext"],
+            _ => &outbound["settings"]["servers"],
+        };
+        for server in servers.as_array().into_iter().flatten() {
+            checker.port(&location, "port", &server["port"]);
+            for user in server["users"].as_array().into_iter().flatten() {
+                checker.id(&location, &user["id"]);
+                let flow = user["flow"].as_str().unwrap_or_default();
+                checker.flow(&location, flow, network, security);
+            }
+        }
+        if security == "reality" {
+            let reality = &stream["realitySettings"];
+            checker.reality(&location, &reality["publicKey"], &reality["shortId"]);
+        }
+    }
+
+    checker.tags("inbounds", entries(config, "inbounds").iter());
+    checker.tags("outbounds", entries(config, "outbounds").iter());
+
+    // The API tag acts as an outbound in routing rules.
+    let outbound_tags: HashSet<&str> = entries(config, "outbounds")
+        .iter()
+        .filter_map(|o| o["tag"].as_str())
+        .chain(config["api"]["tag"].as_str())
+        .collect();
+    let routing = &config["routing"];
+    let balancer_tags: HashSet<&str> = entries(routing, "balancers")
+        .iter()
+        .filter_map(|b| b["tag"].as_str())
+        .collect();
+    for (index, rule) in entries(routing, "rules").iter().enumerate() {
+        let location = format!("routing.rules[{}]", index);
+        if let Some(tag) = rule["outboundTag"].as_str()
+            && !outbound_tags.contains(tag)
+        {
+            checker.report(&location, format!("no outbound is tagged {}", tag));
+        }
+        if let Some(tag) = rule["balancerTag"].as_str()
+            && !balancer_tags.contains(tag)
+        {
+            checker.report(&location, format!("no balancer is tagged {}", tag));
+        }
+    }
+}
+
+fn check_singbox(checker: &mut Checker, config: &Value) {
+    for (index, inbound) in entries(config, "inbounds").iter().enumerate() {
+        checker.port(
+            &location("inbounds", index, inbound),
+            "listen_port",
+            &inbound["listen_port"],
+        );
+    }
+
+    for (index, outbound) in entries(config, "outbounds").iter().enumerate() {
+        let location = location("outbounds", index, outbound);
+        checker.port(&location, "server_port", &outbound["server_port"]);
+        if matches!(outbound["type"].as_str(), Some("vless" | "vmess" | "tuic")) {
+            checker.id(&location, &outbound["uuid"]);
+        }
+        let tls = &outbound["tls"];
+        let reality = &tls["reality"];
+        let security = if reality["enabled"] == true {
+            checker.reality(&location, &reality["public_key"], &reality["short_id"]);
+            "reality"
+        } else if tls["enabled"] == true {
+            "tls"
+        } else {
+            "none"
+        };
+        let network = outbound["transport"]["type"].as_str().unwrap_or("tcp");
+        let flow = outbound["flow"].as_str().unwrap_or_default();
+        checker.flow(&location, flow, network, security);
+    }
+
+    checker.tags("inbounds", entries(config, "inbounds").iter());
+    checker.tags("outbounds", entries(config, "outbounds").iter());
+}
+
+// Semantic checks on an Xray or sing-box config, beyond what parsing the JSON
+// catches. The format is told apart by how outbounds name their protocol.
+pub fn check(config: &Value) -> Vec<Problem> {
+    let mut checker = Checker {
+        problems: Vec::new(),
+    };
+    if !config.is_object() {
+        checker.report("config", "must be a JSON object");
+    } else if entries(config, "outbounds")
+        .iter()
+        .any(|o| o.get("protocol").is_some())
+    {
+        check_xray(&mut checker, config);
+    } else {
+        check_singbox(&mut checker, config);
+    }
+    checker.problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn messages(config: &Value) -> Vec<String> {
+        check(config).iter().map(Problem::to_string).collect()
+    }
+
+    #[test]
+    fn xray_configs_are_checked() {
+        let good = json!({
+            "inbounds": [{"tag": "socks-in", "port": 10808}],
+            "outbounds": [
+                {"tag": "proxy", "protocol": "vless", "settings": {"vnext": [{
+                    "port": 443,
+                    "users": [{"id": "b831381d-6324-4d53-ad4f-8cda48b30811", "flow": "xtls-rprx-vision"}]
+                }]}, "streamSettings": {"network": "tcp", "security": "reality", "realitySettings": {
+                    "publicKey": "AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI", "shortId": "ab12"
+                }}},
+                {"tag": "direct", "protocol": "freedom"}
+            ],
+            "routing": {"rules": [{"outboundTag": "direct", "ip": ["geoip:private"]}]}
+        });
+        assert!(check(&good).is_empty(), "{:?}", messages(&good));
+
+        let bad = json!({
+            "inbounds": [{"tag": "in", "port": 0}, {"tag": "in", "port": "1000-2000"}],
+            "outbounds": [
+                {"tag": "proxy", "protocol": "vless", "settings": {"vnext": [{
+                    "port": 443,
+                    "users": [{"id": "not-a-uuid-but-much-longer-than-thirty-bytes", "flow": "xtls-rprx-vision"}]
+                }]}, "streamSettings": {"network": "ws", "security": "reality", "realitySettings": {"shortId": "abc"}}}
+            ],
+            "routing": {"rules": [{"outboundTag": "direct"}, {"balancerTag": "auto"}]}
+        });
+        assert_eq!(
+            messages(&bad),
+            [
+                "inbounds[0] (in): port 0 is not in 1-65535",
+                "outbounds[0] (proxy): \"not-a-uuid-but-much-longer-than-thirty-bytes\" is not a UUID",
+                "outbounds[0] (proxy): flow xtls-rprx-vision only works over tcp, not ws",
+                "outbounds[0] (proxy): REALITY needs the server's public key",
+                "outbounds[0] (proxy): REALITY shortId \"abc\" must be an even number of hex digits, at most 16",
+                "inbounds[1]: duplicate tag in",
+                "routing.rules[0]: no outbound is tagged direct",
+                "routing.rules[1]: no balancer is tagged auto",
+            ]
+        );
+    }
+
+    #[test]
+    fn singbox_configs_are_checked() {
+        let config = json!({
+            "inbounds": [{"type": "mixed", "tag": "mixed-in", "listen_port": 70000}],
+            "outbounds": [
+                {"type": "vless", "tag": "nl", "server_port": 443, "uuid": "b831381d-6324-4d53-ad4f-8cda48b30811"},
+                {"type": "trojan", "tag": "nl", "server_port": 443, "flow": "xtls-rprx-vision"}
+            ]
+        });
+        assert_eq!(
+            messages(&config),
+            [
+                "inbounds[0] (mixed-in): listen_port 70000 is not in 1-65535",
+                "outbounds[1] (nl): flow xtls-rprx-vision needs tls or reality security",
+                "outbounds[1]: duplicate tag nl",
+            ]
+        );
+        assert_eq!(messages(&json!([])), ["config: must be a JSON object"]);
+    }
+}
@@ -0,0 +1,321 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::error::PawprintError;
+use crate::profile;
+
+// Encryption of the profile store with age (https://age-encryption.org). The
+// public key in `recipient.txt` marks the store as encrypted and is all it takes
+// to save a profile; reading one needs the private key, kept in one of:
+//
+//   identity.txt   plain, readable only by the user
+//   identity.age   the same, encrypted with a passphrase asked for on the terminal
+//   the OS keyring (secret-tool on Linux, the keychain on macOS)
+//
+// The key is fetched once per run, so listing profiles asks for the passphrase
+// only once.
+pub const SUFFIX: &str = ".age";
+
+const KEYRING_SERVICE: &str = "pawprint-vpn";
+const KEYRING_ACCOUNT: &str = "age-identity";
+
+// Where `profile encrypt` keeps the private key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum KeyStore {
+    // identity.txt next to the profiles
+    File,
+    // identity.age, encrypted with a passphrase
+    Passphrase,
+    // The OS keyring
+    Keyring,
+}
+
+static IDENTITY: OnceLock<String> = OnceLock::new();
+
+fn recipient_file() -> Result<PathBuf, PawprintError> {
+    Ok(profile::base_dir()?.join("recipient.txt"))
+}
+
+fn identity_file() -> Result<PathBuf, PawprintError> {
+    Ok(profile::base_dir()?.join("identity.txt"))
+}
+
+fn protected_identity_file() -> Result<PathBuf, PawprintError> {
+    Ok(profile::base_dir()?.join("identity.age"))
+}
+
+// Runs `args` with `input` on stdin and returns stdout. stderr stays on the
+// terminal, where age asks for passphrases.
+fn run(args: &[&str], input: &[u8]) -> Result<Vec<u8>, PawprintError> {
+    let mut child = Command::new(args[0])
+        .args(&args[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                format!(
+                    "{} not found; profile encryption needs age installed",
+                    args[0]
+                )
+            }
+            _ => format!("Failed to run {}: {}", args[0], e),
+        })?;
+    // Dropped right after so the program sees the end of its input.
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input)
+        .map_err(|e| format!("Failed to write to {}: {}", args[0], e))?;
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to run {}: {}", args[0], e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed", args.join(" ")).into());
+    }
+    Ok(output.stdout)
+}
+
+// Writes a file only the user can read.
+fn write_private(path: &Path, content: &[u8]) -> Result<(), PawprintError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| PawprintError::file(parent, e))?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(content))
+        .map_err(|e| PawprintError::file(path, e))
+}
+
+// The public key profiles are encrypted to, if the store is encrypted.
+pub fn recipient() -> Result<Option<String>, PawprintError> {
+    let path = recipient_file()?;
+    match fs::read_to_string(&path) {
+        Ok(content) => Ok(Some(content.trim().to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(PawprintError::file(&path, e)),
+    }
+}
+
+pub fn encrypt(plain: &str, recipient: &str) -> Result<Vec<u8>, PawprintError> {
+    run(
+        &["age", "--encrypt", "--armor", "--recipient", recipient],
+        plain.as_bytes(),
+    )
+}
+
+fn keyring_lookup() -> Result<String, PawprintError> {
+    let args: &[&str] = if cfg!(target_os = "macos") {
+        &[
+            "security",
+            "find-generic-password",
+            "-s",
+            KEYRING_SERVICE,
+            "-a",
+            KEYRING_ACCOUNT,
+            "-w",
+        ]
+    } else {
+        &[
+            "secret-tool",
+            "lookup",
+            "service",
+            KEYRING_SERVICE,
+            "kind",
+            KEYRING_ACCOUNT,
+        ]
+    };
+    let secret = String::from_utf8_lossy(&run(args, b"")?).trim().to_string();
+    if secret.is_empty() {
+        return Err("The profile key is not in the keyring".into());
+    }
+    Ok(secret)
+}
+
+fn keyring_store(identity: &str) -> Result<(), PawprintError> {
+    if cfg!(target_os = "macos") {
+        // With -w last, security asks for the secret (and again to confirm),
+        // reading lines from stdin, so the key never shows up in the process
+        // list. It takes one line: the key without age-keygen's comments.
+        let key = identity
+            .lines()
+            .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+            .ok_or("age-keygen printed no private key")?;
+        run(
+            &[
+                "security",
+                "add-generic-password",
+                "-U",
+                "-s",
+                KEYRING_SERVICE,
+                "-a",
+                KEYRING_ACCOUNT,
+                "-w",
+            ],
+            format!("{}\n{}\n", key, key).as_bytes(),
+        )?;
+    } else if cfg!(unix) {
+        run(
+            &[
+                "secret-tool",
+                "store",
+                "--label=pawprint-vpn profile key",
+                "service",
+                KEYRING_SERVICE,
+                "kind",
+                KEYRING_ACCOUNT,
+            ],
+            identity.as_bytes(),
+        )?;
+    } else {
+        return Err("Keyring storage is only supported on Linux and macOS".into());
+    }
+    Ok(())
+}
+
+fn keyring_clear() {
+    let args: &[&str] = if cfg!(target_os = "macos") {
+        &[
+            "security",
+            "delete-generic-password",
+            "-s",
+            KEYRING_SERVICE,
+            "-a",
+            KEYRING_ACCOUNT,
+        ]
+    } else {
+        &[
+            "secret-tool",
+            "clear",
+            "service",
+            KEYRING_SERVICE,
+            "kind",
+            KEYRING_ACCOUNT,
+        ]
+    };
+    // Nothing to clear when the key was never kept there.
+    let _ = run(args, b"");
+}
+
+// The private key, from wherever `create_key` put it.
+fn identity() -> Result<&'static str, PawprintError> {
+    if let Some(identity) = IDENTITY.get() {
+        return Ok(identity);
+    }
+    let plain = identity_file()?;
+    let protected = protected_identity_file()?;
+    let identity = if plain.is_file() {
+        fs::read_to_string(&plain).map_err(|e| PawprintError::file(&plain, e))?
+    } else if protected.is_file() {
+        let path = protected.to_string_lossy();
+        String::from_utf8_lossy(&run(&["age", "--decrypt", path.as_ref()], b"")?).into_owned()
+    } else {
+        keyring_lookup()?
+    };
+    Ok(IDENTITY.get_or_init(|| identity))
+}
+
+pub fn decrypt(path: &Path) -> Result<String, PawprintError> {
+    let file = path.to_string_lossy();
+    let plain = run(
+        &["age", "--decrypt", "--identity", "-", file.as_ref()],
+        identity()?.as_bytes(),
+    )?;
+    String::from_utf8(plain)
+        .map_err(|_| format!("{} did not decrypt to text", path.display()).into())
+}
+
+// age-keygen prints `# public key: age1...` above the AGE-SECRET-KEY line.
+fn public_key(generated: &str) -> Option<String> {
+    generated
+        .lines()
+        .find_map(|line| line.strip_prefix("# public key:"))
+        .map(|key| key.trim().to_string())
+}
+
+// Creates a key kept in `store` and returns its public key. The private key
+// never touches the disk unencrypted unless `store` is File.
+pub fn create_key(store: KeyStore) -> Result<String, PawprintError> {
+    let generated = String::from_utf8_lossy(&run(&["age-keygen"], b"")?).into_owned();
+    let recipient = public_key(&generated).ok_or("age-keygen printed no public key")?;
+    match store {
+        KeyStore::File => write_private(&identity_file()?, generated.as_bytes())?,
+        KeyStore::Passphrase => {
+            let protected = run(
+                &["age", "--encrypt", "--armor", "--passphrase"],
+                generated.as_bytes(),
+            )?;
+            write_private(&protected_identity_file()?, &protected)?;
+        }
+        KeyStore::Keyring => keyring_store(&generated)?,
+    }
+    let _ = IDENTITY.set(generated);
+    write_private(&recipient_file()?, format!("{}\n", recipient).as_bytes())?;
+    Ok(recipient)
+}
+
+// Forgets the key once every profile is stored in plain text again.
+pub fn remove_key() -> Result<(), PawprintError> {
+    for path in [
+        identity_file()?,
+        protected_identity_file()?,
+        recipient_file()?,
+    ] {
+        if path.exists() {
+            fs::remove_file(&path).map_err(|e| PawprintError::file(&path, e))?;
+        }
+    }
+    keyring_clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_keys_are_read_from_age_keygen() {
+        let generated = "# created: 2026-01-01T00:00:00Z\n\
+                         # public key: age1qyqszqgpqyqszqgpqyqszqgpqyqszqgp\n\
+                         AGE-SECRET-KEY-1QQQQ\n";
+        assert_eq!(
+            public_key(generated).as_deref(),
+            Some("age1qyqszqgpqyqszqgpqyqszqgpqyqszqgp")
+        );
+        assert_eq!(public_key("AGE-SECRET-KEY-1QQQQ\n"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn helpers_pipe_input_and_report_failures() {
+        assert_eq!(run(&["cat"], b"secret").unwrap(), b"secret");
+        assert_eq!(
+            run(&["false"], b"").unwrap_err().to_string(),
+            "false failed"
+        );
+        let missing = run(&["pawprint-no-such-age"], b"").unwrap_err();
+        assert!(missing.to_string().contains("needs age installed"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_files_are_readable_only_by_the_user() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("pawprint-vault-{}", std::process::id()));
+        let path = dir.join("identity.txt");
+        write_private(&path, b"AGE-SECRET-KEY-1").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read(&path).unwrap(), b"AGE-SECRET-KEY-1");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
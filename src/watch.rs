@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::error::PawprintError;
+use crate::parser::Node;
+use crate::process;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+pub fn default_state_file() -> PathBuf {
+    process::state_dir().join("watch.json")
+}
+
+// A server as remembered between runs. Links carry credentials, so only a
+// digest of the link is stored next to a readable description.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerEntry {
+    pub name: String,
+    pub digest: String,
+}
+
+impl ServerEntry {
+    pub fn new(link: &str, node: &Node) -> ServerEntry {
+        ServerEntry {
+            name: format!(
+                "{} {} ({}:{})",
+                node.protocol(),
+                node.tag(),
+                node.address(),
+                node.port()
+            ),
+            digest: digest(link),
+        }
+    }
+}
+
+// 64-bit FNV-1a, stable across builds unlike std's hashers.
+fn digest(link: &str) -> String {
+    let hash = link.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+// What the last successful update generated.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WatchState {
+    pub output: PathBuf,
+    pub servers: Vec<ServerEntry>,
+}
+
+impl WatchState {
+    pub fn load(path: &Path) -> Result<WatchState, PawprintError> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid watch state {}: {}", path.display(), e))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(WatchState::default()),
+            Err(e) => Err(PawprintError::file(path, e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), PawprintError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+// Servers only in `new` and servers only in `old`.
+pub fn diff<'a>(
+    old: &'a [ServerEntry],
+    new: &'a [ServerEntry],
+) -> (Vec<&'a ServerEntry>, Vec<&'a ServerEntry>) {
+    let added = new.iter().filter(|entry| !old.contains(entry)).collect();
+    let removed = old.iter().filter(|entry| !new.contains(entry)).collect();
+    (added, removed)
+}
+
+#[cfg(unix)]
+extern "C" fn request_stop(_signal: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+// Lets SIGINT and SIGTERM end the loop between updates instead of killing it
+// halfway through writing a config.
+pub fn install_signal_handlers() {
+    #[cfg(unix)]
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+        unsafe {
+            libc::signal(signal, request_stop as *const () as libc::sighandler_t);
+        }
+    }
+}
+
+pub fn stop_requested() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+extern "C" fn request_reload(_signal: libc::c_int) {
+    RELOAD.store(true, Ordering::SeqCst);
+}
+
+// Turns SIGHUP into a reload request instead of the default of exiting.
+pub fn install_reload_handler() {
+    // SAFETY: as above, the handler only stores to an atomic.
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGHUP,
+            request_reload as *const () as libc::sighandler_t,
+        );
+    }
+}
+
+// Whether SIGHUP arrived since the last call.
+pub fn take_reload() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+// Notices edits to a set of files by polling their modification times, which
+// works the same everywhere. A file appearing or disappearing counts too.
+#[derive(Debug, Default)]
+pub struct Changes {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Changes {
+    pub fn new(paths: Vec<PathBuf>) -> Changes {
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let time = modified(&path);
+                (path, time)
+            })
+            .collect();
+        Changes { files }
+    }
+
+    // The files changed since `new` or the previous call.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for (path, time) in &mut self.files {
+            let now = modified(path);
+            if now != *time {
+                *time = now;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+}
+
+// Sleeps for `duration` unless a stop is requested first. Returns false if so.
+pub fn sleep(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        if stop_requested() {
+            return false;
+        }
+        thread::sleep(
+            Duration::from_millis(200).min(deadline.saturating_duration_since(Instant::now())),
+        );
+    }
+    !stop_requested()
+}
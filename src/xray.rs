@@ -0,0 +1,1176 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use tracing::{info, warn};
+
+use crate::backend::BuildOptions;
+use crate::error::PawprintError;
+use crate::parser::{
+    Node, ShadowsocksConfig, TrojanConfig, VlessConfig, VmessConfig, WireguardConfig,
+};
+use crate::spec::{self, DnsRoute, InboundProtocol, InboundSpec, MuxSpec, SockoptSpec, TunSpec};
+use crate::target::CoreTarget;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct XrayConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<serde_json::Value>,
+    pub inbounds: Vec<serde_json::Value>,
+    pub outbounds: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routing: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observatory: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<serde_json::Value>,
+}
+
+// Probed by the observatory to rank balanced outbounds.
+const PROBE_URL: &str = "http://cp.cloudflare.com/generate_204";
+
+// Inbound and virtual outbound of the stats API.
+pub const API_TAG: &str = "api";
+
+// Freedom outbound that fragments and adds noise for the proxy outbounds.
+const FRAGMENT_TAG: &str = "fragment";
+
+// Tags the config uses for its own outbounds and balancer; a node named like
+// one gets a suffix instead, as a duplicate would.
+const RESERVED_TAGS: &[&str] = &[
+    "direct",
+    "block",
+    "dns-out",
+    "balancer",
+    API_TAG,
+    FRAGMENT_TAG,
+];
+
+// Per-network settings from the `path`, `host`, `serviceName`, `mode`, ... link
+// parameters, keyed by their streamSettings field name.
+fn build_transport(
+    network: &str,
+    params: &HashMap<String, String>,
+    target: &CoreTarget,
+) -> Option<(&'static str, serde_json::Value)> {
+    let param = |key: &str| params.get(key).filter(|v| !v.is_empty());
+    let path = param("path").map(String::as_str).unwrap_or("/");
+    let host = param("host").map(String::as_str);
+    let hosts: Vec<&str> = host.map(|h| h.split(',').collect()).unwrap_or_default();
+
+    match network {
+        "ws" => {
+            let mut ws = json!({ "path": path });
+            if let Some(host) = host {
+                if target.supports_ws_host() {
+                    ws["host"] = json!(host);
+                } else {
+                    ws["headers"] = json!({ "Host": host });
+                }
+            }
+            Some(("wsSettings", ws))
+        }
+        "httpupgrade" => {
+            let mut upgrade = json!({ "path": path });
+            if let Some(host) = host {
+                upgrade["host"] = json!(host);
+            }
+            Some(("httpupgradeSettings", upgrade))
+        }
+        "xhttp" | "splithttp" => {
+            let mut xhttp = json!({ "path": path });
+            if let Some(host) = host {
+                xhttp["host"] = json!(host);
+            }
+            if network == "xhttp" {
+                xhttp["mode"] = json!(param("mode").map(String::as_str).unwrap_or("auto"));
+                if let Some(extra) = param("extra")
+                    .and_then(|extra| serde_json::from_str::<serde_json::Value>(extra).ok())
+                {
+                    xhttp["extra"] = extra;
+                }
+                Some(("xhttpSettings", xhttp))
+            } else {
+                Some(("splithttpSettings", xhttp))
+            }
+        }
+        "grpc" => {
+            let mut grpc = json!({
+                "serviceName": param("serviceName").cloned().unwrap_or_default(),
+                "multiMode": param("mode").is_some_and(|mode| mode == "multi")
+            });
+            if let Some(authority) = param("authority") {
+                grpc["authority"] = json!(authority);
+            }
+            Some(("grpcSettings", grpc))
+        }
+        "h2" | "http" => Some(("httpSettings", json!({ "host": hosts, "path": path }))),
+        "kcp" => {
+            let mut kcp = json!({
+                "header": { "type": param("headerType").map(String::as_str).unwrap_or("none") }
+            });
+            if let Some(seed) = param("seed") {
+                kcp["seed"] = json!(seed);
+            }
+            Some(("kcpSettings", kcp))
+        }
+        "tcp" | "raw" if param("headerType").is_some_and(|t| t == "http") => Some((
+            "tcpSettings",
+            json!({
+                "header": {
+                    "type": "http",
+                    "request": {
+                        "path": path.split(',').collect::<Vec<_>>(),
+                        "headers": { "Host": hosts }
+                    }
+                }
+            }),
+        )),
+        _ => None,
+    }
+}
+
+// streamSettings from VLESS-style link parameters.
+fn build_stream_settings(
+    params: &HashMap<String, String>,
+    address: &str,
+    default_security: &str,
+    target: &CoreTarget,
+) -> serde_json::Value {
+    let mut network_type = params
+        .get("type")
+        .cloned()
+        .unwrap_or_else(|| "tcp".to_string());
+
+    if network_type == "xhttp" && !target.supports_xhttp() {
+        network_type = "splithttp".to_string();
+    }
+
+    let security = params
+        .get("security")
+        .cloned()
+        .unwrap_or_else(|| default_security.to_string());
+
+    let mut stream_settings = json!({
+        "network": network_type,
+        "security": security,
+    });
+    if let Some((key, settings)) = build_transport(&network_type, params, target) {
+        stream_settings[key] = settings;
+    }
+
+    if security == "reality" {
+        let pbk = params.get("pbk").cloned().unwrap_or_default();
+        let sni = params.get("sni").cloned().unwrap_or_default();
+        let fp = params
+            .get("fp")
+            .cloned()
+            .unwrap_or_else(|| "chrome".to_string());
+        let sid = params.get("sid").cloned().unwrap_or_default();
+        let mut spx = params
+            .get("spx")
+            .cloned()
+            .unwrap_or_else(|| "/".to_string());
+        if !target.supports_spiderx_query()
+            && let Some((path, _)) = spx.split_once('?')
+        {
+            spx = path.to_string();
+        }
+
+        let mut reality_settings = json!({
+            "publicKey": pbk,
+            "fingerprint": fp,
+            "serverName": sni,
+            "shortId": sid,
+            "spiderX": spx
+        });
+        if target.supports_reality_password() {
+            reality_settings["password"] = json!(pbk);
+        }
+        if let Some(pqv) = params.get("pqv")
+            && target.supports_mldsa65()
+        {
+            reality_settings["mldsa65Verify"] = json!(pqv);
+        }
+
+        stream_settings["realitySettings"] = reality_settings;
+    } else if security == "tls" {
+        let param = |key: &str| params.get(key).filter(|v| !v.is_empty());
+        let sni = param("sni").map(String::as_str).unwrap_or(address);
+        // Clients write either name for skipping certificate checks.
+        let insecure = ["allowInsecure", "insecure"]
+            .iter()
+            .any(|key| param(key).is_some_and(|v| v == "1" || v == "true"));
+
+        let mut tls_settings = json!({
+            "serverName": sni,
+            "allowInsecure": insecure
+        });
+        if let Some(alpn) = param("alpn") {
+            tls_settings["alpn"] = json!(alpn.split(',').collect::<Vec<_>>());
+        }
+        if let Some(fp) = param("fp") {
+            tls_settings["fingerprint"] = json!(fp);
+        }
+        // A base64 ECHConfigList, or a DNS query for it like
+        // `example.com+https://1.1.1.1/dns-query`; Xray takes both as they are.
+        if let Some(ech) = param("ech") {
+            if target.supports_ech() {
+                tls_settings["echConfigList"] = json!(ech);
+            } else {
+                warn!(
+                    "Dropping the ECH config of {}: {} cannot use it, target xray@25.7 or newer",
+                    address, target
+                );
+            }
+        }
+        stream_settings["tlsSettings"] = tls_settings;
+    }
+
+    stream_settings
+}
+
+fn build_vless_outbound(vless_config: &VlessConfig, target: &CoreTarget) -> serde_json::Value {
+    let stream_settings =
+        build_stream_settings(&vless_config.params, &vless_config.address, "tls", target);
+
+    let mut user = json!({
+        "id": vless_config.uuid,
+        "encryption": "none",
+        "level": 0
+    });
+
+    let flow = vless_config.params.get("flow").cloned();
+
+    if let Some(flow_val) = flow {
+        user["flow"] = json!(flow_val);
+    }
+
+    json!({
+        "protocol": "vless",
+        "settings": {
+            "vnext": [{
+                "address": vless_config.address,
+                "port": vless_config.port,
+                "users": [user]
+            }]
+        },
+        "streamSettings": stream_settings,
+        "tag": vless_config.tag
+    })
+}
+
+fn build_vmess_outbound(vmess_config: &VmessConfig, target: &CoreTarget) -> serde_json::Value {
+    let stream_settings =
+        build_stream_settings(&vmess_config.params, &vmess_config.address, "none", target);
+
+    json!({
+        "protocol": "vmess",
+        "settings": {
+            "vnext": [{
+                "address": vmess_config.address,
+                "port": vmess_config.port,
+                "users": [{
+                    "id": vmess_config.uuid,
+                    "alterId": vmess_config.alter_id,
+                    "security": vmess_config.security,
+                    "level": 0
+                }]
+            }]
+        },
+        "streamSettings": stream_settings,
+        "tag": vmess_config.tag
+    })
+}
+
+fn build_trojan_outbound(trojan_config: &TrojanConfig, target: &CoreTarget) -> serde_json::Value {
+    let stream_settings =
+        build_stream_settings(&trojan_config.params, &trojan_config.address, "tls", target);
+
+    json!({
+        "protocol": "trojan",
+        "settings": {
+            "servers": [{
+                "address": trojan_config.address,
+                "port": trojan_config.port,
+                "password": trojan_config.password,
+                "level": 0
+            }]
+        },
+        "streamSettings": stream_settings,
+        "tag": trojan_config.tag
+    })
+}
+
+// Ciphers Xray still implements; the stream ciphers were dropped long ago.
+const SHADOWSOCKS_METHODS: [&str; 9] = [
+    "aes-128-gcm",
+    "aes-256-gcm",
+    "chacha20-poly1305",
+    "chacha20-ietf-poly1305",
+    "xchacha20-poly1305",
+    "xchacha20-ietf-poly1305",
+    "2022-blake3-aes-128-gcm",
+    "2022-blake3-aes-256-gcm",
+    "2022-blake3-chacha20-poly1305",
+];
+
+// The host v2ray-plugin uses when the options leave it out.
+const V2RAY_PLUGIN_HOST: &str = "cloudfront.com";
+
+fn build_shadowsocks_outbound(
+    ss_config: &ShadowsocksConfig,
+    target: &CoreTarget,
+) -> Result<serde_json::Value, PawprintError> {
+    let unsupported = |feature: String| PawprintError::Unsupported {
+        target: target.to_string(),
+        feature,
+    };
+    if !SHADOWSOCKS_METHODS.contains(&ss_config.method.as_str()) && ss_config.method != "none" {
+        return Err(unsupported(format!(
+            "use the {} cipher ({}); use a sing-box target",
+            ss_config.method, ss_config.tag
+        )));
+    }
+    let mut outbound = json!({
+        "protocol": "shadowsocks",
+        "settings": {
+            "servers": [{
+                "address": ss_config.address,
+                "port": ss_config.port,
+                "method": ss_config.method,
+                "password": ss_config.password,
+                "level": 0
+            }]
+        },
+        "tag": ss_config.tag
+    });
+    // Xray cannot run SIP003 plugins, but speaks v2ray-plugin's websocket mode
+    // itself. Anything else would reach the server without the obfuscation it
+    // expects and never connect.
+    let Some(plugin) = ss_config.sip003() else {
+        return Ok(outbound);
+    };
+    if plugin.name != "v2ray-plugin" || plugin.option("mode").is_some_and(|m| m != "websocket") {
+        return Err(unsupported(format!(
+            "run the SIP003 plugin {} ({}); use a sing-box target",
+            ss_config.plugin.as_deref().unwrap_or_default(),
+            ss_config.tag
+        )));
+    }
+    let host = plugin.option("host").unwrap_or(V2RAY_PLUGIN_HOST);
+    let params = HashMap::from([
+        ("host".to_string(), host.to_string()),
+        (
+            "path".to_string(),
+            plugin.option("path").unwrap_or("/").to_string(),
+        ),
+    ]);
+    let (key, ws) = build_transport("ws", &params, target).expect("ws has settings");
+    let mut stream = json!({ "network": "ws", key: ws });
+    if plugin.flag("tls") {
+        stream["security"] = json!("tls");
+        stream["tlsSettings"] = json!({ "serverName": host, "allowInsecure": false });
+    }
+    outbound["streamSettings"] = stream;
+    Ok(outbound)
+}
+
+fn build_wireguard_outbound(wg_config: &WireguardConfig) -> serde_json::Value {
+    let param = |key: &str| wg_config.params.get(key).filter(|v| !v.is_empty());
+    // Everything goes through the tunnel, whatever AllowedIPs said.
+    let mut peer = json!({
+        "publicKey": param("publickey").cloned().unwrap_or_default(),
+        "endpoint": crate::parser::authority(&wg_config.address, wg_config.port),
+    });
+    if let Some(psk) = param("presharedkey") {
+        peer["preSharedKey"] = json!(psk);
+    }
+    if let Some(keepalive) = param("keepalive").and_then(|k| k.parse::<u32>().ok()) {
+        peer["keepAlive"] = json!(keepalive);
+    }
+    let mut settings = json!({
+        "secretKey": wg_config.private_key,
+        "address": wg_config.local_addresses(),
+        "peers": [peer],
+    });
+    if let Some(reserved) = wg_config.reserved() {
+        settings["reserved"] = json!(reserved);
+    }
+    if let Some(mtu) = param("mtu").and_then(|m| m.parse::<u16>().ok()) {
+        settings["mtu"] = json!(mtu);
+    }
+    json!({
+        "protocol": "wireguard",
+        "settings": settings,
+        "tag": wg_config.tag
+    })
+}
+
+fn build_inbound(inbound: &InboundSpec, tag: &str) -> serde_json::Value {
+    let accounts = match (&inbound.username, &inbound.password) {
+        (Some(user), Some(pass)) => Some(json!([{ "user": user, "pass": pass }])),
+        _ => None,
+    };
+    let (protocol, settings) = match inbound.protocol {
+        InboundProtocol::Socks => {
+            let mut settings = json!({
+                "auth": "noauth",
+                "udp": true
+            });
+            if let Some(accounts) = accounts {
+                settings["auth"] = json!("password");
+                settings["accounts"] = accounts;
+            }
+            ("socks", settings)
+        }
+        InboundProtocol::Http => {
+            let mut settings = json!({});
+            if let Some(accounts) = accounts {
+                settings["accounts"] = accounts;
+            }
+            ("http", settings)
+        }
+    };
+
+    let mut value = json!({
+        "port": inbound.port,
+        "protocol": protocol,
+        "settings": settings,
+        "tag": tag
+    });
+    if let Some(listen) = &inbound.listen {
+        value["listen"] = json!(listen);
+    }
+    value
+}
+
+// Xray only creates the interface; addresses and routes are left to the OS, and
+// sniffing recovers the domains that routing rules match on.
+fn build_tun_inbound(tun: &TunSpec) -> serde_json::Value {
+    if tun.stack.is_some() {
+        warn!("The TUN stack setting only applies to sing-box and is ignored for Xray");
+    }
+    info!(
+        "Xray does not configure the TUN interface: assign {} to {} and route traffic (except to the server) through it",
+        tun.address.join(", "),
+        tun.name
+    );
+    json!({
+        "protocol": "tun",
+        "settings": {
+            "name": tun.name,
+            "MTU": tun.mtu,
+            "userLevel": 0
+        },
+        "sniffing": {
+            "enabled": true,
+            "destOverride": ["http", "tls", "quic"]
+        },
+        "tag": "tun-in"
+    })
+}
+
+// Merged into streamSettings.sockopt, next to the dialerProxy of chains and fragments.
+fn add_sockopt(outbound: &mut serde_json::Value, spec: &SockoptSpec) {
+    let stream = &mut outbound["streamSettings"];
+    if stream.is_null() {
+        *stream = json!({});
+    }
+    let sockopt = &mut stream["sockopt"];
+    if spec.tcp_fast_open {
+        sockopt["tcpFastOpen"] = json!(true);
+    }
+    if let Some(mark) = spec.mark {
+        sockopt["mark"] = json!(mark);
+    }
+    if let Some(strategy) = spec.domain_strategy {
+        sockopt["domainStrategy"] = json!(strategy);
+    }
+    if let Some(idle) = spec.tcp_keep_alive_idle {
+        sockopt["tcpKeepAliveIdle"] = json!(idle);
+    }
+}
+
+// Scoped DNS servers for the routed domains, with everything else resolved by the
+// upstream servers (1.1.1.1 unless chosen) through the tunnel. Queries to the
+// scoped servers and traffic to their domains must bypass the proxy, so they are
+// routed to a `direct` outbound.
+fn build_dns(
+    routes: &[DnsRoute],
+    upstream: &[String],
+) -> (serde_json::Value, Vec<serde_json::Value>) {
+    let mut servers: Vec<serde_json::Value> = routes
+        .iter()
+        .map(|route| {
+            let mut server = json!({
+                "address": route.address,
+                "domains": [format!("domain:{}", route.domain)]
+            });
+            if let Some(port) = route.port {
+                server["port"] = json!(port);
+            }
+            server
+        })
+        .collect();
+    if upstream.is_empty() {
+        servers.push(json!("1.1.1.1"));
+    } else {
+        servers.extend(upstream.iter().map(|server| json!(server)));
+    }
+    if routes.is_empty() {
+        return (json!({ "servers": servers }), Vec::new());
+    }
+
+    // Only address literals fit an `ip` rule; servers given by name go with the
+    // domains.
+    let (resolvers, named): (Vec<&DnsRoute>, Vec<&DnsRoute>) = routes
+        .iter()
+        .partition(|r| r.address.parse::<IpAddr>().is_ok());
+    let domains: Vec<String> = routes
+        .iter()
+        .map(|r| format!("domain:{}", r.domain))
+        .chain(named.iter().map(|r| format!("full:{}", r.address)))
+        .collect();
+    let mut rules = Vec::new();
+    if !resolvers.is_empty() {
+        let ips: Vec<&str> = resolvers.iter().map(|r| r.address.as_str()).collect();
+        rules.push(json!({
+            "type": "field",
+            "ip": ips,
+            "outboundTag": "direct"
+        }));
+    }
+    rules.push(json!({
+        "type": "field",
+        "domain": domains,
+        "outboundTag": "direct"
+    }));
+
+    (json!({ "servers": servers }), rules)
+}
+
+// geoip:, ext-ip: and literal addresses or CIDRs match the destination IP; every
+// other matcher (geosite:, domain:, full:, regexp:, keyword:, ext:) the domain.
+fn is_ip_matcher(matcher: &str) -> bool {
+    matcher.starts_with("geoip:")
+        || matcher.starts_with("ext-ip:")
+        || matcher
+            .split_once('/')
+            .map_or(matcher, |(ip, _)| ip)
+            .parse::<IpAddr>()
+            .is_ok()
+}
+
+// Field rules sending the matched domains and IPs to `target`, an
+// (`outboundTag` | `balancerTag`, tag) pair.
+fn build_rules(matchers: &[String], target: (&str, &str)) -> Vec<serde_json::Value> {
+    let (target_key, target_tag) = target;
+    let (ips, domains): (Vec<&String>, Vec<&String>) =
+        matchers.iter().partition(|m| is_ip_matcher(m));
+    let mut rules = Vec::new();
+    if !domains.is_empty() {
+        rules.push(json!({
+            "type": "field",
+            "domain": domains,
+            target_key: target_tag
+        }));
+    }
+    if !ips.is_empty() {
+        rules.push(json!({
+            "type": "field",
+            "ip": ips,
+            target_key: target_tag
+        }));
+    }
+    rules
+}
+
+// Vision already hides the inner TLS and refuses to run inside mux.
+fn add_mux(outbound: &mut serde_json::Value, mux: &MuxSpec) {
+    // WireGuard carries its own packets over UDP.
+    if outbound["protocol"] == "wireguard" {
+        return;
+    }
+    let flow = &outbound["settings"]["vnext"][0]["users"][0]["flow"];
+    if flow.as_str().is_some_and(|f| !f.is_empty()) {
+        warn!(
+            "Not enabling mux on {}: it cannot be combined with flow {}",
+            outbound["tag"].as_str().unwrap_or_default(),
+            flow.as_str().unwrap_or_default()
+        );
+        return;
+    }
+    let mut settings = json!({ "enabled": true, "concurrency": mux.concurrency });
+    if let Some(xudp) = mux.xudp_concurrency {
+        settings["xudpConcurrency"] = json!(xudp);
+    }
+    outbound["mux"] = settings;
+}
+
+fn build_fragment_outbound(options: &BuildOptions) -> Option<serde_json::Value> {
+    if options.fragment.is_none() && options.noises.is_empty() {
+        return None;
+    }
+    let mut settings = json!({});
+    if let Some(fragment) = &options.fragment {
+        settings["fragment"] = json!({
+            "packets": fragment.packets,
+            "length": fragment.length,
+            "interval": fragment.interval,
+        });
+    }
+    if !options.noises.is_empty() {
+        let noises: Vec<serde_json::Value> = options
+            .noises
+            .iter()
+            .map(|noise| {
+                let mut value = json!({ "type": noise.kind, "packet": noise.packet });
+                if let Some(delay) = &noise.delay {
+                    value["delay"] = json!(delay);
+                }
+                value
+            })
+            .collect();
+        settings["noises"] = json!(noises);
+    }
+    Some(json!({
+        "protocol": "freedom",
+        "tag": FRAGMENT_TAG,
+        "settings": settings,
+    }))
+}
+
+pub fn build_config(nodes: &[Node], options: &BuildOptions) -> Result<XrayConfig, PawprintError> {
+    let target = &options.target;
+    let mut outbounds: Vec<serde_json::Value> = Vec::new();
+    for node in nodes {
+        let mut outbound = match node {
+            Node::Vless(vless_config) => build_vless_outbound(vless_config, target),
+            Node::Vmess(vmess_config) => build_vmess_outbound(vmess_config, target),
+            Node::Trojan(trojan_config) => build_trojan_outbound(trojan_config, target),
+            Node::Shadowsocks(ss_config) => build_shadowsocks_outbound(ss_config, target)?,
+            Node::Wireguard(wg_config) => build_wireguard_outbound(wg_config),
+            Node::Plugin(plugin_node) => plugin_node.outbound.clone(),
+            Node::Hysteria2(_) | Node::Tuic(_) => {
+                return Err(PawprintError::Unsupported {
+                    target: target.to_string(),
+                    feature: format!(
+                        "connect to {} servers ({}); use a sing-box target",
+                        node.protocol(),
+                        node.tag()
+                    ),
+                });
+            }
+        };
+
+        // Xray rejects duplicate tags, which are common when nodes share a name.
+        let mut tag = node.tag().to_string();
+        let mut suffix = 2;
+        while RESERVED_TAGS.contains(&tag.as_str())
+            || outbounds.iter().any(|o| o["tag"] == tag.as_str())
+        {
+            tag = format!("{}-{}", node.tag(), suffix);
+            suffix += 1;
+        }
+        outbound["tag"] = json!(tag);
+        outbounds.push(outbound);
+    }
+    if options.chain {
+        for hop in 1..outbounds.len() {
+            let previous = outbounds[hop - 1]["tag"].clone();
+            let stream = &mut outbounds[hop]["streamSettings"];
+            if stream.is_null() {
+                *stream = json!({});
+            }
+            stream["sockopt"]["dialerProxy"] = previous;
+        }
+        // The exit goes first, so everything unmatched leaves through it.
+        outbounds.reverse();
+    }
+    if let Some(mux) = &options.mux {
+        for outbound in &mut outbounds {
+            add_mux(outbound, mux);
+        }
+    }
+    // Fragmenting and noise happen in a freedom outbound the servers dial
+    // through; in a chain only the entry reaches the network itself.
+    let dialer = build_fragment_outbound(options);
+    if dialer.is_some() {
+        for outbound in &mut outbounds {
+            let stream = &mut outbound["streamSettings"];
+            if stream["sockopt"]["dialerProxy"].is_null() {
+                if stream.is_null() {
+                    *stream = json!({});
+                }
+                stream["sockopt"]["dialerProxy"] = json!(FRAGMENT_TAG);
+            }
+        }
+    }
+
+    let default_inbounds = [InboundSpec::default()];
+    let inbound_specs: &[InboundSpec] = if options.inbounds.is_empty() {
+        &default_inbounds
+    } else {
+        &options.inbounds
+    };
+    let mut inbounds: Vec<serde_json::Value> = inbound_specs
+        .iter()
+        .zip(InboundSpec::tags(inbound_specs))
+        .map(|(inbound, tag)| build_inbound(inbound, &tag))
+        .collect();
+    if let Some(tun) = &options.tun {
+        inbounds.push(build_tun_inbound(tun));
+    }
+    // Before the API inbound is added, which must not sniff.
+    if let Some(sniffing) = &options.sniffing {
+        for inbound in &mut inbounds {
+            inbound["sniffing"] = json!({
+                "enabled": true,
+                "destOverride": sniffing.protocols,
+                "routeOnly": sniffing.route_only
+            });
+        }
+    }
+
+    let proxy_tags: Vec<String> = outbounds
+        .iter()
+        .filter_map(|o| o["tag"].as_str().map(str::to_string))
+        .collect();
+    let balance = options.balance && proxy_tags.len() > 1;
+    let proxy = if balance {
+        ("balancerTag", "balancer")
+    } else {
+        ("outboundTag", proxy_tags[0].as_str())
+    };
+    let mut rules = Vec::new();
+    let mut dns = None;
+    if !options.dns_servers.is_empty() {
+        // Queries from clients go to the built-in DNS instead of leaking to
+        // whatever resolver they picked. Its own upstream queries come from no
+        // inbound, so they are not caught here.
+        let inbound_tags: Vec<&serde_json::Value> = inbounds.iter().map(|i| &i["tag"]).collect();
+        rules.push(json!({
+            "type": "field",
+            "inboundTag": inbound_tags,
+            "port": "53",
+            "outboundTag": "dns-out"
+        }));
+        outbounds.push(json!({
+            "protocol": "dns",
+            "tag": "dns-out"
+        }));
+    }
+    if !options.dns_routes.is_empty() || !options.dns_servers.is_empty() {
+        let (servers, dns_rules) = build_dns(&options.dns_routes, &options.dns_servers);
+        dns = Some(servers);
+        rules.extend(dns_rules);
+    }
+    // Inbounds bound to an outbound bypass the matchers below.
+    for (tag, outbound) in InboundSpec::routes(inbound_specs) {
+        let (key, value) = match outbound {
+            spec::DIRECT => ("outboundTag", spec::DIRECT),
+            spec::BLOCK => ("outboundTag", spec::BLOCK),
+            spec::PROXY => proxy,
+            node if proxy_tags.iter().any(|t| t == node) => ("outboundTag", node),
+            node => {
+                return Err(format!(
+                    "Inbound {} is routed to {}, which is not a node; use direct, block, proxy or one of: {}",
+                    tag,
+                    node,
+                    proxy_tags.join(", ")
+                )
+                .into());
+            }
+        };
+        rules.push(json!({ "type": "field", "inboundTag": [tag], key: value }));
+    }
+    // The first matching rule wins: blocking beats everything, and explicit
+    // proxy matchers carve exceptions out of the direct ones.
+    rules.extend(build_rules(&options.rules.block, ("outboundTag", "block")));
+    rules.extend(build_rules(&options.rules.proxy, proxy));
+    rules.extend(build_rules(
+        &options.rules.direct,
+        ("outboundTag", "direct"),
+    ));
+    // Without a catch-all rule Xray would send the rest to the first outbound.
+    if balance {
+        rules.push(json!({
+            "type": "field",
+            "network": "tcp,udp",
+            "balancerTag": "balancer"
+        }));
+    }
+
+    outbounds.extend(dialer);
+    // The API is reached through its own inbound, routed to the virtual "api"
+    // outbound before any other rule can catch it.
+    let stats = options.stats.as_ref().map(|_| json!({}));
+    let (api, policy) = match &options.stats {
+        Some(spec) => {
+            inbounds.push(json!({
+                "tag": API_TAG,
+                "listen": "127.0.0.1",
+                "port": spec.port,
+                "protocol": "dokodemo-door",
+                "settings": { "address": "127.0.0.1" }
+            }));
+            rules.insert(
+                0,
+                json!({ "type": "field", "inboundTag": [API_TAG], "outboundTag": API_TAG }),
+            );
+            let api = json!({ "tag": API_TAG, "services": ["StatsService"] });
+            let policy = json!({
+                "system": {
+                    "statsInboundUplink": true,
+                    "statsInboundDownlink": true,
+                    "statsOutboundUplink": true,
+                    "statsOutboundDownlink": true
+                }
+            });
+            (Some(api), Some(policy))
+        }
+        None => (None, None),
+    };
+    if rules.iter().any(|rule| rule["outboundTag"] == "direct") {
+        outbounds.push(json!({
+            "protocol": "freedom",
+            "tag": "direct"
+        }));
+    }
+    if rules.iter().any(|rule| rule["outboundTag"] == "block") {
+        outbounds.push(json!({
+            "protocol": "blackhole",
+            "tag": "block"
+        }));
+    }
+    if let Some(sockopt) = &options.sockopt {
+        for outbound in outbounds
+            .iter_mut()
+            .filter(|o| o["protocol"] != "blackhole")
+        {
+            add_sockopt(outbound, sockopt);
+        }
+    }
+
+    // geoip and CIDR rules only see domains once they are resolved.
+    let resolve_domains = [
+        &options.rules.block,
+        &options.rules.proxy,
+        &options.rules.direct,
+    ]
+    .iter()
+    .any(|matchers| matchers.iter().any(|m| is_ip_matcher(m)));
+    let routing = (!rules.is_empty()).then(|| {
+        let mut routing = json!({ "rules": rules });
+        if resolve_domains {
+            routing["domainStrategy"] = json!("IPIfNonMatch");
+        }
+        if balance {
+            routing["balancers"] = json!([{
+                "tag": "balancer",
+                "selector": proxy_tags,
+                "strategy": { "type": "leastPing" }
+            }]);
+        }
+        routing
+    });
+
+    // Selectors match tag prefixes, so a node tagged e.g. "d" would also pick up
+    // the "direct" outbound.
+    if balance
+        && let Some(tag) = outbounds[proxy_tags.len()..]
+            .iter()
+            .filter_map(|o| o["tag"].as_str())
+            .find(|tag| proxy_tags.iter().any(|p| tag.starts_with(p.as_str())))
+    {
+        warn!(
+            "The balancer also selects the {} outbound; rename the node it shares a prefix with",
+            tag
+        );
+    }
+    let observatory = balance.then(|| {
+        json!({
+            "subjectSelector": proxy_tags,
+            "probeUrl": PROBE_URL,
+            "probeInterval": "1m",
+            "enableConcurrency": true
+        })
+    });
+
+    let log = options.log.as_ref().map(|spec| {
+        let mut log = json!({ "loglevel": spec.level.as_str() });
+        if let Some(access) = &spec.access {
+            log["access"] = json!(access);
+        }
+        if let Some(error) = &spec.error {
+            log["error"] = json!(error);
+        }
+        if spec.dns {
+            log["dnsLog"] = json!(true);
+        }
+        log
+    });
+
+    Ok(XrayConfig {
+        log,
+        dns,
+        inbounds,
+        outbounds,
+        routing,
+        observatory,
+        api,
+        stats,
+        policy,
+    })
+}
+
+// Link parameters read back from streamSettings, the inverse of build_stream_settings.
+fn stream_params(stream: &serde_json::Value) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut set = |key: &str, value: &serde_json::Value| {
+        let value = match value {
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Array(items) => items
+                .iter()
+                .filter_map(|item| item.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+            _ => return,
+        };
+        if !value.is_empty() {
+            params.insert(key.to_string(), value);
+        }
+    };
+
+    let network = stream["network"].as_str().unwrap_or("tcp");
+    set("type", &json!(network));
+    set(
+        "security",
+        &json!(stream["security"].as_str().unwrap_or("none")),
+    );
+
+    let tls = &stream["tlsSettings"];
+    set("sni", &tls["serverName"]);
+    set("alpn", &tls["alpn"]);
+    set("fp", &tls["fingerprint"]);
+    set("ech", &tls["echConfigList"]);
+    if tls["allowInsecure"] == true {
+        set("allowInsecure", &json!("1"));
+    }
+
+    let reality = &stream["realitySettings"];
+    set("sni", &reality["serverName"]);
+    set("fp", &reality["fingerprint"]);
+    set("pbk", &reality["password"]);
+    set("pbk", &reality["publicKey"]);
+    set("sid", &reality["shortId"]);
+    set("spx", &reality["spiderX"]);
+    set("pqv", &reality["mldsa65Verify"]);
+
+    match network {
+        "ws" => {
+            let ws = &stream["wsSettings"];
+            set("path", &ws["path"]);
+            set("host", &ws["headers"]["Host"]);
+            set("host", &ws["host"]);
+        }
+        "httpupgrade" => {
+            let upgrade = &stream["httpupgradeSettings"];
+            set("path", &upgrade["path"]);
+            set("host", &upgrade["host"]);
+        }
+        "xhttp" | "splithttp" => {
+            let xhttp = &stream[format!("{}Settings", network).as_str()];
+            set("path", &xhttp["path"]);
+            set("host", &xhttp["host"]);
+            set("mode", &xhttp["mode"]);
+            if xhttp["extra"].is_object() {
+                set("extra", &json!(xhttp["extra"].to_string()));
+            }
+        }
+        "grpc" => {
+            let grpc = &stream["grpcSettings"];
+            set("serviceName", &grpc["serviceName"]);
+            set("authority", &grpc["authority"]);
+            if grpc["multiMode"] == true {
+                set("mode", &json!("multi"));
+            }
+        }
+        "h2" | "http" => {
+            let http = &stream["httpSettings"];
+            set("path", &http["path"]);
+            set("host", &http["host"]);
+        }
+        "kcp" => {
+            let kcp = &stream["kcpSettings"];
+            set("headerType", &kcp["header"]["type"]);
+            set("seed", &kcp["seed"]);
+        }
+        "tcp" | "raw" => {
+            let header = &stream[format!("{}Settings", network).as_str()]["header"];
+            if header["type"] == "http" {
+                set("headerType", &json!("http"));
+                set("path", &header["request"]["path"]);
+                set("host", &header["request"]["headers"]["Host"]);
+            }
+        }
+        _ => {}
+    }
+    params
+}
+
+fn field<'a>(value: &'a serde_json::Value, key: &str, tag: &str) -> Result<&'a str, String> {
+    value[key]
+        .as_str()
+        .ok_or_else(|| format!("Outbound {} has no {}", tag, key))
+}
+
+fn port(server: &serde_json::Value, tag: &str) -> Result<u16, String> {
+    server["port"]
+        .as_u64()
+        .and_then(|port| u16::try_from(port).ok())
+        .ok_or_else(|| format!("Outbound {} has no valid port", tag))
+}
+
+// Node described by an Xray outbound, for turning configs back into share links.
+// Returns None for outbounds that are not proxies, such as freedom and blackhole.
+pub fn parse_outbound(outbound: &serde_json::Value) -> Result<Option<Node>, String> {
+    let protocol = outbound["protocol"].as_str().unwrap_or_default();
+    let tag = outbound["tag"].as_str().unwrap_or(protocol).to_string();
+    let settings = &outbound["settings"];
+    let params = stream_params(&outbound["streamSettings"]);
+
+    let node = match protocol {
+        "vless" | "vmess" => {
+            let server = &settings["vnext"][0];
+            let user = &server["users"][0];
+            let address = field(server, "address", &tag)?.to_string();
+            let port = port(server, &tag)?;
+            let uuid = field(user, "id", &tag)?.to_string();
+            if protocol == "vless" {
+                let mut params = params;
+                if let Some(flow) = user["flow"].as_str().filter(|f| !f.is_empty()) {
+                    params.insert("flow".to_string(), flow.to_string());
+                }
+                Node::Vless(VlessConfig {
+                    uuid,
+                    address,
+                    port,
+                    params,
+                    tag,
+                })
+            } else {
+                Node::Vmess(VmessConfig {
+                    uuid,
+                    alter_id: user["alterId"]
+                        .as_u64()
+                        .and_then(|aid| u16::try_from(aid).ok())
+                        .unwrap_or(0),
+                    security: user["security"].as_str().unwrap_or("auto").to_string(),
+                    address,
+                    port,
+                    params,
+                    tag,
+                })
+            }
+        }
+        "trojan" => {
+            let server = &settings["servers"][0];
+            Node::Trojan(TrojanConfig {
+                password: field(server, "password", &tag)?.to_string(),
+                address: field(server, "address", &tag)?.to_string(),
+                port: port(server, &tag)?,
+                params,
+                tag,
+            })
+        }
+        "shadowsocks" => {
+            let server = &settings["servers"][0];
+            // A websocket transport is what v2ray-plugin links turn into.
+            let plugin = (params.get("type").map(String::as_str) == Some("ws")).then(|| {
+                let mut plugin = "v2ray-plugin".to_string();
+                if params.get("security").map(String::as_str) == Some("tls") {
+                    plugin.push_str(";tls");
+                }
+                for key in ["host", "path"] {
+                    if let Some(value) = params.get(key) {
+                        plugin.push_str(&format!(";{}={}", key, value));
+                    }
+                }
+                plugin
+            });
+            Node::Shadowsocks(ShadowsocksConfig {
+                method: field(server, "method", &tag)?.to_string(),
+                password: field(server, "password", &tag)?.to_string(),
+                address: field(server, "address", &tag)?.to_string(),
+                port: port(server, &tag)?,
+                plugin,
+                tag,
+            })
+        }
+        "freedom" | "blackhole" | "dns" | "loopback" => return Ok(None),
+        other => {
+            return Err(format!(
+                "Outbound {} uses {}, which has no share link",
+                tag, other
+            ));
+        }
+    };
+    Ok(Some(node))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scoped_dns_servers_bypass_the_proxy() {
+        let routes: Vec<DnsRoute> = ["corp.example=10.0.0.53", "lan=dns.home.arpa:5353"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let (dns, rules) = build_dns(&routes, &[]);
+        assert_eq!(dns["servers"][1]["port"], 5353);
+        assert_eq!(dns["servers"][2], "1.1.1.1");
+        assert_eq!(rules[0]["ip"], json!(["10.0.0.53"]));
+        assert_eq!(
+            rules[1]["domain"],
+            json!(["domain:corp.example", "domain:lan", "full:dns.home.arpa"])
+        );
+
+        let named: Vec<DnsRoute> = vec!["lan=dns.home.arpa".parse().unwrap()];
+        let (_, rules) = build_dns(&named, &["8.8.8.8".to_string()]);
+        assert_eq!(rules.len(), 1);
+        assert!(rules[0].get("ip").is_none());
+    }
+
+    #[test]
+    fn tags_stay_unique() {
+        let registry = crate::parser::Registry::new();
+        let nodes: Vec<Node> = [
+            "trojan://a@a.example:443#direct",
+            "trojan://b@b.example:443#api",
+            "trojan://c@c.example:443#nl",
+            "trojan://d@d.example:443#nl",
+        ]
+        .iter()
+        .map(|l| registry.parse(l).unwrap())
+        .collect();
+        let config = build_config(&nodes, &BuildOptions::default()).unwrap();
+        let config = serde_json::to_value(config).unwrap();
+        let tags: Vec<&str> = config["outbounds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|o| o["tag"].as_str())
+            .collect();
+        assert_eq!(tags, ["direct-2", "api-2", "nl", "nl-2"]);
+    }
+}
@@ -0,0 +1,302 @@
+use flate2::read::DeflateDecoder;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::error::PawprintError;
+use crate::geo;
+
+// Release assets are Xray-<platform>.zip with a .dgst of checksums next to each.
+pub const DEFAULT_SOURCE: &str = "https://github.com/XTLS/Xray-core/releases";
+const LATEST_API: &str = "https://api.github.com/repos/XTLS/Xray-core/releases/latest";
+
+// Taken out of the release archive; xray finds the data files next to itself.
+const ASSETS: [&str; 2] = ["geoip.dat", "geosite.dat"];
+
+#[cfg(windows)]
+const BINARY: &str = "xray.exe";
+#[cfg(not(windows))]
+const BINARY: &str = "xray";
+
+pub fn default_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("pawprint-vpn")
+        .join("core")
+}
+
+// core.json in the core directory: the version in use and whether `core update`
+// may move it.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    pub active: Option<String>,
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+impl State {
+    pub fn load(dir: &Path) -> Result<State, PawprintError> {
+        let path = dir.join("core.json");
+        match fs::read_to_string(&path) {
+            Ok(content) => Ok(serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid {}: {}", path.display(), e))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(PawprintError::file(&path, e)),
+        }
+    }
+
+    fn save(&self, dir: &Path) -> Result<(), PawprintError> {
+        let path = dir.join("core.json");
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .map_err(|e| PawprintError::file(&path, e))
+    }
+}
+
+// The release asset name for this OS and CPU, e.g. linux-64 or macos-arm64-v8a.
+pub fn platform() -> Result<&'static str, PawprintError> {
+    let platform = match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => "linux-64",
+        ("linux", "x86") => "linux-32",
+        ("linux", "aarch64") => "linux-arm64-v8a",
+        ("linux", "arm") => "linux-arm32-v7a",
+        ("linux", "riscv64") => "linux-riscv64",
+        ("linux", "loongarch64") => "linux-loong64",
+        ("android", "aarch64") => "android-arm64-v8a",
+        ("macos", "x86_64") => "macos-64",
+        ("macos", "aarch64") => "macos-arm64-v8a",
+        ("windows", "x86_64") => "windows-64",
+        ("windows", "x86") => "windows-32",
+        ("windows", "aarch64") => "windows-arm64-v8a",
+        ("freebsd", "x86_64") => "freebsd-64",
+        ("freebsd", "x86") => "freebsd-32",
+        ("freebsd", "aarch64") => "freebsd-arm64-v8a",
+        (os, arch) => {
+            return Err(format!("Xray publishes no build for {} on {}", os, arch).into());
+        }
+    };
+    Ok(platform)
+}
+
+// Release tags carry a leading v: v25.8.3.
+fn tag(version: &str) -> Result<String, PawprintError> {
+    let version = version.trim_start_matches('v');
+    if version.is_empty()
+        || !version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+    {
+        return Err(format!("Invalid xray version: {}", version).into());
+    }
+    Ok(format!("v{}", version))
+}
+
+pub fn latest_version() -> Result<String, PawprintError> {
+    #[derive(Deserialize)]
+    struct Release {
+        tag_name: String,
+    }
+    let release: Release = serde_json::from_slice(&geo::get(LATEST_API)?)
+        .map_err(|e| format!("Unexpected answer from {}: {}", LATEST_API, e))?;
+    Ok(release.tag_name)
+}
+
+// The SHA2-256 line of a .dgst file, which also lists MD5, SHA1 and SHA2-512.
+fn dgst_sha256(dgst: &str) -> Option<String> {
+    dgst.lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(name, _)| name.trim() == "SHA2-256")
+        .map(|(_, sum)| sum.trim().to_ascii_lowercase())
+        .filter(|sum| sum.len() == 64 && sum.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+// The files of a zip archive named in `wanted`, found through its central
+// directory. Only stored and deflated entries, which is what releases use.
+fn unzip(data: &[u8], wanted: &[&str]) -> Result<Vec<(String, Vec<u8>)>, PawprintError> {
+    let invalid = || PawprintError::from("The release archive is not a valid zip file");
+    // The end of central directory record is 22 bytes plus a comment of at most 64 KiB.
+    let end = (data.len().saturating_sub(22 + 0xFFFF)..=data.len().saturating_sub(22))
+        .rev()
+        .find(|&i| data[i..].starts_with(&[0x50, 0x4b, 0x05, 0x06]))
+        .ok_or_else(invalid)?;
+    let count = u16_at(data, end + 10).ok_or_else(invalid)?;
+    let mut offset = u32_at(data, end + 16).ok_or_else(invalid)?;
+
+    let mut files = Vec::new();
+    for _ in 0..count {
+        if !data
+            .get(offset..)
+            .is_some_and(|d| d.starts_with(&[0x50, 0x4b, 0x01, 0x02]))
+        {
+            return Err(invalid());
+        }
+        let method = u16_at(data, offset + 10).ok_or_else(invalid)?;
+        let compressed = u32_at(data, offset + 20).ok_or_else(invalid)?;
+        let size = u32_at(data, offset + 24).ok_or_else(invalid)?;
+        let name_len = u16_at(data, offset + 28).ok_or_else(invalid)?;
+        let extra_len = u16_at(data, offset + 30).ok_or_else(invalid)?;
+        let comment_len = u16_at(data, offset + 32).ok_or_else(invalid)?;
+        let local = u32_at(data, offset + 42).ok_or_else(invalid)?;
+        let name = data
+            .get(offset + 46..offset + 46 + name_len)
+            .ok_or_else(invalid)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        offset += 46 + name_len + extra_len + comment_len;
+        if !wanted.contains(&name.as_str()) {
+            continue;
+        }
+
+        // The local header repeats the name but may have an extra field of its own.
+        let start = local
+            + 30
+            + u16_at(data, local + 26).ok_or_else(invalid)?
+            + u16_at(data, local + 28).ok_or_else(invalid)?;
+        let raw = data.get(start..start + compressed).ok_or_else(invalid)?;
+        let content = match method {
+            0 => raw.to_vec(),
+            8 => {
+                let mut content = Vec::with_capacity(size);
+                DeflateDecoder::new(raw)
+                    .read_to_end(&mut content)
+                    .map_err(|e| format!("Failed to unpack {}: {}", name, e))?;
+                content
+            }
+            _ => {
+                return Err(
+                    format!("{} is compressed with unsupported method {}", name, method).into(),
+                );
+            }
+        };
+        files.push((name, content));
+    }
+    Ok(files)
+}
+
+// Downloads `version` (the latest when None) for this platform into
+// `dir/<version>`, checks it against the release's SHA2-256 sum and makes it the
+// core in use. A pinned version stays in use through `core update`. Returns the
+// version, or None if it was already the one in use.
+pub fn install(
+    dir: &Path,
+    source: &str,
+    version: Option<&str>,
+    force: bool,
+) -> Result<Option<String>, PawprintError> {
+    let version = match version {
+        Some(version) => tag(version)?,
+        None => latest_version()?,
+    };
+    let mut state = State::load(dir)?;
+    let target = dir.join(&version);
+    if !force && state.active.as_deref() == Some(version.as_str()) && target.join(BINARY).is_file()
+    {
+        return Ok(None);
+    }
+
+    let asset = format!("Xray-{}.zip", platform()?);
+    let url = format!(
+        "{}/download/{}/{}",
+        source.trim_end_matches('/'),
+        version,
+        asset
+    );
+    let dgst = String::from_utf8_lossy(&geo::get(&format!("{}.dgst", url))?).to_string();
+    let expected = dgst_sha256(&dgst)
+        .ok_or_else(|| format!("{}.dgst does not contain a SHA2-256 checksum", url))?;
+    info!("Downloading {}...", url);
+    let archive = geo::get(&url)?;
+    let actual = geo::sha256(&archive);
+    if actual != expected {
+        return Err(format!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            url, expected, actual
+        )
+        .into());
+    }
+
+    let mut wanted = ASSETS.to_vec();
+    wanted.push(BINARY);
+    let files = unzip(&archive, &wanted)?;
+    if !files.iter().any(|(name, _)| name == BINARY) {
+        return Err(format!("{} has no {}", asset, BINARY).into());
+    }
+    // Unpacked next to the final directory, then moved in one go.
+    let temp = dir.join(format!(".{}.tmp", version));
+    if temp.exists() {
+        fs::remove_dir_all(&temp).map_err(|e| PawprintError::file(&temp, e))?;
+    }
+    fs::create_dir_all(&temp).map_err(|e| PawprintError::file(&temp, e))?;
+    for (name, content) in files {
+        let path = temp.join(&name);
+        fs::write(&path, content).map_err(|e| PawprintError::file(&path, e))?;
+        #[cfg(unix)]
+        if name == BINARY {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+                .map_err(|e| PawprintError::file(&path, e))?;
+        }
+    }
+    if target.exists() {
+        fs::remove_dir_all(&target).map_err(|e| PawprintError::file(&target, e))?;
+    }
+    fs::rename(&temp, &target).map_err(|e| PawprintError::file(&target, e))?;
+
+    state.active = Some(version.clone());
+    state.save(dir)?;
+    Ok(Some(version))
+}
+
+// Pins the version in use when `pinned`, or lets `core update` move it again.
+pub fn set_pinned(dir: &Path, pinned: bool) -> Result<(), PawprintError> {
+    let mut state = State::load(dir)?;
+    state.pinned = pinned;
+    state.save(dir)
+}
+
+// Versions unpacked in `dir`, oldest first.
+pub fn installed(dir: &Path) -> Result<Vec<String>, PawprintError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(PawprintError::file(dir, e)),
+    };
+    let mut versions: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join(BINARY).is_file())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    versions.sort_by_key(|v| {
+        v.trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse::<u64>().unwrap_or(0))
+            .collect::<Vec<_>>()
+    });
+    Ok(versions)
+}
+
+// The installed binary in use, if any.
+pub fn active_binary() -> Option<PathBuf> {
+    let dir = default_dir();
+    let version = State::load(&dir).ok()?.active?;
+    Some(dir.join(version).join(BINARY)).filter(|path| path.is_file())
+}
+
+// What to run for `--xray`: the bare default name means the installed core when
+// there is one, anything else is used as given.
+pub fn program(xray: &str) -> PathBuf {
+    match active_binary() {
+        Some(path) if xray == "xray" => path,
+        _ => PathBuf::from(xray),
+    }
+}